@@ -1,13 +1,41 @@
 use std::{
-    ffi::{c_char, CStr},
+    cell::RefCell,
+    ffi::{c_char, c_void, CStr, CString},
     path::{Path, PathBuf},
+    sync::atomic::{AtomicI32, Ordering},
+    time::Duration,
 };
 
 use crate::{
     data_base::{self, DataBase},
     data_entry::DataEntry,
+    metrics::{MENTALSCORE, PHYSICALSCORE},
+    task_progress::TaskProgress,
 };
 
+/// Opaque handle to a loaded `DataBase`.
+///
+/// Created by `OpenDatabase` and released by `CloseDatabase`. Functions taking a handle reuse the
+/// already loaded database instead of loading it again on every call, meaning the database
+/// settings and tag list are only read from disk when needed.
+pub struct DataBaseHandle {
+    data_base: DataBase,
+}
+
+/// The error code and message of the last failed call on this thread.
+struct LastError {
+    code: i32,
+    message: CString,
+}
+
+/// Callback receiving the number of processed files, the total number of files and the
+/// `user_data` pointer passed along with the callback.
+pub type ProgressCallback = Option<unsafe extern "C" fn(u32, u32, *mut c_void)>;
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<LastError>> = const { RefCell::new(None) };
+}
+
 /// fn InitLogger(`logfile_path_ptr`);
 ///
 /// WARNING: Function is incomplete. Currently logs to std::out meaning the logfile_path_ptr
@@ -30,30 +58,20 @@ use crate::{
 /// * The nul terminator must be within `isize::MAX` from `ptr`
 #[no_mangle]
 pub unsafe extern "C" fn InitLogger(logfile_path_ptr: *const c_char) -> i32 {
-    let logfile_path = match try_ptr_to_string(logfile_path_ptr) {
-        Ok(str) => str,
-        Err(ec) => return ec,
-    };
-
-    let result_code = match DataBase::init_logger(PathBuf::from(logfile_path)) {
-        Ok(_) => 0,
-        Err(error) => {
-            log::error!("InitLogger error occured: {error:?}");
-            error.code()
-        }
-    };
-
-    log::logger().flush();
-    result_code
+    let result = try_ptr_to_string(logfile_path_ptr, "logfile_path_ptr")
+        .and_then(|logfile_path| DataBase::init_logger(PathBuf::from(logfile_path)));
+    to_result_code("InitLogger", result)
 }
 
 //
 
 //
 
-/// fn CompressDBToImage(`db_path_ptr`, `result_path_ptr`);
+/// fn CreateDatabase(`db_path_ptr`, `day_switch_offset`);
 ///
-/// Compresses the database at `db_path_ptr` into a image stored at `result_path_ptr`.
+/// Creates a new empty `DataBase` at the provided `db_path_ptr` using `day_switch_offset`.
+/// The directory at `db_path_ptr` must either not exist or be empty. Use `OpenDatabase` to get a
+/// handle to the created database.
 ///
 /// # Safety
 ///
@@ -70,42 +88,30 @@ pub unsafe extern "C" fn InitLogger(logfile_path_ptr: *const c_char) -> i32 {
 ///
 /// * The nul terminator must be within `isize::MAX` from `ptr`
 #[no_mangle]
-pub unsafe extern "C" fn CompressDBToImage(
-    db_path_ptr: *const c_char,
-    result_path_ptr: *const c_char,
-) -> i32 {
-    let data_base = match try_get_db(db_path_ptr) {
-        Ok(db) => db,
-        Err(ec) => return ec,
-    };
-
-    let result_path = match try_ptr_to_string(result_path_ptr) {
-        Ok(str) => str,
-        Err(ec) => return ec,
-    };
-
-    let result_code = match data_base.compress_to_image(Path::new(&result_path)) {
-        Ok(_) => 0,
-        Err(error) => {
-            log::error!("CompressDBToImage error occured: {error:?}");
-            error.code()
-        }
-    };
-
-    log::logger().flush();
-    result_code
+pub unsafe extern "C" fn CreateDatabase(db_path_ptr: *const c_char, day_switch_offset: i32) -> i32 {
+    let result = try_ptr_to_string(db_path_ptr, "db_path_ptr").and_then(|data_base_path| {
+        let day_switch_offset = i8::try_from(day_switch_offset)
+            .map_err(|_| data_base::Error::with_kind(data_base::ErrorKind::OffsetOutOfRange))?;
+        DataBase::create(PathBuf::from(data_base_path), day_switch_offset)?;
+        Ok(())
+    });
+    to_result_code("CreateDatabase", result)
 }
 
 //
 
 //
 
-/// fn ExtractDBFromImage(`db_image_path_ptr`, `db_path_ptr`);
+/// fn OpenDatabase(`db_path_ptr`, `handle_out`);
 ///
-/// Attempts to extract a `DataBase` from the provided `db_image_path_ptr` into `db_path_ptr`.
+/// Loads the `DataBase` at the provided `db_path_ptr` and stores a handle to it in `handle_out`.
+/// The handle can then be passed to any of the `WithHandle` functions, and must be released
+/// using `CloseDatabase` once it is no longer needed.
 ///
 /// # Safety
 ///
+/// `handle_out` must be non-null and valid for writes.
+///
 /// Any parameter mentioning `ptr` must satisfy the requirements of `CStr::from_ptr`:
 ///
 /// * The memory pointed to by `ptr` must contain a valid nul terminator at the
@@ -119,45 +125,31 @@ pub unsafe extern "C" fn CompressDBToImage(
 ///
 /// * The nul terminator must be within `isize::MAX` from `ptr`
 #[no_mangle]
-pub unsafe extern "C" fn ExtractDBFromImage(
-    db_image_path_ptr: *const c_char,
+pub unsafe extern "C" fn OpenDatabase(
     db_path_ptr: *const c_char,
+    handle_out: *mut *mut DataBaseHandle,
 ) -> i32 {
-    let db_path = match try_ptr_to_string(db_path_ptr) {
-        Ok(str) => str,
-        Err(ec) => return ec,
-    };
-
-    let db_image_path = match try_ptr_to_string(db_image_path_ptr) {
-        Ok(str) => str,
-        Err(ec) => return ec,
-    };
-
-    let result_code = match DataBase::load_from_image(
-        Path::new(&db_image_path),
-        Path::new(&db_path).to_path_buf(),
-    ) {
-        Ok(_) => 0,
-        Err(error) => {
-            log::error!("ExtractDBFromImage error occured: {error:?}");
-            error.code()
-        }
-    };
+    if handle_out.is_null() {
+        return to_result_code("OpenDatabase", Err(null_pointer_error("handle_out")));
+    }
 
-    log::logger().flush();
-    result_code
+    let result = try_get_db(db_path_ptr).map(|data_base| {
+        let handle = Box::new(DataBaseHandle { data_base });
+        unsafe { *handle_out = Box::into_raw(handle) };
+    });
+    to_result_code("OpenDatabase", result)
 }
 
-//
-
-//
-
-/// fn RegenerateCaches(`db_path_ptr`);
+/// fn OpenDatabaseReadOnly(`db_path_ptr`, `handle_out`);
 ///
-/// Attempts to regenerate the cache files in the `DataBase` at the provided `db_path_ptr`.
+/// Same as `OpenDatabase`, but the database is opened for browsing only. Any function modifying
+/// the database returns the `ReadOnly` error when given the handle. Nothing is written to the
+/// database folder, making it safe to open a database on a mounted backup or an extracted image.
 ///
 /// # Safety
 ///
+/// `handle_out` must be non-null and valid for writes.
+///
 /// Any parameter mentioning `ptr` must satisfy the requirements of `CStr::from_ptr`:
 ///
 /// * The memory pointed to by `ptr` must contain a valid nul terminator at the
@@ -171,72 +163,87 @@ pub unsafe extern "C" fn ExtractDBFromImage(
 ///
 /// * The nul terminator must be within `isize::MAX` from `ptr`
 #[no_mangle]
-pub unsafe extern "C" fn RegenerateCaches(db_path_ptr: *const c_char) -> i32 {
-    let data_base = match try_get_db(db_path_ptr) {
-        Ok(db) => db,
-        Err(ec) => return ec,
-    };
-
-    let result_code = match data_base.regen_caches() {
-        Ok(_) => 0,
-        Err(error) => {
-            log::error!("RegenerateCaches error occured: {error:?}");
-            error.code()
-        }
-    };
+pub unsafe extern "C" fn OpenDatabaseReadOnly(
+    db_path_ptr: *const c_char,
+    handle_out: *mut *mut DataBaseHandle,
+) -> i32 {
+    if handle_out.is_null() {
+        return to_result_code(
+            "OpenDatabaseReadOnly",
+            Err(null_pointer_error("handle_out")),
+        );
+    }
 
-    log::logger().flush();
-    result_code
+    let result = try_ptr_to_string(db_path_ptr, "db_path_ptr")
+        .and_then(|db_path| DataBase::open_read_only(PathBuf::from(db_path)))
+        .map(|data_base| {
+            let handle = Box::new(DataBaseHandle { data_base });
+            unsafe { *handle_out = Box::into_raw(handle) };
+        });
+    to_result_code("OpenDatabaseReadOnly", result)
 }
 
 //
 
 //
 
-/// fn ResumeTask(`db_path_ptr`);
+/// fn CloseDatabase(`handle`);
 ///
-/// Attempts to resume any non-finished tasks in the `DataBase` at the provided `db_path_ptr`.
+/// Releases a handle created by `OpenDatabase`. Passing a null `handle` does nothing.
 ///
 /// # Safety
 ///
-/// Any parameter mentioning `ptr` must satisfy the requirements of `CStr::from_ptr`:
-///
-/// * The memory pointed to by `ptr` must contain a valid nul terminator at the
-///   end of the string.
-///
-/// * `ptr` must be [valid] for reads of bytes up to and including the nul terminator.
-///   This means in particular:
-///
-///     * The entire memory range of this `CStr` must be contained within a single allocation!
-///     * `ptr` must be non-null even for a zero-length cstr.
+/// `handle` must be a handle returned by `OpenDatabase` that has not already been closed. The
+/// handle must not be used again after this call.
+#[no_mangle]
+pub unsafe extern "C" fn CloseDatabase(handle: *mut DataBaseHandle) {
+    if handle.is_null() {
+        return;
+    }
+    drop(unsafe { Box::from_raw(handle) });
+}
+
+//
+
+//
+
+/// fn GetLastErrorCode();
 ///
-/// * The nul terminator must be within `isize::MAX` from `ptr`
+/// Returns the error code of the last function called on this thread, or `0` if said function
+/// succeeded. The codes match the ones listed in `ErrorKind::code()`.
 #[no_mangle]
-pub unsafe extern "C" fn ResumeTask(db_path_ptr: *const c_char) -> i32 {
-    let data_base = match try_get_db(db_path_ptr) {
-        Ok(db) => db,
-        Err(ec) => return ec,
-    };
+pub extern "C" fn GetLastErrorCode() -> i32 {
+    LAST_ERROR.with_borrow(|last_error| last_error.as_ref().map_or(0, |error| error.code))
+}
 
-    let result_code = match data_base.resume_task() {
-        Ok(_) => 0,
-        Err(error) => {
-            log::error!("ResumeTask error occured: {error:?}");
-            error.code()
-        }
-    };
+//
 
-    log::logger().flush();
-    result_code
+//
+
+/// fn GetLastErrorMessage();
+///
+/// Returns a UTF-8 message describing the error of the last function called on this thread, or
+/// null if said function succeeded. The message includes details such as the unknown tag name or
+/// the underlying io error.
+///
+/// The returned string is owned by the library and stays valid until the next function call on
+/// the same thread. Copy it if it is needed for longer than that.
+#[no_mangle]
+pub extern "C" fn GetLastErrorMessage() -> *const c_char {
+    LAST_ERROR.with_borrow(|last_error| {
+        last_error
+            .as_ref()
+            .map_or(std::ptr::null(), |error| error.message.as_ptr())
+    })
 }
 
 //
 
 //
 
-/// fn MergeTags(`db_path_ptr`, `tag1`, `tag2`);
+/// fn CompressDBToImage(`db_path_ptr`, `result_path_ptr`);
 ///
-/// Attempts to merge `tag1` into `tag2` in the `DataBase` at the provided `db_path_ptr`.
+/// Compresses the database at `db_path_ptr` into a image stored at `result_path_ptr`.
 ///
 /// # Safety
 ///
@@ -253,35 +260,23 @@ pub unsafe extern "C" fn ResumeTask(db_path_ptr: *const c_char) -> i32 {
 ///
 /// * The nul terminator must be within `isize::MAX` from `ptr`
 #[no_mangle]
-pub unsafe extern "C" fn MergeTags(db_path_ptr: *const c_char, tag1: u16, tag2: u16) -> i32 {
-    let data_base = match try_get_db(db_path_ptr) {
-        Ok(db) => db,
-        Err(ec) => return ec,
-    };
-
-    let result_code = match data_base.merge_tags(tag1, tag2) {
-        Ok(_) => 0,
-        Err(error) => {
-            log::error!("MergeTags error occured: {error:?}");
-
-            error.code()
-        }
-    };
-
-    log::logger().flush();
-    result_code
+pub unsafe extern "C" fn CompressDBToImage(
+    db_path_ptr: *const c_char,
+    result_path_ptr: *const c_char,
+) -> i32 {
+    let result = try_get_db(db_path_ptr)
+        .and_then(|data_base| compress_db_to_image(&data_base, result_path_ptr));
+    to_result_code("CompressDBToImage", result)
 }
 
-//
-
-//
-
-/// fn RegenerateTagSums(`db_path_ptr`);
+/// fn CompressDBToImageWithHandle(`handle`, `result_path_ptr`);
 ///
-/// Attempts to regenerate all tag sums in the `DataBase` at the provided `db_path_ptr`.
+/// Handle variant of `CompressDBToImage`.
 ///
 /// # Safety
 ///
+/// `handle` must be a handle returned by `OpenDatabase` that has not been closed.
+///
 /// Any parameter mentioning `ptr` must satisfy the requirements of `CStr::from_ptr`:
 ///
 /// * The memory pointed to by `ptr` must contain a valid nul terminator at the
@@ -295,32 +290,30 @@ pub unsafe extern "C" fn MergeTags(db_path_ptr: *const c_char, tag1: u16, tag2:
 ///
 /// * The nul terminator must be within `isize::MAX` from `ptr`
 #[no_mangle]
-pub unsafe extern "C" fn RegenerateTagSums(db_path_ptr: *const c_char) -> i32 {
-    let data_base = match try_get_db(db_path_ptr) {
-        Ok(db) => db,
-        Err(ec) => return ec,
-    };
-
-    let result_code = match data_base.regen_tag_sums() {
-        Ok(_) => 0,
-        Err(error) => {
-            log::error!("RegenerateTagSums error occured: {error:?}");
-
-            error.code()
-        }
-    };
+pub unsafe extern "C" fn CompressDBToImageWithHandle(
+    handle: *const DataBaseHandle,
+    result_path_ptr: *const c_char,
+) -> i32 {
+    let result = try_get_handle_db(handle)
+        .and_then(|data_base| compress_db_to_image(data_base, result_path_ptr));
+    to_result_code("CompressDBToImage", result)
+}
 
-    log::logger().flush();
-    result_code
+unsafe fn compress_db_to_image(
+    data_base: &DataBase,
+    result_path_ptr: *const c_char,
+) -> Result<(), data_base::Error> {
+    let result_path = try_ptr_to_string(result_path_ptr, "result_path_ptr")?;
+    data_base.compress_to_image(Path::new(&result_path))
 }
 
 //
 
 //
 
-/// fn RenameTag(`db_path_ptr`, `old_tag_ptr`, `new_tag_ptr`);
+/// fn ExtractDBFromImage(`db_image_path_ptr`, `db_path_ptr`);
 ///
-/// Attempts to rename `old_tag_ptr` to `new_tag_ptr` in the `DataBase` at `db_path_ptr`.
+/// Attempts to extract a `DataBase` from the provided `db_image_path_ptr` into `db_path_ptr`.
 ///
 /// # Safety
 ///
@@ -337,41 +330,26 @@ pub unsafe extern "C" fn RegenerateTagSums(db_path_ptr: *const c_char) -> i32 {
 ///
 /// * The nul terminator must be within `isize::MAX` from `ptr`
 #[no_mangle]
-pub unsafe extern "C" fn RenameTag(
+pub unsafe extern "C" fn ExtractDBFromImage(
+    db_image_path_ptr: *const c_char,
     db_path_ptr: *const c_char,
-    old_tag_ptr: *const c_char,
-    new_tag_ptr: *const c_char,
 ) -> i32 {
-    let data_base = match try_get_db(db_path_ptr) {
-        Ok(db) => db,
-        Err(ec) => return ec,
-    };
-    let Ok(old_tag) = try_ptr_to_string(old_tag_ptr) else {
-        return -2;
-    };
-    let Ok(new_tag) = try_ptr_to_string(new_tag_ptr) else {
-        return -3;
-    };
-
-    let result_code = match data_base.rename_tag(old_tag.to_string(), new_tag.to_string()) {
-        Ok(_) => 0,
-        Err(error) => {
-            log::error!("RenameTag error occured: {error:?}");
-            error.code()
-        }
-    };
-
-    log::logger().flush();
-    result_code
+    let result = (|| {
+        let db_path = try_ptr_to_string(db_path_ptr, "db_path_ptr")?;
+        let db_image_path = try_ptr_to_string(db_image_path_ptr, "db_image_path_ptr")?;
+        DataBase::load_from_image(Path::new(&db_image_path), PathBuf::from(db_path))?;
+        Ok(())
+    })();
+    to_result_code("ExtractDBFromImage", result)
 }
 
 //
 
 //
 
-/// fn TemporaryUpdateDatabase(`db_path_ptr`) 6
+/// fn RegenerateCaches(`db_path_ptr`);
 ///
-/// Attempts to upgrade the `DataBase` at the proided `db_path_ptr` to the new format.
+/// Attempts to regenerate the cache files in the `DataBase` at the provided `db_path_ptr`.
 ///
 /// # Safety
 ///
@@ -388,38 +366,59 @@ pub unsafe extern "C" fn RenameTag(
 ///
 /// * The nul terminator must be within `isize::MAX` from `ptr`
 #[no_mangle]
-pub unsafe extern "C" fn TemporaryUpdateDatabase(
-    db_path_ptr: *const c_char,
-    current_day_switch_offset: i32,
-) -> i32 {
-    let data_base_path = match try_ptr_to_string(db_path_ptr) {
-        Ok(db_path) => db_path,
-        Err(ec) => return ec,
-    };
-
-    let result_code =
-        match DataBase::upgrade_database(Path::new(&data_base_path), current_day_switch_offset) {
-            Ok(_) => 0,
-            Err(error) => {
-                println!("Error occured!\n{:?}", error);
+pub unsafe extern "C" fn RegenerateCaches(db_path_ptr: *const c_char) -> i32 {
+    let result = try_get_db(db_path_ptr).and_then(|data_base| data_base.regen_caches());
+    to_result_code("RegenerateCaches", result)
+}
 
-                error.code()
-            }
-        };
+/// fn RegenerateCachesWithHandle(`handle`);
+///
+/// Handle variant of `RegenerateCaches`.
+///
+/// # Safety
+///
+/// `handle` must be a handle returned by `OpenDatabase` that has not been closed.
+#[no_mangle]
+pub unsafe extern "C" fn RegenerateCachesWithHandle(handle: *const DataBaseHandle) -> i32 {
+    let result = try_get_handle_db(handle).and_then(|data_base| data_base.regen_caches());
+    to_result_code("RegenerateCaches", result)
+}
 
-    log::logger().flush();
-    result_code
+/// fn RegenerateCachesWithProgress(`handle`, `callback`, `user_data`, `cancel_flag`);
+///
+/// Progress reporting variant of `RegenerateCachesWithHandle`.
+///
+/// `callback` is called with the number of processed files and the total number of files each
+/// time a file is processed, together with `user_data`. It may be null. The task is cancelled
+/// once the `i32` behind `cancel_flag` is set to a nonzero value. `cancel_flag` may be null.
+/// A cancelled task returns the `Cancelled` error and can be finished using `ResumeTask`.
+///
+/// # Safety
+///
+/// `handle` must be a handle returned by `OpenDatabase` that has not been closed.
+/// `cancel_flag` must either be null or point to a `i32` that stays valid until the function
+/// returns. The flag should only be written to atomically while the function runs.
+#[no_mangle]
+pub unsafe extern "C" fn RegenerateCachesWithProgress(
+    handle: *const DataBaseHandle,
+    callback: ProgressCallback,
+    user_data: *mut c_void,
+    cancel_flag: *const i32,
+) -> i32 {
+    let progress = unsafe { c_task_progress(callback, user_data, cancel_flag) };
+    let result = try_get_handle_db(handle)
+        .and_then(|data_base| data_base.regen_caches_with_progress(&progress));
+    to_result_code("RegenerateCaches", result)
 }
 
 //
 
 //
 
-/// fn InsertDataEntry(`db_path_ptr`, `year`, `month`, `day`, `data`, `data_length`);
+/// fn ResumeTask(`db_path_ptr`);
 ///
-/// Attempts to insert the `DataEntry` stored in the `data` parameter to the `DataFile` matching the
-/// provided `year`, `month` and `day` in the `DataBase` at the provided `db_path_ptr`.
-/// Any existing entry at the target location is overwritten.
+/// Attempts to resume any non-finished tasks in the `DataBase` at the provided `db_path_ptr`. A
+/// task still held by a running process is not resumed, and `DataBaseBusy` is returned.
 ///
 /// # Safety
 ///
@@ -436,54 +435,114 @@ pub unsafe extern "C" fn TemporaryUpdateDatabase(
 ///
 /// * The nul terminator must be within `isize::MAX` from `ptr`
 #[no_mangle]
-pub unsafe extern "C" fn InsertDataEntry(
-    db_path_ptr: *const c_char,
-    year: i32,
-    month: u8,
-    day: u8,
-    hour: u8,
-    data: *const u16,
-    data_length: u32,
-) -> i32 {
-    if data.is_null() {
-        return -3;
-    }
+pub unsafe extern "C" fn ResumeTask(db_path_ptr: *const c_char) -> i32 {
+    let result = try_get_db(db_path_ptr).and_then(|data_base| data_base.resume_task());
+    to_result_code("ResumeTask", result)
+}
 
-    let data = unsafe { std::slice::from_raw_parts(data, data_length as usize) };
+/// fn ResumeTaskWithHandle(`handle`);
+///
+/// Handle variant of `ResumeTask`.
+///
+/// # Safety
+///
+/// `handle` must be a handle returned by `OpenDatabase` that has not been closed.
+#[no_mangle]
+pub unsafe extern "C" fn ResumeTaskWithHandle(handle: *const DataBaseHandle) -> i32 {
+    let result = try_get_handle_db(handle).and_then(|data_base| data_base.resume_task());
+    to_result_code("ResumeTask", result)
+}
 
-    let data_base = match try_get_db(db_path_ptr) {
-        Ok(db) => db,
-        Err(ec) => return ec,
-    };
+/// fn ResumeTaskWithProgress(`handle`, `callback`, `user_data`, `cancel_flag`);
+///
+/// Progress reporting variant of `ResumeTaskWithHandle`.
+///
+/// `callback` is called with the number of processed files and the total number of files each
+/// time a file is processed, together with `user_data`. It may be null. The task is cancelled
+/// once the `i32` behind `cancel_flag` is set to a nonzero value. `cancel_flag` may be null.
+/// A cancelled task returns the `Cancelled` error and can be finished using `ResumeTask`.
+///
+/// # Safety
+///
+/// `handle` must be a handle returned by `OpenDatabase` that has not been closed.
+/// `cancel_flag` must either be null or point to a `i32` that stays valid until the function
+/// returns. The flag should only be written to atomically while the function runs.
+#[no_mangle]
+pub unsafe extern "C" fn ResumeTaskWithProgress(
+    handle: *const DataBaseHandle,
+    callback: ProgressCallback,
+    user_data: *mut c_void,
+    cancel_flag: *const i32,
+) -> i32 {
+    let progress = unsafe { c_task_progress(callback, user_data, cancel_flag) };
+    let result = try_get_handle_db(handle)
+        .and_then(|data_base| data_base.resume_task_with_progress(&progress));
+    to_result_code("ResumeTask", result)
+}
 
-    let data_entry = match DataEntry::from_c_data(data, hour, data_base.settings()) {
-        Ok(data_entry) => data_entry,
-        Err(error) => {
-            log::error!("InsertDataEntry error occured! {error:?}");
-            return data_base::Error::from(error).code();
-        }
-    };
+//
 
-    let result_code = match data_base.insert_data_entry(year, month, day, data_entry) {
-        Ok(_) => 0,
-        Err(error) => {
-            log::error!("InsertDataEntry error occured: {error:?}");
-            error.code()
-        }
-    };
+//
 
-    log::logger().flush();
-    result_code
+/// The lock held on a database, as returned by `GetLockInfo`.
+///
+/// `task_id` uses the same ids as the status file: `0` none, `1` regenerate caches, `2`
+/// regenerate tag sums, `3` merge tags, `4` rename tag, `5` remove tag, `6` repair and `7` undo.
+/// The owner fields are only set if `has_owner` is true, which is not the case for locks created
+/// by older versions. `started` is a unix timestamp in seconds. `host` must be released using
+/// `FreeLockInfo`.
+///
+/// `is_stale` is only true if the owning process is known to no longer exist. It is false both
+/// for a running owner and for an owner that can't be checked, such as one on another host.
+#[repr(C)]
+pub struct CLockInfo {
+    pub is_locked: bool,
+    pub task_id: u8,
+    pub is_stale: bool,
+    pub has_owner: bool,
+    pub pid: u32,
+    pub started: i64,
+    pub host: *mut c_char,
 }
 
-//
+impl CLockInfo {
+    fn new(lock_info: Option<data_base::LockInfo>) -> CLockInfo {
+        let mut c_lock_info = CLockInfo {
+            is_locked: false,
+            task_id: 0,
+            is_stale: false,
+            has_owner: false,
+            pid: 0,
+            started: 0,
+            host: std::ptr::null_mut(),
+        };
+        let Some(lock_info) = lock_info else {
+            return c_lock_info;
+        };
 
-//
+        c_lock_info.is_locked = true;
+        c_lock_info.task_id = lock_info.task.task_id();
+        c_lock_info.is_stale = lock_info.is_stale();
+        if let Some(owner) = lock_info.owner {
+            c_lock_info.has_owner = true;
+            c_lock_info.pid = owner.pid;
+            c_lock_info.started = owner.started.unix_timestamp();
+            c_lock_info.host = CString::new(owner.host)
+                .map(CString::into_raw)
+                .unwrap_or(std::ptr::null_mut());
+        }
+        c_lock_info
+    }
+}
 
-/// fn AddDataEntry(`db_path_ptr`, `year`, `month`, `day`, `data`, `data_length`);
+/// fn GetLockInfo(`db_path_ptr`, `info_out`);
 ///
-/// Attempts to add the `DataEntry` stored in the `data` parameter to the `DataFile` matching the
-/// provided `year`, `month` and `day` in the `DataBase` at the provided `db_path_ptr`.
+/// Writes the lock held on the `DataBase` at the provided `db_path_ptr` to `info_out`. If the
+/// database isn't busy `is_locked` is false. A stale lock, whose owning process no longer exists,
+/// can be finished using `ResumeTask` or removed using `BreakLock`. Any other lock can only be
+/// removed using `BreakLock`, once it is certain that its owner is gone.
+///
+/// The result must be released using `FreeLockInfo`.
 ///
 /// # Safety
 ///
@@ -499,46 +558,1777 @@ pub unsafe extern "C" fn InsertDataEntry(
 ///     * `ptr` must be non-null even for a zero-length cstr.
 ///
 /// * The nul terminator must be within `isize::MAX` from `ptr`
+///
+/// `info_out` must be non-null and valid for writes.
 #[no_mangle]
-pub unsafe extern "C" fn AddDataEntry(
-    db_path_ptr: *const c_char,
-    year: i32,
-    month: u8,
-    day: u8,
-    hour: u8,
-    data: *const u16,
-    data_length: u32,
+pub unsafe extern "C" fn GetLockInfo(db_path_ptr: *const c_char, info_out: *mut CLockInfo) -> i32 {
+    let result = try_get_db(db_path_ptr)
+        .and_then(|data_base| unsafe { get_lock_info(&data_base, info_out) });
+    to_result_code("GetLockInfo", result)
+}
+
+/// fn GetLockInfoWithHandle(`handle`, `info_out`);
+///
+/// Handle variant of `GetLockInfo`.
+///
+/// # Safety
+///
+/// `handle` must be a handle returned by `OpenDatabase` that has not been closed.
+///
+/// `info_out` must be non-null and valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn GetLockInfoWithHandle(
+    handle: *const DataBaseHandle,
+    info_out: *mut CLockInfo,
 ) -> i32 {
-    if data.is_null() {
-        return -3;
+    let result = try_get_handle_db(handle)
+        .and_then(|data_base| unsafe { get_lock_info(data_base, info_out) });
+    to_result_code("GetLockInfo", result)
+}
+
+unsafe fn get_lock_info(
+    data_base: &DataBase,
+    info_out: *mut CLockInfo,
+) -> Result<(), data_base::Error> {
+    if info_out.is_null() {
+        return Err(null_pointer_error("info_out"));
+    }
+
+    let lock_info = CLockInfo::new(data_base.lock_info()?);
+    unsafe { info_out.write(lock_info) };
+    Ok(())
+}
+
+/// fn FreeLockInfo(`info`);
+///
+/// Releases the host string of a `CLockInfo` filled in by `GetLockInfo` and sets it to null.
+/// Passing a null `info` pointer does nothing.
+///
+/// # Safety
+///
+/// `info` must be null or point to a `CLockInfo` filled in by `GetLockInfo`.
+#[no_mangle]
+pub unsafe extern "C" fn FreeLockInfo(info: *mut CLockInfo) {
+    let Some(info) = (unsafe { info.as_mut() }) else {
+        return;
+    };
+    if !info.host.is_null() {
+        drop(unsafe { CString::from_raw(info.host) });
+        info.host = std::ptr::null_mut();
+    }
+}
+
+/// fn BreakLock(`db_path_ptr`);
+///
+/// Removes the lock of the `DataBase` at the provided `db_path_ptr` without finishing the active
+/// task, together with every writer lock of short writes. Should only be used on a stale lock, see
+/// `GetLockInfo`, or when `WritesInProgress` is returned because of a crashed process.
+///
+/// # Safety
+///
+/// Any parameter mentioning `ptr` must satisfy the requirements of `CStr::from_ptr`:
+///
+/// * The memory pointed to by `ptr` must contain a valid nul terminator at the
+///   end of the string.
+///
+/// * `ptr` must be [valid] for reads of bytes up to and including the nul terminator.
+///   This means in particular:
+///
+///     * The entire memory range of this `CStr` must be contained within a single allocation!
+///     * `ptr` must be non-null even for a zero-length cstr.
+///
+/// * The nul terminator must be within `isize::MAX` from `ptr`
+#[no_mangle]
+pub unsafe extern "C" fn BreakLock(db_path_ptr: *const c_char) -> i32 {
+    let result = try_get_db(db_path_ptr).and_then(|data_base| data_base.break_lock());
+    to_result_code("BreakLock", result)
+}
+
+/// fn BreakLockWithHandle(`handle`);
+///
+/// Handle variant of `BreakLock`.
+///
+/// # Safety
+///
+/// `handle` must be a handle returned by `OpenDatabase` that has not been closed.
+#[no_mangle]
+pub unsafe extern "C" fn BreakLockWithHandle(handle: *const DataBaseHandle) -> i32 {
+    let result = try_get_handle_db(handle).and_then(|data_base| data_base.break_lock());
+    to_result_code("BreakLock", result)
+}
+
+/// fn SetLockTimeoutWithHandle(`handle`, `timeout_ms`);
+///
+/// Sets how many milliseconds the tasks of the handle wait for a busy database before failing
+/// with `DataBaseBusy`. A `timeout_ms` of 0 makes them fail immediately, which is the default.
+///
+/// # Safety
+///
+/// `handle` must be a handle returned by `OpenDatabase` that has not been closed, and must not
+/// be used by any other thread while this function runs.
+#[no_mangle]
+pub unsafe extern "C" fn SetLockTimeoutWithHandle(
+    handle: *mut DataBaseHandle,
+    timeout_ms: u32,
+) -> i32 {
+    let result = match unsafe { handle.as_mut() } {
+        Some(handle) => {
+            let timeout = (timeout_ms > 0).then(|| Duration::from_millis(timeout_ms as u64));
+            handle.data_base.set_lock_timeout(timeout);
+            Ok(())
+        }
+        None => Err(null_pointer_error("handle")),
+    };
+    to_result_code("SetLockTimeout", result)
+}
+
+/// fn SetRegenerationThreadsWithHandle(`handle`, `threads`);
+///
+/// Sets how many threads the tasks of the handle use to regenerate caches and stat sums. A
+/// `threads` of 0 uses one thread per available cpu, which is the default.
+///
+/// # Safety
+///
+/// `handle` must be a handle returned by `OpenDatabase` that has not been closed, and must not
+/// be used by any other thread while this function runs.
+#[no_mangle]
+pub unsafe extern "C" fn SetRegenerationThreadsWithHandle(
+    handle: *mut DataBaseHandle,
+    threads: u32,
+) -> i32 {
+    let result = match unsafe { handle.as_mut() } {
+        Some(handle) => {
+            handle.data_base.set_regeneration_threads(threads as usize);
+            Ok(())
+        }
+        None => Err(null_pointer_error("handle")),
+    };
+    to_result_code("SetRegenerationThreads", result)
+}
+
+//
+
+//
+
+/// Callback receiving a change made to the database and the `user_data` pointer passed along
+/// with the callback. The event is only valid until the callback returns.
+pub type ChangeCallback = Option<unsafe extern "C" fn(*const CChangeEvent, *mut c_void)>;
+
+/// A change made to the database, passed to a `ChangeCallback`.
+///
+/// `kind` is one of the following:
+/// * 0: Entry added, at `year`, `month`, `day`, the offset-compensated `hour` and `minute`.
+/// * 1: Entry overwritten, see 0.
+/// * 2: Entry removed, see 0.
+/// * 3: Tag `tag_id` added with the name `name`.
+/// * 4: Tag `tag_id` renamed from `old_name` to `name`.
+/// * 5: Tag `tag_id` merged into `other_tag_id`.
+/// * 6: Tag `tag_id` removed.
+/// * 7: Diary entry added at `year`, `month` and `day`.
+/// * 8: Caches regenerated. Every cached value should be read again.
+/// * 9: Metric `metric_id` added with the name `name`.
+/// * 10: Metric `metric_id` renamed from `old_name` to `name`.
+/// * 11: Diary entry removed at `year`, `month` and `day`.
+///
+/// `external` is true if the change was made by another process. Fields that don't apply to the
+/// kind are 0, and `name` and `old_name` are null.
+#[repr(C)]
+pub struct CChangeEvent {
+    pub kind: u8,
+    pub external: bool,
+    pub year: i32,
+    pub month: u8,
+    pub day: u8,
+    pub hour: i8,
+    pub minute: u8,
+    pub tag_id: u16,
+    pub other_tag_id: u16,
+    pub metric_id: u16,
+    pub name: *const c_char,
+    pub old_name: *const c_char,
+}
+
+/// fn SubscribeToChanges(`handle`, `callback`, `user_data`, `subscription_out`);
+///
+/// Registers `callback` to be called with every change made to the database of the handle, both
+/// through the handle and by other processes. The id of the subscription is written to
+/// `subscription_out`, and is used to remove the callback using `UnsubscribeFromChanges`.
+///
+/// The callback is called from a background thread, and may use the handle as long as it doesn't
+/// close it. Changes made by other processes are found by scanning the database every poll
+/// interval, see `SetPollIntervalWithHandle`.
+///
+/// # Safety
+///
+/// `handle` must be a handle returned by `OpenDatabase` that has not been closed.
+///
+/// `subscription_out` must be non-null and valid for writes.
+///
+/// `user_data` must stay valid until the callback is removed or the handle is closed, and must be
+/// safe to use from the background thread.
+#[no_mangle]
+pub unsafe extern "C" fn SubscribeToChanges(
+    handle: *const DataBaseHandle,
+    callback: ChangeCallback,
+    user_data: *mut c_void,
+    subscription_out: *mut u64,
+) -> i32 {
+    let result = (|| {
+        let data_base = unsafe { try_get_handle_db(handle) }?;
+        let Some(callback) = callback else {
+            return Err(null_pointer_error("callback"));
+        };
+        if subscription_out.is_null() {
+            return Err(null_pointer_error("subscription_out"));
+        }
+
+        let user_data = SendPtr(user_data.cast_const());
+        let subscription = data_base.subscribe(move |event, origin| {
+            let user_data = &user_data;
+            deliver_c_change_event(event, origin, |c_event| unsafe {
+                callback(c_event, user_data.0.cast_mut())
+            });
+        })?;
+        unsafe { *subscription_out = subscription.0 };
+        Ok(())
+    })();
+    to_result_code("SubscribeToChanges", result)
+}
+
+/// fn UnsubscribeFromChanges(`handle`, `subscription`);
+///
+/// Removes the callback registered by `SubscribeToChanges` as `subscription`. Unknown
+/// subscriptions are ignored.
+///
+/// # Safety
+///
+/// `handle` must be a handle returned by `OpenDatabase` that has not been closed.
+#[no_mangle]
+pub unsafe extern "C" fn UnsubscribeFromChanges(
+    handle: *const DataBaseHandle,
+    subscription: u64,
+) -> i32 {
+    let result = unsafe { try_get_handle_db(handle) }.map(|data_base| {
+        data_base.unsubscribe(data_base::SubscriptionId(subscription));
+    });
+    to_result_code("UnsubscribeFromChanges", result)
+}
+
+/// fn SetPollIntervalWithHandle(`handle`, `interval_ms`);
+///
+/// Sets how many milliseconds pass between the scans for changes made by other processes while a
+/// callback is registered using `SubscribeToChanges`. Defaults to 1000.
+///
+/// # Safety
+///
+/// `handle` must be a handle returned by `OpenDatabase` that has not been closed, and must not
+/// be used by any other thread while this function runs.
+#[no_mangle]
+pub unsafe extern "C" fn SetPollIntervalWithHandle(
+    handle: *mut DataBaseHandle,
+    interval_ms: u32,
+) -> i32 {
+    let result = match unsafe { handle.as_mut() } {
+        Some(handle) => {
+            handle
+                .data_base
+                .set_poll_interval(Duration::from_millis(interval_ms as u64));
+            Ok(())
+        }
+        None => Err(null_pointer_error("handle")),
+    };
+    to_result_code("SetPollInterval", result)
+}
+
+//
+
+//
+
+/// fn CheckDatabase(`db_path_ptr`, `report_out`);
+///
+/// Checks the `DataBase` at the provided `db_path_ptr` for problems without modifying it. A
+/// report listing one problem per line is written to `report_out`. If no problems were found the
+/// report is a empty string.
+///
+/// The report must be released using `FreeCheckReport`.
+///
+/// # Safety
+///
+/// Any parameter mentioning `ptr` must satisfy the requirements of `CStr::from_ptr`:
+///
+/// * The memory pointed to by `ptr` must contain a valid nul terminator at the
+///   end of the string.
+///
+/// * `ptr` must be [valid] for reads of bytes up to and including the nul terminator.
+///   This means in particular:
+///
+///     * The entire memory range of this `CStr` must be contained within a single allocation!
+///     * `ptr` must be non-null even for a zero-length cstr.
+///
+/// * The nul terminator must be within `isize::MAX` from `ptr`
+///
+/// `report_out` must be non-null and valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn CheckDatabase(
+    db_path_ptr: *const c_char,
+    report_out: *mut *mut c_char,
+) -> i32 {
+    let result = try_get_db(db_path_ptr)
+        .and_then(|data_base| unsafe { check_database(&data_base, report_out) });
+    to_result_code("CheckDatabase", result)
+}
+
+/// fn CheckDatabaseWithHandle(`handle`, `report_out`);
+///
+/// Handle variant of `CheckDatabase`.
+///
+/// # Safety
+///
+/// `handle` must be a handle returned by `OpenDatabase` that has not been closed.
+///
+/// `report_out` must be non-null and valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn CheckDatabaseWithHandle(
+    handle: *const DataBaseHandle,
+    report_out: *mut *mut c_char,
+) -> i32 {
+    let result = try_get_handle_db(handle)
+        .and_then(|data_base| unsafe { check_database(data_base, report_out) });
+    to_result_code("CheckDatabase", result)
+}
+
+unsafe fn check_database(
+    data_base: &DataBase,
+    report_out: *mut *mut c_char,
+) -> Result<(), data_base::Error> {
+    if report_out.is_null() {
+        return Err(null_pointer_error("report_out"));
+    }
+
+    let report = data_base.check()?;
+    // Nul bytes are replaced so the report can always be handed over as a C string.
+    let report = CString::new(report.to_string().replace('\0', "?"))
+        .expect("All nul bytes have been replaced.");
+    unsafe { report_out.write(report.into_raw()) };
+    Ok(())
+}
+
+/// fn RepairDatabase(`db_path_ptr`, `report_out`);
+///
+/// Salvages every corrupted data file in the `DataBase` at the provided `db_path_ptr`, then
+/// regenerates the stat sums and caches. The originals are moved to the `quarantine` folder of
+/// the database. A report listing one repaired file per line is written to `report_out`. If no
+/// files were repaired the report is a empty string.
+///
+/// The report must be released using `FreeCheckReport`.
+///
+/// # Safety
+///
+/// Any parameter mentioning `ptr` must satisfy the requirements of `CStr::from_ptr`:
+///
+/// * The memory pointed to by `ptr` must contain a valid nul terminator at the
+///   end of the string.
+///
+/// * `ptr` must be [valid] for reads of bytes up to and including the nul terminator.
+///   This means in particular:
+///
+///     * The entire memory range of this `CStr` must be contained within a single allocation!
+///     * `ptr` must be non-null even for a zero-length cstr.
+///
+/// * The nul terminator must be within `isize::MAX` from `ptr`
+///
+/// `report_out` must be non-null and valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn RepairDatabase(
+    db_path_ptr: *const c_char,
+    report_out: *mut *mut c_char,
+) -> i32 {
+    let result = try_get_db(db_path_ptr)
+        .and_then(|data_base| unsafe { repair_database(&data_base, report_out) });
+    to_result_code("RepairDatabase", result)
+}
+
+/// fn RepairDatabaseWithHandle(`handle`, `report_out`);
+///
+/// Handle variant of `RepairDatabase`.
+///
+/// # Safety
+///
+/// `handle` must be a handle returned by `OpenDatabase` that has not been closed.
+///
+/// `report_out` must be non-null and valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn RepairDatabaseWithHandle(
+    handle: *const DataBaseHandle,
+    report_out: *mut *mut c_char,
+) -> i32 {
+    let result = try_get_handle_db(handle)
+        .and_then(|data_base| unsafe { repair_database(data_base, report_out) });
+    to_result_code("RepairDatabase", result)
+}
+
+unsafe fn repair_database(
+    data_base: &DataBase,
+    report_out: *mut *mut c_char,
+) -> Result<(), data_base::Error> {
+    if report_out.is_null() {
+        return Err(null_pointer_error("report_out"));
+    }
+
+    let report = data_base.repair()?;
+    // Nul bytes are replaced so the report can always be handed over as a C string.
+    let report = CString::new(report.to_string().replace('\0', "?"))
+        .expect("All nul bytes have been replaced.");
+    unsafe { report_out.write(report.into_raw()) };
+    Ok(())
+}
+
+/// fn FreeCheckReport(`report`);
+///
+/// Releases a report written by `CheckDatabase` or `RepairDatabase`. Passing a null pointer does
+/// nothing.
+///
+/// # Safety
+///
+/// `report` must be null or a report written by `CheckDatabase` or `RepairDatabase` that has not
+/// been released yet.
+#[no_mangle]
+pub unsafe extern "C" fn FreeCheckReport(report: *mut c_char) {
+    if !report.is_null() {
+        drop(unsafe { CString::from_raw(report) });
+    }
+}
+
+//
+
+//
+
+/// fn MergeTags(`db_path_ptr`, `tag1`, `tag2`);
+///
+/// Attempts to merge `tag1` into `tag2` in the `DataBase` at the provided `db_path_ptr`.
+///
+/// # Safety
+///
+/// Any parameter mentioning `ptr` must satisfy the requirements of `CStr::from_ptr`:
+///
+/// * The memory pointed to by `ptr` must contain a valid nul terminator at the
+///   end of the string.
+///
+/// * `ptr` must be [valid] for reads of bytes up to and including the nul terminator.
+///   This means in particular:
+///
+///     * The entire memory range of this `CStr` must be contained within a single allocation!
+///     * `ptr` must be non-null even for a zero-length cstr.
+///
+/// * The nul terminator must be within `isize::MAX` from `ptr`
+#[no_mangle]
+pub unsafe extern "C" fn MergeTags(db_path_ptr: *const c_char, tag1: u16, tag2: u16) -> i32 {
+    let result = try_get_db(db_path_ptr).and_then(|data_base| data_base.merge_tags(tag1, tag2));
+    to_result_code("MergeTags", result)
+}
+
+/// fn MergeTagsWithHandle(`handle`, `tag1`, `tag2`);
+///
+/// Handle variant of `MergeTags`.
+///
+/// # Safety
+///
+/// `handle` must be a handle returned by `OpenDatabase` that has not been closed.
+#[no_mangle]
+pub unsafe extern "C" fn MergeTagsWithHandle(
+    handle: *const DataBaseHandle,
+    tag1: u16,
+    tag2: u16,
+) -> i32 {
+    let result = try_get_handle_db(handle).and_then(|data_base| data_base.merge_tags(tag1, tag2));
+    to_result_code("MergeTags", result)
+}
+
+/// fn MergeTagsWithProgress(`handle`, `tag1`, `tag2`, `callback`, `user_data`, `cancel_flag`);
+///
+/// Progress reporting variant of `MergeTagsWithHandle`.
+///
+/// `callback` is called with the number of processed files and the total number of files each
+/// time a file is processed, together with `user_data`. It may be null. The task is cancelled
+/// once the `i32` behind `cancel_flag` is set to a nonzero value. `cancel_flag` may be null.
+/// A cancelled task returns the `Cancelled` error and can be finished using `ResumeTask`.
+///
+/// # Safety
+///
+/// `handle` must be a handle returned by `OpenDatabase` that has not been closed.
+/// `cancel_flag` must either be null or point to a `i32` that stays valid until the function
+/// returns. The flag should only be written to atomically while the function runs.
+#[no_mangle]
+pub unsafe extern "C" fn MergeTagsWithProgress(
+    handle: *const DataBaseHandle,
+    tag1: u16,
+    tag2: u16,
+    callback: ProgressCallback,
+    user_data: *mut c_void,
+    cancel_flag: *const i32,
+) -> i32 {
+    let progress = unsafe { c_task_progress(callback, user_data, cancel_flag) };
+    let result = try_get_handle_db(handle)
+        .and_then(|data_base| data_base.merge_tags_with_progress(tag1, tag2, &progress));
+    to_result_code("MergeTags", result)
+}
+
+//
+
+//
+
+/// fn RegenerateTagSums(`db_path_ptr`);
+///
+/// Attempts to regenerate all tag sums in the `DataBase` at the provided `db_path_ptr`.
+///
+/// # Safety
+///
+/// Any parameter mentioning `ptr` must satisfy the requirements of `CStr::from_ptr`:
+///
+/// * The memory pointed to by `ptr` must contain a valid nul terminator at the
+///   end of the string.
+///
+/// * `ptr` must be [valid] for reads of bytes up to and including the nul terminator.
+///   This means in particular:
+///
+///     * The entire memory range of this `CStr` must be contained within a single allocation!
+///     * `ptr` must be non-null even for a zero-length cstr.
+///
+/// * The nul terminator must be within `isize::MAX` from `ptr`
+#[no_mangle]
+pub unsafe extern "C" fn RegenerateTagSums(db_path_ptr: *const c_char) -> i32 {
+    let result = try_get_db(db_path_ptr).and_then(|data_base| data_base.regen_tag_sums());
+    to_result_code("RegenerateTagSums", result)
+}
+
+/// fn RegenerateTagSumsWithHandle(`handle`);
+///
+/// Handle variant of `RegenerateTagSums`.
+///
+/// # Safety
+///
+/// `handle` must be a handle returned by `OpenDatabase` that has not been closed.
+#[no_mangle]
+pub unsafe extern "C" fn RegenerateTagSumsWithHandle(handle: *const DataBaseHandle) -> i32 {
+    let result = try_get_handle_db(handle).and_then(|data_base| data_base.regen_tag_sums());
+    to_result_code("RegenerateTagSums", result)
+}
+
+/// fn RegenerateTagSumsWithProgress(`handle`, `callback`, `user_data`, `cancel_flag`);
+///
+/// Progress reporting variant of `RegenerateTagSumsWithHandle`.
+///
+/// `callback` is called with the number of processed files and the total number of files each
+/// time a file is processed, together with `user_data`. It may be null. The task is cancelled
+/// once the `i32` behind `cancel_flag` is set to a nonzero value. `cancel_flag` may be null.
+/// A cancelled task returns the `Cancelled` error and can be finished using `ResumeTask`.
+///
+/// # Safety
+///
+/// `handle` must be a handle returned by `OpenDatabase` that has not been closed.
+/// `cancel_flag` must either be null or point to a `i32` that stays valid until the function
+/// returns. The flag should only be written to atomically while the function runs.
+#[no_mangle]
+pub unsafe extern "C" fn RegenerateTagSumsWithProgress(
+    handle: *const DataBaseHandle,
+    callback: ProgressCallback,
+    user_data: *mut c_void,
+    cancel_flag: *const i32,
+) -> i32 {
+    let progress = unsafe { c_task_progress(callback, user_data, cancel_flag) };
+    let result = try_get_handle_db(handle)
+        .and_then(|data_base| data_base.regen_tag_sums_with_progress(&progress));
+    to_result_code("RegenerateTagSums", result)
+}
+
+//
+
+//
+
+/// fn RenameTag(`db_path_ptr`, `old_tag_ptr`, `new_tag_ptr`);
+///
+/// Attempts to rename `old_tag_ptr` to `new_tag_ptr` in the `DataBase` at `db_path_ptr`.
+///
+/// # Safety
+///
+/// Any parameter mentioning `ptr` must satisfy the requirements of `CStr::from_ptr`:
+///
+/// * The memory pointed to by `ptr` must contain a valid nul terminator at the
+///   end of the string.
+///
+/// * `ptr` must be [valid] for reads of bytes up to and including the nul terminator.
+///   This means in particular:
+///
+///     * The entire memory range of this `CStr` must be contained within a single allocation!
+///     * `ptr` must be non-null even for a zero-length cstr.
+///
+/// * The nul terminator must be within `isize::MAX` from `ptr`
+#[no_mangle]
+pub unsafe extern "C" fn RenameTag(
+    db_path_ptr: *const c_char,
+    old_tag_ptr: *const c_char,
+    new_tag_ptr: *const c_char,
+) -> i32 {
+    let result = try_get_db(db_path_ptr)
+        .and_then(|data_base| rename_tag(&data_base, old_tag_ptr, new_tag_ptr));
+    to_result_code("RenameTag", result)
+}
+
+/// fn RenameTagWithHandle(`handle`, `old_tag_ptr`, `new_tag_ptr`);
+///
+/// Handle variant of `RenameTag`.
+///
+/// # Safety
+///
+/// `handle` must be a handle returned by `OpenDatabase` that has not been closed.
+///
+/// Any parameter mentioning `ptr` must satisfy the requirements of `CStr::from_ptr`:
+///
+/// * The memory pointed to by `ptr` must contain a valid nul terminator at the
+///   end of the string.
+///
+/// * `ptr` must be [valid] for reads of bytes up to and including the nul terminator.
+///   This means in particular:
+///
+///     * The entire memory range of this `CStr` must be contained within a single allocation!
+///     * `ptr` must be non-null even for a zero-length cstr.
+///
+/// * The nul terminator must be within `isize::MAX` from `ptr`
+#[no_mangle]
+pub unsafe extern "C" fn RenameTagWithHandle(
+    handle: *const DataBaseHandle,
+    old_tag_ptr: *const c_char,
+    new_tag_ptr: *const c_char,
+) -> i32 {
+    let result = try_get_handle_db(handle)
+        .and_then(|data_base| rename_tag(data_base, old_tag_ptr, new_tag_ptr));
+    to_result_code("RenameTag", result)
+}
+
+unsafe fn rename_tag(
+    data_base: &DataBase,
+    old_tag_ptr: *const c_char,
+    new_tag_ptr: *const c_char,
+) -> Result<(), data_base::Error> {
+    let old_tag = try_ptr_to_string(old_tag_ptr, "old_tag_ptr")?;
+    let new_tag = try_ptr_to_string(new_tag_ptr, "new_tag_ptr")?;
+    data_base.rename_tag(old_tag, new_tag)
+}
+
+//
+
+//
+
+/// fn TemporaryUpdateDatabase(`db_path_ptr`) 6
+///
+/// Attempts to upgrade the `DataBase` at the proided `db_path_ptr` to the new format. If a
+/// migration fails the error code of `MigrationFailed` is returned.
+///
+/// # Safety
+///
+/// Any parameter mentioning `ptr` must satisfy the requirements of `CStr::from_ptr`:
+///
+/// * The memory pointed to by `ptr` must contain a valid nul terminator at the
+///   end of the string.
+///
+/// * `ptr` must be [valid] for reads of bytes up to and including the nul terminator.
+///   This means in particular:
+///
+///     * The entire memory range of this `CStr` must be contained within a single allocation!
+///     * `ptr` must be non-null even for a zero-length cstr.
+///
+/// * The nul terminator must be within `isize::MAX` from `ptr`
+#[no_mangle]
+pub unsafe extern "C" fn TemporaryUpdateDatabase(
+    db_path_ptr: *const c_char,
+    current_day_switch_offset: i32,
+) -> i32 {
+    let result = try_ptr_to_string(db_path_ptr, "db_path_ptr").and_then(|data_base_path| {
+        DataBase::upgrade_database(Path::new(&data_base_path), current_day_switch_offset)
+    });
+    to_result_code("TemporaryUpdateDatabase", result)
+}
+
+//
+
+//
+
+/// fn ChangeDaySwitchOffset(`db_path_ptr`, `new_offset`);
+///
+/// Changes the day_switch_offset of the `DataBase` at the provided `db_path_ptr` to `new_offset`.
+/// Any data or diary entry crossing the new day boundary is moved to the day it now belongs to.
+///
+/// # Safety
+///
+/// Any parameter mentioning `ptr` must satisfy the requirements of `CStr::from_ptr`:
+///
+/// * The memory pointed to by `ptr` must contain a valid nul terminator at the
+///   end of the string.
+///
+/// * `ptr` must be [valid] for reads of bytes up to and including the nul terminator.
+///   This means in particular:
+///
+///     * The entire memory range of this `CStr` must be contained within a single allocation!
+///     * `ptr` must be non-null even for a zero-length cstr.
+///
+/// * The nul terminator must be within `isize::MAX` from `ptr`
+#[no_mangle]
+pub unsafe extern "C" fn ChangeDaySwitchOffset(db_path_ptr: *const c_char, new_offset: i32) -> i32 {
+    let result = try_get_db(db_path_ptr)
+        .and_then(|mut data_base| change_day_switch_offset(&mut data_base, new_offset));
+    to_result_code("ChangeDaySwitchOffset", result)
+}
+
+/// fn ChangeDaySwitchOffsetWithHandle(`handle`, `new_offset`);
+///
+/// Handle variant of `ChangeDaySwitchOffset`. The handle stays valid and uses the new offset
+/// afterwards.
+///
+/// # Safety
+///
+/// `handle` must be a handle returned by `OpenDatabase` that has not been closed, and must not
+/// be used by any other thread while this function runs.
+#[no_mangle]
+pub unsafe extern "C" fn ChangeDaySwitchOffsetWithHandle(
+    handle: *mut DataBaseHandle,
+    new_offset: i32,
+) -> i32 {
+    let result = match unsafe { handle.as_mut() } {
+        Some(handle) => change_day_switch_offset(&mut handle.data_base, new_offset),
+        None => Err(null_pointer_error("handle")),
+    };
+    to_result_code("ChangeDaySwitchOffset", result)
+}
+
+fn change_day_switch_offset(
+    data_base: &mut DataBase,
+    new_offset: i32,
+) -> Result<(), data_base::Error> {
+    let new_offset = i8::try_from(new_offset)
+        .map_err(|_| data_base::Error::with_kind(data_base::ErrorKind::OffsetOutOfRange))?;
+    data_base.change_day_switch_offset(new_offset)
+}
+
+//
+
+//
+
+/// fn InsertDataEntry(`db_path_ptr`, `year`, `month`, `day`, `hour`, `data`, `data_length`);
+///
+/// Attempts to insert the `DataEntry` stored in the `data` parameter to the `DataFile` matching the
+/// provided `year`, `month` and `day` in the `DataBase` at the provided `db_path_ptr`.
+/// Any existing entry at the target location is overwritten.
+///
+/// `hour` is the wall-clock hour of the entry, which is stored at minute 0. With a
+/// day_switch_offset of +4 an entry at 02:00 the morning after `day` is still added to `day`,
+/// using `hour` 2. See `InsertDataEntryAt` to store the entry at any minute.
+///
+/// # Safety
+///
+/// Any parameter mentioning `ptr` must satisfy the requirements of `CStr::from_ptr`:
+///
+/// * The memory pointed to by `ptr` must contain a valid nul terminator at the
+///   end of the string.
+///
+/// * `ptr` must be [valid] for reads of bytes up to and including the nul terminator.
+///   This means in particular:
+///
+///     * The entire memory range of this `CStr` must be contained within a single allocation!
+///     * `ptr` must be non-null even for a zero-length cstr.
+///
+/// * The nul terminator must be within `isize::MAX` from `ptr`
+#[no_mangle]
+pub unsafe extern "C" fn InsertDataEntry(
+    db_path_ptr: *const c_char,
+    year: i32,
+    month: u8,
+    day: u8,
+    hour: u8,
+    data: *const u16,
+    data_length: u32,
+) -> i32 {
+    let result = try_get_db(db_path_ptr).and_then(|data_base| {
+        insert_data_entry(
+            &data_base,
+            year,
+            month,
+            day,
+            hour,
+            0,
+            data,
+            data_length,
+            std::ptr::null(),
+            0,
+        )
+    });
+    to_result_code("InsertDataEntry", result)
+}
+
+/// fn InsertDataEntryWithHandle(`handle`, `year`, `month`, `day`, `hour`, `data`, `data_length`);
+///
+/// Handle variant of `InsertDataEntry`.
+///
+/// # Safety
+///
+/// `handle` must be a handle returned by `OpenDatabase` that has not been closed.
+///
+/// `data` must be valid for reads of `data_length` values.
+#[no_mangle]
+pub unsafe extern "C" fn InsertDataEntryWithHandle(
+    handle: *const DataBaseHandle,
+    year: i32,
+    month: u8,
+    day: u8,
+    hour: u8,
+    data: *const u16,
+    data_length: u32,
+) -> i32 {
+    let result = try_get_handle_db(handle).and_then(|data_base| {
+        insert_data_entry(
+            data_base,
+            year,
+            month,
+            day,
+            hour,
+            0,
+            data,
+            data_length,
+            std::ptr::null(),
+            0,
+        )
+    });
+    to_result_code("InsertDataEntry", result)
+}
+
+/// fn InsertDataEntryAt(`db_path_ptr`, `year`, `month`, `day`, `hour`, `minute`, `data`, `data_length`);
+///
+/// Attempts to insert the `DataEntry` stored in the `data` parameter to the `DataFile` matching the
+/// provided `year`, `month` and `day` in the `DataBase` at the provided `db_path_ptr`.
+/// Any existing entry at the target location is overwritten.
+///
+/// `data` holds the mental and physical score followed by the tag ids of the entry. See
+/// `InsertDataEntryWithMetrics` to store the values of further metrics.
+///
+/// `hour` and `minute` are the wall-clock time of the entry. With a day_switch_offset of +4 an
+/// entry at 02:30 the morning after `day` is still added to `day`, using `hour` 2 and `minute` 30.
+///
+/// # Safety
+///
+/// Any parameter mentioning `ptr` must satisfy the requirements of `CStr::from_ptr`:
+///
+/// * The memory pointed to by `ptr` must contain a valid nul terminator at the
+///   end of the string.
+///
+/// * `ptr` must be [valid] for reads of bytes up to and including the nul terminator.
+///   This means in particular:
+///
+///     * The entire memory range of this `CStr` must be contained within a single allocation!
+///     * `ptr` must be non-null even for a zero-length cstr.
+///
+/// * The nul terminator must be within `isize::MAX` from `ptr`
+#[no_mangle]
+pub unsafe extern "C" fn InsertDataEntryAt(
+    db_path_ptr: *const c_char,
+    year: i32,
+    month: u8,
+    day: u8,
+    hour: u8,
+    minute: u8,
+    data: *const u16,
+    data_length: u32,
+) -> i32 {
+    let result = try_get_db(db_path_ptr).and_then(|data_base| {
+        insert_data_entry(
+            &data_base,
+            year,
+            month,
+            day,
+            hour,
+            minute,
+            data,
+            data_length,
+            std::ptr::null(),
+            0,
+        )
+    });
+    to_result_code("InsertDataEntryAt", result)
+}
+
+/// fn InsertDataEntryAtWithHandle(`handle`, `year`, `month`, `day`, `hour`, `minute`, `data`, `data_length`);
+///
+/// Handle variant of `InsertDataEntryAt`.
+///
+/// # Safety
+///
+/// `handle` must be a handle returned by `OpenDatabase` that has not been closed.
+///
+/// `data` must be valid for reads of `data_length` values.
+#[no_mangle]
+pub unsafe extern "C" fn InsertDataEntryAtWithHandle(
+    handle: *const DataBaseHandle,
+    year: i32,
+    month: u8,
+    day: u8,
+    hour: u8,
+    minute: u8,
+    data: *const u16,
+    data_length: u32,
+) -> i32 {
+    let result = try_get_handle_db(handle).and_then(|data_base| {
+        insert_data_entry(
+            data_base,
+            year,
+            month,
+            day,
+            hour,
+            minute,
+            data,
+            data_length,
+            std::ptr::null(),
+            0,
+        )
+    });
+    to_result_code("InsertDataEntryAt", result)
+}
+
+/// fn InsertDataEntryWithMetrics(`db_path_ptr`, `year`, `month`, `day`, `hour`, `minute`, `data`, `data_length`, `metrics`, `metric_count`);
+///
+/// Attempts to insert the `DataEntry` stored in the `data` parameter to the `DataFile` matching the
+/// provided `year`, `month` and `day` in the `DataBase` at the provided `db_path_ptr`.
+/// Any existing entry at the target location is overwritten.
+///
+/// `data` holds the mental and physical score followed by the tag ids of the entry. `metrics`
+/// holds the values of any further metric, and may be null if `metric_count` is 0.
+///
+/// `hour` and `minute` are the wall-clock time of the entry. With a day_switch_offset of +4 an
+/// entry at 02:30 the morning after `day` is still added to `day`, using `hour` 2 and `minute` 30.
+///
+/// # Safety
+///
+/// Any parameter mentioning `ptr` must satisfy the requirements of `CStr::from_ptr`:
+///
+/// * The memory pointed to by `ptr` must contain a valid nul terminator at the
+///   end of the string.
+///
+/// * `ptr` must be [valid] for reads of bytes up to and including the nul terminator.
+///   This means in particular:
+///
+///     * The entire memory range of this `CStr` must be contained within a single allocation!
+///     * `ptr` must be non-null even for a zero-length cstr.
+///
+/// * The nul terminator must be within `isize::MAX` from `ptr`
+#[no_mangle]
+pub unsafe extern "C" fn InsertDataEntryWithMetrics(
+    db_path_ptr: *const c_char,
+    year: i32,
+    month: u8,
+    day: u8,
+    hour: u8,
+    minute: u8,
+    data: *const u16,
+    data_length: u32,
+    metrics: *const CMetricValue,
+    metric_count: u32,
+) -> i32 {
+    let result = try_get_db(db_path_ptr).and_then(|data_base| {
+        insert_data_entry(
+            &data_base,
+            year,
+            month,
+            day,
+            hour,
+            minute,
+            data,
+            data_length,
+            metrics,
+            metric_count,
+        )
+    });
+    to_result_code("InsertDataEntryWithMetrics", result)
+}
+
+/// fn InsertDataEntryWithMetricsWithHandle(`handle`, `year`, `month`, `day`, `hour`, `minute`, `data`, `data_length`, `metrics`, `metric_count`);
+///
+/// Handle variant of `InsertDataEntryWithMetrics`.
+///
+/// # Safety
+///
+/// `handle` must be a handle returned by `OpenDatabase` that has not been closed.
+///
+/// `data` must be valid for reads of `data_length` values, and `metrics` for reads of
+/// `metric_count` values.
+#[no_mangle]
+pub unsafe extern "C" fn InsertDataEntryWithMetricsWithHandle(
+    handle: *const DataBaseHandle,
+    year: i32,
+    month: u8,
+    day: u8,
+    hour: u8,
+    minute: u8,
+    data: *const u16,
+    data_length: u32,
+    metrics: *const CMetricValue,
+    metric_count: u32,
+) -> i32 {
+    let result = try_get_handle_db(handle).and_then(|data_base| {
+        insert_data_entry(
+            data_base,
+            year,
+            month,
+            day,
+            hour,
+            minute,
+            data,
+            data_length,
+            metrics,
+            metric_count,
+        )
+    });
+    to_result_code("InsertDataEntryWithMetrics", result)
+}
+
+#[allow(clippy::too_many_arguments)]
+unsafe fn insert_data_entry(
+    data_base: &DataBase,
+    year: i32,
+    month: u8,
+    day: u8,
+    hour: u8,
+    minute: u8,
+    data: *const u16,
+    data_length: u32,
+    metrics: *const CMetricValue,
+    metric_count: u32,
+) -> Result<(), data_base::Error> {
+    if data.is_null() {
+        return Err(null_pointer_error("data"));
+    }
+
+    let data = unsafe { std::slice::from_raw_parts(data, data_length as usize) };
+    let metrics = unsafe { try_read_metric_values(metrics, metric_count) }?;
+    let data_entry = DataEntry::from_c_data(data, &metrics, hour, minute, data_base.settings())?;
+    data_base.insert_data_entry(year, month, day, data_entry)
+}
+
+//
+
+//
+
+/// fn AddDataEntry(`db_path_ptr`, `year`, `month`, `day`, `hour`, `data`, `data_length`);
+///
+/// Attempts to add the `DataEntry` stored in the `data` parameter to the `DataFile` matching the
+/// provided `year`, `month` and `day` in the `DataBase` at the provided `db_path_ptr`.
+///
+/// `hour` is the wall-clock hour of the entry, which is stored at minute 0. With a
+/// day_switch_offset of +4 an entry at 02:00 the morning after `day` is still added to `day`,
+/// using `hour` 2. See `AddDataEntryAt` to store the entry at any minute.
+///
+/// # Safety
+///
+/// Any parameter mentioning `ptr` must satisfy the requirements of `CStr::from_ptr`:
+///
+/// * The memory pointed to by `ptr` must contain a valid nul terminator at the
+///   end of the string.
+///
+/// * `ptr` must be [valid] for reads of bytes up to and including the nul terminator.
+///   This means in particular:
+///
+///     * The entire memory range of this `CStr` must be contained within a single allocation!
+///     * `ptr` must be non-null even for a zero-length cstr.
+///
+/// * The nul terminator must be within `isize::MAX` from `ptr`
+#[no_mangle]
+pub unsafe extern "C" fn AddDataEntry(
+    db_path_ptr: *const c_char,
+    year: i32,
+    month: u8,
+    day: u8,
+    hour: u8,
+    data: *const u16,
+    data_length: u32,
+) -> i32 {
+    let result = try_get_db(db_path_ptr).and_then(|data_base| {
+        add_data_entry(
+            &data_base,
+            year,
+            month,
+            day,
+            hour,
+            0,
+            data,
+            data_length,
+            std::ptr::null(),
+            0,
+        )
+    });
+    to_result_code("AddDataEntry", result)
+}
+
+/// fn AddDataEntryWithHandle(`handle`, `year`, `month`, `day`, `hour`, `data`, `data_length`);
+///
+/// Handle variant of `AddDataEntry`.
+///
+/// # Safety
+///
+/// `handle` must be a handle returned by `OpenDatabase` that has not been closed.
+///
+/// `data` must be valid for reads of `data_length` values.
+#[no_mangle]
+pub unsafe extern "C" fn AddDataEntryWithHandle(
+    handle: *const DataBaseHandle,
+    year: i32,
+    month: u8,
+    day: u8,
+    hour: u8,
+    data: *const u16,
+    data_length: u32,
+) -> i32 {
+    let result = try_get_handle_db(handle).and_then(|data_base| {
+        add_data_entry(
+            data_base,
+            year,
+            month,
+            day,
+            hour,
+            0,
+            data,
+            data_length,
+            std::ptr::null(),
+            0,
+        )
+    });
+    to_result_code("AddDataEntry", result)
+}
+
+/// fn AddDataEntryAt(`db_path_ptr`, `year`, `month`, `day`, `hour`, `minute`, `data`, `data_length`);
+///
+/// Attempts to add the `DataEntry` stored in the `data` parameter to the `DataFile` matching the
+/// provided `year`, `month` and `day` in the `DataBase` at the provided `db_path_ptr`.
+///
+/// `data` holds the mental and physical score followed by the tag ids of the entry. See
+/// `AddDataEntryWithMetrics` to store the values of further metrics.
+///
+/// `hour` and `minute` are the wall-clock time of the entry. With a day_switch_offset of +4 an
+/// entry at 02:30 the morning after `day` is still added to `day`, using `hour` 2 and `minute` 30.
+///
+/// # Safety
+///
+/// Any parameter mentioning `ptr` must satisfy the requirements of `CStr::from_ptr`:
+///
+/// * The memory pointed to by `ptr` must contain a valid nul terminator at the
+///   end of the string.
+///
+/// * `ptr` must be [valid] for reads of bytes up to and including the nul terminator.
+///   This means in particular:
+///
+///     * The entire memory range of this `CStr` must be contained within a single allocation!
+///     * `ptr` must be non-null even for a zero-length cstr.
+///
+/// * The nul terminator must be within `isize::MAX` from `ptr`
+#[no_mangle]
+pub unsafe extern "C" fn AddDataEntryAt(
+    db_path_ptr: *const c_char,
+    year: i32,
+    month: u8,
+    day: u8,
+    hour: u8,
+    minute: u8,
+    data: *const u16,
+    data_length: u32,
+) -> i32 {
+    let result = try_get_db(db_path_ptr).and_then(|data_base| {
+        add_data_entry(
+            &data_base,
+            year,
+            month,
+            day,
+            hour,
+            minute,
+            data,
+            data_length,
+            std::ptr::null(),
+            0,
+        )
+    });
+    to_result_code("AddDataEntryAt", result)
+}
+
+/// fn AddDataEntryAtWithHandle(`handle`, `year`, `month`, `day`, `hour`, `minute`, `data`, `data_length`);
+///
+/// Handle variant of `AddDataEntryAt`.
+///
+/// # Safety
+///
+/// `handle` must be a handle returned by `OpenDatabase` that has not been closed.
+///
+/// `data` must be valid for reads of `data_length` values.
+#[no_mangle]
+pub unsafe extern "C" fn AddDataEntryAtWithHandle(
+    handle: *const DataBaseHandle,
+    year: i32,
+    month: u8,
+    day: u8,
+    hour: u8,
+    minute: u8,
+    data: *const u16,
+    data_length: u32,
+) -> i32 {
+    let result = try_get_handle_db(handle).and_then(|data_base| {
+        add_data_entry(
+            data_base,
+            year,
+            month,
+            day,
+            hour,
+            minute,
+            data,
+            data_length,
+            std::ptr::null(),
+            0,
+        )
+    });
+    to_result_code("AddDataEntryAt", result)
+}
+
+/// fn AddDataEntryWithMetrics(`db_path_ptr`, `year`, `month`, `day`, `hour`, `minute`, `data`, `data_length`, `metrics`, `metric_count`);
+///
+/// Attempts to add the `DataEntry` stored in the `data` parameter to the `DataFile` matching the
+/// provided `year`, `month` and `day` in the `DataBase` at the provided `db_path_ptr`.
+///
+/// `data` holds the mental and physical score followed by the tag ids of the entry. `metrics`
+/// holds the values of any further metric, and may be null if `metric_count` is 0.
+///
+/// `hour` and `minute` are the wall-clock time of the entry. With a day_switch_offset of +4 an
+/// entry at 02:30 the morning after `day` is still added to `day`, using `hour` 2 and `minute` 30.
+///
+/// # Safety
+///
+/// Any parameter mentioning `ptr` must satisfy the requirements of `CStr::from_ptr`:
+///
+/// * The memory pointed to by `ptr` must contain a valid nul terminator at the
+///   end of the string.
+///
+/// * `ptr` must be [valid] for reads of bytes up to and including the nul terminator.
+///   This means in particular:
+///
+///     * The entire memory range of this `CStr` must be contained within a single allocation!
+///     * `ptr` must be non-null even for a zero-length cstr.
+///
+/// * The nul terminator must be within `isize::MAX` from `ptr`
+#[no_mangle]
+pub unsafe extern "C" fn AddDataEntryWithMetrics(
+    db_path_ptr: *const c_char,
+    year: i32,
+    month: u8,
+    day: u8,
+    hour: u8,
+    minute: u8,
+    data: *const u16,
+    data_length: u32,
+    metrics: *const CMetricValue,
+    metric_count: u32,
+) -> i32 {
+    let result = try_get_db(db_path_ptr).and_then(|data_base| {
+        add_data_entry(
+            &data_base,
+            year,
+            month,
+            day,
+            hour,
+            minute,
+            data,
+            data_length,
+            metrics,
+            metric_count,
+        )
+    });
+    to_result_code("AddDataEntryWithMetrics", result)
+}
+
+/// fn AddDataEntryWithMetricsWithHandle(`handle`, `year`, `month`, `day`, `hour`, `minute`, `data`, `data_length`, `metrics`, `metric_count`);
+///
+/// Handle variant of `AddDataEntryWithMetrics`.
+///
+/// # Safety
+///
+/// `handle` must be a handle returned by `OpenDatabase` that has not been closed.
+///
+/// `data` must be valid for reads of `data_length` values, and `metrics` for reads of
+/// `metric_count` values.
+#[no_mangle]
+pub unsafe extern "C" fn AddDataEntryWithMetricsWithHandle(
+    handle: *const DataBaseHandle,
+    year: i32,
+    month: u8,
+    day: u8,
+    hour: u8,
+    minute: u8,
+    data: *const u16,
+    data_length: u32,
+    metrics: *const CMetricValue,
+    metric_count: u32,
+) -> i32 {
+    let result = try_get_handle_db(handle).and_then(|data_base| {
+        add_data_entry(
+            data_base,
+            year,
+            month,
+            day,
+            hour,
+            minute,
+            data,
+            data_length,
+            metrics,
+            metric_count,
+        )
+    });
+    to_result_code("AddDataEntryWithMetrics", result)
+}
+
+#[allow(clippy::too_many_arguments)]
+unsafe fn add_data_entry(
+    data_base: &DataBase,
+    year: i32,
+    month: u8,
+    day: u8,
+    hour: u8,
+    minute: u8,
+    data: *const u16,
+    data_length: u32,
+    metrics: *const CMetricValue,
+    metric_count: u32,
+) -> Result<(), data_base::Error> {
+    if data.is_null() {
+        return Err(null_pointer_error("data"));
     }
 
     let data = unsafe { std::slice::from_raw_parts(data, data_length as usize) };
+    let metrics = unsafe { try_read_metric_values(metrics, metric_count) }?;
+    let data_entry = DataEntry::from_c_data(data, &metrics, hour, minute, data_base.settings())?;
+    data_base.add_data_entry(year, month, day, data_entry)
+}
+
+//
+
+//
+
+/// fn RemoveDataEntry(`db_path_ptr`, `year`, `month`, `day`, `hour`);
+///
+/// Attempts to remove the `DataEntry` at the provided `hour` and minute 0 from the `DataFile`
+/// matching the provided `year`, `month` and `day` in the `DataBase` at the provided
+/// `db_path_ptr`.
+///
+/// `hour` is the wall-clock hour of the entry, the same hour used when the entry was added. See
+/// `RemoveDataEntryAt` to remove a entry at any minute.
+///
+/// # Safety
+///
+/// Any parameter mentioning `ptr` must satisfy the requirements of `CStr::from_ptr`:
+///
+/// * The memory pointed to by `ptr` must contain a valid nul terminator at the
+///   end of the string.
+///
+/// * `ptr` must be [valid] for reads of bytes up to and including the nul terminator.
+///   This means in particular:
+///
+///     * The entire memory range of this `CStr` must be contained within a single allocation!
+///     * `ptr` must be non-null even for a zero-length cstr.
+///
+/// * The nul terminator must be within `isize::MAX` from `ptr`
+#[no_mangle]
+pub unsafe extern "C" fn RemoveDataEntry(
+    db_path_ptr: *const c_char,
+    year: i32,
+    month: u8,
+    day: u8,
+    hour: u8,
+) -> i32 {
+    let result = try_get_db(db_path_ptr)
+        .and_then(|data_base| remove_data_entry(&data_base, year, month, day, hour, 0));
+    to_result_code("RemoveDataEntry", result)
+}
+
+/// fn RemoveDataEntryWithHandle(`handle`, `year`, `month`, `day`, `hour`);
+///
+/// Handle variant of `RemoveDataEntry`.
+///
+/// # Safety
+///
+/// `handle` must be a handle returned by `OpenDatabase` that has not been closed.
+#[no_mangle]
+pub unsafe extern "C" fn RemoveDataEntryWithHandle(
+    handle: *const DataBaseHandle,
+    year: i32,
+    month: u8,
+    day: u8,
+    hour: u8,
+) -> i32 {
+    let result = try_get_handle_db(handle)
+        .and_then(|data_base| remove_data_entry(data_base, year, month, day, hour, 0));
+    to_result_code("RemoveDataEntry", result)
+}
+
+/// fn RemoveDataEntryAt(`db_path_ptr`, `year`, `month`, `day`, `hour`, `minute`);
+///
+/// Attempts to remove the `DataEntry` at the provided `hour` and `minute` from the `DataFile`
+/// matching the provided `year`, `month` and `day` in the `DataBase` at the provided
+/// `db_path_ptr`.
+///
+/// `hour` and `minute` are the wall-clock time of the entry, the same time used when the entry
+/// was added.
+///
+/// # Safety
+///
+/// Any parameter mentioning `ptr` must satisfy the requirements of `CStr::from_ptr`:
+///
+/// * The memory pointed to by `ptr` must contain a valid nul terminator at the
+///   end of the string.
+///
+/// * `ptr` must be [valid] for reads of bytes up to and including the nul terminator.
+///   This means in particular:
+///
+///     * The entire memory range of this `CStr` must be contained within a single allocation!
+///     * `ptr` must be non-null even for a zero-length cstr.
+///
+/// * The nul terminator must be within `isize::MAX` from `ptr`
+#[no_mangle]
+pub unsafe extern "C" fn RemoveDataEntryAt(
+    db_path_ptr: *const c_char,
+    year: i32,
+    month: u8,
+    day: u8,
+    hour: u8,
+    minute: u8,
+) -> i32 {
+    let result = try_get_db(db_path_ptr)
+        .and_then(|data_base| remove_data_entry(&data_base, year, month, day, hour, minute));
+    to_result_code("RemoveDataEntryAt", result)
+}
+
+/// fn RemoveDataEntryAtWithHandle(`handle`, `year`, `month`, `day`, `hour`, `minute`);
+///
+/// Handle variant of `RemoveDataEntryAt`.
+///
+/// # Safety
+///
+/// `handle` must be a handle returned by `OpenDatabase` that has not been closed.
+#[no_mangle]
+pub unsafe extern "C" fn RemoveDataEntryAtWithHandle(
+    handle: *const DataBaseHandle,
+    year: i32,
+    month: u8,
+    day: u8,
+    hour: u8,
+    minute: u8,
+) -> i32 {
+    let result = try_get_handle_db(handle)
+        .and_then(|data_base| remove_data_entry(data_base, year, month, day, hour, minute));
+    to_result_code("RemoveDataEntryAt", result)
+}
+
+fn remove_data_entry(
+    data_base: &DataBase,
+    year: i32,
+    month: u8,
+    day: u8,
+    hour: u8,
+    minute: u8,
+) -> Result<(), data_base::Error> {
+    if hour >= 24 {
+        log::error!("RemoveDataEntry: Hour [{}] is out of range!", hour);
+        return Err(data_base::Error::with_kind(
+            data_base::ErrorKind::InvalidData,
+        ));
+    }
+    let hour = DataEntry::compensate_hour(hour as i8, data_base.settings().day_switch_offset);
+    data_base.remove_data_entry(year, month, day, hour, minute)
+}
+
+//
+
+//
+
+/// C representation of a single `DataEntry`, as returned by `GetDataEntries`.
+///
+/// `hour` is the wall-clock hour of the entry, the same hour that is passed to `AddDataEntry`.
+/// Entries with a minute other than 0 are returned at their hour as well. `mental_score` and
+/// `physical_score` are the values of the built-in metrics, or 0 if the entry has no value for
+/// them. Use `GetDataEntriesWithMetrics` to read the minute and every metric value.
+///
+/// `tags` points to `tag_count` tag ids. The memory is owned by the library and must be released
+/// together with the rest of the array using `FreeDataEntries`.
+#[repr(C)]
+pub struct CDataEntry {
+    pub hour: u8,
+    pub mental_score: u8,
+    pub physical_score: u8,
+    pub tag_count: u32,
+    pub tags: *mut u16,
+}
+
+impl From<DataEntry> for CDataEntry {
+    fn from(entry: DataEntry) -> Self {
+        let score = |metric_id| {
+            entry
+                .metric(metric_id)
+                .and_then(|value| u8::try_from(value).ok())
+                .unwrap_or(0)
+        };
+        let (mental_score, physical_score) = (score(MENTALSCORE), score(PHYSICALSCORE));
+        let hour = entry.wall_clock_hour();
+        let tags = Box::into_raw(entry.tags.into_boxed_slice());
+        CDataEntry {
+            hour,
+            mental_score,
+            physical_score,
+            tag_count: tags.len() as u32,
+            tags: tags as *mut u16,
+        }
+    }
+}
+
+/// C representation of a single `DataEntry`, as returned by `GetDataEntriesWithMetrics`.
+///
+/// `hour` is the wall-clock hour of the entry, the same hour that is passed to `AddDataEntryAt`.
+/// `compensated_hour` is the offset-compensated hour, which is counted from midnight at the start
+/// of the requested day and can therefore be negative or above 23. `minute` is the minute within
+/// either hour.
+///
+/// `metrics` points to `metric_count` metric values, ordered by metric id, and `tags` points to
+/// `tag_count` tag ids. The memory is owned by the library and must be released together with the
+/// rest of the array using `FreeDataEntriesWithMetrics`.
+#[repr(C)]
+pub struct CDataEntryWithMetrics {
+    pub hour: u8,
+    pub compensated_hour: i8,
+    pub minute: u8,
+    pub metric_count: u32,
+    pub metrics: *mut CMetricValue,
+    pub tag_count: u32,
+    pub tags: *mut u16,
+}
+
+impl From<DataEntry> for CDataEntryWithMetrics {
+    fn from(entry: DataEntry) -> Self {
+        let hour = entry.wall_clock_hour();
+        let metrics: Box<[CMetricValue]> = entry
+            .metrics
+            .iter()
+            .map(|(&metric_id, &value)| CMetricValue { metric_id, value })
+            .collect();
+        let metrics = Box::into_raw(metrics);
+        let tags = Box::into_raw(entry.tags.into_boxed_slice());
+        CDataEntryWithMetrics {
+            hour,
+            compensated_hour: entry.hour,
+            minute: entry.minute,
+            metric_count: metrics.len() as u32,
+            metrics: metrics as *mut CMetricValue,
+            tag_count: tags.len() as u32,
+            tags: tags as *mut u16,
+        }
+    }
+}
+
+/// The value of a single metric of a data entry.
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct CMetricValue {
+    pub metric_id: u16,
+    pub value: i16,
+}
+
+/// fn GetDataEntries(`db_path_ptr`, `year`, `month`, `day`, `entries_out`, `length_out`);
+///
+/// Reads all data entries of the provided `year`, `month` and `day` in the `DataBase` at the
+/// provided `db_path_ptr`. The entries are sorted in the order they occured within the day.
+///
+/// On success `entries_out` is set to a library allocated array of `CDataEntry` and `length_out`
+/// to the number of entries in said array. The array must be released using `FreeDataEntries`.
+/// If the day has no entries `entries_out` is set to null and `length_out` to 0.
+///
+/// # Safety
+///
+/// `entries_out` and `length_out` must be non-null and valid for writes.
+///
+/// Any parameter mentioning `ptr` must satisfy the requirements of `CStr::from_ptr`:
+///
+/// * The memory pointed to by `ptr` must contain a valid nul terminator at the
+///   end of the string.
+///
+/// * `ptr` must be [valid] for reads of bytes up to and including the nul terminator.
+///   This means in particular:
+///
+///     * The entire memory range of this `CStr` must be contained within a single allocation!
+///     * `ptr` must be non-null even for a zero-length cstr.
+///
+/// * The nul terminator must be within `isize::MAX` from `ptr`
+#[no_mangle]
+pub unsafe extern "C" fn GetDataEntries(
+    db_path_ptr: *const c_char,
+    year: i32,
+    month: u8,
+    day: u8,
+    entries_out: *mut *mut CDataEntry,
+    length_out: *mut u32,
+) -> i32 {
+    let result = try_get_db(db_path_ptr).and_then(|data_base| {
+        get_data_entries(&data_base, year, month, day, entries_out, length_out)
+    });
+    to_result_code("GetDataEntries", result)
+}
+
+/// fn GetDataEntriesWithHandle(`handle`, `year`, `month`, `day`, `entries_out`, `length_out`);
+///
+/// Handle variant of `GetDataEntries`.
+///
+/// # Safety
+///
+/// `handle` must be a handle returned by `OpenDatabase` that has not been closed.
+///
+/// `entries_out` and `length_out` must be non-null and valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn GetDataEntriesWithHandle(
+    handle: *const DataBaseHandle,
+    year: i32,
+    month: u8,
+    day: u8,
+    entries_out: *mut *mut CDataEntry,
+    length_out: *mut u32,
+) -> i32 {
+    let result = try_get_handle_db(handle).and_then(|data_base| {
+        get_data_entries(data_base, year, month, day, entries_out, length_out)
+    });
+    to_result_code("GetDataEntries", result)
+}
+
+/// fn GetDataEntriesWithMetrics(`db_path_ptr`, `year`, `month`, `day`, `entries_out`,
+/// `length_out`);
+///
+/// Same as `GetDataEntries`, but the entries are returned as `CDataEntryWithMetrics`, holding the
+/// minute and every metric value of each entry. The array must be released using
+/// `FreeDataEntriesWithMetrics`.
+///
+/// # Safety
+///
+/// See `GetDataEntries`.
+#[no_mangle]
+pub unsafe extern "C" fn GetDataEntriesWithMetrics(
+    db_path_ptr: *const c_char,
+    year: i32,
+    month: u8,
+    day: u8,
+    entries_out: *mut *mut CDataEntryWithMetrics,
+    length_out: *mut u32,
+) -> i32 {
+    let result = try_get_db(db_path_ptr).and_then(|data_base| {
+        get_data_entries(&data_base, year, month, day, entries_out, length_out)
+    });
+    to_result_code("GetDataEntriesWithMetrics", result)
+}
 
-    let data_base = match try_get_db(db_path_ptr) {
-        Ok(db) => db,
-        Err(ec) => return ec,
-    };
+/// fn GetDataEntriesWithMetricsWithHandle(`handle`, `year`, `month`, `day`, `entries_out`,
+/// `length_out`);
+///
+/// Handle variant of `GetDataEntriesWithMetrics`.
+///
+/// # Safety
+///
+/// `handle` must be a handle returned by `OpenDatabase` that has not been closed.
+///
+/// `entries_out` and `length_out` must be non-null and valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn GetDataEntriesWithMetricsWithHandle(
+    handle: *const DataBaseHandle,
+    year: i32,
+    month: u8,
+    day: u8,
+    entries_out: *mut *mut CDataEntryWithMetrics,
+    length_out: *mut u32,
+) -> i32 {
+    let result = try_get_handle_db(handle).and_then(|data_base| {
+        get_data_entries(data_base, year, month, day, entries_out, length_out)
+    });
+    to_result_code("GetDataEntriesWithMetrics", result)
+}
 
-    let data_entry = match DataEntry::from_c_data(data, hour, data_base.settings()) {
-        Ok(data_entry) => data_entry,
-        Err(error) => {
-            log::error!("AddDataEntry error occured! {error:?}");
-            log::logger().flush();
-            return data_base::Error::from(error).code();
-        }
-    };
+/// Reads the entries of the day into a library owned array of `T`. See `into_c_data_entries`.
+unsafe fn get_data_entries<T: From<DataEntry>>(
+    data_base: &DataBase,
+    year: i32,
+    month: u8,
+    day: u8,
+    entries_out: *mut *mut T,
+    length_out: *mut u32,
+) -> Result<(), data_base::Error> {
+    if entries_out.is_null() {
+        return Err(null_pointer_error("entries_out"));
+    }
+    if length_out.is_null() {
+        return Err(null_pointer_error("length_out"));
+    }
 
-    let result_code = match data_base.add_data_entry(year, month, day, data_entry) {
-        Ok(_) => 0,
-        Err(error) => {
-            log::error!("AddDataEntry error occured: {error:?}");
-            error.code()
-        }
-    };
+    let (entries, length) = into_c_data_entries(data_base.get_data_entries(year, month, day)?);
+    unsafe {
+        *entries_out = entries;
+        *length_out = length;
+    }
+    Ok(())
+}
 
-    log::logger().flush();
-    result_code
+//
+
+//
+
+/// fn FreeDataEntries(`entries`, `length`);
+///
+/// Releases an array of `CDataEntry` previously returned by `GetDataEntries`, including the tag
+/// arrays of each entry. Passing a null `entries` pointer does nothing.
+///
+/// # Safety
+///
+/// `entries` and `length` must be the exact values returned by `GetDataEntries`, and the array
+/// must not have been released already.
+#[no_mangle]
+pub unsafe extern "C" fn FreeDataEntries(entries: *mut CDataEntry, length: u32) {
+    if entries.is_null() {
+        return;
+    }
+
+    let entries =
+        unsafe { Box::from_raw(std::ptr::slice_from_raw_parts_mut(entries, length as usize)) };
+    for entry in entries.iter() {
+        unsafe { free_c_array(entry.tags, entry.tag_count) };
+    }
+}
+
+/// fn FreeDataEntriesWithMetrics(`entries`, `length`);
+///
+/// Releases an array of `CDataEntryWithMetrics` previously returned by
+/// `GetDataEntriesWithMetrics`, including the metric and tag arrays of each entry. Passing a null
+/// `entries` pointer does nothing.
+///
+/// # Safety
+///
+/// `entries` and `length` must be the exact values returned by `GetDataEntriesWithMetrics`, and
+/// the array must not have been released already.
+#[no_mangle]
+pub unsafe extern "C" fn FreeDataEntriesWithMetrics(
+    entries: *mut CDataEntryWithMetrics,
+    length: u32,
+) {
+    if entries.is_null() {
+        return;
+    }
+
+    let entries =
+        unsafe { Box::from_raw(std::ptr::slice_from_raw_parts_mut(entries, length as usize)) };
+    for entry in entries.iter() {
+        unsafe {
+            free_c_array(entry.metrics, entry.metric_count);
+            free_c_array(entry.tags, entry.tag_count);
+        }
+    }
 }
 
 //
@@ -566,25 +2356,45 @@ pub unsafe extern "C" fn AddDataEntry(
 /// * The nul terminator must be within `isize::MAX` from `ptr`
 #[no_mangle]
 pub unsafe extern "C" fn AddTag(db_path_ptr: *const c_char, tag_name_ptr: *const c_char) -> i32 {
-    let data_base = match try_get_db(db_path_ptr) {
-        Ok(db) => db,
-        Err(ec) => return ec,
-    };
-    let Ok(tag_name) = try_ptr_to_string(tag_name_ptr) else {
-        return -2;
-    };
-
-    let result_code = match data_base.add_tag(tag_name) {
-        Ok(_) => 0,
-        Err(error) => {
-            log::error!("AddTag error occured: {error:?}");
+    let result = try_get_db(db_path_ptr).and_then(|data_base| add_tag(&data_base, tag_name_ptr));
+    to_result_code("AddTag", result)
+}
 
-            error.code()
-        }
-    };
+/// fn AddTagWithHandle(`handle`, `tag_name_ptr`);
+///
+/// Handle variant of `AddTag`.
+///
+/// # Safety
+///
+/// `handle` must be a handle returned by `OpenDatabase` that has not been closed.
+///
+/// Any parameter mentioning `ptr` must satisfy the requirements of `CStr::from_ptr`:
+///
+/// * The memory pointed to by `ptr` must contain a valid nul terminator at the
+///   end of the string.
+///
+/// * `ptr` must be [valid] for reads of bytes up to and including the nul terminator.
+///   This means in particular:
+///
+///     * The entire memory range of this `CStr` must be contained within a single allocation!
+///     * `ptr` must be non-null even for a zero-length cstr.
+///
+/// * The nul terminator must be within `isize::MAX` from `ptr`
+#[no_mangle]
+pub unsafe extern "C" fn AddTagWithHandle(
+    handle: *const DataBaseHandle,
+    tag_name_ptr: *const c_char,
+) -> i32 {
+    let result = try_get_handle_db(handle).and_then(|data_base| add_tag(data_base, tag_name_ptr));
+    to_result_code("AddTag", result)
+}
 
-    log::logger().flush();
-    result_code
+unsafe fn add_tag(
+    data_base: &DataBase,
+    tag_name_ptr: *const c_char,
+) -> Result<(), data_base::Error> {
+    let tag_name = try_ptr_to_string(tag_name_ptr, "tag_name_ptr")?;
+    data_base.add_tag(tag_name)
 }
 
 //
@@ -612,21 +2422,254 @@ pub unsafe extern "C" fn AddTag(db_path_ptr: *const c_char, tag_name_ptr: *const
 /// * The nul terminator must be within `isize::MAX` from `ptr`
 #[no_mangle]
 pub unsafe extern "C" fn RemoveTag(db_path_ptr: *const c_char, tag_id: u16) -> i32 {
-    let data_base = match try_get_db(db_path_ptr) {
-        Ok(db) => db,
-        Err(ec) => return ec,
-    };
+    let result = try_get_db(db_path_ptr).and_then(|data_base| data_base.remove_tag(tag_id));
+    to_result_code("RemoveTag", result)
+}
 
-    let result_code = match data_base.remove_tag(tag_id) {
-        Ok(_) => 0,
-        Err(error) => {
-            log::error!("RemoveTag error occured: {error:?}");
-            error.code()
-        }
-    };
+/// fn RemoveTagWithHandle(`handle`, `tag_id`);
+///
+/// Handle variant of `RemoveTag`.
+///
+/// # Safety
+///
+/// `handle` must be a handle returned by `OpenDatabase` that has not been closed.
+#[no_mangle]
+pub unsafe extern "C" fn RemoveTagWithHandle(handle: *const DataBaseHandle, tag_id: u16) -> i32 {
+    let result = try_get_handle_db(handle).and_then(|data_base| data_base.remove_tag(tag_id));
+    to_result_code("RemoveTag", result)
+}
 
-    log::logger().flush();
-    result_code
+/// fn RemoveTagWithProgress(`handle`, `tag_id`, `callback`, `user_data`, `cancel_flag`);
+///
+/// Progress reporting variant of `RemoveTagWithHandle`.
+///
+/// `callback` is called with the number of processed files and the total number of files each
+/// time a file is processed, together with `user_data`. It may be null. The task is cancelled
+/// once the `i32` behind `cancel_flag` is set to a nonzero value. `cancel_flag` may be null.
+/// A cancelled task returns the `Cancelled` error and can be finished using `ResumeTask`.
+///
+/// # Safety
+///
+/// `handle` must be a handle returned by `OpenDatabase` that has not been closed.
+/// `cancel_flag` must either be null or point to a `i32` that stays valid until the function
+/// returns. The flag should only be written to atomically while the function runs.
+#[no_mangle]
+pub unsafe extern "C" fn RemoveTagWithProgress(
+    handle: *const DataBaseHandle,
+    tag_id: u16,
+    callback: ProgressCallback,
+    user_data: *mut c_void,
+    cancel_flag: *const i32,
+) -> i32 {
+    let progress = unsafe { c_task_progress(callback, user_data, cancel_flag) };
+    let result = try_get_handle_db(handle)
+        .and_then(|data_base| data_base.remove_tag_with_progress(tag_id, &progress));
+    to_result_code("RemoveTag", result)
+}
+
+/// fn UndoLastOperation(`db_path_ptr`);
+///
+/// Attempts to undo the last `MergeTags` or `RemoveTag` run on the database at the path
+/// specified by `db_path_ptr`. Fails with `NothingToUndo` if the history is empty, and with
+/// `UndoConflict` if a later change to the database prevents the operation from being undone.
+///
+/// # Safety
+///
+/// Any parameter mentioning `ptr` must satisfy the requirements of `CStr::from_ptr`:
+///
+/// * The memory pointed to by `ptr` must contain a valid nul terminator at the
+///   end of the string.
+///
+/// * `ptr` must be [valid] for reads of bytes up to and including the nul terminator.
+///   This means in particular:
+///
+///     * The entire memory range of this `CStr` must be contained within a single allocation!
+///     * `ptr` must be non-null even for a zero-length cstr.
+///
+/// * The nul terminator must be within `isize::MAX` from `ptr`
+#[no_mangle]
+pub unsafe extern "C" fn UndoLastOperation(db_path_ptr: *const c_char) -> i32 {
+    let result = try_get_db(db_path_ptr).and_then(|data_base| data_base.undo_last());
+    to_result_code("UndoLastOperation", result)
+}
+
+/// fn UndoLastOperationWithHandle(`handle`);
+///
+/// Handle variant of `UndoLastOperation`.
+///
+/// # Safety
+///
+/// `handle` must be a handle returned by `OpenDatabase` that has not been closed.
+#[no_mangle]
+pub unsafe extern "C" fn UndoLastOperationWithHandle(handle: *const DataBaseHandle) -> i32 {
+    let result = try_get_handle_db(handle).and_then(|data_base| data_base.undo_last());
+    to_result_code("UndoLastOperation", result)
+}
+
+//
+
+//
+
+/// fn AddMetric(`db_path_ptr`, `metric_name_ptr`, `min`, `max`, `metric_id_out`);
+///
+/// Attempts to add a metric with the name provided with `metric_name_ptr` to the database at the
+/// path specified by `db_path_ptr`. Every value of the metric must lie within `min` to `max`,
+/// inclusive. The id of the new metric is written to `metric_id_out`.
+///
+/// # Safety
+///
+/// `metric_id_out` must be non-null and valid for writes.
+///
+/// Any parameter mentioning `ptr` must satisfy the requirements of `CStr::from_ptr`:
+///
+/// * The memory pointed to by `ptr` must contain a valid nul terminator at the
+///   end of the string.
+///
+/// * `ptr` must be [valid] for reads of bytes up to and including the nul terminator.
+///   This means in particular:
+///
+///     * The entire memory range of this `CStr` must be contained within a single allocation!
+///     * `ptr` must be non-null even for a zero-length cstr.
+///
+/// * The nul terminator must be within `isize::MAX` from `ptr`
+#[no_mangle]
+pub unsafe extern "C" fn AddMetric(
+    db_path_ptr: *const c_char,
+    metric_name_ptr: *const c_char,
+    min: i16,
+    max: i16,
+    metric_id_out: *mut u16,
+) -> i32 {
+    let result = try_get_db(db_path_ptr)
+        .and_then(|data_base| add_metric(&data_base, metric_name_ptr, min, max, metric_id_out));
+    to_result_code("AddMetric", result)
+}
+
+/// fn AddMetricWithHandle(`handle`, `metric_name_ptr`, `min`, `max`, `metric_id_out`);
+///
+/// Handle variant of `AddMetric`.
+///
+/// # Safety
+///
+/// `handle` must be a handle returned by `OpenDatabase` that has not been closed.
+///
+/// `metric_id_out` must be non-null and valid for writes.
+///
+/// Any parameter mentioning `ptr` must satisfy the requirements of `CStr::from_ptr`:
+///
+/// * The memory pointed to by `ptr` must contain a valid nul terminator at the
+///   end of the string.
+///
+/// * `ptr` must be [valid] for reads of bytes up to and including the nul terminator.
+///   This means in particular:
+///
+///     * The entire memory range of this `CStr` must be contained within a single allocation!
+///     * `ptr` must be non-null even for a zero-length cstr.
+///
+/// * The nul terminator must be within `isize::MAX` from `ptr`
+#[no_mangle]
+pub unsafe extern "C" fn AddMetricWithHandle(
+    handle: *const DataBaseHandle,
+    metric_name_ptr: *const c_char,
+    min: i16,
+    max: i16,
+    metric_id_out: *mut u16,
+) -> i32 {
+    let result = try_get_handle_db(handle)
+        .and_then(|data_base| add_metric(data_base, metric_name_ptr, min, max, metric_id_out));
+    to_result_code("AddMetric", result)
+}
+
+unsafe fn add_metric(
+    data_base: &DataBase,
+    metric_name_ptr: *const c_char,
+    min: i16,
+    max: i16,
+    metric_id_out: *mut u16,
+) -> Result<(), data_base::Error> {
+    if metric_id_out.is_null() {
+        return Err(null_pointer_error("metric_id_out"));
+    }
+
+    let metric_name = try_ptr_to_string(metric_name_ptr, "metric_name_ptr")?;
+    let metric_id = data_base.add_metric(metric_name, min, max)?;
+    unsafe { *metric_id_out = metric_id };
+    Ok(())
+}
+
+//
+
+//
+
+/// fn RenameMetric(`db_path_ptr`, `old_metric_ptr`, `new_metric_ptr`);
+///
+/// Attempts to rename the metric provided with `old_metric_ptr` to `new_metric_ptr` in the
+/// database at the path specified by `db_path_ptr`.
+///
+/// # Safety
+///
+/// Any parameter mentioning `ptr` must satisfy the requirements of `CStr::from_ptr`:
+///
+/// * The memory pointed to by `ptr` must contain a valid nul terminator at the
+///   end of the string.
+///
+/// * `ptr` must be [valid] for reads of bytes up to and including the nul terminator.
+///   This means in particular:
+///
+///     * The entire memory range of this `CStr` must be contained within a single allocation!
+///     * `ptr` must be non-null even for a zero-length cstr.
+///
+/// * The nul terminator must be within `isize::MAX` from `ptr`
+#[no_mangle]
+pub unsafe extern "C" fn RenameMetric(
+    db_path_ptr: *const c_char,
+    old_metric_ptr: *const c_char,
+    new_metric_ptr: *const c_char,
+) -> i32 {
+    let result = try_get_db(db_path_ptr)
+        .and_then(|data_base| rename_metric(&data_base, old_metric_ptr, new_metric_ptr));
+    to_result_code("RenameMetric", result)
+}
+
+/// fn RenameMetricWithHandle(`handle`, `old_metric_ptr`, `new_metric_ptr`);
+///
+/// Handle variant of `RenameMetric`.
+///
+/// # Safety
+///
+/// `handle` must be a handle returned by `OpenDatabase` that has not been closed.
+///
+/// Any parameter mentioning `ptr` must satisfy the requirements of `CStr::from_ptr`:
+///
+/// * The memory pointed to by `ptr` must contain a valid nul terminator at the
+///   end of the string.
+///
+/// * `ptr` must be [valid] for reads of bytes up to and including the nul terminator.
+///   This means in particular:
+///
+///     * The entire memory range of this `CStr` must be contained within a single allocation!
+///     * `ptr` must be non-null even for a zero-length cstr.
+///
+/// * The nul terminator must be within `isize::MAX` from `ptr`
+#[no_mangle]
+pub unsafe extern "C" fn RenameMetricWithHandle(
+    handle: *const DataBaseHandle,
+    old_metric_ptr: *const c_char,
+    new_metric_ptr: *const c_char,
+) -> i32 {
+    let result = try_get_handle_db(handle)
+        .and_then(|data_base| rename_metric(data_base, old_metric_ptr, new_metric_ptr));
+    to_result_code("RenameMetric", result)
+}
+
+unsafe fn rename_metric(
+    data_base: &DataBase,
+    old_metric_ptr: *const c_char,
+    new_metric_ptr: *const c_char,
+) -> Result<(), data_base::Error> {
+    let old_metric = try_ptr_to_string(old_metric_ptr, "old_metric_ptr")?;
+    let new_metric = try_ptr_to_string(new_metric_ptr, "new_metric_ptr")?;
+    data_base.rename_metric(old_metric, new_metric)
 }
 
 //
@@ -635,11 +2678,42 @@ pub unsafe extern "C" fn RemoveTag(db_path_ptr: *const c_char, tag_id: u16) -> i
 
 /// fn AddDiaryEntry(`db_path_ptr`, `title_ptr`, `text_ptr`);
 ///
-/// Creates a diary entry with the title `title_ptr` and text `text_ptr`.
-/// The location and timestamp is automatically set based on the current system time.
+/// Creates a diary entry with the title `title_ptr` and text `text_ptr`.
+/// The location and timestamp is automatically set based on the current system time.
+///
+/// # Safety
+///
+/// Any parameter mentioning `ptr` must satisfy the requirements of `CStr::from_ptr`:
+///
+/// * The memory pointed to by `ptr` must contain a valid nul terminator at the
+///   end of the string.
+///
+/// * `ptr` must be [valid] for reads of bytes up to and including the nul terminator.
+///   This means in particular:
+///
+///     * The entire memory range of this `CStr` must be contained within a single allocation!
+///     * `ptr` must be non-null even for a zero-length cstr.
+///
+/// * The nul terminator must be within `isize::MAX` from `ptr`
+#[no_mangle]
+pub unsafe extern "C" fn AddDiaryEntry(
+    db_path_ptr: *const c_char,
+    title_ptr: *const c_char,
+    text_ptr: *const c_char,
+) -> i32 {
+    let result = try_get_db(db_path_ptr)
+        .and_then(|data_base| add_diary_entry(&data_base, title_ptr, text_ptr));
+    to_result_code("AddDiaryEntry", result)
+}
+
+/// fn AddDiaryEntryWithHandle(`handle`, `title_ptr`, `text_ptr`);
+///
+/// Handle variant of `AddDiaryEntry`.
 ///
 /// # Safety
 ///
+/// `handle` must be a handle returned by `OpenDatabase` that has not been closed.
+///
 /// Any parameter mentioning `ptr` must satisfy the requirements of `CStr::from_ptr`:
 ///
 /// * The memory pointed to by `ptr` must contain a valid nul terminator at the
@@ -653,32 +2727,24 @@ pub unsafe extern "C" fn RemoveTag(db_path_ptr: *const c_char, tag_id: u16) -> i
 ///
 /// * The nul terminator must be within `isize::MAX` from `ptr`
 #[no_mangle]
-pub unsafe extern "C" fn AddDiaryEntry(
-    db_path_ptr: *const c_char,
+pub unsafe extern "C" fn AddDiaryEntryWithHandle(
+    handle: *const DataBaseHandle,
     title_ptr: *const c_char,
     text_ptr: *const c_char,
 ) -> i32 {
-    let data_base = match try_get_db(db_path_ptr) {
-        Ok(db) => db,
-        Err(ec) => return ec,
-    };
-    let Ok(title) = try_ptr_to_string(title_ptr) else {
-        return -2;
-    };
-    let Ok(text) = try_ptr_to_string(text_ptr) else {
-        return -3;
-    };
-
-    let result_code = match data_base.add_diary_entry(title.to_string(), text.to_string()) {
-        Ok(_) => 0,
-        Err(error) => {
-            log::error!("AddDiaryEntry error occured: {error:?}");
-            error.code()
-        }
-    };
+    let result = try_get_handle_db(handle)
+        .and_then(|data_base| add_diary_entry(data_base, title_ptr, text_ptr));
+    to_result_code("AddDiaryEntry", result)
+}
 
-    log::logger().flush();
-    result_code
+unsafe fn add_diary_entry(
+    data_base: &DataBase,
+    title_ptr: *const c_char,
+    text_ptr: *const c_char,
+) -> Result<(), data_base::Error> {
+    let title = try_ptr_to_string(title_ptr, "title_ptr")?;
+    let text = try_ptr_to_string(text_ptr, "text_ptr")?;
+    data_base.add_diary_entry(title, text)
 }
 
 //
@@ -723,30 +2789,83 @@ pub unsafe extern "C" fn InsertDiaryEntry(
     title_ptr: *const c_char,
     text_ptr: *const c_char,
 ) -> i32 {
-    let data_base = match try_get_db(db_path_ptr) {
-        Ok(db) => db,
-        Err(ec) => return ec,
-    };
-    let Ok(title) = try_ptr_to_string(title_ptr) else {
-        return -2;
-    };
-    let Ok(text) = try_ptr_to_string(text_ptr) else {
-        return -3;
-    };
+    let result = try_get_db(db_path_ptr).and_then(|data_base| {
+        insert_diary_entry(
+            &data_base, year, month, day, hour, minute, title_ptr, text_ptr,
+        )
+    });
+    to_result_code("InsertDiaryEntry", result)
+}
+
+/// fn InsertDiaryEntryWithHandle(
+///     `handle`,
+///     `year`,
+///     `month`,
+///     `day`,
+///     `hour`,
+///     `minute`
+///     `title_ptr`,
+///     `text_ptr`);
+///
+/// Handle variant of `InsertDiaryEntry`.
+///
+/// # Safety
+///
+/// `handle` must be a handle returned by `OpenDatabase` that has not been closed.
+///
+/// Any parameter mentioning `ptr` must satisfy the requirements of `CStr::from_ptr`:
+///
+/// * The memory pointed to by `ptr` must contain a valid nul terminator at the
+///   end of the string.
+///
+/// * `ptr` must be [valid] for reads of bytes up to and including the nul terminator.
+///   This means in particular:
+///
+///     * The entire memory range of this `CStr` must be contained within a single allocation!
+///     * `ptr` must be non-null even for a zero-length cstr.
+///
+/// * The nul terminator must be within `isize::MAX` from `ptr`
+#[no_mangle]
+pub unsafe extern "C" fn InsertDiaryEntryWithHandle(
+    handle: *const DataBaseHandle,
+    year: i32,
+    month: u8,
+    day: u8,
+    hour: u8,
+    minute: u8,
+    title_ptr: *const c_char,
+    text_ptr: *const c_char,
+) -> i32 {
+    let result = try_get_handle_db(handle).and_then(|data_base| {
+        insert_diary_entry(
+            data_base, year, month, day, hour, minute, title_ptr, text_ptr,
+        )
+    });
+    to_result_code("InsertDiaryEntry", result)
+}
+
+#[allow(clippy::too_many_arguments)]
+unsafe fn insert_diary_entry(
+    data_base: &DataBase,
+    year: i32,
+    month: u8,
+    day: u8,
+    hour: u8,
+    minute: u8,
+    title_ptr: *const c_char,
+    text_ptr: *const c_char,
+) -> Result<(), data_base::Error> {
+    let title = try_ptr_to_string(title_ptr, "title_ptr")?;
+    let text = try_ptr_to_string(text_ptr, "text_ptr")?;
 
     /*
-    let result_code = match data_base.NOTIMPLEMENTED {
-        Ok(_) => 0,
-        Err(error) => {
-            log::error!("InsertDiaryEntry error occured: {error:?}");
-            error.code()
-        }
-    }; */
+    data_base.NOTIMPLEMENTED
+    */
 
-    let result_code = -99;
     log::error!("InsertDiaryEntry is not yet implemented!");
-    log::logger().flush();
-    result_code
+    Err(data_base::Error::with_kind(
+        data_base::ErrorKind::NotImplemented,
+    ))
 }
 
 //
@@ -790,30 +2909,91 @@ pub unsafe extern "C" fn OverwriteDiaryEntry(
     title_ptr: *const c_char,
     text_ptr: *const c_char,
 ) -> i32 {
-    let data_base = match try_get_db(db_path_ptr) {
-        Ok(db) => db,
-        Err(ec) => return ec,
-    };
-    let Ok(title) = try_ptr_to_string(title_ptr) else {
-        return -2;
-    };
-    let Ok(text) = try_ptr_to_string(text_ptr) else {
-        return -3;
-    };
+    let result = try_get_db(db_path_ptr).and_then(|data_base| {
+        overwrite_diary_entry(
+            &data_base,
+            diary_index,
+            year,
+            month,
+            day,
+            title_ptr,
+            text_ptr,
+        )
+    });
+    to_result_code("OverwriteDiaryEntry", result)
+}
+
+/// fn OverwriteDiaryEntryWithHandle(
+///     `handle`,
+///     `diary_index`,
+///     `year`,
+///     `month`,
+///     `day`,
+///     `title_ptr`,
+///     `text_ptr`);
+///
+/// Handle variant of `OverwriteDiaryEntry`.
+///
+/// # Safety
+///
+/// `handle` must be a handle returned by `OpenDatabase` that has not been closed.
+///
+/// Any parameter mentioning `ptr` must satisfy the requirements of `CStr::from_ptr`:
+///
+/// * The memory pointed to by `ptr` must contain a valid nul terminator at the
+///   end of the string.
+///
+/// * `ptr` must be [valid] for reads of bytes up to and including the nul terminator.
+///   This means in particular:
+///
+///     * The entire memory range of this `CStr` must be contained within a single allocation!
+///     * `ptr` must be non-null even for a zero-length cstr.
+///
+/// * The nul terminator must be within `isize::MAX` from `ptr`
+#[no_mangle]
+pub unsafe extern "C" fn OverwriteDiaryEntryWithHandle(
+    handle: *const DataBaseHandle,
+    diary_index: u32,
+    year: i32,
+    month: u8,
+    day: u8,
+    title_ptr: *const c_char,
+    text_ptr: *const c_char,
+) -> i32 {
+    let result = try_get_handle_db(handle).and_then(|data_base| {
+        overwrite_diary_entry(
+            data_base,
+            diary_index,
+            year,
+            month,
+            day,
+            title_ptr,
+            text_ptr,
+        )
+    });
+    to_result_code("OverwriteDiaryEntry", result)
+}
+
+unsafe fn overwrite_diary_entry(
+    data_base: &DataBase,
+    diary_index: u32,
+    year: i32,
+    month: u8,
+    day: u8,
+    title_ptr: *const c_char,
+    text_ptr: *const c_char,
+) -> Result<(), data_base::Error> {
+    let title = try_ptr_to_string(title_ptr, "title_ptr")?;
+    let text = try_ptr_to_string(text_ptr, "text_ptr")?;
 
     /*
-    let result_code = match data_base.NOTIMPLEMENTED {
-        Ok(_) => 0,
-        Err(error) => {
-            log::error!("OverwriteDiaryEntry error occured: {error:?}");
-            error.code()
-        }
-    }; */
+    data_base.NOTIMPLEMENTED
+    */
 
-    let result_code = -99;
     log::error!("OverwriteDiaryEntry is not yet implemented!");
-    log::logger().flush();
-    result_code
+    Err(data_base::Error::with_kind(
+        data_base::ErrorKind::NotImplemented,
+    ))
 }
 
 //
@@ -852,26 +3032,138 @@ pub unsafe extern "C" fn RemoveDiaryEntry(
     day: u8,
     diary_index: u32,
 ) -> i32 {
-    let data_base = match try_get_db(db_path_ptr) {
-        Ok(db) => db,
-        Err(ec) => return ec,
-    };
+    let result = try_get_db(db_path_ptr)
+        .and_then(|data_base| remove_diary_entry(&data_base, year, month, day, diary_index));
+    to_result_code("RemoveDiaryEntry", result)
+}
+
+/// fn RemoveDiaryEntryWithHandle(
+///     `handle`,
+///     `year`,
+///     `month`,
+///     `day`,
+///     `diary_index`);
+///
+/// Handle variant of `RemoveDiaryEntry`.
+///
+/// # Safety
+///
+/// `handle` must be a handle returned by `OpenDatabase` that has not been closed.
+#[no_mangle]
+pub unsafe extern "C" fn RemoveDiaryEntryWithHandle(
+    handle: *const DataBaseHandle,
+    year: i32,
+    month: u8,
+    day: u8,
+    diary_index: u32,
+) -> i32 {
+    let result = try_get_handle_db(handle)
+        .and_then(|data_base| remove_diary_entry(data_base, year, month, day, diary_index));
+    to_result_code("RemoveDiaryEntry", result)
+}
 
+fn remove_diary_entry(
+    data_base: &DataBase,
+    year: i32,
+    month: u8,
+    day: u8,
+    diary_index: u32,
+) -> Result<(), data_base::Error> {
     /*
-    let result_code = match data_base.NOTIMPLEMENTED {
-        Ok(_) => 0,
+    data_base.NOTIMPLEMENTED
+    */
+
+    log::error!("RemoveDiaryEntry is not yet implemented!");
+    Err(data_base::Error::with_kind(
+        data_base::ErrorKind::NotImplemented,
+    ))
+}
+
+/// Returns the result code of the provided `result`, `0` on success and the error code on failure.
+/// Also logs any error and updates the last error of this thread, making the details available
+/// through `GetLastErrorMessage`.
+fn to_result_code(function_name: &str, result: Result<(), data_base::Error>) -> i32 {
+    let result_code = match result {
+        Ok(()) => {
+            LAST_ERROR.set(None);
+            0
+        }
         Err(error) => {
-            log::error!("RemoveDiaryEntry error occured: {error:?}");
-            error.code()
+            log::error!("{function_name} error occured: {error:?}");
+            let code = error.code();
+            // Nul bytes are replaced so the message can always be handed over as a C string.
+            let message = CString::new(error.to_string().replace('\0', "?"))
+                .expect("All nul bytes have been replaced.");
+            LAST_ERROR.set(Some(LastError { code, message }));
+            code
         }
-    };*/
+    };
 
-    let result_code = -99;
-    log::error!("RemoveDiaryEntry is not yet implemented!");
     log::logger().flush();
     result_code
 }
 
+//
+
+//
+
+/// Returns a `NullPointer` error for the provided `parameter`.
+fn null_pointer_error(parameter: &'static str) -> data_base::Error {
+    data_base::Error::with_kind(data_base::ErrorKind::NullPointer(parameter))
+}
+
+//
+
+//
+
+/// Reads the `metric_count` metric values at `metrics` into (metric_id, value) pairs. `metrics` may
+/// only be null if `metric_count` is 0.
+unsafe fn try_read_metric_values(
+    metrics: *const CMetricValue,
+    metric_count: u32,
+) -> Result<Vec<(u16, i16)>, data_base::Error> {
+    if metric_count == 0 {
+        return Ok(Vec::new());
+    }
+    if metrics.is_null() {
+        return Err(null_pointer_error("metrics"));
+    }
+
+    let metrics = unsafe { std::slice::from_raw_parts(metrics, metric_count as usize) };
+    Ok(metrics
+        .iter()
+        .map(|metric| (metric.metric_id, metric.value))
+        .collect())
+}
+
+/// Converts the provided entries into a library owned array of `T`, returning a pointer to the
+/// array and its length. A empty `Vec` results in a null pointer and a length of 0.
+fn into_c_data_entries<T: From<DataEntry>>(entries: Vec<DataEntry>) -> (*mut T, u32) {
+    if entries.is_empty() {
+        return (std::ptr::null_mut(), 0);
+    }
+
+    let entries: Box<[T]> = entries.into_iter().map(T::from).collect();
+    let length = entries.len() as u32;
+    (Box::into_raw(entries) as *mut T, length)
+}
+
+/// Releases a library owned array of `length` values at `array`, as allocated by
+/// `into_c_data_entries`. A null `array` is ignored.
+///
+/// # Safety
+///
+/// `array` must be null or a boxed slice of exactly `length` values that wasn't released already.
+unsafe fn free_c_array<T>(array: *mut T, length: u32) {
+    if !array.is_null() {
+        drop(unsafe { Box::from_raw(std::ptr::slice_from_raw_parts_mut(array, length as usize)) });
+    }
+}
+
+//
+
+//
+
 /// Attempts to create a rust `String` using the provided `ptr`.
 ///
 /// # Safety
@@ -888,13 +3180,18 @@ pub unsafe extern "C" fn RemoveDiaryEntry(
 ///     * `ptr` must be non-null even for a zero-length cstr.
 ///
 /// * The nul terminator must be within `isize::MAX` from `ptr`
-unsafe fn try_ptr_to_string(ptr: *const c_char) -> Result<String, i32> {
+unsafe fn try_ptr_to_string(
+    ptr: *const c_char,
+    parameter: &'static str,
+) -> Result<String, data_base::Error> {
     if ptr.is_null() {
-        return Err(-1);
+        return Err(null_pointer_error(parameter));
     }
     let cstr = unsafe { CStr::from_ptr(ptr) };
     let Ok(str) = cstr.to_str() else {
-        return Err(-2);
+        return Err(data_base::Error::with_kind(
+            data_base::ErrorKind::InvalidUtf8(parameter),
+        ));
     };
     Ok(str.to_string())
 }
@@ -919,14 +3216,420 @@ unsafe fn try_ptr_to_string(ptr: *const c_char) -> Result<String, i32> {
 ///     * `ptr` must be non-null even for a zero-length cstr.
 ///
 /// * The nul terminator must be within `isize::MAX` from `ptr`
-unsafe fn try_get_db(db_path_ptr: *const c_char) -> Result<DataBase, i32> {
-    let db_path_str = try_ptr_to_string(db_path_ptr)?;
-    match DataBase::load(PathBuf::from(&db_path_str)) {
-        Ok(db_path) => Ok(db_path),
-        Err(err) => {
-            log::error!("Could not load database at {:?}", db_path_str);
-            log::logger().flush();
-            Err(err.code())
+unsafe fn try_get_db(db_path_ptr: *const c_char) -> Result<DataBase, data_base::Error> {
+    let db_path_str = try_ptr_to_string(db_path_ptr, "db_path_ptr")?;
+    DataBase::load(PathBuf::from(&db_path_str)).inspect_err(|_| {
+        log::error!("Could not load database at {:?}", db_path_str);
+    })
+}
+
+//
+
+//
+
+/// Attempts to get the `DataBase` behind the provided `handle`.
+///
+/// # Safety
+///
+/// `handle` must either be null or a handle returned by `OpenDatabase` that has not been closed.
+unsafe fn try_get_handle_db<'a>(
+    handle: *const DataBaseHandle,
+) -> Result<&'a DataBase, data_base::Error> {
+    match unsafe { handle.as_ref() } {
+        Some(handle) => Ok(&handle.data_base),
+        None => Err(null_pointer_error("handle")),
+    }
+}
+
+//
+
+//
+
+/// Pointer passed through to a C callback. The pointer is only ever handed back to the caller.
+struct SendPtr<T>(*const T);
+
+// SAFETY: The pointers are only dereferenced by the caller or through an atomic load, and the
+// caller guarantees they stay valid until the function returns.
+unsafe impl<T> Send for SendPtr<T> {}
+unsafe impl<T> Sync for SendPtr<T> {}
+
+/// Creates a `TaskProgress` calling `callback` with `user_data`, which is cancelled once the
+/// `i32` behind `cancel_flag` is nonzero. Both `callback` and `cancel_flag` may be null.
+///
+/// # Safety
+///
+/// `cancel_flag` must either be null or point to a `i32` that stays valid as long as the returned
+/// `TaskProgress` is used.
+unsafe fn c_task_progress(
+    callback: ProgressCallback,
+    user_data: *mut c_void,
+    cancel_flag: *const i32,
+) -> TaskProgress<'static> {
+    let mut progress = TaskProgress::new();
+
+    if let Some(callback) = callback {
+        let user_data = SendPtr(user_data.cast_const());
+        progress = progress.with_callback(move |processed, total| {
+            let user_data = &user_data;
+            unsafe { callback(processed, total, user_data.0.cast_mut()) }
+        });
+    }
+
+    if !cancel_flag.is_null() {
+        let cancel_flag = SendPtr(cancel_flag);
+        progress = progress.with_cancel_check(move || {
+            let cancel_flag = &cancel_flag;
+            unsafe { AtomicI32::from_ptr(cancel_flag.0.cast_mut()) }.load(Ordering::Relaxed) != 0
+        });
+    }
+
+    progress
+}
+
+/// Converts `event` into a `CChangeEvent` and passes it to `deliver`. The names of the event are
+/// only valid until `deliver` returns.
+fn deliver_c_change_event(
+    event: &data_base::ChangeEvent,
+    origin: data_base::ChangeOrigin,
+    deliver: impl FnOnce(*const CChangeEvent),
+) {
+    use data_base::ChangeEvent;
+
+    let mut c_event = CChangeEvent {
+        kind: 0,
+        external: origin == data_base::ChangeOrigin::External,
+        year: 0,
+        month: 0,
+        day: 0,
+        hour: 0,
+        minute: 0,
+        tag_id: 0,
+        other_tag_id: 0,
+        metric_id: 0,
+        name: std::ptr::null(),
+        old_name: std::ptr::null(),
+    };
+    let set_date = |c_event: &mut CChangeEvent, date: &time::Date| {
+        c_event.year = date.year();
+        c_event.month = date.month() as u8;
+        c_event.day = date.day();
+    };
+    // Nul bytes are replaced so the names can always be handed over as C strings.
+    let to_c_string = |name: &str| {
+        CString::new(name.replace('\0', "?")).expect("All nul bytes have been replaced.")
+    };
+    let mut name = None;
+    let mut old_name = None;
+
+    match event {
+        ChangeEvent::EntryAdded { date, hour, minute } => {
+            c_event.kind = 0;
+            set_date(&mut c_event, date);
+            c_event.hour = *hour;
+            c_event.minute = *minute;
+        }
+        ChangeEvent::EntryOverwritten { date, hour, minute } => {
+            c_event.kind = 1;
+            set_date(&mut c_event, date);
+            c_event.hour = *hour;
+            c_event.minute = *minute;
+        }
+        ChangeEvent::EntryRemoved { date, hour, minute } => {
+            c_event.kind = 2;
+            set_date(&mut c_event, date);
+            c_event.hour = *hour;
+            c_event.minute = *minute;
+        }
+        ChangeEvent::TagAdded {
+            tag_id,
+            name: tag_name,
+        } => {
+            c_event.kind = 3;
+            c_event.tag_id = *tag_id;
+            name = Some(to_c_string(tag_name));
+        }
+        ChangeEvent::TagRenamed {
+            tag_id,
+            old_name: old_tag_name,
+            new_name,
+        } => {
+            c_event.kind = 4;
+            c_event.tag_id = *tag_id;
+            name = Some(to_c_string(new_name));
+            old_name = Some(to_c_string(old_tag_name));
+        }
+        ChangeEvent::TagsMerged { tag_1, tag_2 } => {
+            c_event.kind = 5;
+            c_event.tag_id = *tag_1;
+            c_event.other_tag_id = *tag_2;
+        }
+        ChangeEvent::TagRemoved { tag_id } => {
+            c_event.kind = 6;
+            c_event.tag_id = *tag_id;
+        }
+        ChangeEvent::DiaryEntryAdded { date } => {
+            c_event.kind = 7;
+            set_date(&mut c_event, date);
+        }
+        ChangeEvent::DiaryEntryRemoved { date } => {
+            c_event.kind = 11;
+            set_date(&mut c_event, date);
+        }
+        ChangeEvent::CachesRegenerated => c_event.kind = 8,
+        ChangeEvent::MetricAdded {
+            metric_id,
+            name: metric_name,
+        } => {
+            c_event.kind = 9;
+            c_event.metric_id = *metric_id;
+            name = Some(to_c_string(metric_name));
+        }
+        ChangeEvent::MetricRenamed {
+            metric_id,
+            old_name: old_metric_name,
+            new_name,
+        } => {
+            c_event.kind = 10;
+            c_event.metric_id = *metric_id;
+            name = Some(to_c_string(new_name));
+            old_name = Some(to_c_string(old_metric_name));
+        }
+    }
+
+    if let Some(name) = &name {
+        c_event.name = name.as_ptr();
+    }
+    if let Some(old_name) = &old_name {
+        c_event.old_name = old_name.as_ptr();
+    }
+    deliver(&c_event);
+}
+
+//
+
+//
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::BTreeMap,
+        ffi::{CStr, CString},
+    };
+
+    use super::{
+        AddTagWithHandle, CDataEntry, CDataEntryWithMetrics, CloseDatabase, DataBaseHandle,
+        FreeDataEntries, FreeDataEntriesWithMetrics, GetDataEntriesWithHandle,
+        GetDataEntriesWithMetricsWithHandle, GetLastErrorCode, GetLastErrorMessage,
+        InsertDataEntryWithHandle, OpenDatabase,
+    };
+    use crate::{
+        data_base::DataBase,
+        data_entry::DataEntry,
+        metrics::{MENTALSCORE, PHYSICALSCORE},
+        test_utils::{create_database, entry, temp_dir},
+    };
+
+    /// Opens a handle to a new in-memory database with a offset of +4.
+    fn memory_handle(name: &str) -> DataBaseHandle {
+        DataBaseHandle {
+            data_base: create_database(name, 4),
+        }
+    }
+
+    //
+
+    //
+
+    /// Returns the `length` values at `array`, which may be dangling if `length` is 0.
+    fn read_c_array<'a, T>(array: *const T, length: u32) -> &'a [T] {
+        if length == 0 {
+            return &[];
         }
+        unsafe { std::slice::from_raw_parts(array, length as usize) }
+    }
+
+    /// Adds two entries to 2024-01-01: one at 02:30 the next morning with the tag 1 and the
+    /// built-in metrics, and one at 09:00 with a mental score and a user-defined metric.
+    fn add_entries(data_base: &DataBase) -> u16 {
+        data_base.add_tag("a".to_string()).unwrap();
+        data_base
+            .insert_data_entry(2024, 1, 1, entry(26, 30, vec![1]))
+            .unwrap();
+        let metric_id = data_base.add_metric("sleep".to_string(), 0, 12).unwrap();
+        let metrics = BTreeMap::from([(MENTALSCORE, 40), (metric_id, 8)]);
+        data_base
+            .insert_data_entry(2024, 1, 1, DataEntry::new(9, metrics, vec![]))
+            .unwrap();
+        metric_id
+    }
+
+    //
+
+    //
+
+    #[test]
+    fn get_data_entries_keeps_the_original_layout() {
+        let handle = memory_handle("c_entries");
+        add_entries(&handle.data_base);
+
+        let (mut entries, mut length) = (std::ptr::null_mut::<CDataEntry>(), 0);
+        let result =
+            unsafe { GetDataEntriesWithHandle(&handle, 2024, 1, 1, &mut entries, &mut length) };
+        assert_eq!((result, length), (0, 2));
+        let read = read_c_array(entries, length)
+            .iter()
+            .map(|c_entry| {
+                (
+                    c_entry.hour,
+                    c_entry.mental_score,
+                    c_entry.physical_score,
+                    read_c_array(c_entry.tags, c_entry.tag_count).to_vec(),
+                )
+            })
+            .collect::<Vec<_>>();
+        // The physical score the second entry lacks is returned as 0.
+        assert_eq!(read, vec![(9, 40, 0, vec![]), (2, 60, 70, vec![1])]);
+        unsafe { FreeDataEntries(entries, length) };
+    }
+
+    #[test]
+    fn get_data_entries_with_metrics_returns_minutes_and_every_metric() {
+        let handle = memory_handle("c_entries_metrics");
+        let metric_id = add_entries(&handle.data_base);
+
+        let (mut entries, mut length) = (std::ptr::null_mut::<CDataEntryWithMetrics>(), 0);
+        let result = unsafe {
+            GetDataEntriesWithMetricsWithHandle(&handle, 2024, 1, 1, &mut entries, &mut length)
+        };
+        assert_eq!((result, length), (0, 2));
+        let read = read_c_array(entries, length)
+            .iter()
+            .map(|c_entry| {
+                let metrics = read_c_array(c_entry.metrics, c_entry.metric_count)
+                    .iter()
+                    .map(|metric| (metric.metric_id, metric.value))
+                    .collect::<Vec<_>>();
+                (
+                    c_entry.hour,
+                    c_entry.compensated_hour,
+                    c_entry.minute,
+                    metrics,
+                    read_c_array(c_entry.tags, c_entry.tag_count).to_vec(),
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            read,
+            vec![
+                (9, 9, 0, vec![(MENTALSCORE, 40), (metric_id, 8)], vec![]),
+                (
+                    2,
+                    26,
+                    30,
+                    vec![(MENTALSCORE, 60), (PHYSICALSCORE, 70)],
+                    vec![1]
+                ),
+            ]
+        );
+        unsafe { FreeDataEntriesWithMetrics(entries, length) };
+    }
+
+    #[test]
+    fn get_data_entries_returns_null_for_a_day_without_entries() {
+        let handle = memory_handle("c_no_entries");
+        let mut entries = std::ptr::NonNull::<CDataEntry>::dangling().as_ptr();
+        let mut length = 5;
+
+        let result =
+            unsafe { GetDataEntriesWithHandle(&handle, 2024, 1, 1, &mut entries, &mut length) };
+        assert_eq!((result, length), (0, 0));
+        assert!(entries.is_null());
+        // Freeing the null array returned for a empty day does nothing.
+        unsafe { FreeDataEntries(entries, length) };
+    }
+
+    #[test]
+    fn get_data_entries_rejects_invalid_arguments() {
+        let handle = memory_handle("c_invalid_entries");
+        let (mut entries, mut length) = (std::ptr::null_mut::<CDataEntry>(), 0);
+
+        let result =
+            unsafe { GetDataEntriesWithHandle(&handle, 2024, 2, 30, &mut entries, &mut length) };
+        assert_eq!(result, 15);
+        let result = unsafe {
+            GetDataEntriesWithHandle(&handle, 2024, 1, 1, std::ptr::null_mut(), &mut length)
+        };
+        assert_eq!(result, 26);
+        let result = unsafe {
+            GetDataEntriesWithHandle(std::ptr::null(), 2024, 1, 1, &mut entries, &mut length)
+        };
+        assert_eq!(result, 26);
+        assert!(entries.is_null());
+    }
+
+    #[test]
+    fn handles_reuse_the_loaded_database() {
+        let db_path = temp_dir("c_handle").join("db");
+        DataBase::create(db_path.clone(), 0).unwrap();
+        let db_path_str = CString::new(db_path.to_str().unwrap()).unwrap();
+
+        let mut handle = std::ptr::null_mut();
+        assert_eq!(
+            unsafe { OpenDatabase(db_path_str.as_ptr(), &mut handle) },
+            0
+        );
+        let tag_name = CString::new("walk").unwrap();
+        assert_eq!(unsafe { AddTagWithHandle(handle, tag_name.as_ptr()) }, 0);
+        let data = [60, 70, 1];
+        let result = unsafe { InsertDataEntryWithHandle(handle, 2024, 1, 1, 8, data.as_ptr(), 3) };
+        assert_eq!(result, 0);
+
+        let entries = unsafe { &(*handle).data_base }.get_data_entries(2024, 1, 1);
+        assert_eq!(entries.unwrap()[0].tags, vec![1]);
+        unsafe { CloseDatabase(handle) };
+        std::fs::remove_dir_all(db_path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn open_database_leaves_the_handle_untouched_on_failure() {
+        let db_path = temp_dir("c_handle_missing").join("missing");
+        let db_path_str = CString::new(db_path.to_str().unwrap()).unwrap();
+
+        let mut handle = std::ptr::null_mut();
+        assert_ne!(
+            unsafe { OpenDatabase(db_path_str.as_ptr(), &mut handle) },
+            0
+        );
+        assert!(handle.is_null());
+        assert_eq!(
+            unsafe { OpenDatabase(db_path_str.as_ptr(), std::ptr::null_mut()) },
+            26
+        );
+        // Closing a null handle does nothing.
+        unsafe { CloseDatabase(handle) };
+        std::fs::remove_dir_all(db_path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn last_error_is_kept_per_thread_until_the_next_call() {
+        let handle = memory_handle("c_last_error");
+        let (mut entries, mut length) = (std::ptr::null_mut::<CDataEntry>(), 0);
+
+        let result =
+            unsafe { GetDataEntriesWithHandle(&handle, 2024, 2, 30, &mut entries, &mut length) };
+        assert_eq!((result, GetLastErrorCode()), (15, 15));
+        let message = unsafe { CStr::from_ptr(GetLastErrorMessage()) };
+        assert_eq!(message.to_str().unwrap(), "The provided date is not valid.");
+        std::thread::spawn(|| {
+            assert_eq!(GetLastErrorCode(), 0);
+            assert!(GetLastErrorMessage().is_null());
+        })
+        .join()
+        .unwrap();
+
+        let result =
+            unsafe { GetDataEntriesWithHandle(&handle, 2024, 2, 1, &mut entries, &mut length) };
+        assert_eq!((result, GetLastErrorCode()), (0, 0));
+        assert!(GetLastErrorMessage().is_null());
     }
 }
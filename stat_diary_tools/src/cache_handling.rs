@@ -1,14 +1,51 @@
 use std::{
-    collections::HashSet,
+    collections::{BTreeMap, BTreeSet},
     ffi::OsStr,
-    fs::File,
-    io::{self, BufWriter, Write},
+    fmt::Write,
+    io,
     path::Path,
 };
 
 use log::{error, warn};
 
-use crate::{data_entry::DataFile, db_path::DataBasePath, utilities::read_sorted_directory};
+use crate::{
+    data_entry::DataFile,
+    db_path::DataBasePath,
+    regeneration::{self, RegenerationError},
+    storage::Storage,
+    task_progress::{Cancelled, TaskProgress},
+};
+
+//
+
+//
+
+#[derive(Debug)]
+pub enum CacheError {
+    Io(io::Error),
+    Cancelled,
+}
+
+impl From<io::Error> for CacheError {
+    fn from(value: io::Error) -> Self {
+        Self::Io(value)
+    }
+}
+
+impl From<Cancelled> for CacheError {
+    fn from(_: Cancelled) -> Self {
+        Self::Cancelled
+    }
+}
+
+impl From<RegenerationError> for CacheError {
+    fn from(value: RegenerationError) -> Self {
+        match value {
+            RegenerationError::Io(e) => Self::Io(e),
+            RegenerationError::Cancelled => Self::Cancelled,
+        }
+    }
+}
 
 //
 
@@ -16,17 +53,17 @@ use crate::{data_entry::DataFile, db_path::DataBasePath, utilities::read_sorted_
 
 #[derive(Debug, Clone)]
 pub struct ScoreAvg {
-    pub min: u8,
-    pub max: u8,
-    pub total: u32,
+    pub min: i16,
+    pub max: i16,
+    pub total: i64,
     pub count: u32,
 }
 
 impl Default for ScoreAvg {
     fn default() -> Self {
         ScoreAvg {
-            min: u8::MAX,
-            max: 0,
+            min: i16::MAX,
+            max: i16::MIN,
             total: 0,
             count: 0,
         }
@@ -34,10 +71,10 @@ impl Default for ScoreAvg {
 }
 
 impl ScoreAvg {
-    pub fn add(&mut self, score: u8) {
+    pub fn add(&mut self, score: i16) {
         self.min = self.min.min(score);
         self.max = self.max.max(score);
-        self.total += score as u32;
+        self.total += score as i64;
         self.count += 1;
     }
     pub fn avg(&self) -> f32 {
@@ -51,36 +88,77 @@ impl ScoreAvg {
     }
 }
 
+/// The scores of every metric and the tags found in a set of data entries. Metrics without a
+/// value in any of the entries are left out.
 #[derive(Debug, Default, Clone)]
 pub struct Overview {
-    pub m_score: ScoreAvg,
-    pub p_score: ScoreAvg,
-    pub tags: HashSet<u16>,
+    pub metrics: BTreeMap<u16, ScoreAvg>,
+    pub tags: BTreeSet<u16>,
 }
 
 impl Overview {
+    /// Returns the overview as it is written to a cache line. Each metric is listed as
+    /// "{metric_id} {min} {max} {avg} {total} {count} |", ordered by id, followed by the tags.
+    ///
+    /// The total and count make it possible to merge cache lines without reading the data files
+    /// they were created from.
     fn to_data_str(&self) -> String {
-        let mut data_str = format!(
-            "{} {} {} | {} {} {} |",
-            self.m_score.min,
-            self.m_score.max,
-            self.m_score.avg(),
-            self.p_score.min,
-            self.p_score.max,
-            self.p_score.avg()
-        );
+        let mut data_str = self
+            .metrics
+            .iter()
+            .map(|(metric_id, score)| {
+                format!(
+                    "{} {} {} {} {} {} |",
+                    metric_id,
+                    score.min,
+                    score.max,
+                    score.avg(),
+                    score.total,
+                    score.count
+                )
+            })
+            .collect::<Vec<_>>()
+            .join(" ");
         for tag in &self.tags {
             data_str.push_str(&format!(" {}", tag));
         }
         data_str
     }
 
-    fn merge(&mut self, other: &Overview) {
+    /// Parses the data of a cache line written by `to_data_str`. Returns `None` if the data does
+    /// not follow the format, which is also the case for cache lines written before the total and
+    /// count of each metric were recorded.
+    fn from_data_str(data_str: &str) -> Option<Overview> {
+        let mut overview = Overview::default();
+        let mut parts = data_str.split('|').collect::<Vec<_>>();
+        let tags = parts.pop()?;
+
+        for part in parts {
+            let values = part.split_whitespace().collect::<Vec<_>>();
+            let [metric_id, min, max, _avg, total, count] = values[..] else {
+                return None;
+            };
+            let score = ScoreAvg {
+                min: min.parse().ok()?,
+                max: max.parse().ok()?,
+                total: total.parse().ok()?,
+                count: count.parse().ok()?,
+            };
+            overview.metrics.insert(metric_id.parse().ok()?, score);
+        }
+        for tag in tags.split_whitespace() {
+            overview.tags.insert(tag.parse().ok()?);
+        }
+        Some(overview)
+    }
+
+    pub fn merge(&mut self, other: &Overview) {
         for tag in &other.tags {
             self.tags.insert(*tag);
         }
-        self.m_score.merge(&other.m_score);
-        self.p_score.merge(&other.p_score);
+        for (metric_id, score) in &other.metrics {
+            self.metrics.entry(*metric_id).or_default().merge(score);
+        }
     }
 }
 
@@ -88,38 +166,215 @@ impl Overview {
 
 //
 
-// TODO ############################################################ TODO
-// Better checks to ensure a year folder is actually a valid year folder?
+/// Updates the caches after the data file at `data_file_path` has been changed or removed.
+///
+/// Only the line of the changed day in the month cache and the line of its month in the year
+/// cache are recomputed. The remaining lines are kept as they are. The month line is merged from
+/// the day lines of the month cache, so only the changed data file is read. If either cache does
+/// not exist yet it is created from scratch instead.
+pub fn update_day_caches(db_path: &DataBasePath, data_file_path: &Path) -> Result<(), CacheError> {
+    let storage = db_path.storage();
+    let (Some(month_folder), Some(filename)) =
+        (data_file_path.parent(), data_file_path.file_name())
+    else {
+        warn!("Can not update the caches of {:?}!", data_file_path);
+        return Ok(());
+    };
+    let Some(year_folder) = month_folder.parent() else {
+        warn!("Can not update the caches of {:?}!", data_file_path);
+        return Ok(());
+    };
+    let Ok(month_index) = is_month_folder(storage, month_folder) else {
+        return Ok(());
+    };
+
+    if !storage.exists(&year_folder.join("year_cache.txt")) {
+        create_year_cache(db_path, year_folder, &TaskProgress::default())?;
+        return Ok(());
+    }
+
+    let month_cache_path = month_folder.join("month_cache.txt");
+    let mut month_overview = None;
+    if storage.exists(&month_cache_path) {
+        let day_line =
+            read_overview(db_path, data_file_path)?.map(|overview| overview.to_data_str());
+        update_cache_line(
+            storage,
+            &month_cache_path,
+            &filename.to_string_lossy(),
+            day_line,
+        )?;
+        month_overview = read_month_cache(storage, &month_cache_path)?;
+    }
+
+    // The month cache is created from scratch if it is missing or written in an older format.
+    let month_overview = match month_overview {
+        Some(month_overview) => month_overview,
+        None => create_month_cache(db_path, month_folder, &TaskProgress::default())?,
+    };
+    update_cache_line(
+        storage,
+        &year_folder.join("year_cache.txt"),
+        &month_index.to_string(),
+        Some(month_overview.to_data_str()),
+    )?;
+
+    Ok(())
+}
+
+//
+
+//
 
-/// Regenerates all caches in the provided database.
-pub fn regenerate_caches(db_path: &DataBasePath) -> Result<(), io::Error> {
-    for year_path in read_sorted_directory(&db_path.data())? {
-        if year_path.is_file() {
+/// Returns the overview of the month by merging every day line of the month cache at
+/// `cache_path`, or `None` if a line can't be parsed.
+fn read_month_cache(
+    storage: &dyn Storage,
+    cache_path: &Path,
+) -> Result<Option<Overview>, CacheError> {
+    let mut month_overview = Overview::default();
+    for line in storage.read_lines(cache_path)? {
+        let Some(day_overview) = line
+            .split_once(" | ")
+            .and_then(|(_, data_str)| Overview::from_data_str(data_str))
+        else {
             warn!(
-                "Encountered unexpected file in data folder! {:?}",
-                year_path
+                "Could not parse the line [{}] of the month cache {:?}!",
+                line, cache_path
             );
-            continue;
-        }
+            return Ok(None);
+        };
+        month_overview.merge(&day_overview);
+    }
+    Ok(Some(month_overview))
+}
 
-        let mut result_writer = BufWriter::new(File::create(year_path.join("year_cache.txt"))?);
-        for month_path in read_sorted_directory(&year_path)? {
-            let Ok(month_index) = is_month_folder(&month_path) else {
-                continue;
-            };
+//
+
+//
+
+/// Replaces the line starting with `key` in the cache file at `cache_path` with `data_str`. If
+/// `data_str` is `None` the line is removed instead. The lines are kept sorted by key in the same
+/// order a full regeneration writes them.
+fn update_cache_line(
+    storage: &dyn Storage,
+    cache_path: &Path,
+    key: &str,
+    data_str: Option<String>,
+) -> Result<(), CacheError> {
+    let mut lines: Vec<(String, String)> = storage
+        .read_lines(cache_path)?
+        .into_iter()
+        .filter_map(|line| {
+            let (line_key, line_data) = line.split_once(" | ")?;
+            Some((line_key.to_string(), line_data.to_string()))
+        })
+        .filter(|(line_key, _)| line_key != key)
+        .collect();
+
+    if let Some(data_str) = data_str {
+        lines.push((key.to_string(), data_str));
+    }
+    lines.sort_by(|a, b| a.0.cmp(&b.0));
+
+    let mut content = String::new();
+    for (line_key, line_data) in lines {
+        writeln!(content, "{} | {}", line_key, line_data)
+            .expect("Writing to a String can not fail.");
+    }
+    storage.write_atomic(cache_path, content.as_bytes())?;
+
+    Ok(())
+}
+
+//
+
+//
 
-            let avg_month_scores = create_month_cache(&month_path)?;
-            writeln!(
-                result_writer,
-                "{} | {}",
-                month_index,
-                avg_month_scores.to_data_str(),
-            )?;
+/// Returns the overview of the data file at `file`, or `None` if the file does not exist, is
+/// corrupted or is misplaced.
+fn read_overview(db_path: &DataBasePath, file: &Path) -> Result<Option<Overview>, CacheError> {
+    if !db_path.storage().exists(file) {
+        return Ok(None);
+    }
+    match DataFile::read_from_file(db_path, file) {
+        Ok(data_file) => Ok(Some(data_file.get_overview())),
+        Err(crate::data_entry::Error::Io(io_err)) => Err(io_err.into()),
+        Err(_) => {
+            error!(
+                "Data file [{:?}] is corrupted or misplaced! This file will not be represented in the cache!",
+                file
+            );
+            Ok(None)
         }
-        log::info!("Created year cache: {:?}", year_path.join("year_cache.txt"));
-        result_writer.flush()?;
     }
+}
+
+//
+
+//
+
+/// Creates a year cache in the provided year folder, regenerating the month cache of each month
+/// in the year.
+///
+/// If a year_cache.txt file already exists then it gets overwritten.
+fn create_year_cache(
+    db_path: &DataBasePath,
+    year_path: &Path,
+    progress: &TaskProgress,
+) -> Result<(), CacheError> {
+    let storage = db_path.storage();
+    let mut month_overviews = Vec::new();
+    for month_path in storage.list(year_path)? {
+        let Ok(month_index) = is_month_folder(storage, &month_path) else {
+            continue;
+        };
+
+        let avg_month_scores = create_month_cache(db_path, &month_path, progress)?;
+        month_overviews.push((month_index, avg_month_scores));
+    }
+    write_year_cache(storage, year_path, &month_overviews)?;
+    Ok(())
+}
+
+//
 
+//
+
+/// Writes the year cache of the year folder at `year_path`, containing one line for each of the
+/// provided `(month_index, overview)` pairs.
+///
+/// If a year_cache.txt file already exists then it gets overwritten.
+pub fn write_year_cache(
+    storage: &dyn Storage,
+    year_path: &Path,
+    month_overviews: &[(u8, Overview)],
+) -> Result<(), io::Error> {
+    let content = year_cache_content(month_overviews);
+    storage.write(&year_path.join("year_cache.txt"), content.as_bytes())?;
+    log::info!("Created year cache: {:?}", year_path.join("year_cache.txt"));
+    Ok(())
+}
+
+//
+
+//
+
+/// Writes the month cache of the month folder at `month_folder`, containing one line for each of
+/// the provided `(file_name, overview)` pairs.
+///
+/// If a month_cache.txt file already exists then it gets overwritten.
+pub fn write_month_cache(
+    storage: &dyn Storage,
+    month_folder: &Path,
+    day_overviews: &[(String, Overview)],
+) -> Result<(), io::Error> {
+    let content = month_cache_content(day_overviews);
+    storage.write(&month_folder.join("month_cache.txt"), content.as_bytes())?;
+    log::info!(
+        "Created month cache: {:?}",
+        month_folder.join("month_cache.txt")
+    );
     Ok(())
 }
 
@@ -127,11 +382,37 @@ pub fn regenerate_caches(db_path: &DataBasePath) -> Result<(), io::Error> {
 
 //
 
+/// Returns the content of a year cache containing one line for each `(month_index, overview)`
+/// pair.
+pub fn year_cache_content(month_overviews: &[(u8, Overview)]) -> String {
+    let mut content = String::new();
+    for (month_index, overview) in month_overviews {
+        writeln!(content, "{} | {}", month_index, overview.to_data_str())
+            .expect("Writing to a String can not fail.");
+    }
+    content
+}
+
+/// Returns the content of a month cache containing one line for each `(file_name, overview)`
+/// pair.
+pub fn month_cache_content(day_overviews: &[(String, Overview)]) -> String {
+    let mut content = String::new();
+    for (file_name, overview) in day_overviews {
+        writeln!(content, "{} | {}", file_name, overview.to_data_str())
+            .expect("Writing to a String can not fail.");
+    }
+    content
+}
+
+//
+
+//
+
 /// Returns a u8 representing the month index in the name of the provided folder IF it is a folder
 /// and has a filename that can be parsed into a valid u8 month index. (To be valid it has to be
 /// between 1..=12)
-fn is_month_folder(month_path: &Path) -> Result<u8, ()> {
-    if month_path.is_file() {
+pub fn is_month_folder(storage: &dyn Storage, month_path: &Path) -> Result<u8, ()> {
+    if storage.is_file(month_path) {
         if month_path.file_name() != Some(OsStr::new("year_cache.txt")) {
             warn!("Ignoring unexpected file in year folder: {:?}", month_path);
         }
@@ -160,64 +441,115 @@ fn is_month_folder(month_path: &Path) -> Result<u8, ()> {
 //
 
 /// Creates a month cache in the provided month folder. Reads all available day items and saves
-/// min max and avg m/p scores for each day in separate rows in a month_cache.txt file placed
+/// min max and avg of each metric for each day in separate rows in a month_cache.txt file placed
 /// inside the provided month folder.
 ///
 /// If a month_cache.txt file already exists then it gets overwritten.
 ///
 /// Returns a overview over all days in this month.
-fn create_month_cache(month_folder: &Path) -> Result<Overview, io::Error> {
-    let mut result_writer = BufWriter::new(File::create(month_folder.join("month_cache.txt"))?);
+fn create_month_cache(
+    db_path: &DataBasePath,
+    month_folder: &Path,
+    progress: &TaskProgress,
+) -> Result<Overview, CacheError> {
+    let month_data = regeneration::read_month(db_path, month_folder, progress)?;
+    write_month_cache(db_path.storage(), month_folder, &month_data.day_overviews)?;
+    Ok(month_data.overview)
+}
 
-    let mut month_overview = Overview::default();
+//
 
-    for file in read_sorted_directory(month_folder)? {
-        if !DataFile::is_data_file(&file) {
-            continue;
-        }
+//
 
-        let Some(filename) = file.file_name() else {
-            warn!("Skipping data file without name: {:?}", file);
-            continue;
-        };
+#[cfg(test)]
+mod tests {
+    use std::{collections::BTreeMap, path::PathBuf};
 
-        let mut overview = Overview::default();
+    use crate::{
+        data_base::DataBase,
+        metrics::MENTALSCORE,
+        test_utils::{create_database, derived_files, entry},
+    };
 
-        let data_file = match DataFile::read_from_file(&file) {
-            Ok(data_file) => data_file,
-            Err(crate::data_entry::Error::CorruptedDataFile) => {
-                error!("Data file [{:?}] is corrupted! This file will not be represented in the cache!", file);
-                continue;
-            }
-            Err(crate::data_entry::Error::Io(io_err)) => return Err(io_err),
-            _ => continue, // Remaining errors can not occur here.
-        };
+    /// Returns the content of every month and year cache of the database, keyed by path.
+    fn cache_files(database: &DataBase) -> BTreeMap<PathBuf, Vec<u8>> {
+        let stat_sums = database.path().stat_sums();
+        derived_files(database)
+            .into_iter()
+            .filter(|(file, _)| !file.starts_with(&stat_sums))
+            .collect()
+    }
 
-        for data in data_file.entries().values() {
-            overview.m_score.add(data.mental_score);
-            overview.p_score.add(data.physical_score);
+    /// Returns the caches of `database` after regenerating them from scratch.
+    fn regenerated_caches(database: &DataBase) -> BTreeMap<PathBuf, Vec<u8>> {
+        database.regen_caches().unwrap();
+        cache_files(database)
+    }
 
-            for tag in &data.tags {
-                overview.tags.insert(*tag);
-            }
+    //
+
+    //
+
+    #[test]
+    fn updated_caches_match_a_full_regeneration() {
+        let database = create_database("update_caches", 0);
+        let writes = [
+            (2023, 11, 5, entry(8, 0, vec![])),
+            (2023, 12, 30, entry(20, 45, vec![])),
+            (2024, 1, 2, entry(9, 0, vec![])),
+            (2024, 1, 2, entry(9, 30, vec![])),
+            (2024, 1, 20, entry(12, 0, vec![])),
+        ];
+        for (year, month, day, data_entry) in writes {
+            database
+                .insert_data_entry(year, month, day, data_entry)
+                .unwrap();
         }
-
-        //month_overview.merge(&overview); // */
-        month_overview.merge(&data_file.get_overview());
-
-        writeln!(
-            result_writer,
-            "{} | {}",
-            filename.to_string_lossy(),
-            overview.to_data_str(),
-        )?;
+        let mut changed_entry = entry(9, 0, vec![]);
+        changed_entry.metrics.insert(MENTALSCORE, 10);
+        database
+            .insert_data_entry(2024, 1, 2, changed_entry)
+            .unwrap();
+        database.remove_data_entry(2023, 11, 5, 8, 0).unwrap();
+
+        let updated = cache_files(&database);
+        assert_eq!(
+            updated
+                .keys()
+                .filter(|file| file.ends_with("year_cache.txt"))
+                .count(),
+            2
+        );
+        assert_eq!(updated, regenerated_caches(&database));
     }
 
-    log::info!(
-        "Created month cache: {:?}",
-        month_folder.join("month_cache.txt")
-    );
-    result_writer.flush()?;
-
-    Ok(month_overview)
+    #[test]
+    fn missing_caches_are_created_on_the_next_write() {
+        let database = create_database("missing_caches", 0);
+        database
+            .insert_data_entry(2024, 1, 2, entry(9, 0, vec![]))
+            .unwrap();
+        database
+            .insert_data_entry(2024, 2, 2, entry(9, 0, vec![]))
+            .unwrap();
+        let storage = database.path().storage();
+        let year_folder = database.path().data().join("2024");
+        storage
+            .remove_file(&year_folder.join("1").join("month_cache.txt"))
+            .unwrap();
+        database
+            .insert_data_entry(2024, 1, 3, entry(9, 0, vec![]))
+            .unwrap();
+        storage
+            .remove_file(&year_folder.join("year_cache.txt"))
+            .unwrap();
+        database
+            .insert_data_entry(2024, 2, 3, entry(9, 0, vec![]))
+            .unwrap();
+
+        let updated = cache_files(&database);
+        assert!(updated.contains_key(&year_folder.join("1").join("month_cache.txt")));
+        assert!(updated.contains_key(&year_folder.join("year_cache.txt")));
+        assert_eq!(updated, regenerated_caches(&database));
+    }
 }
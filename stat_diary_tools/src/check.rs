@@ -0,0 +1,525 @@
+use std::{
+    collections::HashMap,
+    ffi::OsStr,
+    fmt,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    cache_handling,
+    data_base::Error,
+    data_entry::{self, DataEntry, DataFile},
+    db_path::DataBasePath,
+    metrics::{MetricList, MetricsError},
+    regeneration,
+    settings_file::Settings,
+    stat_sums::{StatSumsError, Tags},
+    tags::{TagList, TagsError},
+    task_progress::TaskProgress,
+};
+
+type Result<T> = std::result::Result<T, Error>;
+
+/// A single problem found by `DataBase::check`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Problem {
+    /// The data file could not be parsed.
+    CorruptedDataFile(PathBuf),
+    /// The path of the data file does not match its date.
+    MisplacedDataFile(PathBuf),
+    /// The entry at `hour` and `minute` lies outside of the day of its data file.
+    HourOutOfRange { file: PathBuf, hour: i8, minute: u8 },
+    /// The entry at `hour` has a minute above 59.
+    MinuteOutOfRange { file: PathBuf, hour: i8, minute: u8 },
+    /// The entry at `hour` and `minute` has a value outside of the range of its metric.
+    MetricOutOfRange {
+        file: PathBuf,
+        hour: i8,
+        minute: u8,
+        metric_id: u16,
+        value: i16,
+    },
+    /// The entry at `hour` and `minute` has a value for a metric id that is missing from the
+    /// metrics file.
+    UnknownMetricId {
+        file: PathBuf,
+        hour: i8,
+        minute: u8,
+        metric_id: u16,
+    },
+    /// The entry at `hour` and `minute` contains a tag id that is missing from the tags file.
+    UnknownTagId {
+        file: PathBuf,
+        hour: i8,
+        minute: u8,
+        tag_id: u16,
+    },
+    /// The entry at `hour` and `minute` contains the same tag id more than once.
+    DuplicateTag {
+        file: PathBuf,
+        hour: i8,
+        minute: u8,
+        tag_id: u16,
+    },
+    /// The tags file could not be parsed, so the tags of the entries were not checked.
+    CorruptedTagsFile(String),
+    /// The metrics file could not be parsed, so the metric values of the entries were not
+    /// checked.
+    CorruptedMetricsFile(String),
+    /// The tag id is in use in the tags file, but is also listed in reclaimed.tags and would be
+    /// handed out again to the next new tag.
+    ReclaimedTagId(u16),
+    /// The stat sum file could not be parsed.
+    CorruptedStatSumsFile(PathBuf),
+    /// The stat sum file does not match the sums of the data files, or is missing.
+    StatSumsMismatch(PathBuf),
+    /// The cache file does not match the data files, or is missing.
+    StaleCache(PathBuf),
+    /// A temporary file left behind by a interrupted write.
+    LeftoverTempFile(PathBuf),
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Problem::CorruptedDataFile(file) => write!(f, "Corrupted data file: {file:?}"),
+            Problem::MisplacedDataFile(file) => write!(f, "Misplaced data file: {file:?}"),
+            Problem::HourOutOfRange { file, hour, minute } => write!(
+                f,
+                "Hour {hour} of the entry at minute {minute} is outside of the day in {file:?}"
+            ),
+            Problem::MinuteOutOfRange { file, hour, minute } => {
+                write!(f, "Minute {minute} above 59 at hour {hour} in {file:?}")
+            }
+            Problem::MetricOutOfRange {
+                file,
+                hour,
+                minute,
+                metric_id,
+                value,
+            } => write!(
+                f,
+                "Value {value} of metric {metric_id} out of range at hour {hour}, minute {minute} in {file:?}"
+            ),
+            Problem::UnknownMetricId {
+                file,
+                hour,
+                minute,
+                metric_id,
+            } => write!(
+                f,
+                "Unknown metric id {metric_id} at hour {hour}, minute {minute} in {file:?}"
+            ),
+            Problem::UnknownTagId {
+                file,
+                hour,
+                minute,
+                tag_id,
+            } => write!(
+                f,
+                "Unknown tag id {tag_id} at hour {hour}, minute {minute} in {file:?}"
+            ),
+            Problem::DuplicateTag {
+                file,
+                hour,
+                minute,
+                tag_id,
+            } => write!(
+                f,
+                "Duplicate tag id {tag_id} at hour {hour}, minute {minute} in {file:?}"
+            ),
+            Problem::CorruptedTagsFile(reason) => write!(f, "Corrupted tags file: {reason}"),
+            Problem::CorruptedMetricsFile(reason) => {
+                write!(f, "Corrupted metrics file: {reason}")
+            }
+            Problem::ReclaimedTagId(tag_id) => {
+                write!(f, "Tag id {tag_id} is in use but listed as reclaimed")
+            }
+            Problem::CorruptedStatSumsFile(file) => write!(f, "Corrupted stat sums file: {file:?}"),
+            Problem::StatSumsMismatch(file) => {
+                write!(f, "Stat sums do not match the data: {file:?}")
+            }
+            Problem::StaleCache(file) => write!(f, "Stale cache: {file:?}"),
+            Problem::LeftoverTempFile(file) => write!(f, "Leftover temporary file: {file:?}"),
+        }
+    }
+}
+
+/// The result of `DataBase::check`, listing every problem found in the database.
+#[derive(Debug, Default)]
+pub struct CheckReport {
+    pub problems: Vec<Problem>,
+}
+
+impl CheckReport {
+    /// Returns true if no problems were found.
+    pub fn is_ok(&self) -> bool {
+        self.problems.is_empty()
+    }
+}
+
+/// Lists one problem per line.
+impl fmt::Display for CheckReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for problem in &self.problems {
+            writeln!(f, "{problem}")?;
+        }
+        Ok(())
+    }
+}
+
+//
+
+//
+
+/// Checks the database at `db_path` without modifying it. See `DataBase::check`.
+pub(crate) fn check(
+    db_path: &DataBasePath,
+    settings: &Settings,
+    threads: usize,
+) -> Result<CheckReport> {
+    let mut report = CheckReport::default();
+
+    let tag_list = match TagList::from_file(db_path) {
+        Ok(tag_list) => Some(tag_list),
+        Err(TagsError::CorruptedTagsFile(reason)) => {
+            report.problems.push(Problem::CorruptedTagsFile(reason));
+            None
+        }
+        Err(error) => return Err(error.into()),
+    };
+    if let Some(tag_list) = &tag_list {
+        let mut reclaimed_ids = tag_list.reclaimed_ids().to_vec();
+        reclaimed_ids.sort_unstable();
+        reclaimed_ids.dedup();
+        for tag_id in reclaimed_ids {
+            if tag_list.tag_exists(tag_id) {
+                report.problems.push(Problem::ReclaimedTagId(tag_id));
+            }
+        }
+    }
+
+    let metric_list = match MetricList::from_file(db_path) {
+        Ok(metric_list) => Some(metric_list),
+        Err(MetricsError::CorruptedMetricsFile(reason)) => {
+            report.problems.push(Problem::CorruptedMetricsFile(reason));
+            None
+        }
+        Err(error) => return Err(error.into()),
+    };
+
+    check_data_files(
+        db_path,
+        settings,
+        tag_list.as_ref(),
+        metric_list.as_ref(),
+        &mut report,
+    )?;
+    check_caches_and_stat_sums(db_path, threads, &mut report)?;
+
+    for file in db_path.storage().walk_files(db_path.root())? {
+        if file.extension() == Some(OsStr::new("tmp")) {
+            report.problems.push(Problem::LeftoverTempFile(file));
+        }
+    }
+
+    if report.is_ok() {
+        log::info!(
+            "Checked the database at {:?}. No problems found.",
+            db_path.root()
+        );
+    } else {
+        log::warn!(
+            "Checked the database at {:?}. Found {} problems.",
+            db_path.root(),
+            report.problems.len()
+        );
+    }
+    Ok(report)
+}
+
+//
+
+//
+
+/// Reads every data file, reporting files that can't be read and entries with invalid values.
+/// Tags are only checked against the tags file if `tag_list` is provided, and metric values are
+/// only checked against the metrics file if `metric_list` is provided.
+fn check_data_files(
+    db_path: &DataBasePath,
+    settings: &Settings,
+    tag_list: Option<&TagList>,
+    metric_list: Option<&MetricList>,
+    report: &mut CheckReport,
+) -> Result<()> {
+    let storage = db_path.storage();
+    if !storage.exists(&db_path.data()) {
+        return Ok(());
+    }
+
+    for file in storage.walk_files(&db_path.data())? {
+        if !DataFile::is_data_file(storage, &file) {
+            continue;
+        }
+
+        let data_file = match DataFile::read_from_file(db_path, &file) {
+            Ok(data_file) => data_file,
+            Err(data_entry::Error::CorruptedDataFile) => {
+                report.problems.push(Problem::CorruptedDataFile(file));
+                continue;
+            }
+            Err(data_entry::Error::MisplacedDataFile) => {
+                report.problems.push(Problem::MisplacedDataFile(file));
+                continue;
+            }
+            Err(error) => return Err(error.into()),
+        };
+
+        let mut entries: Vec<&DataEntry> = data_file.entries().values().collect();
+        entries.sort_by_key(|entry| entry.time());
+        for entry in entries {
+            check_entry(&file, entry, settings, tag_list, metric_list, report);
+        }
+    }
+    Ok(())
+}
+
+/// Reports every invalid value in `entry`.
+fn check_entry(
+    file: &Path,
+    entry: &DataEntry,
+    settings: &Settings,
+    tag_list: Option<&TagList>,
+    metric_list: Option<&MetricList>,
+    report: &mut CheckReport,
+) {
+    let (hour, minute) = entry.time();
+    // Databases opened read-only might still store wall-clock hours.
+    let valid_hour = if settings.compensated_hours() {
+        DataEntry::is_valid_hour(hour, settings.day_switch_offset)
+    } else {
+        (0..24).contains(&hour)
+    };
+    if !valid_hour {
+        report.problems.push(Problem::HourOutOfRange {
+            file: file.to_path_buf(),
+            hour,
+            minute,
+        });
+    }
+    if !DataEntry::is_valid_minute(entry.minute) {
+        report.problems.push(Problem::MinuteOutOfRange {
+            file: file.to_path_buf(),
+            hour,
+            minute,
+        });
+    }
+
+    if let Some(metric_list) = metric_list {
+        for (&metric_id, &value) in &entry.metrics {
+            match metric_list.get(metric_id) {
+                Ok(metric) if metric.contains(value) => {}
+                Ok(_) => report.problems.push(Problem::MetricOutOfRange {
+                    file: file.to_path_buf(),
+                    hour,
+                    minute,
+                    metric_id,
+                    value,
+                }),
+                Err(_) => report.problems.push(Problem::UnknownMetricId {
+                    file: file.to_path_buf(),
+                    hour,
+                    minute,
+                    metric_id,
+                }),
+            }
+        }
+    }
+
+    let mut seen_tags = Vec::new();
+    for &tag_id in &entry.tags {
+        if seen_tags.contains(&tag_id) {
+            report.problems.push(Problem::DuplicateTag {
+                file: file.to_path_buf(),
+                hour,
+                minute,
+                tag_id,
+            });
+            continue;
+        }
+        seen_tags.push(tag_id);
+
+        if tag_list.is_some_and(|tag_list| !tag_list.tag_exists(tag_id)) {
+            report.problems.push(Problem::UnknownTagId {
+                file: file.to_path_buf(),
+                hour,
+                minute,
+                tag_id,
+            });
+        }
+    }
+}
+
+//
+
+//
+
+/// Compares the caches and stat sums with those a regeneration would write.
+fn check_caches_and_stat_sums(
+    db_path: &DataBasePath,
+    threads: usize,
+    report: &mut CheckReport,
+) -> Result<()> {
+    let storage = db_path.storage();
+    let regenerated = regeneration::read_database(db_path, threads, &TaskProgress::default())?;
+
+    let month_caches = regenerated
+        .months
+        .iter()
+        .map(|(month_path, day_overviews)| {
+            (
+                month_path.join("month_cache.txt"),
+                cache_handling::month_cache_content(day_overviews),
+            )
+        });
+    let year_caches = regenerated
+        .years
+        .iter()
+        .map(|(year_path, month_overviews)| {
+            (
+                year_path.join("year_cache.txt"),
+                cache_handling::year_cache_content(month_overviews),
+            )
+        });
+    for (cache_path, expected) in month_caches.chain(year_caches) {
+        let is_current = storage.exists(&cache_path)
+            && cache_lines(&storage.read_to_string(&cache_path)?) == cache_lines(&expected);
+        if !is_current {
+            report.problems.push(Problem::StaleCache(cache_path));
+        }
+    }
+
+    let mut expected_sums: HashMap<PathBuf, Tags> = regenerated
+        .tag_sums
+        .into_files(db_path)
+        .into_iter()
+        .collect();
+    let mut sum_files: Vec<PathBuf> = expected_sums.keys().cloned().collect();
+    if storage.exists(&db_path.stat_sums()) {
+        sum_files.extend(
+            storage
+                .walk_files(&db_path.stat_sums())?
+                .into_iter()
+                .filter(|file| file.extension() == Some(OsStr::new("txt"))),
+        );
+    }
+    sum_files.sort();
+    sum_files.dedup();
+
+    for sum_file in sum_files {
+        let expected = expected_sums.remove(&sum_file).unwrap_or_default();
+        match Tags::read(storage, &sum_file) {
+            Ok(tags) if tags.matches(&expected) => {}
+            Ok(_) => report.problems.push(Problem::StatSumsMismatch(sum_file)),
+            Err(StatSumsError::Io(error)) => return Err(error.into()),
+            Err(_) => report
+                .problems
+                .push(Problem::CorruptedStatSumsFile(sum_file)),
+        }
+    }
+    Ok(())
+}
+
+/// Returns the lines of a cache file in a comparable form. Cache files written by older versions
+/// list the tags of a line in any order.
+fn cache_lines(content: &str) -> Vec<String> {
+    let mut lines: Vec<String> = content
+        .lines()
+        .map(|line| match line.rsplit_once('|') {
+            Some((scores, tags)) => {
+                let mut tags: Vec<&str> = tags.split_whitespace().collect();
+                tags.sort_unstable();
+                format!("{}| {}", scores, tags.join(" "))
+            }
+            None => line.to_string(),
+        })
+        .collect();
+    lines.sort();
+    lines
+}
+
+//
+
+//
+
+#[cfg(test)]
+mod tests {
+    use time::Month;
+
+    use super::Problem;
+    use crate::test_utils::{create_database, data_file_path, date};
+
+    #[test]
+    fn report_names_the_hour_and_minute_of_each_invalid_entry() {
+        let database = create_database("check_report", 0);
+        database.add_tag("a".to_string()).unwrap();
+        let file = data_file_path(&database, date(2024, Month::January, 1));
+        let storage = database.path().storage();
+        storage.create_dir_all(file.parent().unwrap()).unwrap();
+        #[rustfmt::skip]
+        let bytes = [
+            0xFF, 0xFE,
+            // 30:15 is outside of the day and holds tag 1 twice and the unknown tag 9.
+            30, 15, 1, 0, 0, 0, 60, 0, 1, 0, 1, 0, 9, 0xFF, 0xFF,
+            // 08:45 holds a mental score above 100 and a value of the unknown metric 77.
+            8, 45, 2, 0, 0, 1, 244, 0, 77, 0, 5, 0xFF, 0xFF,
+        ];
+        storage.write(&file, &bytes).unwrap();
+
+        let report = database.check().unwrap();
+        let expected = [
+            Problem::HourOutOfRange {
+                file: file.clone(),
+                hour: 30,
+                minute: 15,
+            },
+            Problem::DuplicateTag {
+                file: file.clone(),
+                hour: 30,
+                minute: 15,
+                tag_id: 1,
+            },
+            Problem::UnknownTagId {
+                file: file.clone(),
+                hour: 30,
+                minute: 15,
+                tag_id: 9,
+            },
+            Problem::MetricOutOfRange {
+                file: file.clone(),
+                hour: 8,
+                minute: 45,
+                metric_id: 0,
+                value: 500,
+            },
+            Problem::UnknownMetricId {
+                file: file.clone(),
+                hour: 8,
+                minute: 45,
+                metric_id: 77,
+            },
+        ];
+        for problem in &expected {
+            assert!(report.problems.contains(problem), "{problem}");
+        }
+
+        let text = report.to_string();
+        assert!(text.contains(&format!(
+            "Duplicate tag id 1 at hour 30, minute 15 in {file:?}"
+        )));
+        assert!(text.contains(&format!(
+            "Value 500 of metric 0 out of range at hour 8, minute 45 in {file:?}"
+        )));
+        assert!(text.contains("Hour 30 of the entry at minute 15 is outside of the day"));
+    }
+}
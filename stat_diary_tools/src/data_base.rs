@@ -1,35 +1,103 @@
 use std::{
+    collections::HashMap,
+    ffi::OsStr,
+    fmt,
     fs::File,
+    hash::{DefaultHasher, Hash, Hasher},
     io::{self, BufWriter, Write},
     path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::SystemTime,
 };
 
 use image::ImageError;
 use log::{error, info};
 use time::{Date, Duration, OffsetDateTime, PrimitiveDateTime, Time, UtcOffset};
-use walkdir::WalkDir;
 
 use crate::{
     backup::{self, BackupImageError},
-    cache_handling,
+    cache_handling::{self, CacheError},
+    check,
     data_entry::{self, DataEntry, DataFile},
+    day_switch_offset,
     db_path::{DataBasePath, DataBasePathError},
-    db_status::{ActiveTask, DBStatus, DBStatusError},
+    db_status::{DBStatus, DBStatusError, WriterLock},
     diary_file::{self, DiaryEntry, DiaryFile},
+    entry_range,
+    history::{ChangedEntry, HistoryError, Operation, UndoRecord},
+    journal::{Journal, JournalStep},
     logger::DBLogger,
+    metrics::{self, MetricList, MetricsError, METRICSFILENAME},
+    migrations::{self, CURRENT_SCHEMA_VERSION, OLDEST_READABLE_SCHEMA_VERSION},
+    notifications::Notifier,
+    regeneration::{self, RegenerationError, RegenerationTargets},
+    repair,
     settings_file::{self, Settings},
     stat_sums::{self, StatSumsError},
-    tags::{TagList, TagsError},
-    update_database, DATAFILEEXTENSION, DIARYFILEEXTENSION,
+    storage::{FileSystemStorage, Storage},
+    tags::{TagList, TagsError, TagsSnapshot},
+    task_progress::{Cancelled, TaskProgress},
+    DATAFILEEXTENSION, DIARYFILEEXTENSION,
 };
 
 pub struct DataBase {
     path: DataBasePath,
     settings: Settings,
+    /// The state of the settings file when `settings` was read from it.
+    settings_file_state: Mutex<Option<FileState>>,
+    tag_list: Mutex<Option<CachedTagList>>,
+    metric_list: Mutex<Option<CachedMetricList>>,
+    lock_timeout: Option<std::time::Duration>,
+    /// The number of threads used to regenerate caches and stat sums, where 0 means one thread
+    /// per available cpu.
+    regeneration_threads: usize,
+    read_only: bool,
+    notifier: Notifier,
 }
 
+/// A `TagList` kept in memory between operations together with the state of the tags file it
+/// was read from. If the tags file changes on disk the cached list is discarded and reloaded.
+struct CachedTagList {
+    tag_list: TagList,
+    file_state: Option<FileState>,
+}
+
+/// A `MetricList` kept in memory between operations, like `CachedTagList`. The state is `None`
+/// while the database has no metrics file and holds the built-in metrics.
+struct CachedMetricList {
+    metric_list: MetricList,
+    file_state: Option<FileState>,
+}
+
+/// The modification time and length of a file, used to detect when a file read into memory has
+/// been changed. See `file_changed`.
+///
+/// A rewrite keeping the length within the resolution of the file system clock keeps the
+/// modification time as well. The content is therefore hashed too, but only while the file was
+/// modified within `AMBIGUOUSMODIFICATIONWINDOW`, since any later rewrite changes the
+/// modification time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct FileState {
+    modified: SystemTime,
+    len: u64,
+    hash: Option<u64>,
+}
+
+/// How recently a file has to have been modified for a rewrite to possibly keep its modification
+/// time. Covers the 2 second resolution of the coarsest common file systems.
+const AMBIGUOUSMODIFICATIONWINDOW: std::time::Duration = std::time::Duration::from_secs(2);
+
+const SETTINGSFILENAME: &str = "db_settings.txt";
+const TAGSFILENAME: &str = "tags.txt";
+
 type Result<T> = std::result::Result<T, Error>;
 
+pub use crate::check::{CheckReport, Problem};
+pub use crate::db_status::{ActiveTask, LockInfo, LockOwner, OwnerStatus};
+pub use crate::metrics::{Metric, MENTALSCORE, PHYSICALSCORE};
+pub use crate::notifications::{ChangeEvent, ChangeOrigin, SubscriptionId};
+pub use crate::repair::{RepairReport, RepairedFile};
+
 // Public functions
 impl DataBase {
     pub fn init_logger(logfile_path: PathBuf) -> Result<()> {
@@ -51,9 +119,162 @@ impl DataBase {
     /// * `db_path` does not lead to a existing directory.
     /// * `db_path` leads to a directory, but the directory is missing a `.db_marker` file
     pub fn load(db_path: PathBuf) -> Result<DataBase> {
-        let path = DataBasePath::new(db_path)?;
-        let settings = Settings::load(&path)?;
-        Ok(DataBase { path, settings })
+        DataBase::load_with_storage(db_path, Arc::new(FileSystemStorage))
+    }
+
+    /// Same as `load`, but the database at `db_path` is read from and written to the provided
+    /// `storage` instead of the file system.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error in the following situations, but is not limited to just
+    /// these cases:
+    ///
+    /// * `db_path` does not lead to a existing directory in `storage`.
+    /// * `db_path` leads to a directory, but the directory is missing a `.db_marker` file
+    /// * The database is stored in a newer schema version than this library supports.
+    /// * The database is stored in an older schema version and could not be migrated.
+    pub fn load_with_storage(db_path: PathBuf, storage: Arc<dyn Storage>) -> Result<DataBase> {
+        let mut data_base = DataBase::open(DataBasePath::with_storage(db_path, storage)?, false)?;
+
+        if data_base.settings.schema_version < CURRENT_SCHEMA_VERSION {
+            data_base.migrate()?;
+        }
+
+        Ok(data_base)
+    }
+
+    /// Same as `load`, but a database stored in an older schema version is not migrated.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error in the following situations, but is not limited to just
+    /// these cases:
+    ///
+    /// * `db_path` does not lead to a existing directory.
+    /// * `db_path` leads to a directory, but the directory is missing a `.db_marker` file
+    /// * The database is not stored in the current schema version.
+    pub fn load_without_migrating(db_path: PathBuf) -> Result<DataBase> {
+        let data_base = DataBase::open(DataBasePath::new(db_path)?, false)?;
+
+        let schema_version = data_base.settings.schema_version;
+        if schema_version < CURRENT_SCHEMA_VERSION {
+            error!(
+                "The database is stored in schema version {} and has to be migrated to {}!",
+                schema_version, CURRENT_SCHEMA_VERSION
+            );
+            return Err(Error::with_kind(ErrorKind::OutdatedSchema(schema_version)));
+        }
+
+        Ok(data_base)
+    }
+
+    /// Opens the database at `db_path` for browsing only. Every method that would modify the
+    /// database returns a `ReadOnly` error instead.
+    ///
+    /// Nothing is written to the database folder, not even lock files, making it safe to open a
+    /// database on a mounted backup or an extracted image. A database from an older version that
+    /// stores wall-clock hours is not migrated, its hours are compensated in memory when read.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error in the following situations, but is not limited to just
+    /// these cases:
+    ///
+    /// * `db_path` does not lead to a existing directory.
+    /// * `db_path` leads to a directory, but the directory is missing a `.db_marker` file
+    /// * The database is stored in a schema version that can't be read without migrating it.
+    pub fn open_read_only(db_path: PathBuf) -> Result<DataBase> {
+        let data_base = DataBase::open(DataBasePath::new(db_path)?, true)?;
+
+        let schema_version = data_base.settings.schema_version;
+        if schema_version < OLDEST_READABLE_SCHEMA_VERSION {
+            error!(
+                "The database is stored in schema version {} and can't be read without migrating it!",
+                schema_version
+            );
+            return Err(Error::with_kind(ErrorKind::OutdatedSchema(schema_version)));
+        }
+
+        Ok(data_base)
+    }
+
+    /// Creates a new empty database at `db_path` using the provided `day_switch_offset`, and
+    /// returns it loaded.
+    ///
+    /// `db_path` is created if it does not exist.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error in the following situations, but is not limited to just
+    /// these cases:
+    ///
+    /// * `day_switch_offset` is outside of the acceptable -12 to 12 range.
+    /// * `db_path` already contains a database.
+    /// * `db_path` leads to a directory that is not empty.
+    /// * An io error occured.
+    pub fn create(db_path: PathBuf, day_switch_offset: i8) -> Result<DataBase> {
+        DataBase::create_with_storage(db_path, day_switch_offset, Arc::new(FileSystemStorage))
+    }
+
+    /// Same as `create`, but the database is created in and accessed through the provided
+    /// `storage` instead of the file system. Creating a database in a `MemoryStorage` results in
+    /// a database that is never written to disk.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error in the following situations, but is not limited to just
+    /// these cases:
+    ///
+    /// * `day_switch_offset` is outside of the acceptable -12 to 12 range.
+    /// * `db_path` already contains a database.
+    /// * `db_path` leads to a directory that is not empty.
+    /// * An io error occured.
+    pub fn create_with_storage(
+        db_path: PathBuf,
+        day_switch_offset: i8,
+        storage: Arc<dyn Storage>,
+    ) -> Result<DataBase> {
+        info!(
+            "Attempting to create a new database at path: {:?} with day_switch_offset: {}",
+            db_path, day_switch_offset
+        );
+        if !(-12..=12).contains(&day_switch_offset) {
+            error!("Attempted to create a database with a day_switch_offset outside of -12..=12!");
+            return Err(Error::with_kind(ErrorKind::OffsetOutOfRange));
+        }
+
+        if storage.exists(&db_path.join(".db_marker")) {
+            error!("A database already exists at path: {:?}", db_path);
+            return Err(Error::with_kind(ErrorKind::DataBaseAlreadyExists));
+        }
+
+        storage.create_dir_all(&db_path)?;
+        if !storage.list(&db_path)?.is_empty() {
+            error!(
+                "Can not create a database in the non-empty directory: {:?}",
+                db_path
+            );
+            return Err(Error::with_kind(ErrorKind::DirectoryNotEmpty));
+        }
+
+        storage.create_dir_all(&db_path.join("data"))?;
+        storage.create_dir_all(&db_path.join("stat_sums"))?;
+        storage.write(&db_path.join("tags.txt"), &[])?;
+        metrics::write_built_in_metrics(storage.as_ref(), &db_path)?;
+
+        let settings = format!(
+            "day_switch_offset={}\nschema_version={}\n",
+            day_switch_offset, CURRENT_SCHEMA_VERSION
+        );
+        storage.write(&db_path.join("db_settings.txt"), settings.as_bytes())?;
+
+        // The marker is created last, so a interrupted creation is never mistaken for a database.
+        storage.write(&db_path.join(".db_marker"), &[])?;
+
+        info!("Successfully created a new database at path: {:?}", db_path);
+
+        DataBase::load_with_storage(db_path, storage)
     }
 
     /// Attempts to extract a database from `img_path` into `db_path`.
@@ -86,50 +307,62 @@ impl DataBase {
     /// Attempts to resume any unfinished task.
     /// Will also return `Ok` if no task was active.  
     ///
+    /// Only a task left behind by a process that no longer exists, or a task cancelled by the
+    /// current process, is resumed. See `LockInfo::is_stale`. A lock whose owner can't be checked
+    /// has to be removed using `break_lock` instead.
+    ///
     /// # Errors
     ///
     /// This function will return an error in the following situations, but is not limited to just
     /// these cases:
     ///
+    /// * The task is still held by a running process. (Returns `DataBaseBusy`.)
     /// * The active task is unknown. (The task id does not match any known tasks.)
     /// * The active task is missing data. (The task id is known but the required data is missing.)
     /// * The task is resumed but encountered an error.
     pub fn resume_task(&self) -> Result<()> {
-        let activate_error = match DBStatus::lock(&self.path, ActiveTask::None) {
-            Ok(db_status) => {
-                db_status.unlock();
-                return Ok(());
-            }
-            Err(db_error) => db_error,
-        };
+        self.resume_task_with_progress(&TaskProgress::default())
+    }
 
-        let DBStatusError::DataBaseBusy(active_task, db_status) = activate_error else {
-            return Err(activate_error.into());
+    /// Same as `resume_task`, but reports the progress of the resumed task to `progress` and
+    /// stops if it is cancelled. A cancelled task stays active and can be resumed again.
+    pub fn resume_task_with_progress(&self, progress: &TaskProgress) -> Result<()> {
+        self.check_writable()?;
+        let Some((active_task, db_status)) = DBStatus::take_over(&self.path)? else {
+            return Ok(());
         };
 
-        match active_task {
-            ActiveTask::None => {}
-            ActiveTask::RegenerateCaches => cache_handling::regenerate_caches(&self.path)?,
-            ActiveTask::RegenerateTagSums => stat_sums::regenerate_tag_sums(&self.path)?,
+        let result = match active_task {
+            ActiveTask::None => Ok(()),
+            ActiveTask::RegenerateCaches => self.intr_regen_caches(progress),
+            ActiveTask::RegenerateTagSums => self.intr_regen_tag_sums(progress),
+
+            ActiveTask::MergeTags(tag_1, tag_2) => self
+                .intr_merge_tags(tag_1, tag_2, progress)
+                .inspect_err(|e| error!("merge_tags() failed due to: {e:?}")),
+            ActiveTask::RenameTag(old_name, new_name) => self
+                .intr_rename_tag(old_name, new_name)
+                .inspect_err(|e| error!("rename_tag() failed due to: {e:?}")),
+            ActiveTask::RemoveTag(tag_id) => self
+                .intr_remove_tag(tag_id, progress)
+                .inspect_err(|e| error!("remove_tag() failed due to: {e:?}")),
+            ActiveTask::Repair => self
+                .intr_repair(progress)
+                .map(|_| ())
+                .inspect_err(|e| error!("repair() failed due to: {e:?}")),
+            ActiveTask::Undo(record_number) => self
+                .intr_undo(record_number, progress)
+                .inspect_err(|e| error!("undo_last() failed due to: {e:?}")),
+        };
 
-            ActiveTask::MergeTags(tag_1, tag_2) => {
-                if let Err(e) = self.intr_merge_tags(tag_1, tag_2) {
-                    error!("merge_tags() failed due to: {e:?}");
-                    return Err(e);
-                }
-            }
-            ActiveTask::RenameTag(old_name, new_name) => {
-                if let Err(e) = self.intr_rename_tag(old_name, new_name) {
-                    error!("rename_tag() failed due to: {e:?}");
-                    return Err(e);
-                }
-            }
-            ActiveTask::RemoveTag(tag_id) => {
-                if let Err(e) = self.intr_remove_tag(tag_id) {
-                    error!("remove_tag() failed due to: {e:?}");
-                    return Err(e);
-                }
+        // Unlike the other tasks a failed resume keeps the database locked, since the task is
+        // still unfinished.
+        if let Err(error) = result {
+            if matches!(error.kind, ErrorKind::Cancelled) {
+                info!("The resumed task was cancelled! It can be resumed again later.");
             }
+            db_status.suspend();
+            return Err(error);
         }
 
         db_status.unlock();
@@ -150,15 +383,16 @@ impl DataBase {
     /// If it encounters a unknown or corrupted file a warning or error is logged. The function
     /// will then continue on skipping the bad file.
     pub fn regen_caches(&self) -> Result<()> {
-        log::info!("Attempting to regenerate caches...");
-        let db_status = DBStatus::lock(&self.path, ActiveTask::RegenerateCaches)?;
+        self.regen_caches_with_progress(&TaskProgress::default())
+    }
 
-        if let Err(error) = cache_handling::regenerate_caches(&self.path) {
-            db_status.unlock();
-            return Err(error.into());
-        }
+    /// Same as `regen_caches`, but reports the progress to `progress` and stops if it is
+    /// cancelled. A cancelled regeneration leaves the database busy until `resume_task` is called.
+    pub fn regen_caches_with_progress(&self, progress: &TaskProgress) -> Result<()> {
+        log::info!("Attempting to regenerate caches...");
+        let db_status = self.lock(ActiveTask::RegenerateCaches)?;
 
-        db_status.unlock();
+        finish_task(db_status, self.intr_regen_caches(progress))?;
         log::info!("Finished regenerating caches!");
         Ok(())
     }
@@ -173,19 +407,56 @@ impl DataBase {
     /// * The database is busy.
     /// * An io error occured.
     pub fn regen_tag_sums(&self) -> Result<()> {
-        log::info!("Attempting to regenerate tag sums...");
-        let db_status = DBStatus::lock(&self.path, ActiveTask::RegenerateTagSums)?;
+        self.regen_tag_sums_with_progress(&TaskProgress::default())
+    }
 
-        if let Err(error) = stat_sums::regenerate_tag_sums(&self.path) {
-            db_status.unlock();
-            return Err(error.into());
-        }
+    /// Same as `regen_tag_sums`, but reports the progress to `progress` and stops if it is
+    /// cancelled. A cancelled regeneration leaves the database busy until `resume_task` is called.
+    pub fn regen_tag_sums_with_progress(&self, progress: &TaskProgress) -> Result<()> {
+        log::info!("Attempting to regenerate tag sums...");
+        let db_status = self.lock(ActiveTask::RegenerateTagSums)?;
 
-        db_status.unlock();
+        finish_task(db_status, self.intr_regen_tag_sums(progress))?;
         log::info!("Finished regenerating tag sums!");
         Ok(())
     }
 
+    /// Repairs every data file that was cut short, for example by a interrupted write or a damaged
+    /// disk. Every complete entry before the damage is salvaged and the data file is rewritten
+    /// with those entries, or removed if no entry could be salvaged. The original file is moved to
+    /// the `quarantine` folder of the database, and the repair is recorded in
+    /// `quarantine/repairs.txt`. Afterwards the stat sums and caches
+    /// are regenerated to match the repaired data files.
+    ///
+    /// Returns a report listing every repaired data file.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error in the following situations, but is not limited to just
+    /// these cases:
+    ///
+    /// * The database is busy.
+    /// * The database was opened as read-only.
+    /// * An io error occured.
+    pub fn repair(&self) -> Result<RepairReport> {
+        self.repair_with_progress(&TaskProgress::default())
+    }
+
+    /// Same as `repair`, but reports the progress of the regeneration to `progress` and stops if
+    /// it is cancelled. A cancelled repair leaves the database busy until `resume_task` is called.
+    pub fn repair_with_progress(&self, progress: &TaskProgress) -> Result<RepairReport> {
+        log::info!("Attempting to repair the database...");
+        let db_status = self.lock(ActiveTask::Repair)?;
+
+        let mut report = RepairReport::default();
+        let result = self
+            .intr_repair(progress)
+            .map(|repair_report| report = repair_report);
+        finish_task(db_status, result)?;
+        log::info!("Finished repairing the database!");
+        Ok(report)
+    }
+
     /// Merges `tag_1` into `tag_2`. Any existing reference to `tag_1` will be changed to `tag_2` if
     /// `tag_2` doesn't already exist in that context.
     ///
@@ -198,21 +469,30 @@ impl DataBase {
     /// * An io error occured.
     /// * `tag_1` or `tag_2` does not exist.
     pub fn merge_tags(&self, tag_1: u16, tag_2: u16) -> Result<()> {
+        self.merge_tags_with_progress(tag_1, tag_2, &TaskProgress::default())
+    }
+
+    /// Same as `merge_tags`, but reports the progress to `progress` and stops if it is cancelled.
+    /// A cancelled merge leaves the database busy until `resume_task` is called.
+    pub fn merge_tags_with_progress(
+        &self,
+        tag_1: u16,
+        tag_2: u16,
+        progress: &TaskProgress,
+    ) -> Result<()> {
         info!(
             "Attempting to merge tag id: {} into tag id: {}",
             tag_1, tag_2
         );
-        let db_status = DBStatus::lock(&self.path, ActiveTask::MergeTags(tag_1, tag_2))?;
+        self.with_tag_list(|tag_list| {
+            tag_list.get_tag(tag_1)?;
+            tag_list.get_tag(tag_2)?;
+            Ok(())
+        })?;
 
-        // TODO Error handling...
-        if let Err(e) = self.intr_merge_tags(tag_1, tag_2) {
-            db_status.unlock();
-            return Err(e);
-        }
+        let db_status = self.lock(ActiveTask::MergeTags(tag_1, tag_2))?;
 
-        db_status.unlock();
-
-        Ok(())
+        finish_task(db_status, self.intr_merge_tags(tag_1, tag_2, progress))
     }
 
     /// Renames `old_tag` to `new_tag`.
@@ -228,10 +508,7 @@ impl DataBase {
     /// * `new_tag` already exists. (Meaning a merge is required instead.)
     pub fn rename_tag(&self, old_tag: String, new_tag: String) -> Result<()> {
         info!("Attempting to rename tag: [{}] to [{}]", old_tag, new_tag);
-        let db_status = DBStatus::lock(
-            &self.path,
-            ActiveTask::RenameTag(old_tag.clone(), new_tag.clone()),
-        )?;
+        let db_status = self.lock(ActiveTask::RenameTag(old_tag.clone(), new_tag.clone()))?;
 
         if let Err(error) = self.intr_rename_tag(old_tag.clone(), new_tag.clone()) {
             db_status.unlock();
@@ -250,13 +527,21 @@ impl DataBase {
     /// This function will return an error in the following situations, but is not limited to just
     /// these cases:
     ///
+    /// * The database is not stored in the file system. (Returns `NotImplemented`.)
     pub fn compress_to_image(&self, target_path: &Path) -> Result<()> {
         // TODO: Create a DBStatus::is_locked() function.
         info!(
             "Attempting to compress the database into a image at path: {:?}",
             target_path
         );
-        DBStatus::lock(&self.path, ActiveTask::None)?.unlock();
+        if !self.path.storage().is_file_system() {
+            error!("Only databases stored in the file system can be compressed to a image!");
+            return Err(Error::with_kind(ErrorKind::NotImplemented));
+        }
+        // A read-only database is never locked, since that would write a lock file.
+        if !self.read_only {
+            self.lock(ActiveTask::None)?.unlock();
+        }
 
         if let Err(error) = backup::compress_database_to_image(&self.path, target_path) {
             return Err(error.into());
@@ -270,29 +555,80 @@ impl DataBase {
         Ok(())
     }
 
-    pub fn upgrade_database(db_path: &Path, current_day_switch_offset: i32) -> Result<i32> {
+    /// Converts the legacy text database at `db_path` into a database by marking it as a
+    /// database of schema version 0, after which loading it runs every migration.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error in the following situations, but is not limited to just
+    /// these cases:
+    ///
+    /// * A migration failed, in which case the database is left unconverted.
+    /// * An io error occured.
+    pub fn upgrade_database(db_path: &Path, current_day_switch_offset: i32) -> Result<()> {
         // Mark the db with a .db_marker to ensure it can be recognised as a db by the load()
         // method.
         File::create(db_path.join(".db_marker"))?;
 
-        let mut writer = BufWriter::new(File::create(db_path.join("db_settings.txt"))?);
+        let mut writer = BufWriter::new(File::create(db_path.join(SETTINGSFILENAME))?);
         writeln!(writer, "day_switch_offset={}", current_day_switch_offset)?;
+        writeln!(writer, "schema_version=0")?;
         writer.flush()?;
 
-        let database = DataBase::load(db_path.to_path_buf())?;
+        DataBase::load(db_path.to_path_buf()).inspect_err(|error| {
+            if let ErrorKind::MigrationFailed(version) = error.kind {
+                log::error!(
+                    "Migration to schema version {} failed when attempting to upgrade the database at path: {:?}",
+                    version,
+                    db_path
+                );
+            }
+        })?;
+        Ok(())
+    }
 
-        if let Err(e) = update_database::temporary_update_database(&database.path) {
-            log::error!(
-                "Encountered error: [{:?}] when attempting to upgrade the database at path: {:?}",
-                e,
-                db_path
-            );
-            return Ok(-1);
-        }
+    /// Returns the schema version the database is stored in.
+    pub fn schema_version(&self) -> u32 {
+        self.settings.schema_version
+    }
 
-        // TODO:
+    /// Changes the day_switch_offset of the database to `new_offset`. Every data and diary entry
+    /// crossing the new day boundary is moved into the day file it belongs to, after which the
+    /// caches and stat sums are regenerated.
+    ///
+    /// The changes are made on a copy of the database which replaces the original once complete,
+    /// meaning the original stays intact if the change fails or is interrupted.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error in the following situations, but is not limited to just
+    /// these cases:
+    ///
+    /// * `new_offset` is outside of the acceptable -12 to 12 range.
+    /// * The database is busy.
+    /// * A copy left behind by an interrupted offset change still exists.
+    /// * A data or diary file is corrupted.
+    /// * An io error occured.
+    pub fn change_day_switch_offset(&mut self, new_offset: i8) -> Result<()> {
+        log::info!(
+            "Attempting to change the day_switch_offset from {} to {}...",
+            self.settings.day_switch_offset,
+            new_offset
+        );
+        self.check_writable()?;
+        {
+            // Every data file might be rewritten, which is not reported entry by entry.
+            let mut changes = self.notifier.local_changes();
+            changes.touch_all();
+            day_switch_offset::change_day_switch_offset(self, new_offset)?;
+        }
 
-        Ok(0)
+        self.reload_settings()?;
+        *self
+            .tag_list
+            .get_mut()
+            .unwrap_or_else(|poisoned| poisoned.into_inner()) = None;
+        Ok(())
     }
 
     /// Returns a `Vec` containing all data files in this database.
@@ -303,45 +639,113 @@ impl DataBase {
     /// these cases:
     ///
     /// * An io error occured.
-    /// * A walkdir error occured.
     ///
-    /// **NOTE**: If a datafile is corrupted it will be skipped. The error is added to the log
-    /// instead of returned by this method.
+    /// **NOTE**: If a datafile is corrupted, misplaced or misnamed it will be skipped. The error is
+    /// added to the log instead of returned by this method.
     pub fn data_files(&self) -> Result<Vec<DataFile>> {
         let mut data_files = Vec::new();
-        for path in WalkDir::new(self.path.data()) {
-            let path = path?;
-            let filepath = path.path();
+        let storage = self.path.storage();
+        for file_path in storage.walk_files(&self.path.data())? {
+            let filepath = file_path.as_path();
 
-            if !DataFile::is_data_file(filepath) {
+            if !DataFile::is_data_file(storage, filepath) {
                 continue;
             }
 
-            let data_file = match DataFile::read_from_file(filepath) {
+            let mut data_file = match DataFile::read_from_file(&self.path, filepath) {
                 Ok(data_file) => data_file,
                 Err(crate::data_entry::Error::CorruptedDataFile) => {
                     error!("Data file [{:?}] is corrupted! Skipping file...", filepath);
                     continue;
                 }
+                Err(crate::data_entry::Error::MisplacedDataFile) => {
+                    error!("Data file [{:?}] is misplaced! Skipping file...", filepath);
+                    continue;
+                }
                 Err(crate::data_entry::Error::Io(io_err)) => {
                     return Err(Error::with_kind(ErrorKind::Io(io_err)))
                 }
                 _ => continue, // Remaining errors can't occur here.
             };
+            if !self.settings.compensated_hours() {
+                data_file.compensate_hours(self.settings.day_switch_offset);
+            }
             data_files.push(data_file);
         }
         Ok(data_files)
     }
 
+    /// Returns all `DataEntry` values stored for the provided `year`, `month`, and `day`, sorted
+    /// by hour. If no data file exists for the date an empty `Vec` is returned.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error in the following situations, but is not limited to just
+    /// these cases:
+    ///
+    /// * The provided date is invalid.
+    /// * The data file for the date is corrupted.
+    /// * An io error occured.
+    pub fn get_data_entries(&self, year: i32, month: u8, day: u8) -> Result<Vec<DataEntry>> {
+        let date = DataBase::parse_date(year, month, day)?;
+        let mut datafile = DataFile::open_data_file(&self.path, &self.day_file_path(date))?;
+        if !self.settings.compensated_hours() {
+            datafile.compensate_hours(self.settings.day_switch_offset);
+        }
+
+        let mut entries: Vec<DataEntry> = datafile.entries().values().cloned().collect();
+        entries.sort_by_key(|entry| entry.time());
+        Ok(entries)
+    }
+
+    /// Returns an iterator over every `DataEntry` in the days from `start` to `end`, both
+    /// inclusive, in chronological order. Each entry is paired with the date of the day it belongs
+    /// to.
+    ///
+    /// The days follow the day_switch_offset of the database, so with an offset of +4 an entry at
+    /// 02:00 belongs to the previous date with the compensated hour 26. Use
+    /// `DataEntry::wall_clock_datetime` to get the actual time of an entry.
+    ///
+    /// Only the year and month folders overlapping the range are visited. Corrupted data files are
+    /// skipped, and the problem is logged instead.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error in the following situations, but is not limited to just
+    /// these cases:
+    ///
+    /// * An io error occured while listing the data folders.
+    pub fn entries_between(
+        &self,
+        start: Date,
+        end: Date,
+    ) -> Result<impl Iterator<Item = (Date, DataEntry)>> {
+        let legacy_offset =
+            (!self.settings.compensated_hours()).then_some(self.settings.day_switch_offset);
+        Ok(entry_range::entries_between(
+            &self.path,
+            start,
+            end,
+            legacy_offset,
+        )?)
+    }
+
     /// Inserts the provided `DataEntry` in the `DataFile` for the provided date, overwriting any
-    /// existing entry at the specific hour.
+    /// existing entry at the specific hour and minute. The stat sums and caches are updated to
+    /// include the new entry.
+    ///
+    /// The hour of `new_entry` must be offset-compensated. See `DataEntry::compensate_hour`.
     ///
     /// # Errors
     ///
     /// This function will return an error in the following situations, but is not limited to just
     /// these cases:
     ///
+    /// * The database is busy.
     /// * The provided date is invalid.
+    /// * The hour of `new_entry` is outside of the day, or its minute is above 59.
+    /// * A metric of `new_entry` does not exist, or its value is outside of the range of the
+    ///   metric.
     pub fn insert_data_entry(
         &self,
         year: i32,
@@ -349,36 +753,63 @@ impl DataBase {
         day: u8,
         new_entry: DataEntry,
     ) -> Result<()> {
-        let datetime = self.parse_compensated_datetime(year, month, day, new_entry.hour)?;
-        let date_path = self.get_date_file_path(datetime)?;
-        let mut datafile = DataFile::open_data_file(&date_path)?;
+        let date = DataBase::parse_date(year, month, day)?;
+        self.validate_time(new_entry.hour, new_entry.minute)?;
+        self.with_metric_list(|metric_list| Ok(metric_list.validate(&new_entry)?))?;
+        let _writer_lock = self.lock_shared()?;
+        let mut changes = self.notifier.local_changes();
+        let mut datafile = DataFile::open_data_file(&self.path, &self.day_file_path(date))?;
+        changes.touch_data_file(datafile.file_path());
         let prev_entry = datafile.overwrite_entry(new_entry.clone());
         datafile.save()?;
 
+        changes.push(match prev_entry {
+            Some(_) => ChangeEvent::EntryOverwritten {
+                date,
+                hour: new_entry.hour,
+                minute: new_entry.minute,
+            },
+            None => ChangeEvent::EntryAdded {
+                date,
+                hour: new_entry.hour,
+                minute: new_entry.minute,
+            },
+        });
         if let Some(prev_entry) = prev_entry {
             stat_sums::remove_tags(
                 &self.path,
-                datetime.date(),
-                prev_entry.hour,
+                date,
+                prev_entry.wall_clock_hour(),
                 &prev_entry.tags,
             )?;
         }
-        stat_sums::add_tags(&self.path, datetime.date(), new_entry.hour, &new_entry.tags)?;
+        stat_sums::add_tags(
+            &self.path,
+            date,
+            new_entry.wall_clock_hour(),
+            &new_entry.tags,
+        )?;
 
-        // TODO: Update caches as well.
+        cache_handling::update_day_caches(&self.path, datafile.file_path())?;
         Ok(())
     }
 
     /// Adds the provided `DataEntry` to the `DataFile` matching the provided `year`, `month`, and
-    /// `day`.
+    /// `day`. The stat sums and caches are updated to include the new entry.
+    ///
+    /// The hour of `new_entry` must be offset-compensated. See `DataEntry::compensate_hour`.
     ///
     /// # Errors
     ///
     /// This function will return an error in the following situations, but is not limited to just
     /// these cases:
     ///
+    /// * The database is busy.
     /// * The provided date is invalid.
-    /// * A `DataEntry` already exists at the hour specified.
+    /// * The hour of `new_entry` is outside of the day, or its minute is above 59.
+    /// * A metric of `new_entry` does not exist, or its value is outside of the range of the
+    ///   metric.
+    /// * A `DataEntry` already exists at the hour and minute specified.
     pub fn add_data_entry(
         &self,
         year: i32,
@@ -386,15 +817,78 @@ impl DataBase {
         day: u8,
         new_entry: DataEntry,
     ) -> Result<()> {
-        let datetime = self.parse_compensated_datetime(year, month, day, new_entry.hour)?;
-        let date_path = self.get_date_file_path(datetime)?;
-        let mut datafile = DataFile::open_data_file(&date_path)?;
+        let date = DataBase::parse_date(year, month, day)?;
+        self.validate_time(new_entry.hour, new_entry.minute)?;
+        self.with_metric_list(|metric_list| Ok(metric_list.validate(&new_entry)?))?;
+        let _writer_lock = self.lock_shared()?;
+        let mut changes = self.notifier.local_changes();
+        let mut datafile = DataFile::open_data_file(&self.path, &self.day_file_path(date))?;
+        changes.touch_data_file(datafile.file_path());
         datafile.add_entry(new_entry.clone())?;
         datafile.save()?;
+        changes.push(ChangeEvent::EntryAdded {
+            date,
+            hour: new_entry.hour,
+            minute: new_entry.minute,
+        });
+
+        stat_sums::add_tags(
+            &self.path,
+            date,
+            new_entry.wall_clock_hour(),
+            &new_entry.tags,
+        )?;
+
+        cache_handling::update_day_caches(&self.path, datafile.file_path())?;
+        Ok(())
+    }
+
+    /// Removes the `DataEntry` at the offset-compensated `hour` and `minute` from the `DataFile`
+    /// matching the provided `year`, `month`, and `day`. The stat sums and caches are updated to no longer
+    /// include the entry, and the data file is removed if it has no entries left.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error in the following situations, but is not limited to just
+    /// these cases:
+    ///
+    /// * The database is busy.
+    /// * The provided date is invalid.
+    /// * No `DataEntry` exists at the hour and minute specified.
+    /// * The data file is corrupted.
+    pub fn remove_data_entry(
+        &self,
+        year: i32,
+        month: u8,
+        day: u8,
+        hour: i8,
+        minute: u8,
+    ) -> Result<()> {
+        let date = DataBase::parse_date(year, month, day)?;
+        let _writer_lock = self.lock_shared()?;
+        let mut date_path = self.day_file_path(date);
+        date_path.add_extension(DATAFILEEXTENSION);
+        if !self.path.storage().exists(&date_path) {
+            return Err(Error::with_kind(ErrorKind::EntryDoesNotExist));
+        }
+
+        let mut datafile = DataFile::read_from_file(&self.path, &date_path)?;
+        let Some(removed_entry) = datafile.remove_entry(hour, minute) else {
+            return Err(Error::with_kind(ErrorKind::EntryDoesNotExist));
+        };
+        let mut changes = self.notifier.local_changes();
+        changes.touch_data_file(datafile.file_path());
+        datafile.save()?;
+        changes.push(ChangeEvent::EntryRemoved { date, hour, minute });
 
-        stat_sums::add_tags(&self.path, datetime.date(), new_entry.hour, &new_entry.tags)?;
+        stat_sums::remove_tags(
+            &self.path,
+            date,
+            removed_entry.wall_clock_hour(),
+            &removed_entry.tags,
+        )?;
+        cache_handling::update_day_caches(&self.path, datafile.file_path())?;
 
-        // TODO: Update caches with the added data entry.
         Ok(())
     }
 
@@ -405,15 +899,22 @@ impl DataBase {
     /// This function will return an error in the following situations, but is not limited to just
     /// these cases:
     ///
+    /// * The database is busy.
     /// * The proivded tag name already exists.
     pub fn add_tag(&self, tag_name: String) -> Result<()> {
-        //DBStatus::lock(&self.path, ActiveTask::None)?.unlock();
-
         log::info!("Attempting to add tag: [{tag_name}]");
-
-        TagList::from_file(&self.path)?
-            .add_tag(tag_name.clone())?
-            .save()?;
+        let _writer_lock = self.lock_shared()?;
+        let mut changes = self.notifier.local_changes();
+        changes.touch(&self.path.root().join(TAGSFILENAME));
+
+        let tag_id = self.with_tag_list(|tag_list| {
+            tag_list.add_tag(tag_name.clone())?.save()?;
+            Ok(*tag_list.get_id(&tag_name)?)
+        })?;
+        changes.push(ChangeEvent::TagAdded {
+            tag_id,
+            name: tag_name.clone(),
+        });
 
         log::info!("Successfully added tag: [{tag_name}]");
 
@@ -429,12 +930,141 @@ impl DataBase {
     ///
     /// * The provided `tag_id` doesn't exist in the database.
     pub fn remove_tag(&self, tag_id: u16) -> Result<()> {
-        let db_status = DBStatus::lock(&self.path, ActiveTask::RemoveTag(tag_id))?;
+        self.remove_tag_with_progress(tag_id, &TaskProgress::default())
+    }
 
-        self.intr_remove_tag(tag_id)?;
+    /// Same as `remove_tag`, but reports the progress to `progress` and stops if it is cancelled.
+    /// A cancelled removal leaves the database busy until `resume_task` is called.
+    pub fn remove_tag_with_progress(&self, tag_id: u16, progress: &TaskProgress) -> Result<()> {
+        self.with_tag_list(|tag_list| {
+            tag_list.get_tag(tag_id)?;
+            Ok(())
+        })?;
 
-        db_status.unlock();
+        let db_status = self.lock(ActiveTask::RemoveTag(tag_id))?;
+
+        finish_task(db_status, self.intr_remove_tag(tag_id, progress))
+    }
+
+    /// Undoes the last `merge_tags` or `remove_tag` that hasn't been undone yet. Every entry the
+    /// operation changed gets its old tags back, and the tags file and reclaimed ids are restored
+    /// as they were before the operation. Afterwards the stat sums and caches are regenerated.
+    ///
+    /// Each operation writes a undo record to the `history` folder of the database, of which the
+    /// last 32 are kept. Calling this repeatedly undoes the operations in reverse order.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error in the following situations, but is not limited to just
+    /// these cases:
+    ///
+    /// * The database is busy.
+    /// * The database was opened as read-only.
+    /// * An io error occured.
+    /// * There is no operation left to undo.
+    /// * A later change conflicts with the operation, for example if the removed tag id was given
+    ///   to a new tag, the tags were otherwise changed, or a changed entry was edited since. The
+    ///   database is left unchanged.
+    pub fn undo_last(&self) -> Result<()> {
+        self.undo_last_with_progress(&TaskProgress::default())
+    }
+
+    /// Same as `undo_last`, but reports the progress to `progress` and stops if it is cancelled.
+    /// A cancelled undo leaves the database busy until `resume_task` is called.
+    pub fn undo_last_with_progress(&self, progress: &TaskProgress) -> Result<()> {
+        log::info!("Attempting to undo the last operation...");
+        self.check_writable()?;
+        let Some(undo_record) = UndoRecord::last(&self.path)? else {
+            return Err(Error::with_kind(ErrorKind::NothingToUndo));
+        };
+
+        let db_status = self.lock(ActiveTask::Undo(undo_record.number()))?;
+        if let Err(error) = self.check_undo_conflicts(&undo_record) {
+            db_status.unlock();
+            return Err(error);
+        }
+
+        finish_task(db_status, self.intr_undo(undo_record.number(), progress))
+    }
+
+    /// Returns every metric of the database, ordered by id. Databases without a metrics file hold
+    /// the built-in mental and physical score.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error in the following situations, but is not limited to just
+    /// these cases:
+    ///
+    /// * The metrics file is corrupted.
+    pub fn metrics(&self) -> Result<Vec<Metric>> {
+        self.with_metric_list(|metric_list| Ok(metric_list.iter().cloned().collect()))
+    }
+
+    /// Adds a metric with the provided `name` whose values lie within `min..=max`, returning the
+    /// id of the new metric.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error in the following situations, but is not limited to just
+    /// these cases:
+    ///
+    /// * The database is busy.
+    /// * The provided metric name already exists.
+    /// * The name is empty or contains a line break, or `min` is above `max`.
+    pub fn add_metric(&self, name: String, min: i16, max: i16) -> Result<u16> {
+        log::info!("Attempting to add metric: [{name}] with range {min}..={max}");
+        let _writer_lock = self.lock_shared()?;
+        let mut changes = self.notifier.local_changes();
+        changes.touch(&self.path.root().join(METRICSFILENAME));
+
+        let metric_id = self.with_metric_list(|metric_list| {
+            let metric_id = metric_list.add_metric(name.clone(), min, max)?;
+            metric_list.save()?;
+            Ok(metric_id)
+        })?;
+        changes.push(ChangeEvent::MetricAdded {
+            metric_id,
+            name: name.clone(),
+        });
+
+        log::info!("Successfully added metric: [{name}]");
+        Ok(metric_id)
+    }
+
+    /// Renames the metric `old_name` to `new_name`. The id and range of the metric are kept.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error in the following situations, but is not limited to just
+    /// these cases:
+    ///
+    /// * The database is busy.
+    /// * `old_name` doesn't exist.
+    /// * `new_name` already exists, is empty, or contains a line break.
+    pub fn rename_metric(&self, old_name: String, new_name: String) -> Result<()> {
+        info!(
+            "Attempting to rename metric: [{}] to [{}]",
+            old_name, new_name
+        );
+        let _writer_lock = self.lock_shared()?;
+        let mut changes = self.notifier.local_changes();
+        changes.touch(&self.path.root().join(METRICSFILENAME));
+
+        let metric_id = self.with_metric_list(|metric_list| {
+            let metric_id = metric_list.rename_metric(&old_name, new_name.clone())?;
+            metric_list.save()?;
+            Ok(metric_id)
+        })?;
+        changes.push(ChangeEvent::MetricRenamed {
+            metric_id,
+            old_name: old_name.clone(),
+            new_name: new_name.clone(),
+        });
 
+        info!(
+            "Successfully renamed metric: [{}] to [{}]",
+            old_name, new_name
+        );
         Ok(())
     }
 
@@ -451,16 +1081,21 @@ impl DataBase {
                 OffsetDateTime::now_utc()
             }
         };
-        let datetime = timestamp + Duration::hours(self.settings.day_switch_offset as i64);
-        let mut diary_path = self.get_date_file_path(datetime)?;
+        let mut diary_path = self.get_date_file_path(timestamp)?;
         diary_path.add_extension(DIARYFILEEXTENSION);
 
         let diary_entry = DiaryEntry::new(title, text, timestamp);
 
-        let mut diary_file = DiaryFile::open(&diary_path)?;
+        let _writer_lock = self.lock_shared()?;
+        let mut changes = self.notifier.local_changes();
+        changes.touch(&diary_path);
+        let mut diary_file = DiaryFile::open(&self.path, &diary_path)?;
 
         diary_file.add_entry(diary_entry);
         diary_file.save()?;
+        if let Some(date) = DataFile::date_from_path(&diary_path) {
+            changes.push(ChangeEvent::DiaryEntryAdded { date });
+        }
 
         Ok(())
     }
@@ -487,11 +1122,15 @@ impl DataBase {
         todo!();
     }
 
-    pub fn parse_datetime(year: i32, month: u8, day: u8, hour: u8) -> Result<OffsetDateTime> {
+    pub fn parse_date(year: i32, month: u8, day: u8) -> Result<Date> {
         let month =
             time::Month::try_from(month).map_err(|_| Error::with_kind(ErrorKind::InvalidDate))?;
-        let date = Date::from_calendar_date(year, month, day)
-            .map_err(|_| Error::with_kind(ErrorKind::InvalidDate))?;
+        Date::from_calendar_date(year, month, day)
+            .map_err(|_| Error::with_kind(ErrorKind::InvalidDate))
+    }
+
+    pub fn parse_datetime(year: i32, month: u8, day: u8, hour: u8) -> Result<OffsetDateTime> {
+        let date = DataBase::parse_date(year, month, day)?;
 
         let time =
             Time::from_hms(hour, 0, 0).map_err(|_| Error::with_kind(ErrorKind::InvalidDate))?;
@@ -518,8 +1157,12 @@ impl DataBase {
     /// year/month_number/day-weekday_nr
     pub fn get_date_file_path(&self, mut datetime: OffsetDateTime) -> Result<PathBuf> {
         datetime -= Duration::hours(self.settings().day_switch_offset as i64);
-        let date = datetime.date();
+        Ok(self.day_file_path(datetime.date()))
+    }
 
+    /// Returns the file path of the day with the provided `date`, in the following format:
+    /// year/month_number/day-weekday_nr
+    pub fn day_file_path(&self, date: Date) -> PathBuf {
         let filename = format!(
             "{}-{}",
             date.day(),
@@ -533,100 +1176,842 @@ impl DataBase {
             filename
         )));
 
-        Ok(data_file_path)
+        data_file_path
+    }
+
+    pub fn settings(&self) -> &Settings {
+        &self.settings
+    }
+
+    /// Returns whether the settings file has been changed since the settings of this instance
+    /// were read, for example by another instance changing the day_switch_offset.
+    pub fn settings_changed(&self) -> bool {
+        let mut settings_file_state = self
+            .settings_file_state
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        file_changed(&self.path, SETTINGSFILENAME, &mut settings_file_state)
+    }
+
+    /// Reads the settings of this instance from the settings file again.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error in the following situations, but is not limited to just
+    /// these cases:
+    ///
+    /// * The settings file is missing or corrupted.
+    /// * An io error occured.
+    pub fn reload_settings(&mut self) -> Result<()> {
+        *self
+            .settings_file_state
+            .get_mut()
+            .unwrap_or_else(|poisoned| poisoned.into_inner()) =
+            file_state(&self.path, SETTINGSFILENAME);
+        self.settings = Settings::load(&self.path)?;
+        Ok(())
+    }
+
+    pub fn database_path(&self) -> &Path {
+        self.path.root()
+    }
+
+    pub fn path(&self) -> &DataBasePath {
+        &self.path
+    }
+
+    /// Checks the database for problems without modifying it, returning a report listing every
+    /// problem found. The following is checked:
+    ///
+    /// * Data files that can't be parsed or are misplaced.
+    /// * Entries with a hour outside of their day, or a minute above 59.
+    /// * Metric values outside of the range of their metric, and metric ids that are missing from
+    ///   the metrics file.
+    /// * Tag ids that are missing from the tags file, and entries containing a tag id twice.
+    /// * Tag ids in use that are also listed as reclaimed.
+    /// * Stat sums and caches that don't match the data files.
+    /// * Temporary files left behind by interrupted writes.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error in the following situations, but is not limited to just
+    /// these cases:
+    ///
+    /// * An io error occured.
+    pub fn check(&self) -> Result<CheckReport> {
+        log::info!("Checking the database at {:?}...", self.path.root());
+        check::check(&self.path, &self.settings, self.regeneration_threads)
+    }
+
+    /// Returns the task and owner of the lock currently held on the database, or `None` if the
+    /// database isn't busy.
+    ///
+    /// If `LockInfo::is_stale` reports that the owning process no longer exists, the task can be
+    /// finished using `resume_task` or abandoned using `break_lock`. If the status of the owner is
+    /// unknown, only `break_lock` can remove the lock.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error in the following situations, but is not limited to just
+    /// these cases:
+    ///
+    /// * An io error occured.
+    /// * The status file is corrupted.
+    pub fn lock_info(&self) -> Result<Option<LockInfo>> {
+        Ok(DBStatus::lock_info(&self.path)?)
+    }
+
+    /// Removes the lock of the database without finishing the active task. Every writer lock of
+    /// short writes is removed as well, including those left behind by crashed processes.
+    ///
+    /// **NOTE**: The lock is removed no matter which process holds it, so this should only be used
+    /// when the lock is stale. An abandoned `merge_tags` or `remove_tag` leaves every data entry
+    /// pointing at a existing tag, but the stat sums and caches might be outdated until they are
+    /// regenerated.
+    pub fn break_lock(&self) -> Result<()> {
+        self.check_writable()?;
+        if let Some(lock_info) = self.lock_info()? {
+            log::warn!(
+                "Breaking the lock of the database at {:?} held for {:?} by {:?}",
+                self.path.root(),
+                lock_info.task,
+                lock_info.owner
+            );
+        }
+        Ok(DBStatus::break_lock(&self.path)?)
+    }
+
+    /// Sets how long a task waits for the database to stop being busy before failing with
+    /// `DataBaseBusy`. With the default `None` the task fails immediately.
+    pub fn set_lock_timeout(&mut self, timeout: Option<std::time::Duration>) {
+        self.lock_timeout = timeout;
+    }
+
+    /// Returns how long a task waits for the database to stop being busy. See `set_lock_timeout`.
+    pub fn lock_timeout(&self) -> Option<std::time::Duration> {
+        self.lock_timeout
+    }
+
+    /// Sets how many threads are used to read the data files when regenerating caches and stat
+    /// sums. With the default 0 one thread per available cpu is used.
+    pub fn set_regeneration_threads(&mut self, threads: usize) {
+        self.regeneration_threads = threads;
+    }
+
+    /// Returns the number of threads used to regenerate caches and stat sums, where 0 means one
+    /// thread per available cpu.
+    pub fn regeneration_threads(&self) -> usize {
+        self.regeneration_threads
+    }
+
+    /// Registers `callback` to be called with every change made to the database, until it is
+    /// removed using `unsubscribe`.
+    ///
+    /// Changes made through this `DataBase` are reported as `ChangeOrigin::Local` shortly after
+    /// the method making them returns. Changes made by other processes are found by scanning the
+    /// files of the database every poll interval, and are reported as `ChangeOrigin::External`.
+    ///
+    /// The callbacks are called from a background thread, one event at a time. They may use the
+    /// database, but should return quickly since they hold up the following events.
+    ///
+    /// # Errors
+    /// - `Io`: The initial scan of the database failed.
+    pub fn subscribe(
+        &self,
+        callback: impl Fn(&ChangeEvent, ChangeOrigin) + Send + Sync + 'static,
+    ) -> Result<SubscriptionId> {
+        Ok(self.notifier.subscribe(callback)?)
+    }
+
+    /// Removes the callback registered as `id`. Returns false if no callback is registered as
+    /// `id`.
+    pub fn unsubscribe(&self, id: SubscriptionId) -> bool {
+        self.notifier.unsubscribe(id)
+    }
+
+    /// Sets how often the files of the database are scanned for changes made by other processes
+    /// while a callback is subscribed. Defaults to one second.
+    pub fn set_poll_interval(&mut self, interval: std::time::Duration) {
+        self.notifier.set_poll_interval(interval);
+    }
+
+    /// Returns how often the files of the database are scanned for changes made by other
+    /// processes.
+    pub fn poll_interval(&self) -> std::time::Duration {
+        self.notifier.poll_interval()
+    }
+}
+
+//
+
+//
+
+// Private functions
+impl DataBase {
+    /// Runs `operation` on the cached `TagList`, loading it from the tags file first if no list is
+    /// cached or if the tags file has changed since it was cached.
+    ///
+    /// If `operation` fails the cached list is discarded, since it might have been left partially
+    /// modified.
+    fn with_tag_list<T>(&self, operation: impl FnOnce(&mut TagList) -> Result<T>) -> Result<T> {
+        let mut cache = self
+            .tag_list
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+
+        let reload = match cache.as_mut() {
+            Some(cached) => file_changed(&self.path, TAGSFILENAME, &mut cached.file_state),
+            None => true,
+        };
+        if reload {
+            // The state is read first, so that a write during the read is noticed next time.
+            let tags_file_state = file_state(&self.path, TAGSFILENAME);
+            *cache = Some(CachedTagList {
+                tag_list: TagList::from_file(&self.path)?,
+                file_state: tags_file_state,
+            });
+        }
+
+        let cached = cache.as_mut().expect("The cache was filled above.");
+        match operation(&mut cached.tag_list) {
+            Ok(value) => {
+                cached.file_state = file_state(&self.path, TAGSFILENAME);
+                Ok(value)
+            }
+            Err(error) => {
+                *cache = None;
+                Err(error)
+            }
+        }
+    }
+
+    /// Runs `operation` on the cached `MetricList`, loading it from the metrics file first if no
+    /// list is cached or if the metrics file has changed since it was cached. See `with_tag_list`.
+    fn with_metric_list<T>(
+        &self,
+        operation: impl FnOnce(&mut MetricList) -> Result<T>,
+    ) -> Result<T> {
+        let mut cache = self
+            .metric_list
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+
+        let reload = match cache.as_mut() {
+            Some(cached) => file_changed(&self.path, METRICSFILENAME, &mut cached.file_state),
+            None => true,
+        };
+        if reload {
+            // The state is read first, so that a write during the read is noticed next time.
+            let metrics_file_state = file_state(&self.path, METRICSFILENAME);
+            *cache = Some(CachedMetricList {
+                metric_list: MetricList::from_file(&self.path)?,
+                file_state: metrics_file_state,
+            });
+        }
+
+        let cached = cache.as_mut().expect("The cache was filled above.");
+        match operation(&mut cached.metric_list) {
+            Ok(value) => {
+                cached.file_state = file_state(&self.path, METRICSFILENAME);
+                Ok(value)
+            }
+            Err(error) => {
+                *cache = None;
+                Err(error)
+            }
+        }
+    }
+
+    /// Returns a `InvalidData` error if `hour` is not a offset-compensated hour within a day, or if
+    /// `minute` is not a minute within the hour.
+    fn validate_time(&self, hour: i8, minute: u8) -> Result<()> {
+        if !DataEntry::is_valid_hour(hour, self.settings.day_switch_offset) {
+            error!(
+                "The hour {} is outside of the day! Expected a hour in the range {}..{}.",
+                hour,
+                self.settings.day_switch_offset,
+                self.settings.day_switch_offset + 24
+            );
+            return Err(Error::with_kind(ErrorKind::InvalidData));
+        }
+        if !DataEntry::is_valid_minute(minute) {
+            error!("The minute {} is outside of the hour!", minute);
+            return Err(Error::with_kind(ErrorKind::InvalidData));
+        }
+        Ok(())
+    }
+
+    /// Reads the settings of the database at `path` and returns the database without checking
+    /// whether it has to be migrated.
+    ///
+    /// Returns a `UnsupportedSchemaVersion` error if the database is stored in a schema version
+    /// newer than `CURRENT_SCHEMA_VERSION`.
+    fn open(path: DataBasePath, read_only: bool) -> Result<DataBase> {
+        let settings_file_state = file_state(&path, SETTINGSFILENAME);
+        let settings = Settings::load(&path)?;
+        if settings.schema_version > CURRENT_SCHEMA_VERSION {
+            error!(
+                "The database is stored in schema version {}, but only versions up to {} are supported!",
+                settings.schema_version, CURRENT_SCHEMA_VERSION
+            );
+            return Err(Error::with_kind(ErrorKind::UnsupportedSchemaVersion(
+                settings.schema_version,
+            )));
+        }
+
+        Ok(DataBase {
+            notifier: Notifier::new(path.clone()),
+            path,
+            settings,
+            settings_file_state: Mutex::new(settings_file_state),
+            tag_list: Mutex::new(None),
+            metric_list: Mutex::new(None),
+            lock_timeout: None,
+            regeneration_threads: 0,
+            read_only,
+        })
     }
 
-    pub fn settings(&self) -> &Settings {
-        &self.settings
+    /// Migrates the database to `CURRENT_SCHEMA_VERSION`, then reloads the migrated settings. See
+    /// `migrations::migrate`.
+    fn migrate(&mut self) -> Result<()> {
+        self.check_writable()?;
+        migrations::migrate(self)?;
+        self.reload_settings()?;
+        *self
+            .tag_list
+            .get_mut()
+            .unwrap_or_else(|poisoned| poisoned.into_inner()) = None;
+        *self
+            .metric_list
+            .get_mut()
+            .unwrap_or_else(|poisoned| poisoned.into_inner()) = None;
+        Ok(())
     }
 
-    pub fn database_path(&self) -> &Path {
-        self.path.root()
+    fn intr_rename_tag(&self, old_tag: String, new_tag: String) -> Result<()> {
+        let mut changes = self.notifier.local_changes();
+        changes.touch(&self.path.root().join(TAGSFILENAME));
+        let tag_id = self.with_tag_list(|tag_list| {
+            let tag_id = *tag_list.get_id(&old_tag)?;
+            tag_list
+                .rename_tag(old_tag.clone(), new_tag.clone())?
+                .save()?;
+            Ok(tag_id)
+        })?;
+        changes.push(ChangeEvent::TagRenamed {
+            tag_id,
+            old_name: old_tag,
+            new_name: new_tag,
+        });
+        Ok(())
     }
 
-    pub fn path(&self) -> &DataBasePath {
-        &self.path
+    fn intr_regen_caches(&self, progress: &TaskProgress) -> Result<()> {
+        let mut changes = self.notifier.local_changes();
+        changes.touch_all();
+        progress.start(self.data_file_count()?);
+        regeneration::regenerate(
+            &self.path,
+            RegenerationTargets::CACHES,
+            self.regeneration_threads,
+            progress,
+        )?;
+        changes.push(ChangeEvent::CachesRegenerated);
+        Ok(())
     }
-}
-
-//
-
-//
 
-// Private functions
-impl DataBase {
-    fn intr_rename_tag(&self, old_tag: String, new_tag: String) -> Result<()> {
-        TagList::from_file(&self.path)?
-            .rename_tag(old_tag, new_tag)?
-            .save()?;
+    fn intr_regen_tag_sums(&self, progress: &TaskProgress) -> Result<()> {
+        progress.start(self.data_file_count()?);
+        regeneration::regenerate(
+            &self.path,
+            RegenerationTargets::TAG_SUMS,
+            self.regeneration_threads,
+            progress,
+        )?;
         Ok(())
     }
 
-    fn intr_merge_tags(&self, tag_1: u16, tag_2: u16) -> Result<()> {
-        TagList::from_file(&self.path)?
-            .merge_tags(tag_1, tag_2)?
-            .save()?;
+    /// Salvages the corrupted data files, then regenerates the stat sums and caches.
+    fn intr_repair(&self, progress: &TaskProgress) -> Result<RepairReport> {
+        let mut changes = self.notifier.local_changes();
+        changes.touch_all();
+        let report = repair::repair_data_files(&self.path)?;
+        progress.start(self.data_file_count()?);
+        regeneration::regenerate(
+            &self.path,
+            RegenerationTargets::ALL,
+            self.regeneration_threads,
+            progress,
+        )?;
+        changes.push(ChangeEvent::CachesRegenerated);
+        Ok(report)
+    }
 
-        for mut data_file in self.data_files()? {
-            data_file.merge_tags(tag_1, tag_2).save()?;
+    /// Merges `tag_1` into `tag_2`, rewriting the data files before `tag_1` is removed from the
+    /// tags file. The rewrites are journaled, so an interrupted merge is rolled forward from the
+    /// first file that wasn't rewritten.
+    fn intr_merge_tags(&self, tag_1: u16, tag_2: u16, progress: &TaskProgress) -> Result<()> {
+        let mut changes = self.notifier.local_changes();
+        changes.touch_all();
+        let undo_record = self.begin_undo_record(Operation::MergeTags(tag_1, tag_2))?;
+        self.run_journaled_rewrite(
+            "merge_tags",
+            &format!("{} {}", tag_1, tag_2),
+            None,
+            progress,
+            |data_file| {
+                let old_entries = data_file.entries().clone();
+                data_file.merge_tags(tag_1, tag_2);
+                if let Some(undo_record) = &undo_record {
+                    undo_record.record_changes(&old_entries, data_file)?;
+                }
+                Ok(())
+            },
+            |tag_list| {
+                // The tags file might have been saved just before the task was interrupted.
+                if tag_list.tag_exists(tag_1) {
+                    tag_list.merge_tags(tag_1, tag_2)?.save()?;
+                }
+                Ok(())
+            },
+        )?;
+        if let Some(undo_record) = undo_record {
+            undo_record.finish()?;
         }
-
+        changes.push(ChangeEvent::TagsMerged { tag_1, tag_2 });
+        changes.push(ChangeEvent::CachesRegenerated);
         log::info!(
             "Successfully merged tag id: {} into tag id: {}",
             tag_1,
             tag_2
         );
+        Ok(())
+    }
 
-        log::info!("merge_tags(): Attempting to regenerate caches...");
-        if let Err(e) = stat_sums::regenerate_tag_sums(&self.path) {
-            error!(
-                "merge_tags() received {:?} when attempting to regenerate tag sums!",
-                e
-            );
+    /// Removes `tag_id` from every data file before it is removed from the tags file. The
+    /// rewrites are journaled the same way as in `intr_merge_tags`.
+    fn intr_remove_tag(&self, tag_id: u16, progress: &TaskProgress) -> Result<()> {
+        log::info!("Attempting to remove tag with id: [{}]", tag_id);
+        let mut changes = self.notifier.local_changes();
+        changes.touch_all();
+        let undo_record = self.begin_undo_record(Operation::RemoveTag(tag_id))?;
+        self.run_journaled_rewrite(
+            "remove_tag",
+            &tag_id.to_string(),
+            None,
+            progress,
+            |data_file| {
+                let old_entries = data_file.entries().clone();
+                data_file.remove_tag(tag_id);
+                if let Some(undo_record) = &undo_record {
+                    undo_record.record_changes(&old_entries, data_file)?;
+                }
+                Ok(())
+            },
+            |tag_list| {
+                // The tags file might have been saved just before the task was interrupted.
+                if tag_list.tag_exists(tag_id) {
+                    tag_list.remove_tag(tag_id)?.save()?;
+                }
+                Ok(())
+            },
+        )?;
+        if let Some(undo_record) = undo_record {
+            undo_record.finish()?;
+        }
+        changes.push(ChangeEvent::TagRemoved { tag_id });
+        changes.push(ChangeEvent::CachesRegenerated);
+        log::info!("Successfully removed tag [{}]", tag_id);
+        Ok(())
+    }
+
+    /// Writes the undo record of `operation`, or reopens it if the operation is being resumed.
+    ///
+    /// Returns `None` if the removed tag is already missing from the tags file without a record
+    /// to resume, in which case the operation can't be undone.
+    fn begin_undo_record(&self, operation: Operation) -> Result<Option<UndoRecord>> {
+        if let Some(undo_record) = UndoRecord::last(&self.path)? {
+            if !undo_record.is_finished() && *undo_record.operation() == operation {
+                return Ok(Some(undo_record));
+            }
         }
-        log::info!("merge_tags(): Finished regenerating caches!");
 
-        log::info!("merge_tags(): Attempting to regenerate tag sums...");
-        if let Err(e) = cache_handling::regenerate_caches(&self.path) {
-            error!(
-                "merge_tags() received {:?} when attempting to regenerate caches!",
-                e
+        let tag_id = operation.removed_tag();
+        let (tag_name, old_tags) = self.with_tag_list(|tag_list| {
+            Ok((tag_list.get_tag(tag_id).ok().cloned(), tag_list.snapshot()))
+        })?;
+        let Some(tag_name) = tag_name else {
+            log::warn!(
+                "Tag id [{}] is already missing from the tags file! [{}] will not be undoable.",
+                tag_id,
+                operation
             );
+            return Ok(None);
+        };
+
+        Ok(Some(UndoRecord::begin(
+            &self.path,
+            operation,
+            self.settings.day_switch_offset,
+            (tag_id, tag_name),
+            old_tags,
+        )?))
+    }
+
+    /// Restores every entry and the tag listed in the undo record with the provided number, then
+    /// removes the record. The rewrites are journaled the same way as in `intr_merge_tags`.
+    ///
+    /// Does nothing if the record no longer exists, meaning it was already undone.
+    fn intr_undo(&self, record_number: u32, progress: &TaskProgress) -> Result<()> {
+        let Some(undo_record) = UndoRecord::open(&self.path, record_number)? else {
+            info!("The undo record {} was already undone.", record_number);
+            return Ok(());
+        };
+        let mut changes = self.notifier.local_changes();
+        changes.touch_all();
+
+        let mut changed_entries: HashMap<&Path, Vec<&ChangedEntry>> = HashMap::new();
+        for changed_entry in undo_record.entries() {
+            changed_entries
+                .entry(&changed_entry.file)
+                .or_default()
+                .push(changed_entry);
         }
-        log::info!("merge_tags(): Finished regenerating tag sums!");
+        let (tag_id, tag_name) = undo_record.removed_tag();
+
+        self.run_journaled_rewrite(
+            "undo",
+            &record_number.to_string(),
+            Some(
+                changed_entries
+                    .keys()
+                    .map(|path| path.to_path_buf())
+                    .collect(),
+            ),
+            progress,
+            |data_file| {
+                let Some(entries) = changed_entries.get(data_file.file_path()) else {
+                    return Ok(());
+                };
+                for changed_entry in entries {
+                    if let Some(entry) = data_file.entries().get(&changed_entry.time()) {
+                        let mut entry = entry.clone();
+                        entry.tags = changed_entry.old_tags.clone();
+                        data_file.overwrite_entry(entry);
+                    }
+                }
+                Ok(())
+            },
+            |tag_list| {
+                if let Some(old_tags) = undo_record.old_tags() {
+                    tag_list.restore_snapshot(old_tags.clone()).save()?;
+                } else if !tag_list.tag_exists(tag_id) {
+                    // The tags file might have been saved just before the task was interrupted.
+                    tag_list.restore_tag(tag_id, tag_name.to_string())?.save()?;
+                }
+                Ok(())
+            },
+        )?;
 
+        changes.push(ChangeEvent::TagAdded {
+            tag_id,
+            name: tag_name.to_string(),
+        });
+        for changed_entry in undo_record.entries() {
+            if let Some(date) = DataFile::date_from_path(&changed_entry.file) {
+                changes.push(ChangeEvent::EntryOverwritten {
+                    date,
+                    hour: changed_entry.hour,
+                    minute: changed_entry.minute,
+                });
+            }
+        }
+        changes.push(ChangeEvent::CachesRegenerated);
+        info!("Successfully undid [{}]", undo_record.operation());
+        undo_record.remove()?;
         Ok(())
     }
 
-    fn intr_remove_tag(&self, tag_id: u16) -> Result<()> {
-        log::info!("Attempting to remove tag with id: [{}]", tag_id);
-        let mut tag_list = TagList::from_file(&self.path)?;
-        tag_list.remove_tag(tag_id)?;
-        for mut datafile in self.data_files()? {
-            datafile.remove_tag(tag_id).save()?;
+    /// Returns a `UndoConflict` error if a change made after the operation of `undo_record`
+    /// prevents it from being undone exactly. Every conflict found is logged.
+    fn check_undo_conflicts(&self, undo_record: &UndoRecord) -> Result<()> {
+        let mut conflicts = Vec::new();
+        if !undo_record.is_finished() {
+            conflicts.push("the operation never finished".to_string());
+        }
+        if undo_record.day_switch_offset() != self.settings.day_switch_offset {
+            conflicts.push("the day switch offset was changed".to_string());
+        }
+
+        let (tag_id, tag_name) = undo_record.removed_tag();
+        self.with_tag_list(|tag_list| {
+            if tag_list.tag_exists(tag_id) {
+                conflicts.push(format!("tag id [{}] was given to another tag", tag_id));
+            }
+            if tag_list.get_id(tag_name).is_ok() {
+                conflicts.push(format!("a tag named [{}] was added", tag_name));
+            }
+            if let Some(old_tags) = undo_record.old_tags() {
+                if conflicts.is_empty() && !tags_match_operation(old_tags, tag_id, tag_list) {
+                    conflicts.push("the tags were changed".to_string());
+                }
+            }
+            Ok(())
+        })?;
+
+        for changed_entry in undo_record.entries() {
+            let current_tags = match DataFile::read_from_file(&self.path, &changed_entry.file) {
+                Ok(data_file) => data_file
+                    .entries()
+                    .get(&changed_entry.time())
+                    .map(|entry| entry.tags.clone()),
+                Err(data_entry::Error::Io(error)) if error.kind() != io::ErrorKind::NotFound => {
+                    return Err(error.into());
+                }
+                Err(_) => None,
+            };
+            if current_tags.as_ref() != Some(&changed_entry.new_tags) {
+                conflicts.push(format!(
+                    "the entry at {}:{:02} in {:?} was changed",
+                    changed_entry.hour, changed_entry.minute, changed_entry.file
+                ));
+            }
+        }
+
+        if conflicts.is_empty() {
+            return Ok(());
+        }
+        for conflict in &conflicts {
+            log::warn!("Can't undo [{}]: {}", undo_record.operation(), conflict);
+        }
+        Err(Error::with_kind(ErrorKind::UndoConflict))
+    }
+
+    /// Rewrites the data files at `data_file_paths` using `rewrite_data` followed by the tags
+    /// file using `rewrite_tags`, then regenerates the stat sums and caches. If `data_file_paths`
+    /// is `None` every data file is rewritten.
+    ///
+    /// The rewrites are planned in a `Journal` before the first file is touched. If a journal of
+    /// the same task already exists the task is rolled forward from it, skipping every file that
+    /// was already rewritten. Since the tags file is rewritten last, no data entry is ever left
+    /// pointing at a tag id that was removed from the tags file.
+    fn run_journaled_rewrite(
+        &self,
+        task_name: &str,
+        task_data: &str,
+        data_file_paths: Option<Vec<PathBuf>>,
+        progress: &TaskProgress,
+        rewrite_data: impl Fn(&mut DataFile) -> Result<()>,
+        rewrite_tags: impl Fn(&mut TagList) -> Result<()>,
+    ) -> Result<()> {
+        let task = format!("{} {}", task_name, task_data);
+        let mut journal = match Journal::resume(&self.path, &task)? {
+            Some(journal) => {
+                info!("{}(): Rolling forward from the journal...", task_name);
+                journal
+            }
+            None => {
+                let data_file_paths = match data_file_paths {
+                    Some(data_file_paths) => data_file_paths,
+                    None => self
+                        .data_files()?
+                        .iter()
+                        .map(|data_file| data_file.file_path().to_path_buf())
+                        .collect(),
+                };
+                let mut steps: Vec<JournalStep> = data_file_paths
+                    .into_iter()
+                    .map(JournalStep::DataFile)
+                    .collect();
+                steps.push(JournalStep::TagsFile);
+                Journal::begin(&self.path, &task, steps)?
+            }
+        };
+
+        let pending_steps = journal.pending_steps();
+        progress.start(pending_steps.len() as u32 + self.data_file_count()?);
+        for (index, step) in pending_steps {
+            match step {
+                // A data file might have been removed since the task was planned.
+                JournalStep::DataFile(file_path) if !self.path.storage().exists(&file_path) => {}
+                JournalStep::DataFile(file_path) => {
+                    let mut data_file = DataFile::read_from_file(&self.path, &file_path)?;
+                    rewrite_data(&mut data_file)?;
+                    data_file.save()?;
+                }
+                JournalStep::TagsFile => self.with_tag_list(&rewrite_tags)?,
+            }
+            journal.complete(index)?;
+            progress.file_processed()?;
         }
-        log::info!("Successfully removed tag [{}]", tag_id);
 
-        log::info!("remove_tag(): Attempting to regenerate caches...");
-        if let Err(e) = cache_handling::regenerate_caches(&self.path) {
+        log::info!(
+            "{}(): Attempting to regenerate tag sums and caches...",
+            task_name
+        );
+        if let Err(e) = regeneration::regenerate(
+            &self.path,
+            RegenerationTargets::ALL,
+            self.regeneration_threads,
+            progress,
+        ) {
+            if matches!(e, RegenerationError::Cancelled) {
+                return Err(e.into());
+            }
             error!(
-                "remove_tag() received {:?} when attempting to regenerate caches!",
-                e
+                "{}() received {:?} when attempting to regenerate tag sums and caches!",
+                task_name, e
             );
         }
-        log::info!("remove_tag(): Finished regenerating caches!");
+        log::info!(
+            "{}(): Finished regenerating tag sums and caches!",
+            task_name
+        );
+
+        journal.finish()?;
+        Ok(())
+    }
+
+    /// Locks the database for `task`, waiting for the lock timeout if the database is busy.
+    ///
+    /// Returns a `ReadOnly` error if the database was opened using `open_read_only`.
+    pub(crate) fn lock(&self, task: ActiveTask) -> Result<DBStatus> {
+        self.check_writable()?;
+        let db_status = match self.lock_timeout {
+            Some(timeout) => DBStatus::lock_with_timeout(&self.path, task, timeout)?,
+            None => DBStatus::lock(&self.path, task)?,
+        };
+        Ok(db_status)
+    }
+
+    /// Acquires a shared writer lock for a short write, waiting for the lock timeout if a task
+    /// holds the database lock. Short writes can run alongside each other but never during a
+    /// task.
+    ///
+    /// Returns a `ReadOnly` error if the database was opened using `open_read_only`.
+    fn lock_shared(&self) -> Result<WriterLock> {
+        self.check_writable()?;
+        let writer_lock = match self.lock_timeout {
+            Some(timeout) => DBStatus::lock_shared_with_timeout(&self.path, timeout)?,
+            None => DBStatus::lock_shared(&self.path)?,
+        };
+        Ok(writer_lock)
+    }
 
-        log::info!("remove_tag(): Attempting to regenerate tag sums...");
-        if let Err(e) = stat_sums::regenerate_tag_sums(&self.path) {
+    /// Returns a `ReadOnly` error if the database was opened using `open_read_only`.
+    fn check_writable(&self) -> Result<()> {
+        if self.read_only {
             error!(
-                "remove_tag() received {:?} when attempting to regenerate tag sums!",
-                e
+                "Attempted to modify the read-only database at {:?}!",
+                self.path.root()
             );
+            return Err(Error::with_kind(ErrorKind::ReadOnly));
         }
-        log::info!("remove_tags(): Finished regenerating tag sums!");
-        tag_list.save()?;
         Ok(())
     }
+
+    /// Returns the number of data files in the database.
+    fn data_file_count(&self) -> Result<u32> {
+        let count = self
+            .path
+            .storage()
+            .walk_files(&self.path.data())?
+            .iter()
+            .filter(|path| path.extension() == Some(OsStr::new(DATAFILEEXTENSION)))
+            .count();
+        Ok(count as u32)
+    }
+}
+
+/// Returns the state of the file named `file_name` in the database root, or `None` if it can't be
+/// read. The content is only hashed if the modification time is ambiguous. See `FileState`.
+fn file_state(db_path: &DataBasePath, file_name: &str) -> Option<FileState> {
+    let file_path = db_path.root().join(file_name);
+    let metadata = db_path.storage().metadata(&file_path).ok()?;
+    let hash = if recently_modified(metadata.modified) {
+        Some(content_hash(db_path, &file_path)?)
+    } else {
+        None
+    };
+    Some(FileState {
+        modified: metadata.modified,
+        len: metadata.len,
+        hash,
+    })
+}
+
+/// Returns whether the file named `file_name` in the database root has changed since `recorded`
+/// was read using `file_state`.
+///
+/// The content is only read if the modification time and length match a state recorded while the
+/// modification time was ambiguous. Once it no longer is, `recorded` is replaced by the current
+/// state, so that later calls only compare the metadata.
+fn file_changed(db_path: &DataBasePath, file_name: &str, recorded: &mut Option<FileState>) -> bool {
+    let file_path = db_path.root().join(file_name);
+    let metadata = db_path.storage().metadata(&file_path).ok();
+    let (Some(state), Some(metadata)) = (recorded.as_ref(), metadata) else {
+        return recorded.is_some() || metadata.is_some();
+    };
+    if state.modified != metadata.modified || state.len != metadata.len {
+        return true;
+    }
+    let Some(hash) = state.hash else {
+        return false;
+    };
+
+    if content_hash(db_path, &file_path) != Some(hash) {
+        return true;
+    }
+    if !recently_modified(metadata.modified) {
+        *recorded = file_state(db_path, file_name);
+    }
+    false
+}
+
+/// Returns whether a file modified at `modified` could be rewritten without its modification time
+/// changing. See `AMBIGUOUSMODIFICATIONWINDOW`.
+fn recently_modified(modified: SystemTime) -> bool {
+    SystemTime::now()
+        .duration_since(modified)
+        .map_or(true, |age| age < AMBIGUOUSMODIFICATIONWINDOW)
+}
+
+/// Returns the hash of the content of the file at `file_path`, or `None` if it can't be read.
+fn content_hash(db_path: &DataBasePath, file_path: &Path) -> Option<u64> {
+    let mut hasher = DefaultHasher::new();
+    db_path.storage().read(file_path).ok()?.hash(&mut hasher);
+    Some(hasher.finish())
+}
+
+/// Returns whether `tag_list` is exactly `old_tags` with the tag `removed_tag_id` removed, as a
+/// merge or removal of the tag leaves it. The next id is not compared, since it is recalculated
+/// whenever the tags file is read.
+fn tags_match_operation(old_tags: &TagsSnapshot, removed_tag_id: u16, tag_list: &TagList) -> bool {
+    let mut expected = old_tags.clone();
+    expected
+        .tags
+        .retain(|(tag_id, _)| *tag_id != removed_tag_id);
+    expected.reclaimed_ids.push(removed_tag_id);
+
+    let current = tag_list.snapshot();
+    current.tags == expected.tags && current.reclaimed_ids == expected.reclaimed_ids
+}
+
+/// Releases the lock held by `db_status` and returns `result`, unless the task was cancelled.
+/// A cancelled task keeps the database locked so that `resume_task` can finish it later.
+fn finish_task(db_status: DBStatus, result: Result<()>) -> Result<()> {
+    match result {
+        Err(error) if matches!(error.kind, ErrorKind::Cancelled) => {
+            info!("The task was cancelled! The database stays busy until the task is resumed.");
+            db_status.suspend();
+            Err(error)
+        }
+        result => {
+            db_status.unlock();
+            result
+        }
+    }
 }
 
 //
@@ -705,6 +2090,61 @@ pub enum ErrorKind {
     CorruptedDiaryFile,
     /// No diary entry exists at the provided index! The index was out of range.
     EntryIndexDoesNotExist,
+    /// A required pointer parameter was null. Holds the name of the parameter.
+    NullPointer(&'static str),
+    /// A string parameter was not valid UTF-8. Holds the name of the parameter.
+    InvalidUtf8(&'static str),
+    /// The requested function is not implemented yet.
+    NotImplemented,
+    /// The task was cancelled before it finished. The database stays busy until the task is
+    /// resumed.
+    Cancelled,
+    /// A database already exists at the provided path.
+    DataBaseAlreadyExists,
+    /// The provided directory is not empty.
+    DirectoryNotEmpty,
+    /// No entry exists at the provided hour!
+    EntryDoesNotExist,
+    /// A data file is not placed at the `year/month/day-weekday_nr` path matching its date.
+    MisplacedDataFile,
+    /// The database was opened as read-only and can't be modified.
+    ReadOnly,
+    /// The database is stored in an older schema version and has to be migrated before it can be
+    /// used. Holds the schema version of the database.
+    OutdatedSchema(u32),
+    /// The database is stored in a newer schema version than this library supports. Holds the
+    /// schema version of the database.
+    UnsupportedSchemaVersion(u32),
+    /// A migration failed or did not pass its verification. The database was left unchanged.
+    /// Holds the schema version the migration was converting to.
+    MigrationFailed(u32),
+    /// The history holds no operation that can be undone.
+    NothingToUndo,
+    /// The last operation can't be undone, since a later change to the database conflicts with
+    /// it. The database was left unchanged.
+    UndoConflict,
+    /// A undo record in the history is corrupted.
+    CorruptedUndoRecord,
+    /// The metrics file is corrupted. The file might contain duplicate ids or metric names, or a
+    /// line might have a unexected format.
+    CorruptedMetricsFile,
+    /// The provided metric name does not exist in the database.
+    UnknownMetric(String),
+    /// The provided metric id does not exist in the database.
+    UnknownMetricId(u16),
+    /// The provided metric name already exists in the database.
+    MetricAlreadyExists,
+    /// A value of the provided entry lies outside of the range of its metric. Holds the id of the
+    /// metric.
+    MetricValueOutOfRange(u16),
+    /// A copy of the database left behind by an interrupted offset change or migration exists
+    /// next to the database. It has to be removed before the database can be changed this way
+    /// again. Holds the path of the copy.
+    LeftoverDataBaseCopy(PathBuf),
+    /// Short writes by other handles or processes did not finish in time, so the task could not
+    /// lock the database. A writer lock left behind by a crashed process on another host can be
+    /// removed using `break_lock`.
+    WritesInProgress,
 }
 
 impl ErrorKind {
@@ -739,6 +2179,28 @@ impl ErrorKind {
     /// * `23` => `NotADiaryFile`
     /// * `24` => `CorruptedDiaryFile`
     /// * `25` => `EntryIndexDoesNotExist`
+    /// * `26` => `NullPointer`
+    /// * `27` => `InvalidUtf8`
+    /// * `28` => `NotImplemented`
+    /// * `29` => `Cancelled`
+    /// * `30` => `DataBaseAlreadyExists`
+    /// * `31` => `DirectoryNotEmpty`
+    /// * `32` => `EntryDoesNotExist`
+    /// * `33` => `MisplacedDataFile`
+    /// * `34` => `ReadOnly`
+    /// * `35` => `OutdatedSchema`
+    /// * `36` => `UnsupportedSchemaVersion`
+    /// * `37` => `MigrationFailed`
+    /// * `38` => `NothingToUndo`
+    /// * `39` => `UndoConflict`
+    /// * `40` => `CorruptedUndoRecord`
+    /// * `41` => `CorruptedMetricsFile`
+    /// * `42` => `UnknownMetric`
+    /// * `43` => `UnknownMetricId`
+    /// * `44` => `MetricAlreadyExists`
+    /// * `45` => `MetricValueOutOfRange`
+    /// * `46` => `LeftoverDataBaseCopy`
+    /// * `47` => `WritesInProgress`
     pub fn code(&self) -> i32 {
         match self {
             ErrorKind::Io(_) => 1,
@@ -766,6 +2228,154 @@ impl ErrorKind {
             ErrorKind::NotADiaryFile => 23,
             ErrorKind::CorruptedDiaryFile => 24,
             ErrorKind::EntryIndexDoesNotExist => 25,
+            ErrorKind::NullPointer(_) => 26,
+            ErrorKind::InvalidUtf8(_) => 27,
+            ErrorKind::NotImplemented => 28,
+            ErrorKind::Cancelled => 29,
+            ErrorKind::DataBaseAlreadyExists => 30,
+            ErrorKind::DirectoryNotEmpty => 31,
+            ErrorKind::EntryDoesNotExist => 32,
+            ErrorKind::MisplacedDataFile => 33,
+            ErrorKind::ReadOnly => 34,
+            ErrorKind::OutdatedSchema(_) => 35,
+            ErrorKind::UnsupportedSchemaVersion(_) => 36,
+            ErrorKind::MigrationFailed(_) => 37,
+            ErrorKind::NothingToUndo => 38,
+            ErrorKind::UndoConflict => 39,
+            ErrorKind::CorruptedUndoRecord => 40,
+            ErrorKind::CorruptedMetricsFile => 41,
+            ErrorKind::UnknownMetric(_) => 42,
+            ErrorKind::UnknownMetricId(_) => 43,
+            ErrorKind::MetricAlreadyExists => 44,
+            ErrorKind::MetricValueOutOfRange(_) => 45,
+            ErrorKind::LeftoverDataBaseCopy(_) => 46,
+            ErrorKind::WritesInProgress => 47,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.kind.fmt(f)
+    }
+}
+
+impl fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ErrorKind::Io(error) => write!(f, "An io error occured: {error}"),
+            ErrorKind::WalkDir(error) => write!(f, "A walkdir error occured: {error}"),
+            ErrorKind::PathDoesNotExist => write!(f, "No object exists at the provided path."),
+            ErrorKind::IsNotDataBase => write!(
+                f,
+                "The provided path is not marked as a database. (Missing .db_marker)"
+            ),
+            ErrorKind::DataBaseBusy => write!(
+                f,
+                "The database is in the middle of a unfinished operation."
+            ),
+            ErrorKind::CorruptedDBStatus => write!(
+                f,
+                "The database is marked as busy, but the data of the active task is missing."
+            ),
+            ErrorKind::UnknownTask => write!(
+                f,
+                "The database is marked as busy, but the active task is unknown."
+            ),
+            ErrorKind::CorruptedTagsFile => write!(f, "The tags file is corrupted."),
+            ErrorKind::UnknownTag(tag) => write!(f, "The tag [{tag}] does not exist."),
+            ErrorKind::UnknownTagId(tag_id) => {
+                write!(f, "The tag id [{tag_id}] does not exist.")
+            }
+            ErrorKind::TagAlreadyExists => write!(f, "The tag already exists."),
+            ErrorKind::InvalidImage => {
+                write!(f, "The provided image is not a valid compressed database.")
+            }
+            ErrorKind::UnableToZip => write!(f, "The database could not be zip compressed."),
+            ErrorKind::Image(error) => write!(f, "A image error occured: {error}"),
+            ErrorKind::InvalidDate => write!(f, "The provided date is not valid."),
+            ErrorKind::EntryAlreadyExists => {
+                write!(f, "An entry already exists at the provided hour.")
+            }
+            ErrorKind::CorruptedDataFile => write!(f, "A data file is corrupted."),
+            ErrorKind::InvalidData => write!(f, "The provided data entry data is invalid."),
+            ErrorKind::CorruptedStatSumsFile => write!(f, "A stat sums file is corrupted."),
+            ErrorKind::MissingSettingsFile => write!(f, "The settings file does not exist."),
+            ErrorKind::CorruptedSettingsFile => write!(f, "The settings file is corrupted."),
+            ErrorKind::OffsetOutOfRange => write!(
+                f,
+                "The day_switch_offset is outside of the acceptable -12 to 12 range."
+            ),
+            ErrorKind::NotADiaryFile => {
+                write!(f, "The provided path does not point to a diary file.")
+            }
+            ErrorKind::CorruptedDiaryFile => write!(f, "A diary file is corrupted."),
+            ErrorKind::EntryIndexDoesNotExist => {
+                write!(f, "No diary entry exists at the provided index.")
+            }
+            ErrorKind::NullPointer(parameter) => {
+                write!(f, "The parameter [{parameter}] was a null pointer.")
+            }
+            ErrorKind::InvalidUtf8(parameter) => {
+                write!(f, "The parameter [{parameter}] was not valid UTF-8.")
+            }
+            ErrorKind::NotImplemented => write!(f, "The function is not implemented yet."),
+            ErrorKind::Cancelled => write!(
+                f,
+                "The task was cancelled. The database stays busy until the task is resumed."
+            ),
+            ErrorKind::DataBaseAlreadyExists => {
+                write!(f, "A database already exists at the provided path.")
+            }
+            ErrorKind::DirectoryNotEmpty => write!(f, "The provided directory is not empty."),
+            ErrorKind::EntryDoesNotExist => write!(f, "No entry exists at the provided hour."),
+            ErrorKind::MisplacedDataFile => write!(
+                f,
+                "A data file is misplaced or misnamed and does not match its date."
+            ),
+            ErrorKind::ReadOnly => {
+                write!(
+                    f,
+                    "The database was opened as read-only and can't be modified."
+                )
+            }
+            ErrorKind::OutdatedSchema(version) => write!(
+                f,
+                "The database is stored in the outdated schema version {version} and has to be migrated."
+            ),
+            ErrorKind::UnsupportedSchemaVersion(version) => write!(
+                f,
+                "The database is stored in schema version {version}, which is newer than this version supports."
+            ),
+            ErrorKind::MigrationFailed(version) => write!(
+                f,
+                "The migration to schema version {version} failed. The database was left unchanged."
+            ),
+            ErrorKind::NothingToUndo => write!(f, "There is no operation to undo."),
+            ErrorKind::UndoConflict => write!(
+                f,
+                "The last operation can't be undone since a later change conflicts with it."
+            ),
+            ErrorKind::CorruptedUndoRecord => write!(f, "A undo record is corrupted."),
+            ErrorKind::CorruptedMetricsFile => write!(f, "The metrics file is corrupted."),
+            ErrorKind::UnknownMetric(metric) => write!(f, "The metric [{metric}] does not exist."),
+            ErrorKind::UnknownMetricId(metric_id) => {
+                write!(f, "The metric id [{metric_id}] does not exist.")
+            }
+            ErrorKind::MetricAlreadyExists => write!(f, "The metric already exists."),
+            ErrorKind::MetricValueOutOfRange(metric_id) => write!(
+                f,
+                "The value of the metric [{metric_id}] is outside of the range of the metric."
+            ),
+            ErrorKind::LeftoverDataBaseCopy(path) => write!(
+                f,
+                "A leftover copy of the database exists at {:?} and has to be removed first.",
+                path
+            ),
+            ErrorKind::WritesInProgress => write!(
+                f,
+                "Short writes to the database did not finish in time, so the database could not be locked."
+            ),
         }
     }
 }
@@ -808,7 +2418,8 @@ impl From<DBStatusError> for Error {
                 DBStatusError::Io(e) => ErrorKind::Io(e),
                 DBStatusError::CorruptedData => ErrorKind::CorruptedDBStatus,
                 DBStatusError::UnknownTask => ErrorKind::UnknownTask,
-                DBStatusError::DataBaseBusy(_, _) => ErrorKind::DataBaseBusy,
+                DBStatusError::DataBaseBusy(_) => ErrorKind::DataBaseBusy,
+                DBStatusError::WritersActive => ErrorKind::WritesInProgress,
             },
         }
     }
@@ -819,8 +2430,49 @@ impl From<StatSumsError> for Error {
         Self {
             kind: match value {
                 StatSumsError::Io(e) => ErrorKind::Io(e),
-                StatSumsError::WalkDir(e) => ErrorKind::WalkDir(e),
                 StatSumsError::CorruptedStatSumFile => ErrorKind::CorruptedStatSumsFile,
+                StatSumsError::Cancelled => ErrorKind::Cancelled,
+            },
+        }
+    }
+}
+
+impl From<Cancelled> for Error {
+    fn from(_: Cancelled) -> Self {
+        Self {
+            kind: ErrorKind::Cancelled,
+        }
+    }
+}
+
+impl From<RegenerationError> for Error {
+    fn from(value: RegenerationError) -> Self {
+        Self {
+            kind: match value {
+                RegenerationError::Io(e) => ErrorKind::Io(e),
+                RegenerationError::Cancelled => ErrorKind::Cancelled,
+            },
+        }
+    }
+}
+
+impl From<HistoryError> for Error {
+    fn from(value: HistoryError) -> Self {
+        Self {
+            kind: match value {
+                HistoryError::Io(e) => ErrorKind::Io(e),
+                HistoryError::CorruptedUndoRecord(_) => ErrorKind::CorruptedUndoRecord,
+            },
+        }
+    }
+}
+
+impl From<CacheError> for Error {
+    fn from(value: CacheError) -> Self {
+        Self {
+            kind: match value {
+                CacheError::Io(e) => ErrorKind::Io(e),
+                CacheError::Cancelled => ErrorKind::Cancelled,
             },
         }
     }
@@ -840,6 +2492,22 @@ impl From<TagsError> for Error {
     }
 }
 
+impl From<MetricsError> for Error {
+    fn from(value: MetricsError) -> Self {
+        Self {
+            kind: match value {
+                MetricsError::Io(e) => ErrorKind::Io(e),
+                MetricsError::CorruptedMetricsFile(_) => ErrorKind::CorruptedMetricsFile,
+                MetricsError::UnknownMetric(metric) => ErrorKind::UnknownMetric(metric),
+                MetricsError::UnknownId(id) => ErrorKind::UnknownMetricId(id),
+                MetricsError::MetricAlreadyExists => ErrorKind::MetricAlreadyExists,
+                MetricsError::ValueOutOfRange(id) => ErrorKind::MetricValueOutOfRange(id),
+                MetricsError::InvalidMetric => ErrorKind::InvalidData,
+            },
+        }
+    }
+}
+
 impl From<BackupImageError> for Error {
     fn from(value: BackupImageError) -> Self {
         Self {
@@ -862,6 +2530,7 @@ impl From<data_entry::Error> for Error {
                 data_entry::Error::InvalidDate => ErrorKind::InvalidDate,
                 data_entry::Error::CorruptedDataFile => ErrorKind::CorruptedDataFile,
                 data_entry::Error::InvalidData => ErrorKind::InvalidData,
+                data_entry::Error::MisplacedDataFile => ErrorKind::MisplacedDataFile,
             },
         }
     }
@@ -902,3 +2571,447 @@ impl From<MergeTagsError> for Error {
         }
     }
 }*/
+
+//
+
+//
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::BTreeMap,
+        path::{Path, PathBuf},
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            Arc,
+        },
+    };
+
+    use time::Month;
+
+    use super::{file_changed, file_state, DataBase, ErrorKind};
+    use crate::{
+        db_status::{ActiveTask, DBStatus},
+        migrations::CURRENT_SCHEMA_VERSION,
+        storage::{MemoryStorage, Storage},
+        task_progress::TaskProgress,
+        test_utils::{
+            create_database, data_file_path, date, day_entries, derived_files, entry, tags,
+            temp_dir,
+        },
+    };
+
+    /// Opens a second instance of `database` sharing its storage.
+    fn second_instance(database: &DataBase) -> DataBase {
+        DataBase::load_with_storage(
+            database.database_path().to_path_buf(),
+            database.path().shared_storage(),
+        )
+        .unwrap()
+    }
+
+    //
+
+    //
+
+    #[test]
+    fn cached_tag_list_is_reloaded_after_another_instance_changes_it() {
+        let database = create_database("tag_cache", 0);
+        let other = second_instance(&database);
+        database.add_tag("a".to_string()).unwrap();
+
+        other.add_tag("b".to_string()).unwrap();
+        // `database` cached the tag list before `other` added its tag.
+        database.add_tag("c".to_string()).unwrap();
+        let expected = vec![
+            (1, "a".to_string()),
+            (2, "b".to_string()),
+            (3, "c".to_string()),
+        ];
+        assert_eq!(tags(&database), expected);
+        assert!(other.add_tag("c".to_string()).is_err());
+    }
+
+    #[test]
+    fn cancelled_task_keeps_the_database_locked_until_resumed() {
+        let database = create_database("cancel_regen", 0);
+        for day in 1..=3 {
+            database
+                .insert_data_entry(2024, 1, day, entry(12, 0, vec![]))
+                .unwrap();
+        }
+
+        let cancel_flag = AtomicBool::new(false);
+        let progress = TaskProgress::new()
+            .with_callback(|processed, _total| {
+                if processed == 1 {
+                    cancel_flag.store(true, Ordering::Relaxed);
+                }
+            })
+            .with_cancel_flag(&cancel_flag);
+        let error = database.regen_caches_with_progress(&progress).unwrap_err();
+        assert!(matches!(error.kind, ErrorKind::Cancelled));
+        assert_eq!(
+            database.lock_info().unwrap().unwrap().task,
+            ActiveTask::RegenerateCaches
+        );
+        let error = database
+            .insert_data_entry(2024, 1, 4, entry(12, 0, vec![]))
+            .unwrap_err();
+        assert!(matches!(error.kind, ErrorKind::DataBaseBusy));
+
+        database.resume_task().unwrap();
+        assert!(database.lock_info().unwrap().is_none());
+        assert!(database.check().unwrap().is_ok());
+    }
+
+    #[test]
+    fn create_writes_a_loadable_database() {
+        let storage: Arc<dyn Storage> = Arc::new(MemoryStorage::new());
+        let db_path = Path::new("/dbs/created");
+        DataBase::create_with_storage(db_path.to_path_buf(), -3, storage.clone()).unwrap();
+
+        let database = DataBase::load_with_storage(db_path.to_path_buf(), storage.clone()).unwrap();
+        assert_eq!(database.settings().day_switch_offset, -3);
+        assert_eq!(database.schema_version(), CURRENT_SCHEMA_VERSION);
+        assert!(
+            storage.is_dir(&db_path.join("data")) && storage.is_dir(&db_path.join("stat_sums"))
+        );
+        assert!(database.check().unwrap().is_ok());
+    }
+
+    #[test]
+    fn create_refuses_invalid_targets() {
+        let storage: Arc<dyn Storage> = Arc::new(MemoryStorage::new());
+        let create = |path: &str, offset| {
+            DataBase::create_with_storage(path.into(), offset, storage.clone())
+                .map(|_| ())
+                .unwrap_err()
+                .kind
+        };
+
+        assert!(matches!(create("/offset", 13), ErrorKind::OffsetOutOfRange));
+        assert!(!storage.exists(Path::new("/offset")));
+
+        storage.create_dir_all(Path::new("/not_empty")).unwrap();
+        storage
+            .write(Path::new("/not_empty/notes.txt"), b"notes")
+            .unwrap();
+        assert!(matches!(
+            create("/not_empty", 0),
+            ErrorKind::DirectoryNotEmpty
+        ));
+        assert_eq!(storage.list(Path::new("/not_empty")).unwrap().len(), 1);
+
+        DataBase::create_with_storage("/existing".into(), 0, storage.clone()).unwrap();
+        assert!(matches!(
+            create("/existing", 2),
+            ErrorKind::DataBaseAlreadyExists
+        ));
+    }
+
+    //
+
+    //
+
+    /// Inserts the same entries into every database in the remove and cache tests. The entries
+    /// span two months of two years.
+    fn insert_entries(database: &DataBase) {
+        for tag in ["a", "b", "c"] {
+            database.add_tag(tag.to_string()).unwrap();
+        }
+        let entries = [
+            (2023, 12, 30, entry(8, 0, vec![0])),
+            (2023, 12, 30, entry(20, 45, vec![0, 1])),
+            (2024, 1, 2, entry(9, 0, vec![2])),
+            (2024, 1, 2, entry(9, 30, vec![1])),
+            (2024, 1, 15, entry(12, 0, vec![])),
+        ];
+        for (year, month, day, data_entry) in entries {
+            database
+                .insert_data_entry(year, month, day, data_entry)
+                .unwrap();
+        }
+    }
+
+    /// Returns the cache files and stat sums of `database` after regenerating them from scratch.
+    fn regenerated_files(database: &DataBase) -> BTreeMap<PathBuf, Vec<u8>> {
+        database.regen_caches().unwrap();
+        database.regen_tag_sums().unwrap();
+        derived_files(database)
+    }
+
+    #[test]
+    fn removing_entries_keeps_stat_sums_and_caches_consistent() {
+        let database = create_database("remove_entry", 0);
+        insert_entries(&database);
+
+        database.remove_data_entry(2024, 1, 2, 9, 30).unwrap();
+        database.remove_data_entry(2023, 12, 30, 20, 45).unwrap();
+        assert_eq!(
+            day_entries(&database, date(2024, Month::January, 2)),
+            vec![(9, 0, vec![2])]
+        );
+
+        let updated = derived_files(&database);
+        assert!(updated.keys().any(|file| file.ends_with("month_cache.txt")));
+        assert!(updated
+            .keys()
+            .any(|file| file.starts_with(database.path().stat_sums())));
+        assert_eq!(updated, regenerated_files(&database));
+    }
+
+    #[test]
+    fn removing_the_last_entry_of_a_day_removes_the_data_file() {
+        let database = create_database("remove_last_entry", 0);
+        insert_entries(&database);
+        let day = date(2024, Month::January, 15);
+        let storage = database.path().storage();
+        assert!(storage.exists(&data_file_path(&database, day)));
+
+        database.remove_data_entry(2024, 1, 15, 12, 0).unwrap();
+        assert!(!storage.exists(&data_file_path(&database, day)));
+        assert!(day_entries(&database, day).is_empty());
+        let updated = derived_files(&database);
+        assert_eq!(updated, regenerated_files(&database));
+    }
+
+    #[test]
+    fn removing_a_missing_entry_fails_without_changes() {
+        let database = create_database("remove_missing_entry", 0);
+        insert_entries(&database);
+        let before = derived_files(&database);
+
+        // No entry at the minute, and no data file for the day.
+        for (day, minute) in [(2, 15), (3, 0)] {
+            let error = database
+                .remove_data_entry(2024, 1, day, 9, minute)
+                .unwrap_err();
+            assert!(matches!(error.kind, ErrorKind::EntryDoesNotExist));
+        }
+        assert_eq!(derived_files(&database), before);
+        assert_eq!(
+            day_entries(&database, date(2024, Month::January, 2)).len(),
+            2
+        );
+    }
+
+    //
+
+    //
+
+    #[test]
+    fn interrupted_merge_is_rolled_forward_on_resume() {
+        let database = create_database("merge_resume", 0);
+        database.add_tag("a".to_string()).unwrap();
+        database.add_tag("b".to_string()).unwrap();
+        for day in 1..=3 {
+            database
+                .insert_data_entry(2024, 1, day, entry(12, 0, vec![1]))
+                .unwrap();
+        }
+
+        // Cancelled once the first data file was rewritten, as if the program stopped there.
+        let cancel_flag = AtomicBool::new(false);
+        let progress = TaskProgress::new()
+            .with_callback(|processed, _total| {
+                if processed == 1 {
+                    cancel_flag.store(true, Ordering::Relaxed);
+                }
+            })
+            .with_cancel_flag(&cancel_flag);
+        let error = database
+            .merge_tags_with_progress(1, 2, &progress)
+            .unwrap_err();
+        assert!(matches!(error.kind, ErrorKind::Cancelled));
+        let journal_path = database.database_path().join(".journal.txt");
+        assert!(database.path().storage().exists(&journal_path));
+        assert_eq!(
+            database.lock_info().unwrap().unwrap().task,
+            ActiveTask::MergeTags(1, 2)
+        );
+        let error = database
+            .insert_data_entry(2024, 1, 4, entry(12, 0, vec![]))
+            .unwrap_err();
+        assert!(matches!(error.kind, ErrorKind::DataBaseBusy));
+
+        database.resume_task().unwrap();
+        assert!(database.lock_info().unwrap().is_none());
+        assert!(!database.path().storage().exists(&journal_path));
+        assert_eq!(tags(&database), vec![(2, "b".to_string())]);
+        for day in 1..=3 {
+            assert_eq!(
+                day_entries(&database, date(2024, Month::January, day)),
+                vec![(12, 0, vec![2])]
+            );
+        }
+        assert!(database.check().unwrap().is_ok());
+    }
+
+    //
+
+    //
+
+    #[test]
+    fn every_write_is_refused_while_a_task_holds_the_lock() {
+        let database = create_database("busy_writes", 0);
+        database.add_tag("a".to_string()).unwrap();
+        database
+            .insert_data_entry(2024, 1, 1, entry(8, 0, vec![1]))
+            .unwrap();
+        let db_status = DBStatus::lock(database.path(), ActiveTask::RegenerateCaches).unwrap();
+
+        let results = [
+            database.insert_data_entry(2024, 1, 1, entry(9, 0, vec![])),
+            database.add_data_entry(2024, 1, 2, entry(9, 0, vec![])),
+            database.remove_data_entry(2024, 1, 1, 8, 0),
+            database.add_tag("b".to_string()),
+            database.rename_tag("a".to_string(), "c".to_string()),
+            database.remove_tag(1),
+        ];
+        for result in results {
+            assert!(matches!(result.unwrap_err().kind, ErrorKind::DataBaseBusy));
+        }
+        db_status.unlock();
+
+        assert_eq!(tags(&database), vec![(1, "a".to_string())]);
+        assert_eq!(
+            day_entries(&database, date(2024, Month::January, 1)),
+            vec![(8, 0, vec![1])]
+        );
+    }
+
+    //
+
+    //
+
+    /// Returns every directory and file below `root` in the file system, with the content of the
+    /// files.
+    fn file_system_snapshot(root: &Path) -> BTreeMap<PathBuf, Option<Vec<u8>>> {
+        walkdir::WalkDir::new(root)
+            .into_iter()
+            .map(|dir_entry| {
+                let path = dir_entry.unwrap().into_path();
+                let content = path.is_file().then(|| std::fs::read(&path).unwrap());
+                (path, content)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn read_only_database_never_writes_to_its_folder() {
+        let dir = temp_dir("read_only");
+        let db_root = dir.join("database");
+        let database = DataBase::create(db_root.clone(), 0).unwrap();
+        database.add_tag("a".to_string()).unwrap();
+        database
+            .insert_data_entry(2024, 1, 1, entry(8, 0, vec![1]))
+            .unwrap();
+        drop(database);
+        let writers_path = db_root.join(".writers");
+        if writers_path.exists() {
+            std::fs::remove_dir_all(&writers_path).unwrap();
+        }
+        let before = file_system_snapshot(&dir);
+
+        let database = DataBase::open_read_only(db_root.clone()).unwrap();
+        assert_eq!(
+            day_entries(&database, date(2024, Month::January, 1)),
+            vec![(8, 0, vec![1])]
+        );
+        // Neither reading a day without data file nor a range creates any folders.
+        assert!(day_entries(&database, date(2025, Month::June, 1)).is_empty());
+        let range = database
+            .entries_between(
+                date(2023, Month::January, 1),
+                date(2025, Month::December, 31),
+            )
+            .unwrap();
+        assert_eq!(range.count(), 1);
+        assert_eq!(database.data_files().unwrap().len(), 1);
+        assert!(database.check().unwrap().is_ok());
+        assert!(database.lock_info().unwrap().is_none());
+        assert_eq!(database.metrics().unwrap().len(), 2);
+
+        let results = [
+            database.insert_data_entry(2024, 1, 2, entry(8, 0, vec![])),
+            database.remove_data_entry(2024, 1, 1, 8, 0),
+            database.add_tag("b".to_string()),
+            database.merge_tags(1, 1),
+            database.regen_caches(),
+            database.repair().map(|_| ()),
+            database.break_lock(),
+        ];
+        for result in results {
+            assert!(matches!(result.unwrap_err().kind, ErrorKind::ReadOnly));
+        }
+        drop(database);
+
+        assert_eq!(file_system_snapshot(&dir), before);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    //
+
+    //
+
+    #[test]
+    fn file_changes_are_detected_from_the_metadata_first() {
+        let dir = temp_dir("file_state");
+        let database = DataBase::create(dir.join("database"), 0).unwrap();
+        let file_path = database.database_path().join("state.txt");
+        let set_modified = |modified| {
+            let file = std::fs::File::options()
+                .write(true)
+                .open(&file_path)
+                .unwrap();
+            file.set_modified(modified).unwrap();
+        };
+
+        // A rewrite keeping the length and modification time of a recently modified file.
+        std::fs::write(&file_path, b"first").unwrap();
+        let mut recorded = file_state(database.path(), "state.txt");
+        let modified = recorded.unwrap().modified;
+        assert!(recorded.unwrap().hash.is_some());
+        std::fs::write(&file_path, b"other").unwrap();
+        set_modified(modified);
+        assert!(file_changed(database.path(), "state.txt", &mut recorded));
+
+        // The content of a file modified long ago is never read.
+        let an_hour_ago = std::time::SystemTime::now() - std::time::Duration::from_secs(3600);
+        set_modified(an_hour_ago);
+        let mut recorded = file_state(database.path(), "state.txt");
+        assert!(recorded.unwrap().hash.is_none());
+        assert!(!file_changed(database.path(), "state.txt", &mut recorded));
+        std::fs::write(&file_path, b"third").unwrap();
+        assert!(file_changed(database.path(), "state.txt", &mut recorded));
+
+        std::fs::remove_file(&file_path).unwrap();
+        assert!(file_changed(database.path(), "state.txt", &mut recorded));
+        let mut missing = None;
+        assert!(!file_changed(database.path(), "state.txt", &mut missing));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn cached_metrics_follow_changes_made_by_another_instance() {
+        let database = create_database("cached_metrics", 0);
+        let other = DataBase::load_with_storage(
+            database.database_path().to_path_buf(),
+            database.path().shared_storage(),
+        )
+        .unwrap();
+        assert_eq!(database.metrics().unwrap().len(), 2);
+
+        let metric_id = other.add_metric("Sleep".to_string(), 0, 12).unwrap();
+        let mut new_entry = entry(8, 30, vec![]);
+        new_entry.metrics.insert(metric_id, 7);
+        database.add_data_entry(2024, 1, 1, new_entry).unwrap();
+
+        other
+            .rename_metric("Sleep".to_string(), "Rest".to_string())
+            .unwrap();
+        let metric = database.metrics().unwrap().pop().unwrap();
+        assert_eq!((metric.id, metric.name.as_str()), (metric_id, "Rest"));
+    }
+}
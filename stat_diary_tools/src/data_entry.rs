@@ -1,15 +1,21 @@
 use std::{
-    collections::HashMap,
+    collections::{BTreeMap, HashMap},
     ffi::OsStr,
-    fs::{self, File},
-    io::{self, BufWriter, Read, Write},
+    io,
     path::{Path, PathBuf},
+    sync::Arc,
 };
 
 use log::warn;
+use time::{Date, Duration, Month, PrimitiveDateTime};
 
 use crate::{
-    cache_handling::Overview, settings_file::Settings, DATAFILEEXTENSION, DIARYFILEEXTENSION,
+    cache_handling::Overview,
+    db_path::DataBasePath,
+    metrics::{MENTALSCORE, PHYSICALSCORE},
+    settings_file::Settings,
+    storage::Storage,
+    DATAFILEEXTENSION, DIARYFILEEXTENSION,
 };
 
 /*
@@ -45,6 +51,9 @@ pub enum Error {
     CorruptedDataFile,
     InvalidDate,
     InvalidData,
+    /// The path of a data file does not follow the `year/month/day-weekday_nr` layout, or the
+    /// weekday number does not match the date.
+    MisplacedDataFile,
 }
 
 impl From<io::Error> for Error {
@@ -53,10 +62,57 @@ impl From<io::Error> for Error {
     }
 }
 
-/// Contains all data entries for one data file and the filepath to said file.
+/// The bytes every data file storing entry minutes, but not metric values, starts with. Data
+/// files written before minutes were stored begin directly with their first entry, whose second
+/// byte is a score of at most 100, so they never start with this marker.
+const MINUTEFORMATMARKER: [u8; 2] = [0xFF, 0xFF];
+/// The bytes every data file storing metric values starts with. See `MINUTEFORMATMARKER`.
+const METRICFORMATMARKER: [u8; 2] = [0xFF, 0xFE];
+
+/// The layouts data files have been stored in, from oldest to newest.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum DataFileFormat {
+    /// Each entry holds its hour, mental score and physical score.
+    Hours,
+    /// Each entry holds its hour, minute, mental score and physical score. The file starts with
+    /// `MINUTEFORMATMARKER`.
+    Minutes,
+    /// Each entry holds its hour, minute and the number of metric values it has, followed by the
+    /// id and value of each metric. The file starts with `METRICFORMATMARKER`.
+    Metrics,
+}
+
+impl DataFileFormat {
+    /// Returns the format of the data file holding `bytes`.
+    pub(crate) fn detect(bytes: &[u8]) -> DataFileFormat {
+        if bytes.starts_with(&METRICFORMATMARKER) {
+            DataFileFormat::Metrics
+        } else if bytes.starts_with(&MINUTEFORMATMARKER) {
+            DataFileFormat::Minutes
+        } else {
+            DataFileFormat::Hours
+        }
+    }
+
+    /// Returns the number of bytes before the first entry of a file in this format.
+    fn header_length(self) -> usize {
+        match self {
+            DataFileFormat::Hours => 0,
+            DataFileFormat::Minutes => MINUTEFORMATMARKER.len(),
+            DataFileFormat::Metrics => METRICFORMATMARKER.len(),
+        }
+    }
+}
+
+/// Contains all data entries for one data file, the filepath to said file, the date of the day
+/// the file holds and the storage the file is saved to.
+///
+/// The entries are keyed by their offset-compensated hour and minute. See `DataEntry::time`.
 pub struct DataFile {
-    entries: HashMap<u8, DataEntry>,
+    entries: HashMap<(i8, u8), DataEntry>,
     file_path: PathBuf,
+    date: Date,
+    storage: Arc<dyn Storage>,
 }
 
 /// Reads the byte at the provided index in the list of bytes, returning the byte or a
@@ -65,83 +121,149 @@ fn read_at_index(bytes: &[u8], index: usize) -> Result<&u8, Error> {
     bytes.get(index).ok_or(Error::CorruptedDataFile)
 }
 
+/// Reads the big endian u16 starting at the provided index in the list of bytes. See
+/// `read_at_index`.
+fn read_u16_at_index(bytes: &[u8], index: usize) -> Result<u16, Error> {
+    Ok(((*read_at_index(bytes, index)? as u16) << 8) | *read_at_index(bytes, index + 1)? as u16)
+}
+
+/// Parses the entry at the start of `bytes`, returning it together with the number of bytes it
+/// takes up. Entries of data files stored before metric values were stored hold the mental and
+/// physical score, which are read as the built-in metrics. See `DataFileFormat`.
+fn parse_entry(bytes: &[u8], format: DataFileFormat) -> Result<(DataEntry, usize), Error> {
+    let hour = read_at_index(bytes, 0)?;
+    let (minute, mut i) = match format {
+        DataFileFormat::Hours => (0, 1),
+        DataFileFormat::Minutes | DataFileFormat::Metrics => (*read_at_index(bytes, 1)?, 2),
+    };
+
+    let mut metrics = BTreeMap::new();
+    if format == DataFileFormat::Metrics {
+        let metric_count = *read_at_index(bytes, i)?;
+        i += 1;
+        for _ in 0..metric_count {
+            let metric_id = read_u16_at_index(bytes, i)?;
+            let value = read_u16_at_index(bytes, i + 2)? as i16;
+            i += 4;
+            if metrics.insert(metric_id, value).is_some() {
+                return Err(Error::CorruptedDataFile);
+            }
+        }
+    } else {
+        metrics.insert(MENTALSCORE, *read_at_index(bytes, i)? as i16);
+        metrics.insert(PHYSICALSCORE, *read_at_index(bytes, i + 1)? as i16);
+        i += 2;
+    }
+
+    let mut tags = Vec::new();
+    loop {
+        let tag_id = read_u16_at_index(bytes, i)?;
+        i += 2;
+        if tag_id == u16::MAX {
+            break;
+        }
+
+        tags.push(tag_id);
+    }
+
+    let data_entry = DataEntry::new(*hour as i8, metrics, tags).with_minute(minute);
+    Ok((data_entry, i))
+}
+
 impl DataFile {
     /// Reads all entries in the provided file and returns a list of assembled DataEntry structs
-    pub fn read_from_file(date_path: &Path) -> Result<DataFile, Error> {
+    ///
+    /// The hours and minutes of the entries are not validated, so that `DataBase::check` can report
+    /// entries outside of the day. Entries are validated before they are written instead.
+    ///
+    /// Returns a `MisplacedDataFile` error if the path of the file does not match its date.
+    pub fn read_from_file(db_path: &DataBasePath, date_path: &Path) -> Result<DataFile, Error> {
+        let (data_file, lost_bytes) = DataFile::salvage_from_file(db_path, date_path)?;
+        if lost_bytes > 0 {
+            return Err(Error::CorruptedDataFile);
+        }
+        Ok(data_file)
+    }
+
+    /// Same as `read_from_file`, except that a file cut short does not result in a
+    /// `CorruptedDataFile` error. Every complete entry before the damage is returned, together
+    /// with the number of bytes at the end of the file that could not be read.
+    ///
+    /// Returns a `MisplacedDataFile` error if the path of the file does not match its date.
+    pub fn salvage_from_file(
+        db_path: &DataBasePath,
+        date_path: &Path,
+    ) -> Result<(DataFile, usize), Error> {
         let file_path = date_path.with_extension(DATAFILEEXTENSION);
-        let bytes: Vec<u8> = io::BufReader::new(File::open(&file_path)?)
-            .bytes()
-            .map_while(Result::ok)
-            .collect();
+        let date = DataFile::checked_date(&file_path)?;
+        let bytes = db_path.storage().read(&file_path)?;
 
-        let mut i = 0;
+        let format = DataFileFormat::detect(&bytes);
+        let mut i = format.header_length();
 
         let mut entries = HashMap::new();
 
         while i < bytes.len() {
-            let hour = read_at_index(&bytes, i)?;
-            let mental_score = read_at_index(&bytes, i + 1)?;
-            let physical_score = read_at_index(&bytes, i + 2)?;
-
-            let mut tags = Vec::new();
-            i += 3;
-            loop {
-                let tag_id = ((*read_at_index(&bytes, i)? as u16) << 8)
-                    | *read_at_index(&bytes, i + 1)? as u16;
-                if tag_id == u16::MAX {
-                    i += 2;
-                    break;
-                }
-                i += 2;
-
-                tags.push(tag_id);
-            }
-
-            let data_entry = DataEntry::new(*hour, *mental_score, *physical_score, tags);
-            entries.insert(*hour, data_entry);
+            let Ok((data_entry, length)) = parse_entry(&bytes[i..], format) else {
+                break;
+            };
+            i += length;
+            entries.insert(data_entry.time(), data_entry);
         }
 
-        Ok(DataFile {
+        let data_file = DataFile {
             entries,
             file_path: file_path.to_path_buf(),
-        })
+            date,
+            storage: db_path.shared_storage(),
+        };
+        Ok((data_file, bytes.len() - i))
     }
 
-    pub fn open_data_file(date_path: &Path) -> Result<DataFile, Error> {
+    /// Reads the data file of the day at `date_path`, or returns a empty data file for the day if
+    /// none exists yet. Nothing is written until the data file is saved.
+    pub fn open_data_file(db_path: &DataBasePath, date_path: &Path) -> Result<DataFile, Error> {
         let file_path = date_path.with_extension(DATAFILEEXTENSION);
-        let Some(file_dir) = file_path.parent() else {
+        if file_path.parent().is_none() {
             log::warn!(
                 "DataFile::open_data_file(): {:?}.parent() was None!",
                 file_path
             );
             return Err(Error::InvalidDate);
-        };
-        fs::create_dir_all(file_dir)?;
+        }
+        let date = DataFile::checked_date(&file_path)?;
 
-        if file_path.exists() {
-            return DataFile::read_from_file(&file_path);
+        if db_path.storage().exists(&file_path) {
+            return DataFile::read_from_file(db_path, &file_path);
         }
 
         let datafile = DataFile {
             entries: HashMap::new(),
             file_path,
+            date,
+            storage: db_path.shared_storage(),
         };
 
         Ok(datafile)
     }
 
-    /// Inserts the new entry at its hour index, overwriting any existing entry already there.
+    /// Inserts the new entry at its hour and minute, overwriting any existing entry already there.
     pub fn overwrite_entry(&mut self, new_entry: DataEntry) -> Option<DataEntry> {
-        self.entries.insert(new_entry.hour, new_entry)
+        self.entries.insert(new_entry.time(), new_entry)
+    }
+
+    /// Removes and returns the entry at the provided `hour` and `minute`, if one exists.
+    pub fn remove_entry(&mut self, hour: i8, minute: u8) -> Option<DataEntry> {
+        self.entries.remove(&(hour, minute))
     }
 
     /// Adds the new entry to the datafile.
-    /// Returns an error if a entry already exist with the same hour.
+    /// Returns an error if a entry already exist with the same hour and minute.
     pub fn add_entry(&mut self, new_entry: DataEntry) -> Result<(), Error> {
-        if self.entries.contains_key(&new_entry.hour) {
+        if self.entries.contains_key(&new_entry.time()) {
             return Err(Error::EntryAlreadyExists);
         }
-        self.entries.insert(new_entry.hour, new_entry);
+        self.entries.insert(new_entry.time(), new_entry);
         Ok(())
     }
 
@@ -149,13 +271,45 @@ impl DataFile {
 
     //
 
+    /// Reads the date of a data or diary file from its path. The path is expected to end with
+    /// `year/month_number/day-weekday_nr`. Returns `None` if the path does not follow said layout,
+    /// or if the weekday number does not match the date.
+    pub fn date_from_path(file_path: &Path) -> Option<Date> {
+        let (day, weekday_nr) = file_path.file_stem()?.to_str()?.split_once('-')?;
+        let month_path = file_path.parent()?;
+        let month = month_path.file_name()?.to_str()?;
+        let year = month_path.parent()?.file_name()?.to_str()?;
+
+        let month = Month::try_from(month.parse::<u8>().ok()?).ok()?;
+        let date = Date::from_calendar_date(year.parse().ok()?, month, day.parse().ok()?).ok()?;
+
+        let weekday_nr = weekday_nr.parse::<u8>().ok()?;
+        (weekday_nr == date.weekday().number_days_from_monday()).then_some(date)
+    }
+
+    /// Returns the date of the data file at `file_path`, logging and returning a
+    /// `MisplacedDataFile` error if the path does not match a valid date.
+    fn checked_date(file_path: &Path) -> Result<Date, Error> {
+        DataFile::date_from_path(file_path).ok_or_else(|| {
+            log::error!(
+                "DataFile: The data file {:?} is misplaced or misnamed! Expected a path ending with year/month/day-weekday_nr.",
+                file_path
+            );
+            Error::MisplacedDataFile
+        })
+    }
+
+    //
+
+    //
+
     /// Returns true if the proivded file is a data file and false if it isn't.
     /// Will log the reason a file is decided to not be a data file to the logger.
     /// If a non-datafile of expected name is encountered this will return false without
     /// logging the reason.
-    pub fn is_data_file(file: &Path) -> bool {
+    pub fn is_data_file(storage: &dyn Storage, file: &Path) -> bool {
         // A directory can not be a datafile.
-        if file.is_dir() {
+        if storage.is_dir(file) {
             //info!("DataFile: Ignoring directory: {:?}", file);
             return false;
         }
@@ -188,8 +342,13 @@ impl DataFile {
 
     //
 
-    /// Returns a reference to the internal HashMap of data entries.
-    pub fn entries(&self) -> &HashMap<u8, DataEntry> {
+    /// Returns the date of the day this data file holds.
+    pub fn date(&self) -> Date {
+        self.date
+    }
+
+    /// Returns a reference to the internal HashMap of data entries, keyed by their hour and minute.
+    pub fn entries(&self) -> &HashMap<(i8, u8), DataEntry> {
         &self.entries
     }
 
@@ -197,6 +356,27 @@ impl DataFile {
 
     //
 
+    /// Converts the wall-clock hours of a data file written before hours were compensated into
+    /// offset-compensated hours. See `DataEntry::compensate_hour`.
+    ///
+    /// Hours that are already compensated are left as they are, meaning it is safe to call this
+    /// more than once on the same file.
+    pub fn compensate_hours(&mut self, day_switch_offset: i8) -> &mut Self {
+        self.entries = self
+            .entries
+            .drain()
+            .map(|(_, mut data_entry)| {
+                data_entry.hour = DataEntry::compensate_hour(data_entry.hour, day_switch_offset);
+                (data_entry.time(), data_entry)
+            })
+            .collect();
+        self
+    }
+
+    //
+
+    //
+
     /// Merges tag_2 into tag_1 in each data entry in this file.
     /// One way to visualise what this does is to imagine that the id of tag_2 is changed to the
     /// same as tag_1, after which any duplicate ids are removed.
@@ -223,24 +403,44 @@ impl DataFile {
 
     //
 
+    /// Returns the path of this data file.
+    pub fn file_path(&self) -> &Path {
+        &self.file_path
+    }
+
     /// Saves this data file to the location it was read from. The old file is overwritten.
+    /// Data files are always saved in the newest `DataFileFormat`, even if they were read from a
+    /// file in a older format.
+    ///
+    /// If the data file has no entries left the file is removed instead. The year and month
+    /// folders of the file are created if they don't exist yet.
     pub fn save(&mut self) -> Result<(), io::Error> {
-        let mut tmp_path = self.file_path.clone();
-        tmp_path.add_extension("tmp");
-
-        let new_file = File::create(&tmp_path)?;
-        let mut writer = BufWriter::new(new_file);
+        if self.entries.is_empty() {
+            if self.storage.exists(&self.file_path) {
+                self.storage.remove_file(&self.file_path)?;
+            }
+            return Ok(());
+        }
 
-        for data_entry in self.entries.values() {
-            data_entry.write(&mut writer)?;
+        if let Some(file_dir) = self.file_path.parent() {
+            self.storage.create_dir_all(file_dir)?;
         }
-        writer.flush()?;
 
-        fs::rename(tmp_path, &self.file_path)?;
+        self.storage
+            .write_atomic(&self.file_path, &self.to_bytes()?)?;
 
         Ok(())
     }
 
+    /// Returns the content of this data file as it is saved, in the newest `DataFileFormat`.
+    pub(crate) fn to_bytes(&self) -> Result<Vec<u8>, io::Error> {
+        let mut bytes = METRICFORMATMARKER.to_vec();
+        for data_entry in self.entries.values() {
+            data_entry.write(&mut bytes)?;
+        }
+        Ok(bytes)
+    }
+
     //
 
     //
@@ -248,8 +448,9 @@ impl DataFile {
     pub fn get_overview(&self) -> Overview {
         let mut overview = Overview::default();
         for data in self.entries().values() {
-            overview.m_score.add(data.mental_score);
-            overview.p_score.add(data.physical_score);
+            for (metric_id, value) in &data.metrics {
+                overview.metrics.entry(*metric_id).or_default().add(*value);
+            }
 
             for tag in &data.tags {
                 overview.tags.insert(*tag);
@@ -257,16 +458,6 @@ impl DataFile {
         }
         overview
     }
-
-    //
-
-    //
-
-    /*
-    /// Returns the date component of the data file pa
-    pub fn get_day(&self) -> (u8, u8) {
-        todo!();
-    }*/
 }
 
 //
@@ -274,25 +465,70 @@ impl DataFile {
 //
 
 /// Contains one statdiary data entry.
-#[derive(Clone)]
+///
+/// `hour` is offset-compensated, meaning it is the number of hours since midnight at the start of
+/// the date of the data file the entry belongs to. With a day_switch_offset of +4 the day runs
+/// from 04:00 to 04:00 the next morning, so an entry at 02:00 the next morning has the hour 26.
+/// With a negative offset the hours before midnight of the previous evening are negative.
+/// The hour therefore always lies in `day_switch_offset..day_switch_offset + 24`.
+///
+/// `minute` is the minute within the hour, in the range 0 to 59. Entries written before minutes
+/// were stored have the minute 0.
+///
+/// `metrics` holds the value of each metric the entry has, keyed by metric id. An entry does not
+/// need a value for every metric of the database. Entries written before metrics were
+/// user-defined hold the built-in mental and physical score. See `MetricList`.
+#[derive(Clone, PartialEq, Eq)]
 pub struct DataEntry {
-    pub hour: u8,
-    pub mental_score: u8,
-    pub physical_score: u8,
+    pub hour: i8,
+    pub minute: u8,
+    pub metrics: BTreeMap<u16, i16>,
     pub tags: Vec<u16>,
 }
 
 impl DataEntry {
-    pub fn new(hour: u8, mental_score: u8, physical_score: u8, tags: Vec<u16>) -> DataEntry {
+    /// Creates a entry at the start of `hour`. Use `with_minute` to place it later in the hour.
+    pub fn new(hour: i8, metrics: BTreeMap<u16, i16>, tags: Vec<u16>) -> DataEntry {
         DataEntry {
             hour,
-            mental_score,
-            physical_score,
+            minute: 0,
+            metrics,
             tags,
         }
     }
 
-    pub fn from_c_data(data: &[u16], hour: u8, db_settings: &Settings) -> Result<DataEntry, Error> {
+    /// Sets the minute within the hour of this entry.
+    pub fn with_minute(mut self, minute: u8) -> DataEntry {
+        self.minute = minute;
+        self
+    }
+
+    /// Creates a `DataEntry` from the data passed through the C API. `hour` is the wall-clock hour
+    /// of the entry, and is compensated using the day_switch_offset of `db_settings`.
+    ///
+    /// `data` holds the mental and physical score followed by the tags, while `metrics` holds the
+    /// id and value of any further metric. The values are checked against the ranges of their
+    /// metrics when the entry is added to the database.
+    pub fn from_c_data(
+        data: &[u16],
+        metrics: &[(u16, i16)],
+        hour: u8,
+        minute: u8,
+        db_settings: &Settings,
+    ) -> Result<DataEntry, Error> {
+        if hour >= 24 {
+            log::error!("DataEntry::from_c_data(): Hour [{}] is out of range!", hour);
+            return Err(Error::InvalidData);
+        }
+        if !DataEntry::is_valid_minute(minute) {
+            log::error!(
+                "DataEntry::from_c_data(): Minute [{}] is out of range!",
+                minute
+            );
+            return Err(Error::InvalidData);
+        }
+        let hour = DataEntry::compensate_hour(hour as i8, db_settings.day_switch_offset);
+
         if data.len() < 2 {
             log::error!("DataEntry::from_c_data(): Data array was too short! Len was {} when 2 is mandatory!", data.len());
             return Err(Error::InvalidData);
@@ -311,8 +547,18 @@ impl DataEntry {
         };
         */
 
-        let m_score = Self::validate_score(data[0])?;
-        let p_score = Self::validate_score(data[1])?;
+        let mut metric_values = BTreeMap::new();
+        metric_values.insert(MENTALSCORE, Self::validate_score(data[0])?);
+        metric_values.insert(PHYSICALSCORE, Self::validate_score(data[1])?);
+        for &(metric_id, value) in metrics {
+            if metric_values.insert(metric_id, value).is_some() {
+                log::error!(
+                    "DataEntry::from_c_data(): Metric [{}] was provided more than once!",
+                    metric_id
+                );
+                return Err(Error::InvalidData);
+            }
+        }
 
         let mut tags = Vec::new();
 
@@ -320,18 +566,65 @@ impl DataEntry {
             tags.push(*tag);
         }
 
-        Ok(DataEntry::new(hour, m_score, p_score, tags))
+        Ok(DataEntry::new(hour, metric_values, tags).with_minute(minute))
+    }
+
+    /// Converts a wall-clock `hour` into the offset-compensated hour used within a day file when
+    /// the day begins `day_switch_offset` hours after midnight.
+    ///
+    /// Hours already in the compensated range are returned unchanged.
+    pub fn compensate_hour(hour: i8, day_switch_offset: i8) -> i8 {
+        if day_switch_offset > 0 && hour < day_switch_offset {
+            hour + 24
+        } else if day_switch_offset < 0 && hour >= 24 + day_switch_offset {
+            hour - 24
+        } else {
+            hour
+        }
+    }
+
+    /// Returns whether `hour` is a valid offset-compensated hour for the provided
+    /// `day_switch_offset`.
+    pub fn is_valid_hour(hour: i8, day_switch_offset: i8) -> bool {
+        (day_switch_offset..day_switch_offset + 24).contains(&hour)
+    }
+
+    /// Returns whether `minute` is a valid minute within a hour.
+    pub fn is_valid_minute(minute: u8) -> bool {
+        minute < 60
+    }
+
+    /// Returns the offset-compensated hour and the minute of this entry, which together identify
+    /// the entry within its data file.
+    pub fn time(&self) -> (i8, u8) {
+        (self.hour, self.minute)
+    }
+
+    /// Returns the wall-clock hour of this entry, in the range 0 to 23.
+    pub fn wall_clock_hour(&self) -> u8 {
+        self.hour.rem_euclid(24) as u8
+    }
+
+    /// Returns the wall-clock date and time of this entry, where `date` is the date of the data
+    /// file the entry belongs to.
+    pub fn wall_clock_datetime(&self, date: Date) -> PrimitiveDateTime {
+        date.midnight() + Duration::hours(self.hour as i64) + Duration::minutes(self.minute as i64)
+    }
+
+    /// Returns the value of the metric with the provided id, or `None` if this entry has no value
+    /// for it.
+    pub fn metric(&self, metric_id: u16) -> Option<i16> {
+        self.metrics.get(&metric_id).copied()
     }
 
-    fn validate_score(score: u16) -> Result<u8, Error> {
-        if !(0..=100).contains(&score) {
+    fn validate_score(score: u16) -> Result<i16, Error> {
+        i16::try_from(score).map_err(|_| {
             log::error!(
                 "DataEntry::from_c_data(): Score [{}] is out of range!",
                 score
             );
-            return Err(Error::InvalidData);
-        }
-        Ok(score as u8)
+            Error::InvalidData
+        })
     }
 
     //
@@ -380,8 +673,20 @@ impl DataEntry {
 
     /// Writes this data_entry in bytes to the provided writer. Ending the write with a 2 byte
     /// u16::MAX marker.
+    ///
+    /// Fails if the entry holds more than 255 metric values.
     pub fn write(&self, writer: &mut impl io::Write) -> Result<(), io::Error> {
-        writer.write_all(&[self.hour, self.mental_score, self.physical_score])?;
+        let Ok(metric_count) = u8::try_from(self.metrics.len()) else {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "A data entry can hold at most 255 metric values!",
+            ));
+        };
+        writer.write_all(&[self.hour as u8, self.minute, metric_count])?;
+        for (metric_id, value) in &self.metrics {
+            writer.write_all(&metric_id.to_be_bytes())?;
+            writer.write_all(&value.to_be_bytes())?;
+        }
 
         for tag_id in &self.tags {
             writer.write_all(&tag_id.to_be_bytes())?;
@@ -391,3 +696,216 @@ impl DataEntry {
         Ok(())
     }
 }
+
+//
+
+//
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use time::{Month, PrimitiveDateTime, Time};
+
+    use super::{DataEntry, DataFile, DataFileFormat, Error};
+    use crate::test_utils::{create_database, data_file_path, date, entry};
+
+    //
+
+    //
+
+    #[test]
+    fn hours_are_compensated_relative_to_the_day_switch_offset() {
+        assert_eq!(DataEntry::compensate_hour(2, 4), 26);
+        assert_eq!(DataEntry::compensate_hour(4, 4), 4);
+        assert_eq!(DataEntry::compensate_hour(26, 4), 26);
+        assert_eq!(DataEntry::compensate_hour(22, -3), -2);
+        assert_eq!(DataEntry::compensate_hour(20, -3), 20);
+        assert_eq!(DataEntry::compensate_hour(23, 0), 23);
+
+        assert!(DataEntry::is_valid_hour(27, 4));
+        assert!(!DataEntry::is_valid_hour(28, 4));
+        assert!(!DataEntry::is_valid_hour(3, 4));
+        assert!(DataEntry::is_valid_hour(-3, -3));
+        assert!(!DataEntry::is_valid_hour(21, -3));
+    }
+
+    #[test]
+    fn wall_clock_time_is_restored_from_the_compensated_hour() {
+        let day = date(2024, Month::January, 1);
+
+        let late_entry = entry(26, 15, vec![]);
+        assert_eq!(late_entry.wall_clock_hour(), 2);
+        assert_eq!(
+            late_entry.wall_clock_datetime(day),
+            PrimitiveDateTime::new(
+                date(2024, Month::January, 2),
+                Time::from_hms(2, 15, 0).unwrap()
+            )
+        );
+
+        let early_entry = entry(-2, 0, vec![]);
+        assert_eq!(early_entry.wall_clock_hour(), 22);
+        assert_eq!(
+            early_entry.wall_clock_datetime(day),
+            PrimitiveDateTime::new(
+                date(2023, Month::December, 31),
+                Time::from_hms(22, 0, 0).unwrap()
+            )
+        );
+    }
+
+    #[test]
+    fn compensated_hours_survive_saving_and_reading() {
+        let database = create_database("compensated_hours", -3);
+        let file_path = data_file_path(&database, date(2024, Month::January, 1));
+        let mut data_file = DataFile::open_data_file(database.path(), &file_path).unwrap();
+        data_file.add_entry(entry(-2, 0, vec![1])).unwrap();
+        data_file.add_entry(entry(20, 0, vec![])).unwrap();
+        data_file.save().unwrap();
+
+        let data_file = DataFile::read_from_file(database.path(), &file_path).unwrap();
+        let mut hours: Vec<i8> = data_file.entries().keys().map(|(hour, _)| *hour).collect();
+        hours.sort();
+        assert_eq!(hours, vec![-2, 20]);
+    }
+
+    #[test]
+    fn legacy_wall_clock_hours_are_compensated_once() {
+        let database = create_database("legacy_hours", 4);
+        let file_path = data_file_path(&database, date(2024, Month::January, 1));
+        let mut data_file = DataFile::open_data_file(database.path(), &file_path).unwrap();
+        data_file.add_entry(entry(2, 0, vec![])).unwrap();
+        data_file.add_entry(entry(10, 0, vec![])).unwrap();
+
+        data_file.compensate_hours(4).compensate_hours(4);
+        let mut hours: Vec<i8> = data_file.entries().keys().map(|(hour, _)| *hour).collect();
+        hours.sort();
+        assert_eq!(hours, vec![10, 26]);
+    }
+
+    //
+
+    //
+
+    #[test]
+    fn date_is_read_from_the_path_of_the_data_file() {
+        let date_from_path = |path: &str| DataFile::date_from_path(Path::new(path));
+
+        // The 1st of January 2024 is a monday.
+        assert_eq!(
+            date_from_path("/db/data/2024/1/1-0.statdiary"),
+            Some(date(2024, Month::January, 1))
+        );
+        assert_eq!(
+            date_from_path("/db/data/2024/2/29-3.statdiary"),
+            Some(date(2024, Month::February, 29))
+        );
+        assert_eq!(date_from_path("/db/data/2024/1/1-1.statdiary"), None);
+        assert_eq!(date_from_path("/db/data/2023/2/29-2.statdiary"), None);
+        assert_eq!(date_from_path("/db/data/2024/13/1-0.statdiary"), None);
+        assert_eq!(date_from_path("/db/data/2024/jan/1-0.statdiary"), None);
+        assert_eq!(date_from_path("/db/data/2024/1/1.statdiary"), None);
+        assert_eq!(date_from_path("1-0.statdiary"), None);
+    }
+
+    #[test]
+    fn data_file_knows_its_own_date() {
+        let database = create_database("data_file_date", 0);
+        let day = date(2024, Month::March, 5);
+        let file_path = data_file_path(&database, day);
+        let mut data_file = DataFile::open_data_file(database.path(), &file_path).unwrap();
+        assert_eq!(data_file.date(), day);
+        data_file.add_entry(entry(8, 0, vec![])).unwrap();
+        data_file.save().unwrap();
+
+        let data_file = DataFile::read_from_file(database.path(), &file_path).unwrap();
+        assert_eq!(data_file.date(), day);
+    }
+
+    #[test]
+    fn misplaced_data_files_are_refused() {
+        let database = create_database("misplaced_data_file", 0);
+        let storage = database.path().storage();
+        let day = date(2024, Month::March, 5);
+        let mut data_file =
+            DataFile::open_data_file(database.path(), &data_file_path(&database, day)).unwrap();
+        data_file.add_entry(entry(8, 0, vec![])).unwrap();
+        data_file.save().unwrap();
+
+        // The file of the 5th moved into the folder of April, where it names a different weekday.
+        let moved_path = database.path().data().join("2024/4/5-1.statdiary");
+        storage
+            .create_dir_all(moved_path.parent().unwrap())
+            .unwrap();
+        storage
+            .rename(&data_file_path(&database, day), &moved_path)
+            .unwrap();
+
+        assert!(matches!(
+            DataFile::read_from_file(database.path(), &moved_path),
+            Err(Error::MisplacedDataFile)
+        ));
+        assert!(matches!(
+            DataFile::salvage_from_file(database.path(), &moved_path),
+            Err(Error::MisplacedDataFile)
+        ));
+        assert!(matches!(
+            DataFile::open_data_file(database.path(), &moved_path),
+            Err(Error::MisplacedDataFile)
+        ));
+    }
+
+    #[test]
+    fn data_file_formats_are_detected_and_saved_in_the_newest_format() {
+        let database = create_database("formats", 0);
+        let storage = database.path().storage();
+        let expected = entry(5, 30, vec![1]);
+
+        let hours = vec![5, 60, 70, 0, 1, 0xFF, 0xFF];
+        let minutes = vec![0xFF, 0xFF, 5, 30, 60, 70, 0, 1, 0xFF, 0xFF];
+        let metrics = vec![
+            0xFF, 0xFE, 5, 30, 2, 0, 0, 0, 60, 0, 1, 0, 70, 0, 1, 0xFF, 0xFF,
+        ];
+        let files = [
+            (date(2024, Month::March, 1), hours, DataFileFormat::Hours),
+            (
+                date(2024, Month::March, 2),
+                minutes,
+                DataFileFormat::Minutes,
+            ),
+            (
+                date(2024, Month::March, 3),
+                metrics,
+                DataFileFormat::Metrics,
+            ),
+        ];
+
+        for (date, bytes, format) in files {
+            assert_eq!(DataFileFormat::detect(&bytes), format);
+            let file_path = data_file_path(&database, date);
+            storage.create_dir_all(file_path.parent().unwrap()).unwrap();
+            storage.write(&file_path, &bytes).unwrap();
+
+            let mut data_file = DataFile::read_from_file(database.path(), &file_path).unwrap();
+            // Entries stored before minutes were stored start at the beginning of the hour.
+            let minute = if format == DataFileFormat::Hours {
+                0
+            } else {
+                30
+            };
+            let expected = expected.clone().with_minute(minute);
+            assert!(data_file.entries().get(&(5, minute)) == Some(&expected));
+            assert_eq!(data_file.entries().len(), 1);
+
+            data_file.save().unwrap();
+            let saved_bytes = storage.read(&file_path).unwrap();
+            assert_eq!(
+                DataFileFormat::detect(&saved_bytes),
+                DataFileFormat::Metrics
+            );
+            let saved_file = DataFile::read_from_file(database.path(), &file_path).unwrap();
+            assert!(saved_file.entries() == data_file.entries());
+        }
+    }
+}
@@ -1,12 +1,36 @@
-use std::{fs, path::Path};
+use std::{
+    collections::{hash_map::Entry, HashMap},
+    path::{Path, PathBuf},
+};
 
-use crate::data_base::{self, DataBase, Error, ErrorKind};
+use time::Duration;
+
+use crate::{
+    data_base::{DataBase, Error, ErrorKind},
+    data_entry::DataFile,
+    db_path::DataBasePath,
+    db_status::ActiveTask,
+    diary_file::DiaryFile,
+    regeneration::{self, RegenerationTargets},
+    settings_file::Settings,
+    storage::Storage,
+    task_progress::TaskProgress,
+    DIARYFILEEXTENSION,
+};
 
 type Result<T> = std::result::Result<T, Error>;
 
-const TEMPDBPATH: &str = "data_base_tmp";
+const COPYSUFFIX: &str = "offset_change";
+const OLDSUFFIX: &str = "offset_change_old";
 
-pub fn change_day_switch_offset(database: DataBase, new_offset: i8) -> Result<()> {
+/// Changes the day_switch_offset of `database` to `new_offset`, moving every data and diary entry
+/// that crosses the new day boundary into its new day file.
+///
+/// The changes are made on a copy of the database placed next to it. The original is left
+/// untouched until the copy is complete, at which point the copy is swapped in. If the program
+/// stops before the swap the original is still intact, but the leftover copy has to be removed
+/// before the offset can be changed again.
+pub fn change_day_switch_offset(database: &DataBase, new_offset: i8) -> Result<()> {
     if !(-12..=12).contains(&new_offset) {
         log::error!("Attempted to set day_switch_offset to a value outside of -12..=12!");
         return Err(Error::with_kind(ErrorKind::OffsetOutOfRange));
@@ -17,47 +41,361 @@ pub fn change_day_switch_offset(database: DataBase, new_offset: i8) -> Result<()
         return Ok(());
     }
 
-    let orignal_path = database.database_path().to_path_buf();
-    let dir_path = match orignal_path.parent() {
-        None => {
-            log::error!("The database had no parent path. This should not be possible.");
-            return Err(Error::with_kind(ErrorKind::PathDoesNotExist));
-        }
-        Some(path) => path.to_path_buf(),
+    let original_path = database.database_path().to_path_buf();
+    let copy_path = scratch_path(&original_path, COPYSUFFIX)?;
+    let old_path = scratch_path(&original_path, OLDSUFFIX)?;
+    let storage = database.path().storage();
+
+    let db_status = database.lock(ActiveTask::None)?;
+    if let Err(error) = ensure_no_leftover_copies(storage, &[&copy_path, &old_path]) {
+        db_status.unlock();
+        return Err(error);
+    }
+
+    let result = create_updated_copy(database, &copy_path, new_offset)
+        .and_then(|()| swap_in_copy(storage, &original_path, &copy_path, &old_path));
+    if let Err(error) = result {
+        log::error!("Failed to change the day_switch_offset!");
+        discard_copy(storage, &original_path, &copy_path);
+        db_status.unlock();
+        return Err(error);
+    }
+
+    // The lock file was removed together with the original database.
+    drop(db_status);
+    log::info!("Finished changing the day_switch_offset to {}.", new_offset);
+
+    Ok(())
+}
+
+/// Returns the path of a scratch copy of the database at `db_root`. The copy is placed next to
+/// the database and named after its folder, as in `{database folder}.{suffix}`, so the copies of
+/// two databases in the same folder never collide.
+pub(crate) fn scratch_path(db_root: &Path, suffix: &str) -> Result<PathBuf> {
+    let (Some(dir_path), Some(db_name)) = (db_root.parent(), db_root.file_name()) else {
+        log::error!("The database had no parent path. This should not be possible.");
+        return Err(Error::with_kind(ErrorKind::PathDoesNotExist));
     };
+    let mut scratch_name = db_name.to_os_string();
+    scratch_name.push(format!(".{}", suffix));
+    Ok(dir_path.join(scratch_name))
+}
 
-    let database_copy = create_temp_copy(database, &dir_path)?;
+/// Returns a `LeftoverDataBaseCopy` error if any of the provided scratch paths already exists.
+/// A leftover copy is never removed automatically, since it might be the only intact version of
+/// the database after an interrupted swap.
+pub(crate) fn ensure_no_leftover_copies(storage: &dyn Storage, paths: &[&Path]) -> Result<()> {
+    if let Some(path) = paths.iter().find(|path| storage.exists(path)) {
+        log::error!(
+            "Found a leftover database copy at {:?}! It has to be removed before continuing.",
+            path
+        );
+        return Err(Error::with_kind(ErrorKind::LeftoverDataBaseCopy(
+            path.to_path_buf(),
+        )));
+    }
+    Ok(())
+}
 
-    update_data_files(&database_copy)?;
-    update_diary_files(&database_copy)?;
+fn create_updated_copy(database: &DataBase, copy_path: &Path, new_offset: i8) -> Result<()> {
+    let database_copy = create_temp_copy(database, copy_path, new_offset)?;
 
-    database_copy.regen_caches()?;
-    database_copy.regen_tag_sums()?;
+    update_data_files(database, &database_copy)?;
+    update_diary_files(database, &database_copy)?;
 
-    fs::rename(dir_path.join(TEMPDBPATH), orignal_path)?;
+    regeneration::regenerate(
+        database_copy.path(),
+        RegenerationTargets::ALL,
+        database.regeneration_threads(),
+        &TaskProgress::default(),
+    )?;
 
     Ok(())
 }
 
-fn create_temp_copy(database: DataBase, dir_path: &Path) -> Result<DataBase> {
+/// Copies everything but the data folder, the stat sums and the lock files of `database` into
+/// `copy_path`, and sets the day_switch_offset of the copy to `new_offset`. The data folder and
+/// stat sums are filled in after the copy is created.
+fn create_temp_copy(database: &DataBase, copy_path: &Path, new_offset: i8) -> Result<DataBase> {
     log::info!("Creating database copy...");
-    log::warn!("The copying logic works fine but is not ideal. For the future a proper copy function should be made.");
-    let img_path = dir_path.join("temp_backup.png");
-    database.compress_to_image(&img_path)?;
-    let database_copy = DataBase::load_from_image(&img_path, dir_path.join(TEMPDBPATH))?;
-    fs::remove_file(img_path)?;
+    let original = database.path();
+    let storage = original.storage();
+    let skipped_paths = [
+        original.data(),
+        original.stat_sums(),
+        original.root().join(".status.txt"),
+        original.root().join(".writers"),
+    ];
+
+    copy_directory(storage, original.root(), copy_path, &skipped_paths)?;
+    storage.create_dir_all(&copy_path.join("data"))?;
+
+    let settings = Settings {
+        day_switch_offset: new_offset,
+        ..database.settings().clone()
+    };
+    settings.save(&DataBasePath::with_storage(
+        copy_path.to_path_buf(),
+        original.shared_storage(),
+    )?)?;
+
+    let database_copy =
+        DataBase::load_with_storage(copy_path.to_path_buf(), original.shared_storage())?;
     log::info!("Finished copying database.");
     Ok(database_copy)
 }
 
-fn update_data_files(database: &DataBase) -> Result<()> {
+/// Replaces the database at `original_path` with the database at `copy_path`. The original is
+/// moved to `old_path` before it is removed, so it is never overwritten by a half moved copy.
+///
+/// If the copy can't be moved into place the original is moved back, leaving the copy at
+/// `copy_path`. Once the copy is in place the swap has succeeded, and failing to remove the
+/// original at `old_path` is only logged.
+///
+/// The lock file of the original database is removed together with the original.
+pub(crate) fn swap_in_copy(
+    storage: &dyn Storage,
+    original_path: &Path,
+    copy_path: &Path,
+    old_path: &Path,
+) -> Result<()> {
+    log::info!("Swapping the original database with the updated copy...");
+    ensure_no_leftover_copies(storage, &[old_path])?;
+    storage.rename(original_path, old_path)?;
+
+    if let Err(error) = storage.rename(copy_path, original_path) {
+        log::error!("Failed to move the database copy into place! Restoring the original...");
+        if let Err(restore_error) = storage.rename(old_path, original_path) {
+            log::error!(
+                "Failed to restore the original database due to: {:?}. It is left at {:?}!",
+                restore_error,
+                old_path
+            );
+        }
+        return Err(error.into());
+    }
+
+    if let Err(error) = storage.remove_dir_all(old_path) {
+        log::warn!(
+            "Failed to remove the replaced database at {:?} due to: {:?}. It has to be removed by hand.",
+            old_path,
+            error
+        );
+    }
+    Ok(())
+}
+
+/// Removes the copy at `copy_path` after a failed change. The copy is kept if the original
+/// database is missing from `original_path`, since the original then could not be restored and
+/// the copy might be needed to recover it.
+pub(crate) fn discard_copy(storage: &dyn Storage, original_path: &Path, copy_path: &Path) {
+    if !storage.exists(copy_path) {
+        return;
+    }
+    if !storage.exists(original_path) {
+        log::error!(
+            "The original database is missing! The copy at {:?} is kept.",
+            copy_path
+        );
+        return;
+    }
+
+    log::info!("Removing the database copy at {:?}...", copy_path);
+    if let Err(error) = storage.remove_dir_all(copy_path) {
+        log::error!(
+            "Failed to remove the database copy at {:?} due to: {:?}",
+            copy_path,
+            error
+        );
+    }
+}
+
+/// Copies the directory at `from` together with everything inside it to `to`, leaving out every
+/// path in `skipped_paths`.
+pub(crate) fn copy_directory(
+    storage: &dyn Storage,
+    from: &Path,
+    to: &Path,
+    skipped_paths: &[PathBuf],
+) -> Result<()> {
+    storage.create_dir_all(to)?;
+    for path in storage.list(from)? {
+        if skipped_paths.contains(&path) {
+            continue;
+        }
+        let target_path = to.join(
+            path.file_name()
+                .expect("Every listed path has a file name."),
+        );
+
+        if storage.is_dir(&path) {
+            copy_directory(storage, &path, &target_path, skipped_paths)?;
+        } else {
+            storage.write(&target_path, &storage.read(&path)?)?;
+        }
+    }
+    Ok(())
+}
+
+/// Writes every data entry of `database` into the data file of `database_copy` its wall-clock
+/// time belongs to under the offset of the copy.
+fn update_data_files(database: &DataBase, database_copy: &DataBase) -> Result<()> {
     log::info!("Updating data files...");
+    let new_offset = database_copy.settings().day_switch_offset;
+    let mut new_files: HashMap<PathBuf, DataFile> = HashMap::new();
+    let storage = database.path().storage();
+
+    for file_path in storage.walk_files(&database.path().data())? {
+        let file_path = file_path.as_path();
+        if !DataFile::is_data_file(storage, file_path) {
+            continue;
+        }
+
+        let data_file = DataFile::read_from_file(database.path(), file_path).inspect_err(|_| {
+            log::error!("Could not read the data file {:?}!", file_path);
+        })?;
+        let date = data_file.date();
+
+        for data_entry in data_file.entries().values() {
+            let datetime = data_entry.wall_clock_datetime(date);
+            let new_date = (datetime - Duration::hours(new_offset as i64)).date();
+            let new_path = database_copy.day_file_path(new_date);
+
+            // The hour is compensated relative to the new date of the entry, while the minute
+            // within the hour stays the same.
+            let mut new_entry = data_entry.clone();
+            let minutes = (datetime - new_date.midnight()).whole_minutes();
+            new_entry.hour = minutes.div_euclid(60) as i8;
+
+            let new_file = match new_files.entry(new_path) {
+                Entry::Occupied(entry) => entry.into_mut(),
+                Entry::Vacant(entry) => {
+                    let data_file = DataFile::open_data_file(database_copy.path(), entry.key())?;
+                    entry.insert(data_file)
+                }
+            };
+            new_file.add_entry(new_entry)?;
+        }
+    }
+
+    for data_file in new_files.values_mut() {
+        data_file.save()?;
+    }
     log::info!("Finished updating data files.");
-    todo!();
+    Ok(())
 }
 
-fn update_diary_files(database: &DataBase) -> Result<()> {
+/// Writes every diary entry of `database` into the diary file of `database_copy` its timestamp
+/// belongs to under the offset of the copy.
+fn update_diary_files(database: &DataBase, database_copy: &DataBase) -> Result<()> {
     log::info!("Updating diary files...");
+    let mut new_files: HashMap<PathBuf, DiaryFile> = HashMap::new();
+
+    for file_path in database
+        .path()
+        .storage()
+        .walk_files(&database.path().data())?
+    {
+        let file_path = file_path.as_path();
+        if !DiaryFile::is_diary_file(file_path) {
+            continue;
+        }
+
+        for diary_entry in DiaryFile::from_file(database.path(), file_path)?.into_entries() {
+            let mut new_path = database_copy.get_date_file_path(diary_entry.timestamp())?;
+            new_path.add_extension(DIARYFILEEXTENSION);
+
+            let new_file = match new_files.entry(new_path) {
+                Entry::Occupied(entry) => entry.into_mut(),
+                Entry::Vacant(entry) => {
+                    let diary_file = DiaryFile::open(database_copy.path(), entry.key())?;
+                    entry.insert(diary_file)
+                }
+            };
+            new_file.add_entry(diary_entry);
+        }
+    }
+
+    for diary_file in new_files.values_mut() {
+        diary_file.save()?;
+    }
     log::info!("Finished updating diary files.");
-    todo!();
+    Ok(())
+}
+
+//
+
+//
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use time::Month;
+
+    use crate::{
+        data_base::ErrorKind,
+        test_utils::{create_database, date, day_entries, entry},
+    };
+
+    #[test]
+    fn changing_the_offset_moves_entries_across_midnight() {
+        let mut database = create_database("offset_change", 0);
+        let (first_day, second_day) =
+            (date(2024, Month::January, 1), date(2024, Month::January, 2));
+        database
+            .insert_data_entry(2024, 1, 1, entry(22, 15, vec![]))
+            .unwrap();
+        database
+            .insert_data_entry(2024, 1, 2, entry(2, 45, vec![]))
+            .unwrap();
+        database
+            .insert_data_entry(2024, 1, 2, entry(12, 0, vec![]))
+            .unwrap();
+
+        database.change_day_switch_offset(4).unwrap();
+        assert_eq!(database.settings().day_switch_offset, 4);
+        assert_eq!(
+            day_entries(&database, first_day),
+            vec![(22, 15, vec![]), (26, 45, vec![])]
+        );
+        assert_eq!(day_entries(&database, second_day), vec![(12, 0, vec![])]);
+
+        database.change_day_switch_offset(-4).unwrap();
+        assert_eq!(day_entries(&database, first_day), vec![]);
+        assert_eq!(
+            day_entries(&database, second_day),
+            vec![(-2, 15, vec![]), (2, 45, vec![]), (12, 0, vec![])]
+        );
+        assert!(database.check().unwrap().is_ok());
+    }
+
+    #[test]
+    fn leftover_copy_is_never_overwritten() {
+        let mut database = create_database("offset_leftover", 0);
+        database
+            .insert_data_entry(2024, 1, 2, entry(2, 0, vec![]))
+            .unwrap();
+        let storage = database.path().shared_storage();
+        let leftover_path = Path::new("/offset_leftover.offset_change");
+        storage.create_dir_all(leftover_path).unwrap();
+        storage
+            .write(&leftover_path.join("note.txt"), b"keep")
+            .unwrap();
+
+        let error = database.change_day_switch_offset(4).unwrap_err();
+        assert!(
+            matches!(error.kind, ErrorKind::LeftoverDataBaseCopy(path) if path == leftover_path)
+        );
+        assert_eq!(
+            storage.read(&leftover_path.join("note.txt")).unwrap(),
+            b"keep"
+        );
+        assert_eq!(database.settings().day_switch_offset, 0);
+        assert_eq!(
+            day_entries(&database, date(2024, Month::January, 2)),
+            vec![(2, 0, vec![])]
+        );
+        assert!(database.lock_info().unwrap().is_none());
+    }
 }
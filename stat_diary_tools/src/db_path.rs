@@ -1,8 +1,11 @@
 use std::{
     ffi::{c_char, CStr},
     path::{Path, PathBuf},
+    sync::Arc,
 };
 
+use crate::storage::{FileSystemStorage, Storage};
+
 #[derive(Debug)]
 pub enum DataBasePathError {
     DoesNotExist,
@@ -25,6 +28,7 @@ impl From<DataBasePathError> for PtrToDBPathError {
 #[derive(Debug, Clone)]
 pub struct DataBasePath {
     db_root: PathBuf,
+    storage: Arc<dyn Storage>,
 }
 
 impl DataBasePath {
@@ -47,16 +51,27 @@ impl DataBasePath {
     /// Attempts to creates a new DataBasePath with the provided db_path as root.
     /// Will only succeed if the folder provided is a valid database with a marker file.
     pub fn new(db_path: PathBuf) -> Result<DataBasePath, DataBasePathError> {
-        let Ok(true) = db_path.try_exists() else {
+        DataBasePath::with_storage(db_path, Arc::new(FileSystemStorage))
+    }
+
+    /// Same as `new`, but the database is looked up in and accessed through the provided
+    /// storage instead of the file system.
+    pub fn with_storage(
+        db_path: PathBuf,
+        storage: Arc<dyn Storage>,
+    ) -> Result<DataBasePath, DataBasePathError> {
+        if !storage.is_dir(&db_path) {
             return Err(DataBasePathError::DoesNotExist);
-        };
+        }
 
-        let db_marker_path = db_path.join(".db_marker");
-        let Ok(true) = db_marker_path.try_exists() else {
+        if !storage.exists(&db_path.join(".db_marker")) {
             return Err(DataBasePathError::IsNotDataBase);
-        };
+        }
 
-        Ok(DataBasePath { db_root: db_path })
+        Ok(DataBasePath {
+            db_root: db_path,
+            storage,
+        })
     }
 
     /// Returns the database root path
@@ -64,6 +79,16 @@ impl DataBasePath {
         &self.db_root
     }
 
+    /// Returns the storage the database is stored in
+    pub fn storage(&self) -> &dyn Storage {
+        self.storage.as_ref()
+    }
+
+    /// Returns a new reference to the storage the database is stored in
+    pub fn shared_storage(&self) -> Arc<dyn Storage> {
+        Arc::clone(&self.storage)
+    }
+
     /// Returns the data folder path
     pub fn data(&self) -> PathBuf {
         self.db_root.join("data")
@@ -1,16 +1,26 @@
 use std::{
-    fs::File,
-    io::{self, Read, Write},
-    path::PathBuf,
+    io,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        Arc, Mutex, MutexGuard,
+    },
+    time::{Duration, Instant},
 };
 
-use crate::db_path::DataBasePath;
+use time::OffsetDateTime;
+
+use crate::{
+    db_path::DataBasePath,
+    process::{self, ProcessStatus},
+    storage::Storage,
+};
 
 //
 
 //
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum ActiveTask {
     None,
     RegenerateCaches,
@@ -18,6 +28,8 @@ pub enum ActiveTask {
     MergeTags(u16, u16),
     RenameTag(String, String),
     RemoveTag(u16),
+    Repair,
+    Undo(u32),
 }
 
 impl ActiveTask {
@@ -33,7 +45,7 @@ impl ActiveTask {
                         .next()
                         .ok_or(DBStatusError::CorruptedData)?
                         .split(|c: char| !c.is_ascii_digit())
-                        .filter(|s| s.is_empty())
+                        .filter(|s| !s.is_empty())
                         // The parse() below will never fail due to the way the string is created.
                         .map(|s| s.parse::<u16>().map_err(|_| DBStatusError::CorruptedData))
                         .collect::<Result<_>>()?;
@@ -73,6 +85,15 @@ impl ActiveTask {
                 };
                 Ok(ActiveTask::RemoveTag(tag_id))
             }
+            "6" => Ok(ActiveTask::Repair),
+            "7" => {
+                let data = parts.next().ok_or(DBStatusError::CorruptedData)?;
+                let Ok(record_number) = data.parse::<u32>() else {
+                    log::error!("db_status::ActiveTask::parse(): Failed to parse Undo. Found unexpected characters in record number! [{}]", data);
+                    return Err(DBStatusError::CorruptedData);
+                };
+                Ok(ActiveTask::Undo(record_number))
+            }
             _ => Err(DBStatusError::UnknownTask),
         }
     }
@@ -90,6 +111,8 @@ impl ActiveTask {
             Self::MergeTags(s1, s2) => &format!("{} {}", s1, s2),
             Self::RenameTag(s1, s2) => &format!("{} {}", s1, s2),
             Self::RemoveTag(tag_id) => &format!("{}", tag_id),
+            Self::Repair => "",
+            Self::Undo(record_number) => &format!("{}", record_number),
         };
         format!("{}|{}", task_id, task_data)
     }
@@ -98,7 +121,8 @@ impl ActiveTask {
 
     //
 
-    fn task_id(&self) -> u8 {
+    /// Returns the id the task is stored as in the status file.
+    pub fn task_id(&self) -> u8 {
         match self {
             Self::None => 0,
             Self::RegenerateCaches => 1,
@@ -106,6 +130,8 @@ impl ActiveTask {
             Self::MergeTags(_, _) => 3,
             Self::RenameTag(_, _) => 4,
             Self::RemoveTag(_) => 5,
+            Self::Repair => 6,
+            Self::Undo(_) => 7,
         }
     }
 }
@@ -117,7 +143,10 @@ impl ActiveTask {
 #[derive(Debug)]
 pub enum DBStatusError {
     Io(io::Error),
-    DataBaseBusy(ActiveTask, DBStatus),
+    DataBaseBusy(ActiveTask),
+    /// Short writes holding a writer lock did not finish in time, so the database could not be
+    /// locked for a task.
+    WritersActive,
     CorruptedData,
     UnknownTask,
 }
@@ -135,6 +164,186 @@ impl From<io::Error> for DBStatusError {
 type Result<T> = std::result::Result<T, DBStatusError>;
 
 const STATUSFILENAME: &str = ".status.txt";
+const WRITERSDIRNAME: &str = ".writers";
+const LOCKPOLLINTERVAL: Duration = Duration::from_millis(100);
+/// How long an exclusive task waits for active short writes to finish.
+const WRITERSDRAINTIMEOUT: Duration = Duration::from_secs(10);
+
+/// Makes the writer markers created by this process unique.
+static WRITERCOUNTER: AtomicU32 = AtomicU32::new(0);
+
+/// The status files of the databases whose task was cancelled or failed in this process. The locks
+/// are not stale, since this process is still running, but can be taken over to resume the task.
+static SUSPENDEDTASKS: Mutex<Vec<PathBuf>> = Mutex::new(Vec::new());
+
+/// How much later than the recorded start of a lock the owning process may have been started. The
+/// start time reported by the platform is only precise to about a second.
+const OWNERSTARTTOLERANCE: time::Duration = time::Duration::seconds(2);
+
+//
+
+//
+
+/// Whether the process holding a lock still exists.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OwnerStatus {
+    /// The owning process is still running.
+    Running,
+    /// The owning process no longer exists, which makes the lock stale.
+    Exited,
+    /// It is unknown whether the owning process still exists. This is the case for owners on
+    /// another host, and on platforms where processes can't be looked up.
+    Unknown,
+}
+
+/// The process holding the lock of a database.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LockOwner {
+    pub pid: u32,
+    pub host: String,
+    pub started: OffsetDateTime,
+}
+
+impl LockOwner {
+    /// Returns the owner information of the current process.
+    fn current() -> LockOwner {
+        LockOwner {
+            pid: std::process::id(),
+            host: process::hostname(),
+            started: OffsetDateTime::now_utc(),
+        }
+    }
+
+    //
+
+    //
+
+    /// Returns whether the owning process still exists.
+    ///
+    /// Only processes on the same host can be checked. A running process started after the lock
+    /// was taken has reused the id of the owner, so the owner is reported as exited.
+    pub fn status(&self) -> OwnerStatus {
+        let host = process::hostname();
+        if host.is_empty() || self.host != host {
+            return OwnerStatus::Unknown;
+        }
+
+        match process::process_status(self.pid) {
+            ProcessStatus::Exited => OwnerStatus::Exited,
+            ProcessStatus::Running(Some(process_started))
+                if process_started > self.started + OWNERSTARTTOLERANCE =>
+            {
+                OwnerStatus::Exited
+            }
+            ProcessStatus::Running(_) => OwnerStatus::Running,
+            ProcessStatus::Unknown => OwnerStatus::Unknown,
+        }
+    }
+
+    /// Returns whether the owning process is known to no longer exist. See `status`.
+    pub fn is_stale(&self) -> bool {
+        self.status() == OwnerStatus::Exited
+    }
+
+    //
+
+    //
+
+    /// Returns whether this is the owner information of a lock taken by the current process.
+    fn is_current_process(&self) -> bool {
+        self.pid == std::process::id() && self.host == process::hostname() && !self.is_stale()
+    }
+
+    //
+
+    //
+
+    /// Returns the `key=value` lines describing the owner, as written to the status file.
+    fn to_lines(&self) -> String {
+        format!(
+            "pid={}\nhost={}\nstarted={}\n",
+            self.pid,
+            self.host,
+            self.started.unix_timestamp()
+        )
+    }
+
+    //
+
+    //
+
+    /// Parses the `key=value` owner lines of a status file. Returns `None` if any of the owner
+    /// values is missing, which is the case for status files written by older versions.
+    fn parse<'a>(lines: impl Iterator<Item = &'a str>) -> Option<LockOwner> {
+        let (mut pid, mut host, mut started) = (None, None, None);
+        for line in lines {
+            match line.split_once('=') {
+                Some(("pid", value)) => pid = value.parse::<u32>().ok(),
+                Some(("host", value)) => host = Some(value.to_string()),
+                Some(("started", value)) => {
+                    started = value
+                        .parse::<i64>()
+                        .ok()
+                        .and_then(|timestamp| OffsetDateTime::from_unix_timestamp(timestamp).ok())
+                }
+                _ => log::warn!("db_status: Unknown line in the status file: [{}]", line),
+            }
+        }
+
+        Some(LockOwner {
+            pid: pid?,
+            host: host?,
+            started: started?,
+        })
+    }
+}
+
+/// The task and owner recorded in the status file of a locked database.
+#[derive(Debug, Clone)]
+pub struct LockInfo {
+    pub task: ActiveTask,
+    /// The process holding the lock. `None` if the lock was created by an older version which
+    /// didn't record its owner.
+    pub owner: Option<LockOwner>,
+}
+
+impl LockInfo {
+    /// Returns whether the process holding the lock still exists. The status of a lock without
+    /// owner information is unknown.
+    pub fn owner_status(&self) -> OwnerStatus {
+        self.owner
+            .as_ref()
+            .map_or(OwnerStatus::Unknown, LockOwner::status)
+    }
+
+    /// Returns whether the process holding the lock is known to no longer exist. A lock without
+    /// owner information is never considered stale.
+    pub fn is_stale(&self) -> bool {
+        self.owner_status() == OwnerStatus::Exited
+    }
+
+    //
+
+    //
+
+    /// Reads the lock information from the status file at `filepath`.
+    fn read(storage: &dyn Storage, filepath: &Path) -> Result<LockInfo> {
+        let data_str = storage.read_to_string(filepath)?;
+        if data_str.is_empty() {
+            // The status file was just created and its content is still being written.
+            return Ok(LockInfo {
+                task: ActiveTask::None,
+                owner: None,
+            });
+        }
+        let mut lines = data_str.lines();
+        let task = ActiveTask::parse(lines.next().unwrap_or_default())?;
+        Ok(LockInfo {
+            task,
+            owner: LockOwner::parse(lines),
+        })
+    }
+}
 
 //
 
@@ -143,37 +352,264 @@ const STATUSFILENAME: &str = ".status.txt";
 #[derive(Debug)]
 pub struct DBStatus {
     status_path: PathBuf,
+    storage: Arc<dyn Storage>,
 }
 
 impl DBStatus {
-    /// Returns whether the database is locked or not.
-    pub fn is_locked(db_path: &DataBasePath) -> bool {
-        let filepath = db_path.root().join(STATUSFILENAME);
-        filepath.exists()
+    fn new(db_path: &DataBasePath) -> DBStatus {
+        DBStatus {
+            status_path: db_path.root().join(STATUSFILENAME),
+            storage: db_path.shared_storage(),
+        }
     }
 
     /// Attempts to lock the database. If the database is already locked a
     /// DBStatusError::DataBaseBusy is returner.
+    ///
+    /// The status file records the task together with the id, host and start time of the current
+    /// process.
     pub fn lock(db_path: &DataBasePath, task: ActiveTask) -> Result<DBStatus> {
         let filepath = db_path.root().join(STATUSFILENAME);
+        let content = DBStatus::status_content(task);
+
+        loop {
+            match db_path.storage().create_new(&filepath, content.as_bytes()) {
+                Ok(()) => {
+                    let db_status = DBStatus::new(db_path);
+                    if let Err(error) = DBStatus::wait_for_writers(db_path) {
+                        db_status.unlock();
+                        return Err(error);
+                    }
+                    return Ok(db_status);
+                }
+                Err(error) if error.kind() != io::ErrorKind::AlreadyExists => {
+                    return Err(error.into())
+                }
+                Err(_) => {
+                    if let Some(lock_info) = DBStatus::lock_info(db_path)? {
+                        return Err(DBStatusError::DataBaseBusy(lock_info.task));
+                    }
+                    // The lock was released after it was found, so locking is attempted again.
+                }
+            }
+        }
+    }
+
+    /// Returns the content of a status file recording `task` and the current process as owner.
+    fn status_content(task: ActiveTask) -> String {
+        format!(
+            "{}\n{}",
+            task.into_data_string(),
+            LockOwner::current().to_lines()
+        )
+    }
+
+    //
+
+    //
+
+    /// Same as `lock`, but if the database is busy the lock is retried until `timeout` has passed.
+    /// A DBStatusError::DataBaseBusy is returned if the database is still busy after the timeout.
+    pub fn lock_with_timeout(
+        db_path: &DataBasePath,
+        task: ActiveTask,
+        timeout: Duration,
+    ) -> Result<DBStatus> {
+        let start = Instant::now();
+        loop {
+            match DBStatus::lock(db_path, task.clone()) {
+                Err(DBStatusError::DataBaseBusy(_)) if start.elapsed() < timeout => {
+                    std::thread::sleep(LOCKPOLLINTERVAL.min(timeout - start.elapsed()));
+                }
+                result => return result,
+            }
+        }
+    }
+
+    //
+
+    //
+
+    /// Returns the task and owner of the lock held on the database, or `None` if the database
+    /// isn't locked.
+    pub fn lock_info(db_path: &DataBasePath) -> Result<Option<LockInfo>> {
+        let filepath = db_path.root().join(STATUSFILENAME);
+        match LockInfo::read(db_path.storage(), &filepath) {
+            Ok(lock_info) => Ok(Some(lock_info)),
+            Err(DBStatusError::Io(error)) if error.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(error) => Err(error),
+        }
+    }
 
-        let db_status = DBStatus {
-            status_path: filepath.clone(),
+    //
+
+    //
+
+    /// Takes over the lock of a unfinished task so the task can be resumed, returning the task.
+    /// Returns `None` if the database isn't locked.
+    ///
+    /// Only a stale lock, or a lock suspended by this process, can be taken over. Any other lock
+    /// belongs to a running task, in which case a DBStatusError::DataBaseBusy is returned. The
+    /// current process is recorded as the new owner of a stale lock.
+    pub fn take_over(db_path: &DataBasePath) -> Result<Option<(ActiveTask, DBStatus)>> {
+        let Some(lock_info) = DBStatus::lock_info(db_path)? else {
+            return Ok(None);
         };
-        match File::create_new(&filepath) {
-            Ok(mut file) => {
-                write!(file, "{}", task.into_data_string())?;
-                Ok(db_status)
+        let db_status = DBStatus::new(db_path);
+
+        let mut suspended_tasks = suspended_tasks();
+        let suspended_index = suspended_tasks
+            .iter()
+            .position(|status_path| *status_path == db_status.status_path);
+        match suspended_index {
+            Some(index)
+                if lock_info
+                    .owner
+                    .as_ref()
+                    .is_some_and(LockOwner::is_current_process) =>
+            {
+                suspended_tasks.swap_remove(index);
             }
-            Err(_) => {
-                let mut data_str = String::new();
-                let mut file = File::open(filepath)?;
-                file.read_to_string(&mut data_str)?;
-                Err(DBStatusError::DataBaseBusy(
-                    ActiveTask::parse(&data_str)?,
-                    db_status,
-                ))
+            _ if lock_info.is_stale() => {
+                log::warn!(
+                    "db_status: Taking over the stale lock of {:?} held for {:?} by {:?}",
+                    db_path.root(),
+                    lock_info.task,
+                    lock_info.owner
+                );
+                let content = DBStatus::status_content(lock_info.task.clone());
+                db_path
+                    .storage()
+                    .write_atomic(&db_status.status_path, content.as_bytes())?;
             }
+            _ => return Err(DBStatusError::DataBaseBusy(lock_info.task)),
+        }
+        Ok(Some((lock_info.task, db_status)))
+    }
+
+    //
+
+    //
+
+    /// Removes the lock of the database no matter which process holds it, together with every
+    /// writer lock of short writes. Does nothing if the database isn't locked.
+    pub fn break_lock(db_path: &DataBasePath) -> Result<()> {
+        let storage = db_path.storage();
+        let filepath = db_path.root().join(STATUSFILENAME);
+        match storage.remove_file(&filepath) {
+            Err(error) if error.kind() != io::ErrorKind::NotFound => return Err(error.into()),
+            _ => (),
+        }
+
+        let writers_path = db_path.root().join(WRITERSDIRNAME);
+        if storage.exists(&writers_path) {
+            storage.remove_dir_all(&writers_path)?;
+        }
+        Ok(())
+    }
+
+    //
+
+    //
+
+    /// Acquires a shared writer lock for a short write, such as adding a single entry.
+    ///
+    /// Any number of short writes can hold a writer lock at the same time, but none can while an
+    /// exclusive task holds the database lock. A DBStatusError::DataBaseBusy is returned if the
+    /// database is locked.
+    ///
+    /// A writer lock is a marker file in the `.writers` folder of the database. The marker is
+    /// created before the database lock is checked, while `lock` creates the database lock before
+    /// it waits for the markers to be removed. Either the writer sees the database lock or the
+    /// exclusive task sees the marker.
+    pub fn lock_shared(db_path: &DataBasePath) -> Result<WriterLock> {
+        let storage = db_path.storage();
+        let writers_path = db_path.root().join(WRITERSDIRNAME);
+        storage.create_dir_all(&writers_path)?;
+
+        let marker_path = writers_path.join(format!(
+            "{}-{}",
+            std::process::id(),
+            WRITERCOUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+        storage.write(&marker_path, LockOwner::current().to_lines().as_bytes())?;
+        let writer_lock = WriterLock {
+            marker_path,
+            storage: db_path.shared_storage(),
+        };
+
+        // A lock released between being found and being read counts as unlocked.
+        if let Some(lock_info) = DBStatus::lock_info(db_path)? {
+            drop(writer_lock);
+            return Err(DBStatusError::DataBaseBusy(lock_info.task));
+        }
+        Ok(writer_lock)
+    }
+
+    //
+
+    //
+
+    /// Same as `lock_shared`, but if the database is busy the lock is retried until `timeout` has
+    /// passed.
+    pub fn lock_shared_with_timeout(
+        db_path: &DataBasePath,
+        timeout: Duration,
+    ) -> Result<WriterLock> {
+        let start = Instant::now();
+        loop {
+            match DBStatus::lock_shared(db_path) {
+                Err(DBStatusError::DataBaseBusy(_)) if start.elapsed() < timeout => {
+                    std::thread::sleep(LOCKPOLLINTERVAL.min(timeout - start.elapsed()));
+                }
+                result => return result,
+            }
+        }
+    }
+
+    //
+
+    //
+
+    /// Waits until every short write holding a writer lock has finished. Markers left behind by
+    /// processes that no longer exist are removed. A DBStatusError::WritersActive is returned if
+    /// the writes don't finish within `WRITERSDRAINTIMEOUT`. Markers whose owner can't be checked
+    /// are only removed by `break_lock`.
+    fn wait_for_writers(db_path: &DataBasePath) -> Result<()> {
+        let storage = db_path.storage();
+        let writers_path = db_path.root().join(WRITERSDIRNAME);
+        let start = Instant::now();
+        loop {
+            let mut active_writers = 0;
+            if storage.exists(&writers_path) {
+                for marker_path in storage.list(&writers_path)? {
+                    // The marker might be removed or still be written to while it is read.
+                    let Ok(content) = storage.read_to_string(&marker_path) else {
+                        active_writers += 1;
+                        continue;
+                    };
+                    match LockOwner::parse(content.lines()) {
+                        Some(owner) if owner.is_stale() => {
+                            log::warn!("db_status: Removing the writer marker {:?} of a process that no longer exists.", marker_path);
+                            let _removal_result = storage.remove_file(&marker_path);
+                        }
+                        _ => active_writers += 1,
+                    }
+                }
+            }
+
+            if active_writers == 0 {
+                return Ok(());
+            }
+            if start.elapsed() >= WRITERSDRAINTIMEOUT {
+                log::error!(
+                    "db_status: {} short writes did not finish within {:?}!",
+                    active_writers,
+                    WRITERSDRAINTIMEOUT
+                );
+                return Err(DBStatusError::WritersActive);
+            }
+            std::thread::sleep(LOCKPOLLINTERVAL);
         }
     }
 
@@ -183,6 +619,155 @@ impl DBStatus {
 
     /// Releases the lock of the database.
     pub fn unlock(self) {
-        let _removal_result = std::fs::remove_file(self.status_path);
+        let _removal_result = self.storage.remove_file(&self.status_path);
+    }
+
+    /// Keeps the database locked after the task holding the lock was cancelled or failed, so that
+    /// `take_over` can resume the task from this process later.
+    pub fn suspend(self) {
+        suspended_tasks().push(self.status_path);
+    }
+}
+
+/// Returns the list of suspended tasks, see `SUSPENDEDTASKS`.
+fn suspended_tasks() -> MutexGuard<'static, Vec<PathBuf>> {
+    SUSPENDEDTASKS
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+//
+
+//
+
+/// A shared lock held by a short write. The lock is released when it is dropped.
+#[derive(Debug)]
+pub struct WriterLock {
+    marker_path: PathBuf,
+    storage: Arc<dyn Storage>,
+}
+
+impl Drop for WriterLock {
+    fn drop(&mut self) {
+        let _removal_result = self.storage.remove_file(&self.marker_path);
+    }
+}
+
+//
+
+//
+
+#[cfg(test)]
+mod tests {
+    use time::OffsetDateTime;
+
+    use super::{ActiveTask, DBStatus, DBStatusError, LockInfo, LockOwner, OwnerStatus};
+    use crate::{
+        process,
+        test_utils::{create_database, entry},
+    };
+
+    #[test]
+    fn owner_status_follows_the_owning_process() {
+        let current = LockOwner::current();
+        assert!(!current.host.is_empty());
+        assert_eq!(current.status(), OwnerStatus::Running);
+        assert!(current.is_current_process());
+
+        // The id of the owner was reused by a process started long after the lock was taken.
+        #[cfg(target_os = "linux")]
+        {
+            let reused = LockOwner {
+                started: OffsetDateTime::UNIX_EPOCH,
+                ..current.clone()
+            };
+            assert_eq!(reused.status(), OwnerStatus::Exited);
+            assert!(reused.is_stale());
+            assert!(!reused.is_current_process());
+        }
+
+        let other_host = LockOwner {
+            host: format!("{}-other", process::hostname()),
+            ..current.clone()
+        };
+        assert_eq!(other_host.status(), OwnerStatus::Unknown);
+        assert!(!other_host.is_stale());
+
+        let without_owner = LockInfo {
+            task: ActiveTask::RegenerateCaches,
+            owner: None,
+        };
+        assert_eq!(without_owner.owner_status(), OwnerStatus::Unknown);
+    }
+
+    #[test]
+    fn owner_lines_are_parsed_back() {
+        let owner = LockOwner {
+            pid: 42,
+            host: "host".to_string(),
+            started: OffsetDateTime::from_unix_timestamp(1_700_000_000).unwrap(),
+        };
+        let lines = owner.to_lines();
+        assert_eq!(LockOwner::parse(lines.lines()), Some(owner));
+
+        // Status files written by older versions have no owner.
+        assert_eq!(LockOwner::parse("pid=42\nhost=host".lines()), None);
+    }
+
+    //
+
+    //
+
+    #[test]
+    fn exclusive_lock_excludes_every_other_lock() {
+        let database = create_database("exclusive_lock", 0);
+        let db_path = database.path();
+
+        let db_status = DBStatus::lock(db_path, ActiveTask::RegenerateCaches).unwrap();
+        assert!(matches!(
+            DBStatus::lock(db_path, ActiveTask::Repair),
+            Err(DBStatusError::DataBaseBusy(ActiveTask::RegenerateCaches))
+        ));
+        assert!(matches!(
+            DBStatus::lock_shared(db_path),
+            Err(DBStatusError::DataBaseBusy(ActiveTask::RegenerateCaches))
+        ));
+        // A failed writer lock leaves no marker behind.
+        assert!(db_path
+            .storage()
+            .list(&database.database_path().join(".writers"))
+            .unwrap()
+            .is_empty());
+
+        db_status.unlock();
+        drop(DBStatus::lock_shared(db_path).unwrap());
+    }
+
+    #[test]
+    fn shared_locks_are_held_alongside_each_other() {
+        let database = create_database("shared_lock", 0);
+        let db_path = database.path();
+
+        let first_writer = DBStatus::lock_shared(db_path).unwrap();
+        let second_writer = DBStatus::lock_shared(db_path).unwrap();
+        database
+            .insert_data_entry(2024, 1, 1, entry(8, 0, vec![]))
+            .unwrap();
+        drop(first_writer);
+        drop(second_writer);
+
+        // The exclusive lock only waits for writers which still hold their lock.
+        DBStatus::lock(db_path, ActiveTask::None).unwrap().unlock();
+    }
+
+    #[test]
+    fn break_lock_removes_the_lock_and_every_writer_lock() {
+        let database = create_database("break_lock", 0);
+        let db_path = database.path();
+
+        let writer = DBStatus::lock_shared(db_path).unwrap();
+        std::mem::forget(writer);
+        database.break_lock().unwrap();
+        DBStatus::lock(db_path, ActiveTask::None).unwrap().unlock();
     }
 }
@@ -1,13 +1,13 @@
 use std::{
     fmt::Display,
-    fs::{self, File},
-    io::{self, BufWriter, Read, Write},
+    io,
     path::{Path, PathBuf},
+    sync::Arc,
 };
 
 use time::OffsetDateTime;
 
-use crate::{DIARYFILEEXTENSION, TIMEFORMAT};
+use crate::{db_path::DataBasePath, storage::Storage, DIARYFILEEXTENSION, TIMEFORMAT};
 
 pub enum Error {
     Io(io::Error),
@@ -31,17 +31,17 @@ type Result<T> = std::result::Result<T, Error>;
 pub struct DiaryFile {
     entries: Vec<DiaryEntry>,
     file_path: PathBuf,
+    storage: Arc<dyn Storage>,
 }
 
 impl DiaryFile {
-    pub fn from_file(file_path: &Path) -> Result<DiaryFile> {
+    pub fn from_file(db_path: &DataBasePath, file_path: &Path) -> Result<DiaryFile> {
         if !DiaryFile::is_diary_file(file_path) {
             log::error!("DiaryFile::from_file(): {file_path:?} does not point to a diary file!");
             return Err(Error::NotADiaryFile);
         }
 
-        let mut file_text = String::new();
-        io::BufReader::new(File::open(file_path)?).read_to_string(&mut file_text)?;
+        let file_text = db_path.storage().read_to_string(file_path)?;
 
         let mut entries = Vec::new();
         for text_block in file_text.split(ENTRYSEPARATOR) {
@@ -51,6 +51,7 @@ impl DiaryFile {
         Ok(DiaryFile {
             entries,
             file_path: file_path.to_path_buf(),
+            storage: db_path.shared_storage(),
         })
     }
 
@@ -61,10 +62,11 @@ impl DiaryFile {
         file_extension == DIARYFILEEXTENSION
     }
 
-    pub fn open(file_path: &Path) -> Result<DiaryFile> {
-        if file_path.exists() && DiaryFile::is_diary_file(file_path) {
-            DiaryFile::from_file(file_path)
-        } else if file_path.exists() {
+    pub fn open(db_path: &DataBasePath, file_path: &Path) -> Result<DiaryFile> {
+        let storage = db_path.storage();
+        if storage.exists(file_path) && DiaryFile::is_diary_file(file_path) {
+            DiaryFile::from_file(db_path, file_path)
+        } else if storage.exists(file_path) {
             log::error!("DiaryFile::open(): {file_path:?} does not point to a diary file!");
             Err(Error::NotADiaryFile)
         } else {
@@ -72,10 +74,11 @@ impl DiaryFile {
                 log::warn!("DiaryFile::open(): {:?}.parent() was None!", file_path);
                 return Err(Error::NotADiaryFile);
             };
-            fs::create_dir_all(file_dir)?;
+            storage.create_dir_all(file_dir)?;
             Ok(DiaryFile {
                 entries: Vec::new(),
                 file_path: file_path.to_path_buf(),
+                storage: db_path.shared_storage(),
             })
         }
     }
@@ -101,14 +104,14 @@ impl DiaryFile {
         &self.entries
     }
 
+    /// Consumes the file and returns its diary entries.
+    pub fn into_entries(self) -> Vec<DiaryEntry> {
+        self.entries
+    }
+
     pub fn save(&mut self) -> Result<()> {
         self.sort_entries();
 
-        let mut tmp_path = self.file_path.clone();
-        tmp_path.add_extension("tmp");
-
-        let mut writer = BufWriter::new(File::create(&tmp_path)?);
-
         let s = self
             .entries
             .iter()
@@ -116,11 +119,7 @@ impl DiaryFile {
             .take(self.entries.iter().len() * 2 - 1)
             .collect::<String>();
 
-        write!(writer, "{}", s)?;
-
-        writer.flush()?;
-
-        fs::rename(tmp_path, &self.file_path)?;
+        self.storage.write_atomic(&self.file_path, s.as_bytes())?;
 
         Ok(())
     }
@@ -147,6 +146,11 @@ impl DiaryEntry {
         }
     }
 
+    /// Returns the time the entry was written.
+    pub fn timestamp(&self) -> OffsetDateTime {
+        self.timestamp
+    }
+
     pub fn from_block(text_block: &str) -> Result<DiaryEntry> {
         let mut text_block_parts = text_block.splitn(2, "\n\n");
         let mut time_and_title_line = text_block_parts
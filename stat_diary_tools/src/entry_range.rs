@@ -0,0 +1,257 @@
+use std::{
+    io,
+    path::{Path, PathBuf},
+};
+
+use log::{error, warn};
+use time::Date;
+
+use crate::{
+    data_entry::{self, DataEntry, DataFile},
+    db_path::DataBasePath,
+    storage::Storage,
+};
+
+/// Returns every data entry in the days from `start` to `end`, both inclusive, together with the
+/// date of the day it belongs to. The entries are returned in chronological order.
+///
+/// Only the year and month folders overlapping the range are visited, and each data file is read
+/// first when the iterator reaches it. Corrupted data files are skipped with a logged warning.
+///
+/// `legacy_offset` is only set for databases from older versions which store wall-clock hours.
+/// The hours are then compensated using the contained day_switch_offset when read.
+pub fn entries_between(
+    db_path: &DataBasePath,
+    start: Date,
+    end: Date,
+    legacy_offset: Option<i8>,
+) -> Result<impl Iterator<Item = (Date, DataEntry)>, io::Error> {
+    let day_files = day_files_between(db_path, start, end)?;
+    let db_path = db_path.clone();
+
+    Ok(day_files.into_iter().flat_map(move |(date, file_path)| {
+        read_sorted_entries(&db_path, &file_path, legacy_offset)
+            .into_iter()
+            .map(move |data_entry| (date, data_entry))
+    }))
+}
+
+//
+
+//
+
+/// Returns the date and path of every data file in the days from `start` to `end`, sorted by
+/// date.
+fn day_files_between(
+    db_path: &DataBasePath,
+    start: Date,
+    end: Date,
+) -> Result<Vec<(Date, PathBuf)>, io::Error> {
+    let mut day_files = Vec::new();
+    if end < start {
+        return Ok(day_files);
+    }
+
+    let storage = db_path.storage();
+    for year_path in storage.list(&db_path.data())? {
+        let Some(year) = folder_number::<i32>(storage, &year_path) else {
+            continue;
+        };
+        if !(start.year()..=end.year()).contains(&year) {
+            continue;
+        }
+
+        for month_path in storage.list(&year_path)? {
+            let Some(month) = folder_number::<u8>(storage, &month_path) else {
+                continue;
+            };
+            let first_month = (start.year(), start.month() as u8);
+            let last_month = (end.year(), end.month() as u8);
+            if !(first_month..=last_month).contains(&(year, month)) {
+                continue;
+            }
+
+            for file_path in storage.list(&month_path)? {
+                if !DataFile::is_data_file(storage, &file_path) {
+                    continue;
+                }
+                let Some(date) = DataFile::date_from_path(&file_path) else {
+                    warn!(
+                        "Skipping data file with invalid name or location: {:?}",
+                        file_path
+                    );
+                    continue;
+                };
+                if (start..=end).contains(&date) {
+                    day_files.push((date, file_path));
+                }
+            }
+        }
+    }
+
+    day_files.sort_by_key(|(date, _)| *date);
+    Ok(day_files)
+}
+
+//
+
+//
+
+/// Returns the number in the name of the folder at `path`, or `None` if `path` is not a folder or
+/// its name is not a number.
+fn folder_number<T: std::str::FromStr>(storage: &dyn Storage, path: &Path) -> Option<T> {
+    if !storage.is_dir(path) {
+        return None;
+    }
+    path.file_name()?.to_str()?.parse::<T>().ok()
+}
+
+//
+
+//
+
+/// Reads the entries of the data file at `file_path` sorted by hour. If the file can not be read
+/// the problem is logged and no entries are returned.
+fn read_sorted_entries(
+    db_path: &DataBasePath,
+    file_path: &Path,
+    legacy_offset: Option<i8>,
+) -> Vec<DataEntry> {
+    let mut data_file = match DataFile::read_from_file(db_path, file_path) {
+        Ok(data_file) => data_file,
+        Err(data_entry::Error::CorruptedDataFile) => {
+            warn!("Data file [{:?}] is corrupted! Skipping file...", file_path);
+            return Vec::new();
+        }
+        Err(e) => {
+            error!(
+                "Could not read data file [{:?}] due to {:?}! Skipping file...",
+                file_path, e
+            );
+            return Vec::new();
+        }
+    };
+
+    if let Some(day_switch_offset) = legacy_offset {
+        data_file.compensate_hours(day_switch_offset);
+    }
+
+    let mut entries: Vec<DataEntry> = data_file.entries().values().cloned().collect();
+    entries.sort_by_key(|entry| entry.time());
+    entries
+}
+
+//
+
+//
+
+#[cfg(test)]
+mod tests {
+    use time::{Date, Month};
+
+    use crate::{
+        data_base::DataBase,
+        test_utils::{create_database, data_file_path, date, entry},
+    };
+
+    /// Returns the date, hour and minute of every entry between `start` and `end`.
+    fn times_between(database: &DataBase, start: Date, end: Date) -> Vec<(Date, i8, u8)> {
+        database
+            .entries_between(start, end)
+            .unwrap()
+            .map(|(date, entry)| (date, entry.hour, entry.minute))
+            .collect()
+    }
+
+    //
+
+    //
+
+    #[test]
+    fn entries_are_returned_in_chronological_order() {
+        let database = create_database("range_order", -3);
+        // Months 2 and 10 sort in the wrong order when compared by name.
+        let writes = [
+            (2024, 10, 1, entry(5, 0, vec![])),
+            (2024, 2, 9, entry(10, 30, vec![])),
+            (2024, 2, 9, entry(-2, 0, vec![])),
+            (2024, 2, 9, entry(10, 5, vec![])),
+            (2023, 12, 31, entry(20, 0, vec![])),
+            (2024, 2, 10, entry(0, 0, vec![])),
+        ];
+        for (year, month, day, data_entry) in writes {
+            database
+                .insert_data_entry(year, month, day, data_entry)
+                .unwrap();
+        }
+
+        let expected = vec![
+            (date(2023, Month::December, 31), 20, 0),
+            (date(2024, Month::February, 9), -2, 0),
+            (date(2024, Month::February, 9), 10, 5),
+            (date(2024, Month::February, 9), 10, 30),
+            (date(2024, Month::February, 10), 0, 0),
+            (date(2024, Month::October, 1), 5, 0),
+        ];
+        let all = times_between(
+            &database,
+            date(2000, Month::January, 1),
+            date(2030, Month::January, 1),
+        );
+        assert_eq!(all, expected);
+    }
+
+    #[test]
+    fn only_days_within_the_range_are_returned() {
+        let database = create_database("range_pruning", 0);
+        for day in [1, 14, 15, 16, 31] {
+            database
+                .insert_data_entry(2024, 1, day, entry(12, 0, vec![]))
+                .unwrap();
+        }
+        database
+            .insert_data_entry(2023, 1, 15, entry(12, 0, vec![]))
+            .unwrap();
+        database
+            .insert_data_entry(2024, 2, 15, entry(12, 0, vec![]))
+            .unwrap();
+
+        let start = date(2024, Month::January, 14);
+        let end = date(2024, Month::January, 16);
+        let days: Vec<u8> = times_between(&database, start, end)
+            .into_iter()
+            .map(|(date, _, _)| date.day())
+            .collect();
+        assert_eq!(days, vec![14, 15, 16]);
+
+        // Both ends of the range are inclusive.
+        assert_eq!(times_between(&database, start, start).len(), 1);
+        assert!(times_between(&database, end, start).is_empty());
+    }
+
+    #[test]
+    fn corrupted_data_files_are_skipped() {
+        let database = create_database("range_corrupted", 0);
+        for day in [1, 2, 3] {
+            database
+                .insert_data_entry(2024, 1, day, entry(12, 0, vec![]))
+                .unwrap();
+        }
+        let file_path = data_file_path(&database, date(2024, Month::January, 2));
+        let storage = database.path().storage();
+        let bytes = storage.read(&file_path).unwrap();
+        storage
+            .write(&file_path, &bytes[..bytes.len() - 3])
+            .unwrap();
+
+        let days: Vec<u8> = times_between(
+            &database,
+            date(2024, Month::January, 1),
+            date(2024, Month::January, 31),
+        )
+        .into_iter()
+        .map(|(date, _, _)| date.day())
+        .collect();
+        assert_eq!(days, vec![1, 3]);
+    }
+}
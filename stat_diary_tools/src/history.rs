@@ -0,0 +1,560 @@
+use std::{
+    collections::HashMap,
+    fmt::{self, Write},
+    io,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use crate::{
+    data_entry::{DataEntry, DataFile},
+    db_path::DataBasePath,
+    storage::Storage,
+    tags::TagsSnapshot,
+};
+
+//
+
+//
+
+#[derive(Debug)]
+pub enum HistoryError {
+    Io(io::Error),
+    CorruptedUndoRecord(PathBuf),
+}
+
+impl From<io::Error> for HistoryError {
+    fn from(value: io::Error) -> Self {
+        Self::Io(value)
+    }
+}
+
+type Result<T> = std::result::Result<T, HistoryError>;
+
+const HISTORYPATH: &str = "history";
+const FINISHEDMARKER: &str = "finished";
+/// The number of undo records kept. The oldest record is removed once a new operation finishes.
+const HISTORYLIMIT: usize = 32;
+
+//
+
+//
+
+/// A destructive tag operation that can be undone.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Operation {
+    MergeTags(u16, u16),
+    RemoveTag(u16),
+}
+
+impl Operation {
+    /// Returns the id of the tag the operation removes from the tags file.
+    pub fn removed_tag(&self) -> u16 {
+        match self {
+            Operation::MergeTags(tag_1, _) => *tag_1,
+            Operation::RemoveTag(tag_id) => *tag_id,
+        }
+    }
+
+    fn parse(data_str: &str) -> Option<Operation> {
+        let mut parts = data_str.split(' ');
+        let operation = match parts.next()? {
+            "merge_tags" => {
+                Operation::MergeTags(parts.next()?.parse().ok()?, parts.next()?.parse().ok()?)
+            }
+            "remove_tag" => Operation::RemoveTag(parts.next()?.parse().ok()?),
+            _ => return None,
+        };
+        parts.next().is_none().then_some(operation)
+    }
+}
+
+impl fmt::Display for Operation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Operation::MergeTags(tag_1, tag_2) => write!(f, "merge_tags {} {}", tag_1, tag_2),
+            Operation::RemoveTag(tag_id) => write!(f, "remove_tag {}", tag_id),
+        }
+    }
+}
+
+/// A data entry whose tags were changed by a recorded operation.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChangedEntry {
+    pub file: PathBuf,
+    pub hour: i8,
+    pub minute: u8,
+    /// The tags of the entry before the operation.
+    pub old_tags: Vec<u16>,
+    /// The tags the operation left the entry with.
+    pub new_tags: Vec<u16>,
+}
+
+impl ChangedEntry {
+    /// Returns the hour and minute of the entry. See `DataEntry::time`.
+    pub fn time(&self) -> (i8, u8) {
+        (self.hour, self.minute)
+    }
+}
+
+//
+
+//
+
+/// The undo record of a single destructive tag operation, listing every entry it changed and the
+/// tags as they were before the operation.
+///
+/// Records are stored in the `history` folder of the database, numbered in the order the
+/// operations were run. Each record uses the following format:
+///
+/// ```text
+/// operation merge_tags 5 7
+/// offset 0
+/// tag 5 running
+/// next_id 8
+/// reclaimed 3
+/// old_tag 5 running
+/// old_tag 7 jogging
+/// entry 9:30|5 7|7|data/2024/1/1-0.statdiary
+/// finished
+/// ```
+///
+/// The `tag` line holds the tag the operation removes. The `next_id`, `reclaimed` and `old_tag`
+/// lines hold the complete tag list from before the operation, which is restored as it was when
+/// the operation is undone. Records written before the tag list was recorded only hold the
+/// removed tag.
+///
+/// Each `entry` line holds the hour and minute, the old tags and the new tags of the entry
+/// followed by the path of its data file. Records written before entries had minutes only hold
+/// the hour, in which case the minute is 0. The lines are appended before the data file is rewritten, so a record
+/// never misses a change. A record without the `finished` marker belongs to a operation that has
+/// not finished yet.
+#[derive(Debug)]
+pub struct UndoRecord {
+    storage: Arc<dyn Storage>,
+    file_path: PathBuf,
+    root: PathBuf,
+    number: u32,
+    operation: Operation,
+    day_switch_offset: i8,
+    removed_tag: (u16, String),
+    old_tags: Option<TagsSnapshot>,
+    entries: Vec<ChangedEntry>,
+    finished: bool,
+}
+
+impl UndoRecord {
+    /// Writes a new undo record for `operation`, numbered after the last record in the history.
+    /// `removed_tag` is the id and name of the tag the operation removes from the tags file, and
+    /// `old_tags` the tag list before the operation.
+    pub fn begin(
+        db_path: &DataBasePath,
+        operation: Operation,
+        day_switch_offset: i8,
+        removed_tag: (u16, String),
+        old_tags: TagsSnapshot,
+    ) -> Result<UndoRecord> {
+        let storage = db_path.shared_storage();
+        let history_path = db_path.root().join(HISTORYPATH);
+        storage.create_dir_all(&history_path)?;
+        let number = record_numbers(storage.as_ref(), &history_path)?
+            .last()
+            .map_or(1, |number| number + 1);
+        let file_path = history_path.join(format!("{}.txt", number));
+
+        let mut content = format!(
+            "operation {}\noffset {}\ntag {} {}\nnext_id {}\nreclaimed {}\n",
+            operation,
+            day_switch_offset,
+            removed_tag.0,
+            removed_tag.1,
+            old_tags.next_id,
+            join_tags(&old_tags.reclaimed_ids)
+        );
+        for (tag_id, tag_name) in &old_tags.tags {
+            writeln!(content, "old_tag {} {}", tag_id, tag_name)
+                .expect("Writing to a String can not fail.");
+        }
+        storage.write_atomic(&file_path, content.as_bytes())?;
+        storage.sync(&file_path)?;
+
+        Ok(UndoRecord {
+            storage,
+            file_path,
+            root: db_path.root().to_path_buf(),
+            number,
+            operation,
+            day_switch_offset,
+            removed_tag,
+            old_tags: Some(old_tags),
+            entries: Vec::new(),
+            finished: false,
+        })
+    }
+
+    //
+
+    //
+
+    /// Returns the most recent undo record, or `None` if the history is empty.
+    pub fn last(db_path: &DataBasePath) -> Result<Option<UndoRecord>> {
+        let history_path = db_path.root().join(HISTORYPATH);
+        let storage = db_path.storage();
+        if !storage.exists(&history_path) {
+            return Ok(None);
+        }
+        match record_numbers(storage, &history_path)?.last() {
+            Some(number) => UndoRecord::open(db_path, *number),
+            None => Ok(None),
+        }
+    }
+
+    /// Returns the undo record with the provided `number`, or `None` if it doesn't exist.
+    pub fn open(db_path: &DataBasePath, number: u32) -> Result<Option<UndoRecord>> {
+        let file_path = db_path
+            .root()
+            .join(HISTORYPATH)
+            .join(format!("{}.txt", number));
+        let storage = db_path.shared_storage();
+        if !storage.exists(&file_path) {
+            return Ok(None);
+        }
+
+        let content = storage.read_to_string(&file_path)?;
+        let Some(record) = UndoRecord::parse(db_path, number, &file_path, &content) else {
+            log::error!("The undo record {:?} is corrupted!", file_path);
+            return Err(HistoryError::CorruptedUndoRecord(file_path));
+        };
+        Ok(Some(record))
+    }
+
+    //
+
+    //
+
+    /// Records every entry whose tags differ between `old_entries` and the entries of
+    /// `data_file`. Must be called before `data_file` is saved.
+    pub fn record_changes(
+        &self,
+        old_entries: &HashMap<(i8, u8), DataEntry>,
+        data_file: &DataFile,
+    ) -> io::Result<()> {
+        let relative_path = data_file
+            .file_path()
+            .strip_prefix(&self.root)
+            .unwrap_or(data_file.file_path());
+
+        let mut lines = Vec::new();
+        for (time, new_entry) in data_file.entries() {
+            let Some(old_entry) = old_entries.get(time) else {
+                continue;
+            };
+            if old_entry.tags != new_entry.tags {
+                lines.push((
+                    *time,
+                    format!(
+                        "entry {}:{:02}|{}|{}|{}\n",
+                        new_entry.hour,
+                        new_entry.minute,
+                        join_tags(&old_entry.tags),
+                        join_tags(&new_entry.tags),
+                        relative_path.to_string_lossy()
+                    ),
+                ));
+            }
+        }
+        if lines.is_empty() {
+            return Ok(());
+        }
+
+        lines.sort();
+        let content: String = lines.into_iter().map(|(_, line)| line).collect();
+        self.storage.append(&self.file_path, content.as_bytes())?;
+        self.storage.sync(&self.file_path)
+    }
+
+    /// Marks the operation as finished, then removes the oldest records beyond the history
+    /// limit.
+    pub fn finish(self) -> io::Result<()> {
+        if !self.finished {
+            self.storage
+                .append(&self.file_path, format!("{}\n", FINISHEDMARKER).as_bytes())?;
+        }
+
+        let history_path = self.root.join(HISTORYPATH);
+        let numbers = record_numbers(self.storage.as_ref(), &history_path)?;
+        let excess = numbers.len().saturating_sub(HISTORYLIMIT);
+        for number in &numbers[..excess] {
+            self.storage
+                .remove_file(&history_path.join(format!("{}.txt", number)))?;
+        }
+        Ok(())
+    }
+
+    /// Removes the record once its operation has been undone.
+    pub fn remove(self) -> io::Result<()> {
+        self.storage.remove_file(&self.file_path)
+    }
+
+    //
+
+    //
+
+    pub fn number(&self) -> u32 {
+        self.number
+    }
+
+    pub fn operation(&self) -> &Operation {
+        &self.operation
+    }
+
+    /// Returns the day switch offset the database had when the operation was run.
+    pub fn day_switch_offset(&self) -> i8 {
+        self.day_switch_offset
+    }
+
+    /// Returns the id and name of the tag the operation removed from the tags file.
+    pub fn removed_tag(&self) -> (u16, &str) {
+        (self.removed_tag.0, &self.removed_tag.1)
+    }
+
+    /// Returns the tag list from before the operation, or `None` if the record was written before
+    /// the tag list was recorded.
+    pub fn old_tags(&self) -> Option<&TagsSnapshot> {
+        self.old_tags.as_ref()
+    }
+
+    pub fn entries(&self) -> &[ChangedEntry] {
+        &self.entries
+    }
+
+    /// Returns true if the operation ran to completion.
+    pub fn is_finished(&self) -> bool {
+        self.finished
+    }
+
+    //
+
+    //
+
+    /// Parses the content of a undo record. Returns `None` if the record is corrupted.
+    fn parse(
+        db_path: &DataBasePath,
+        number: u32,
+        file_path: &Path,
+        content: &str,
+    ) -> Option<UndoRecord> {
+        // The last line might have been cut off when the operation was interrupted. Data files
+        // are only rewritten once their lines are complete, so a cut off line can be ignored.
+        let content = &content[..content.rfind('\n').map_or(0, |index| index + 1)];
+        let mut lines = content.lines();
+        let operation = Operation::parse(lines.next()?.strip_prefix("operation ")?)?;
+        let day_switch_offset = lines.next()?.strip_prefix("offset ")?.parse().ok()?;
+        let (tag_id, tag_name) = lines.next()?.strip_prefix("tag ")?.split_once(' ')?;
+        let removed_tag = (tag_id.parse().ok()?, tag_name.to_string());
+
+        let mut next_id = None;
+        let mut reclaimed_ids = Vec::new();
+        let mut old_tags = Vec::new();
+        let mut entries: Vec<ChangedEntry> = Vec::new();
+        let mut finished = false;
+        for line in lines {
+            if line == FINISHEDMARKER {
+                finished = true;
+                continue;
+            }
+            if let Some(id) = line.strip_prefix("next_id ") {
+                next_id = Some(id.parse().ok()?);
+                continue;
+            }
+            if let Some(tag_ids) = line.strip_prefix("reclaimed ") {
+                reclaimed_ids = parse_tags(tag_ids)?;
+                continue;
+            }
+            if let Some(tag) = line.strip_prefix("old_tag ") {
+                let (tag_id, tag_name) = tag.split_once(' ')?;
+                old_tags.push((tag_id.parse().ok()?, tag_name.to_string()));
+                continue;
+            }
+            let mut parts = line.strip_prefix("entry ")?.splitn(4, '|');
+            let (hour, minute) = parse_time(parts.next()?)?;
+            let entry = ChangedEntry {
+                hour,
+                minute,
+                old_tags: parse_tags(parts.next()?)?,
+                new_tags: parse_tags(parts.next()?)?,
+                file: db_path.root().join(parts.next()?),
+            };
+            // A data file rewritten again after a interruption might be recorded twice. The
+            // first line holds the tags from before the operation.
+            if !entries
+                .iter()
+                .any(|other| other.file == entry.file && other.time() == entry.time())
+            {
+                entries.push(entry);
+            }
+        }
+
+        Some(UndoRecord {
+            storage: db_path.shared_storage(),
+            file_path: file_path.to_path_buf(),
+            root: db_path.root().to_path_buf(),
+            number,
+            operation,
+            day_switch_offset,
+            removed_tag,
+            old_tags: next_id.map(|next_id| TagsSnapshot {
+                tags: old_tags,
+                reclaimed_ids,
+                next_id,
+            }),
+            entries,
+            finished,
+        })
+    }
+}
+
+//
+
+//
+
+/// Returns the most recent undo record of a operation that removed the tag `tag_id` from the tags
+/// file, or `None` if no record in the history did.
+pub fn last_removing(db_path: &DataBasePath, tag_id: u16) -> Result<Option<UndoRecord>> {
+    let history_path = db_path.root().join(HISTORYPATH);
+    let storage = db_path.storage();
+    if !storage.exists(&history_path) {
+        return Ok(None);
+    }
+    for number in record_numbers(storage, &history_path)?.into_iter().rev() {
+        if let Some(undo_record) = UndoRecord::open(db_path, number)? {
+            if undo_record.operation().removed_tag() == tag_id {
+                return Ok(Some(undo_record));
+            }
+        }
+    }
+    Ok(None)
+}
+
+/// Returns the numbers of every undo record in the history folder, sorted in ascending order.
+fn record_numbers(storage: &dyn Storage, history_path: &Path) -> io::Result<Vec<u32>> {
+    let mut numbers: Vec<u32> = storage
+        .list(history_path)?
+        .iter()
+        .filter_map(|path| path.file_stem()?.to_str()?.parse().ok())
+        .collect();
+    numbers.sort_unstable();
+    Ok(numbers)
+}
+
+fn join_tags(tags: &[u16]) -> String {
+    tags.iter()
+        .map(|tag_id| tag_id.to_string())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Parses the `hour:minute` of a entry line, where the minute is missing in records written
+/// before entries had minutes.
+fn parse_time(time: &str) -> Option<(i8, u8)> {
+    match time.split_once(':') {
+        Some((hour, minute)) => Some((hour.parse().ok()?, minute.parse().ok()?)),
+        None => Some((time.parse().ok()?, 0)),
+    }
+}
+
+fn parse_tags(tags: &str) -> Option<Vec<u16>> {
+    tags.split_whitespace()
+        .map(|tag_id| tag_id.parse().ok())
+        .collect()
+}
+
+//
+
+//
+
+#[cfg(test)]
+mod tests {
+    use time::Month;
+
+    use crate::{
+        data_base::ErrorKind,
+        tags::TagList,
+        test_utils::{create_database, date, day_entries, entry, tags},
+    };
+
+    #[test]
+    fn undo_restores_merged_tags() {
+        let database = create_database("undo_merge", 0);
+        database.add_tag("a".to_string()).unwrap();
+        database.add_tag("b".to_string()).unwrap();
+        database
+            .insert_data_entry(2024, 1, 1, entry(8, 0, vec![1]))
+            .unwrap();
+        database
+            .insert_data_entry(2024, 1, 1, entry(9, 0, vec![1, 2]))
+            .unwrap();
+        let old_tags = tags(&database);
+
+        database.merge_tags(1, 2).unwrap();
+        assert_eq!(tags(&database), vec![(2, "b".to_string())]);
+        assert_eq!(
+            day_entries(&database, date(2024, Month::January, 1)),
+            vec![(8, 0, vec![2]), (9, 0, vec![2])]
+        );
+
+        database.undo_last().unwrap();
+        assert_eq!(tags(&database), old_tags);
+        assert_eq!(
+            day_entries(&database, date(2024, Month::January, 1)),
+            vec![(8, 0, vec![1]), (9, 0, vec![1, 2])]
+        );
+        assert!(database.check().unwrap().is_ok());
+        let error = database.undo_last().unwrap_err();
+        assert!(matches!(error.kind, ErrorKind::NothingToUndo));
+    }
+
+    #[test]
+    fn undo_restores_removed_tag_and_reclaimed_ids() {
+        let database = create_database("undo_remove", 0);
+        database.add_tag("a".to_string()).unwrap();
+        database.add_tag("b".to_string()).unwrap();
+        database
+            .insert_data_entry(2024, 1, 1, entry(8, 0, vec![1, 2]))
+            .unwrap();
+        let old_tags = tags(&database);
+
+        database.remove_tag(1).unwrap();
+        assert_eq!(tags(&database), vec![(2, "b".to_string())]);
+        assert_eq!(
+            TagList::from_file(database.path()).unwrap().reclaimed_ids(),
+            &[1]
+        );
+
+        database.undo_last().unwrap();
+        assert_eq!(tags(&database), old_tags);
+        assert!(TagList::from_file(database.path())
+            .unwrap()
+            .reclaimed_ids()
+            .is_empty());
+        assert_eq!(
+            day_entries(&database, date(2024, Month::January, 1)),
+            vec![(8, 0, vec![1, 2])]
+        );
+    }
+
+    #[test]
+    fn undo_conflicts_with_later_tag_changes() {
+        let database = create_database("undo_conflict", 0);
+        database.add_tag("a".to_string()).unwrap();
+        database.add_tag("b".to_string()).unwrap();
+        database.remove_tag(1).unwrap();
+        database
+            .rename_tag("b".to_string(), "c".to_string())
+            .unwrap();
+
+        let error = database.undo_last().unwrap_err();
+        assert!(matches!(error.kind, ErrorKind::UndoConflict));
+        assert_eq!(tags(&database), vec![(2, "c".to_string())]);
+    }
+}
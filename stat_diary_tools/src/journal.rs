@@ -0,0 +1,271 @@
+use std::{
+    io,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use crate::{db_path::DataBasePath, storage::Storage};
+
+//
+
+//
+
+const JOURNALFILENAME: &str = ".journal.txt";
+const PLANNEDMARKER: &str = "planned";
+
+/// One file rewrite planned by a journaled task.
+#[derive(Debug, Clone, PartialEq)]
+pub enum JournalStep {
+    /// Rewrite the data file at the contained path.
+    DataFile(PathBuf),
+    /// Rewrite the tags file.
+    TagsFile,
+}
+
+//
+
+//
+
+/// A write-ahead journal for tasks that rewrite several files, such as `merge_tags` and
+/// `remove_tag`.
+///
+/// Every file rewrite is written to the journal before the first file is touched, and each step
+/// is marked as done once its file has been rewritten. The journal is stored in the database root
+/// using the following format:
+///
+/// ```text
+/// task merge_tags 5 7
+/// data data/2024/1/1-0.statdiary
+/// tags
+/// planned
+/// done 0
+/// ```
+///
+/// A journal missing the `planned` marker was interrupted before any file was rewritten, and is
+/// rolled back by discarding it. Otherwise the task is rolled forward from the first step that
+/// isn't marked as done.
+#[derive(Debug)]
+pub struct Journal {
+    storage: Arc<dyn Storage>,
+    file_path: PathBuf,
+    steps: Vec<JournalStep>,
+    completed: Vec<bool>,
+}
+
+impl Journal {
+    /// Writes a new journal for `task` planning the provided `steps`, replacing any journal left
+    /// behind by an earlier task. The journal is synced to storage before it is returned.
+    pub fn begin(
+        db_path: &DataBasePath,
+        task: &str,
+        steps: Vec<JournalStep>,
+    ) -> io::Result<Journal> {
+        let file_path = db_path.root().join(JOURNALFILENAME);
+
+        let mut content = format!("task {}\n", task);
+        for step in &steps {
+            match step {
+                JournalStep::DataFile(path) => {
+                    let relative_path = path.strip_prefix(db_path.root()).unwrap_or(path);
+                    content.push_str(&format!("data {}\n", relative_path.to_string_lossy()));
+                }
+                JournalStep::TagsFile => content.push_str("tags\n"),
+            }
+        }
+        content.push_str(PLANNEDMARKER);
+        content.push('\n');
+
+        let storage = db_path.shared_storage();
+        storage.write(&file_path, content.as_bytes())?;
+        storage.sync(&file_path)?;
+
+        Ok(Journal {
+            storage,
+            file_path,
+            completed: vec![false; steps.len()],
+            steps,
+        })
+    }
+
+    //
+
+    //
+
+    /// Opens the journal of an interrupted `task` so that it can be rolled forward.
+    ///
+    /// Returns `None` if no journal exists, or if the journal was rolled back. A journal is
+    /// rolled back if it was never completely written, if it belongs to another task or if it is
+    /// corrupted. Rolling back is always safe since the planned rewrites can be repeated.
+    pub fn resume(db_path: &DataBasePath, task: &str) -> io::Result<Option<Journal>> {
+        let file_path = db_path.root().join(JOURNALFILENAME);
+        let storage = db_path.shared_storage();
+        if !storage.exists(&file_path) {
+            return Ok(None);
+        }
+
+        let content = storage.read_to_string(&file_path)?;
+        let Some((steps, completed)) = Journal::parse(db_path.root(), task, &content) else {
+            storage.remove_file(&file_path)?;
+            return Ok(None);
+        };
+
+        Ok(Some(Journal {
+            storage,
+            file_path,
+            steps,
+            completed,
+        }))
+    }
+
+    //
+
+    //
+
+    /// Returns the index and step of every planned step that isn't done yet, in planned order.
+    pub fn pending_steps(&self) -> Vec<(usize, JournalStep)> {
+        self.steps
+            .iter()
+            .enumerate()
+            .filter(|(index, _)| !self.completed[*index])
+            .map(|(index, step)| (index, step.clone()))
+            .collect()
+    }
+
+    //
+
+    //
+
+    /// Marks the step at `index` as done.
+    pub fn complete(&mut self, index: usize) -> io::Result<()> {
+        self.storage
+            .append(&self.file_path, format!("done {}\n", index).as_bytes())?;
+        self.completed[index] = true;
+        Ok(())
+    }
+
+    //
+
+    //
+
+    /// Removes the journal once the task is finished.
+    pub fn finish(self) -> io::Result<()> {
+        self.storage.remove_file(&self.file_path)
+    }
+
+    //
+
+    //
+
+    /// Parses the content of a journal file, returning the planned steps and which of them are
+    /// done. Returns `None` if the journal should be rolled back.
+    fn parse(root: &Path, task: &str, content: &str) -> Option<(Vec<JournalStep>, Vec<bool>)> {
+        let mut lines = content.lines();
+
+        let journal_task = lines.next()?.strip_prefix("task ")?;
+        if journal_task != task {
+            log::warn!(
+                "Found a journal for [{}] while resuming [{}]. Discarding the journal...",
+                journal_task,
+                task
+            );
+            return None;
+        }
+
+        let mut steps = Vec::new();
+        loop {
+            let Some(line) = lines.next() else {
+                log::info!("The journal of [{}] was never completed. No files were rewritten, rolling back...", task);
+                return None;
+            };
+            if line == PLANNEDMARKER {
+                break;
+            }
+            match line.split_once(' ') {
+                Some(("data", path)) => steps.push(JournalStep::DataFile(root.join(path))),
+                None if line == "tags" => steps.push(JournalStep::TagsFile),
+                _ => {
+                    log::error!("The journal of [{}] contains the invalid step [{}]! Discarding the journal...", task, line);
+                    return None;
+                }
+            }
+        }
+
+        let mut completed = vec![false; steps.len()];
+        let done_lines: Vec<&str> = lines.collect();
+        for (line_nr, line) in done_lines.iter().enumerate() {
+            let index = line
+                .strip_prefix("done ")
+                .and_then(|index| index.parse::<usize>().ok())
+                .filter(|index| *index < steps.len());
+            match index {
+                Some(index) => completed[index] = true,
+                // The last line might have been cut off when the task was interrupted.
+                None if line_nr + 1 == done_lines.len() && !content.ends_with('\n') => {}
+                None => {
+                    log::error!("The journal of [{}] contains the invalid line [{}]! Discarding the journal...", task, line);
+                    return None;
+                }
+            }
+        }
+
+        Some((steps, completed))
+    }
+}
+
+//
+
+//
+
+#[cfg(test)]
+mod tests {
+    use super::{Journal, JournalStep};
+    use crate::test_utils::create_database;
+
+    #[test]
+    fn journal_rolls_forward_from_the_first_pending_step() {
+        let database = create_database("journal_forward", 0);
+        let data_path = database.path().data().join("2024/1/1-0.statdiary");
+        let steps = vec![JournalStep::DataFile(data_path), JournalStep::TagsFile];
+
+        let mut journal = Journal::begin(database.path(), "merge_tags 1 2", steps).unwrap();
+        journal.complete(0).unwrap();
+        drop(journal);
+
+        let journal = Journal::resume(database.path(), "merge_tags 1 2")
+            .unwrap()
+            .unwrap();
+        assert_eq!(journal.pending_steps(), vec![(1, JournalStep::TagsFile)]);
+        journal.finish().unwrap();
+        assert!(Journal::resume(database.path(), "merge_tags 1 2")
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn journal_is_rolled_back_if_it_was_never_planned() {
+        let database = create_database("journal_back", 0);
+        let journal_path = database.database_path().join(".journal.txt");
+        let storage = database.path().storage();
+
+        // Interrupted while the planned steps were written.
+        storage
+            .write(
+                &journal_path,
+                b"task merge_tags 1 2\ndata data/2024/1/1-0.statdiary\n",
+            )
+            .unwrap();
+        assert!(Journal::resume(database.path(), "merge_tags 1 2")
+            .unwrap()
+            .is_none());
+        assert!(!storage.exists(&journal_path));
+
+        // Interrupted while a step was marked as done.
+        storage
+            .write(&journal_path, b"task remove_tag 1\ntags\nplanned\ndo")
+            .unwrap();
+        let journal = Journal::resume(database.path(), "remove_tag 1")
+            .unwrap()
+            .unwrap();
+        assert_eq!(journal.pending_steps(), vec![(0, JournalStep::TagsFile)]);
+    }
+}
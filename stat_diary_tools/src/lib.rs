@@ -4,16 +4,31 @@ pub mod analytics;
 mod backup;
 pub mod c_wrapper;
 mod cache_handling;
+mod check;
 pub mod data_base;
 mod data_entry;
 mod day_switch_offset;
 mod db_path;
 mod db_status;
 mod diary_file;
+mod entry_range;
+mod history;
+mod journal;
 mod logger;
+mod metrics;
+mod migrations;
+mod notifications;
+mod process;
+mod regeneration;
+mod repair;
 mod settings_file;
+pub mod shared_data_base;
 mod stat_sums;
+pub mod storage;
 mod tags;
+pub mod task_progress;
+#[cfg(test)]
+mod test_utils;
 mod update_database;
 
 const DATAFILEEXTENSION: &str = "statdiary";
@@ -34,9 +49,6 @@ pub fn init_logger() -> Result<(), SetLoggerError> {
 
 // TODO:
 //
-// Create "create database" method.
-//      Right now we don't have any way to create a new database. Which is less than ideal.
-//
 // Move file path logic to its own file.
 //      Currently data file paths are used by more than one system.
 //      It would be benefitial to move the logic for creating filepaths based on dates to its
@@ -55,36 +67,6 @@ pub fn init_logger() -> Result<(), SetLoggerError> {
 //
 // Insert Diary Entry function
 //
-// Adjust day_switch_offset function
-// (
-//      This will require moving data entries between datafiles.
-//      For the current data entries the "day" changes at 04:00, meaning a entry at 1:00 am will
-//      not be placed in the next day, but rather be left at the current day.
-//      Meaning that if we want to change the offset back to 0, so a new day file begins at 00:00
-//      then we will have to move any entry at 00:00 up to 04:00 to the next day file.
-//
-//      The best way to do this is likely to include a db_settings file which holds the current
-//      offset.
-//
-//      When we change the offset we should make the changes on a copy of the database instead of
-//      the original. Since if however unlikely the program stops mid-change there will be no way
-//      to tell which entries has been moved or not. We could probably find a way to "save" what
-//      has beem changed, but I think in this case a full copy and swap once complete is the better
-//      choice.
-// )
-// Update Data Entry functions to use the day_switch_offset when selecting data files.
-// Update TemporaryUpdateDataBase to take in a int representing the current offset.
-// Update DataBase to include a day_switch_offset field read from db_settings.txt
-//
-// Think about the way we store data entries and the hours they belong to.
-// Currently I am fairly sure we store the hour directly, meaning that with a offset of +4 hours
-// 1 am is actually the next day even though when sorted by value it would show up before all
-// entries of that day. It might be benefitial to instead add the offset to the hour provided.
-// Meaning that if we get a entry at 2 am with a offset of +4 hours we save the entry with hour
-// 2 + 24 to compensate for the offset. That way the hours are easier to sort in the correct order,
-// while at the same time enabling us to safely change the day_switch_offset since we will be able
-// to tell what entries have and haven't been moved.
-//
 // Analytical functions? Potential examples:
 // - Rank tags by scores.
 // - Rank tags by day-scores.
@@ -132,12 +114,13 @@ pub mod utilities {
 
     pub fn print_data_file(datafile: &DataFile, taglist: &TagList) {
         let mut entries: Vec<&DataEntry> = datafile.entries().values().collect();
-        entries.sort_by_key(|a| a.hour);
+        entries.sort_by_key(|a| a.time());
         for entry in entries {
-            print!(
-                "[{}] ms: {}, ps: {}, tags:",
-                entry.hour, entry.mental_score, entry.physical_score
-            );
+            print!("[{}]", entry.hour);
+            for (metric_id, value) in &entry.metrics {
+                print!(" {}: {},", metric_id, value);
+            }
+            print!(" tags:");
             for tag in &entry.tags {
                 print!(" {}", taglist.get_tag(*tag).unwrap());
             }
@@ -150,9 +133,8 @@ pub mod utilities {
     }
 
     pub fn get_datafile(database: &DataBase, year: i32, month: u8, day: u8) -> DataFile {
-        let datetime = DataBase::parse_datetime(year, month, day, 12).unwrap();
-        let filepath = database.get_date_file_path(datetime).unwrap();
-        DataFile::open_data_file(&filepath).unwrap()
+        let date = DataBase::parse_date(year, month, day).unwrap();
+        DataFile::open_data_file(database.path(), &database.day_file_path(date)).unwrap()
     }
 
     /*
@@ -0,0 +1,281 @@
+use std::{collections::BTreeMap, fmt::Write, io, path::Path};
+
+use crate::{data_entry::DataEntry, db_path::DataBasePath, storage::Storage};
+
+#[derive(Debug)]
+pub enum MetricsError {
+    Io(io::Error),
+    CorruptedMetricsFile(String),
+    UnknownMetric(String),
+    UnknownId(u16),
+    MetricAlreadyExists,
+    /// The value is outside of the range of the metric. Holds the id of the metric.
+    ValueOutOfRange(u16),
+    /// The name is empty or contains a line break, or the minimum is above the maximum.
+    InvalidMetric,
+}
+
+impl From<io::Error> for MetricsError {
+    fn from(err: io::Error) -> Self {
+        Self::Io(err)
+    }
+}
+
+type Result<T> = std::result::Result<T, MetricsError>;
+
+pub(crate) const METRICSFILENAME: &str = "metrics.txt";
+
+/// The id of the built-in mental score, which every entry written before metrics were
+/// user-defined holds.
+pub const MENTALSCORE: u16 = 0;
+/// The id of the built-in physical score, which every entry written before metrics were
+/// user-defined holds.
+pub const PHYSICALSCORE: u16 = 1;
+
+//
+
+//
+
+/// A numeric value tracked by data entries, like the mental score or a user-defined metric such
+/// as energy or pain. Every value of the metric lies within `min..=max`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Metric {
+    pub id: u16,
+    pub name: String,
+    pub min: i16,
+    pub max: i16,
+}
+
+impl Metric {
+    /// Returns true if `value` lies within the range of this metric.
+    pub fn contains(&self, value: i16) -> bool {
+        (self.min..=self.max).contains(&value)
+    }
+}
+
+/// The metrics every database starts out with. Databases without a metrics file hold exactly
+/// these.
+pub fn built_in_metrics() -> [Metric; 2] {
+    [
+        Metric {
+            id: MENTALSCORE,
+            name: "mental".to_string(),
+            min: 0,
+            max: 100,
+        },
+        Metric {
+            id: PHYSICALSCORE,
+            name: "physical".to_string(),
+            min: 0,
+            max: 100,
+        },
+    ]
+}
+
+/// Writes a metrics file holding only the built-in metrics to the database at `db_root`,
+/// overwriting any existing metrics file.
+pub(crate) fn write_built_in_metrics(storage: &dyn Storage, db_root: &Path) -> io::Result<()> {
+    let content = metrics_file_content(&built_in_metrics());
+    storage.write_atomic(&db_root.join(METRICSFILENAME), content.as_bytes())
+}
+
+/// Returns the content of a metrics file listing the provided metrics.
+fn metrics_file_content<'a>(metrics: impl IntoIterator<Item = &'a Metric>) -> String {
+    let mut content = String::new();
+    for metric in metrics {
+        writeln!(
+            content,
+            "{} {} {} {}",
+            metric.id, metric.min, metric.max, metric.name
+        )
+        .expect("Writing to a String can not fail.");
+    }
+    content
+}
+
+//
+
+//
+
+/// This is a in-memory representation of the metrics file, listing every metric that data entries
+/// can hold a value for.
+/// Each line of the file follows the format "{metric_id} {min} {max} {metric_name}", where the
+/// name is the remainder of the line and may contain spaces.
+pub struct MetricList {
+    metrics: BTreeMap<u16, Metric>,
+    db_path: DataBasePath,
+}
+
+impl MetricList {
+    /// Attempts to create a MetricList instance using the provided database.
+    /// If said database is missing a metrics.txt file the list holds the built-in metrics.
+    /// It will fail if said file doesn't follow the expected format, or lists a id or name twice.
+    pub fn from_file(db_path: &DataBasePath) -> Result<MetricList> {
+        let filepath = db_path.root().join(METRICSFILENAME);
+        let storage = db_path.storage();
+
+        if !storage.exists(&filepath) {
+            return Ok(MetricList {
+                metrics: built_in_metrics()
+                    .into_iter()
+                    .map(|metric| (metric.id, metric))
+                    .collect(),
+                db_path: db_path.clone(),
+            });
+        }
+
+        let mut metrics = BTreeMap::new();
+        for line in storage.read_lines(&filepath)? {
+            let metric =
+                Self::parse_line(&line).ok_or(MetricsError::CorruptedMetricsFile(line.clone()))?;
+
+            if metrics
+                .values()
+                .any(|other: &Metric| other.name == metric.name)
+            {
+                return Err(MetricsError::CorruptedMetricsFile(
+                    "Duplicate metrics found in metrics file!".to_string(),
+                ));
+            }
+            if metrics.insert(metric.id, metric).is_some() {
+                return Err(MetricsError::CorruptedMetricsFile(
+                    "Duplicate metric ids found in metrics file!".to_string(),
+                ));
+            }
+        }
+
+        Ok(MetricList {
+            metrics,
+            db_path: db_path.clone(),
+        })
+    }
+
+    fn parse_line(line: &str) -> Option<Metric> {
+        let mut parts = line.splitn(4, ' ');
+        let metric = Metric {
+            id: parts.next()?.parse().ok()?,
+            min: parts.next()?.parse().ok()?,
+            max: parts.next()?.parse().ok()?,
+            name: parts.next()?.to_string(),
+        };
+        (!metric.name.is_empty() && metric.min <= metric.max).then_some(metric)
+    }
+
+    //
+
+    //
+
+    /// Returns the metric with the provided id.
+    /// If the id doesn't exist a MetricsError::UnknownId is returned.
+    pub fn get(&self, metric_id: u16) -> Result<&Metric> {
+        self.metrics
+            .get(&metric_id)
+            .ok_or(MetricsError::UnknownId(metric_id))
+    }
+
+    /// Returns the id linked to the provided metric name.
+    /// If the name doesn't exist a MetricsError::UnknownMetric is returned.
+    pub fn get_id(&self, name: &str) -> Result<u16> {
+        self.metrics
+            .values()
+            .find(|metric| metric.name == name)
+            .map(|metric| metric.id)
+            .ok_or(MetricsError::UnknownMetric(name.to_string()))
+    }
+
+    /// Returns a iterator over every metric, ordered by id.
+    pub fn iter(&self) -> impl Iterator<Item = &Metric> {
+        self.metrics.values()
+    }
+
+    //
+
+    //
+
+    /// Checks that every metric value of `entry` belongs to a existing metric and lies within its
+    /// range.
+    pub fn validate(&self, entry: &DataEntry) -> Result<()> {
+        for (&metric_id, &value) in &entry.metrics {
+            if !self.get(metric_id)?.contains(value) {
+                log::error!(
+                    "MetricList::validate(): Value [{}] of metric [{}] is out of range!",
+                    value,
+                    metric_id
+                );
+                return Err(MetricsError::ValueOutOfRange(metric_id));
+            }
+        }
+        Ok(())
+    }
+
+    //
+
+    //
+
+    /// Adds a metric with the provided name and range, returning its id. Ids of metrics are never
+    /// reused, so the new id is one above the highest id in use.
+    pub fn add_metric(&mut self, name: String, min: i16, max: i16) -> Result<u16> {
+        Self::validate_name(&name)?;
+        if min > max {
+            return Err(MetricsError::InvalidMetric);
+        }
+        if self.get_id(&name).is_ok() {
+            return Err(MetricsError::MetricAlreadyExists);
+        }
+
+        let id = self
+            .metrics
+            .keys()
+            .next_back()
+            .map_or(0, |last_id| last_id + 1);
+        self.metrics.insert(id, Metric { id, name, min, max });
+        Ok(id)
+    }
+
+    //
+
+    //
+
+    /// Attempts to rename old_name to new_name while keeping the same id and range.
+    /// If old_name doesn't exist a MetricsError::UnknownMetric will be returned.
+    /// If new_name already exists this will fail with a MetricsError::MetricAlreadyExists.
+    pub fn rename_metric(&mut self, old_name: &str, new_name: String) -> Result<u16> {
+        Self::validate_name(&new_name)?;
+        if self.get_id(&new_name).is_ok() {
+            return Err(MetricsError::MetricAlreadyExists);
+        }
+
+        let metric_id = self.get_id(old_name)?;
+        if let Some(metric) = self.metrics.get_mut(&metric_id) {
+            metric.name = new_name;
+        }
+        Ok(metric_id)
+    }
+
+    fn validate_name(name: &str) -> Result<()> {
+        if name.is_empty() || name.contains(['\n', '\r']) {
+            log::error!("MetricList: [{:?}] is not a valid metric name!", name);
+            return Err(MetricsError::InvalidMetric);
+        }
+        Ok(())
+    }
+
+    //
+
+    //
+
+    /// Saves the metric list to the metrics file of the database.
+    /// The original file is overwritten by the new.
+    ///
+    /// Writes to a .tmp file which when completed is swapped with the original file, ensuring that
+    /// no data is lost in the event of the program stopping mid-write.
+    pub fn save(&self) -> Result<()> {
+        let filepath = self.db_path.root().join(METRICSFILENAME);
+        let content = metrics_file_content(self.metrics.values());
+
+        self.db_path
+            .storage()
+            .write_atomic(&filepath, content.as_bytes())?;
+        Ok(())
+    }
+}
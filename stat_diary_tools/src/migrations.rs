@@ -0,0 +1,521 @@
+use std::{
+    ffi::OsStr,
+    path::{Path, PathBuf},
+};
+
+use log::{error, info, warn};
+
+use crate::{
+    data_base::{DataBase, Error, ErrorKind},
+    data_entry::{DataFile, DataFileFormat},
+    day_switch_offset::{
+        copy_directory, discard_copy, ensure_no_leftover_copies, scratch_path, swap_in_copy,
+    },
+    db_path::DataBasePath,
+    db_status::ActiveTask,
+    metrics::{self, MetricList},
+    regeneration::{self, RegenerationTargets},
+    repair,
+    settings_file::Settings,
+    tags::TagList,
+    task_progress::TaskProgress,
+    update_database,
+};
+
+type Result<T> = std::result::Result<T, Error>;
+
+/// A single step in the format history of the database.
+struct Migration {
+    description: &'static str,
+    /// Converts the database at the provided path from the previous schema version. The settings
+    /// are those of the database before the migration.
+    up: fn(&DataBasePath, &Settings) -> Result<()>,
+    /// Checks that the migrated database (second) holds the same data as the database it was
+    /// migrated from (first).
+    verify: fn(&DataBasePath, &DataBasePath) -> Result<()>,
+}
+
+/// Every migration in the order they are run. The migration at index `n` converts a database
+/// from schema version `n` to `n + 1`.
+///
+/// Migrations are never changed or removed once released. A new format is introduced by adding
+/// a migration to the end of the list.
+const MIGRATIONS: [Migration; 4] = [
+    Migration {
+        description: "Convert the legacy text format",
+        up: convert_legacy_format,
+        verify: verify_legacy_format,
+    },
+    Migration {
+        description: "Store offset-compensated hours",
+        up: compensate_hours,
+        verify: verify_compensated_hours,
+    },
+    Migration {
+        description: "Store the minute of each entry",
+        up: store_minutes,
+        verify: verify_stored_minutes,
+    },
+    Migration {
+        description: "Store user-defined metric values",
+        up: store_metrics,
+        verify: verify_stored_metrics,
+    },
+];
+
+/// The schema version of databases created by this version of the library.
+pub const CURRENT_SCHEMA_VERSION: u32 = MIGRATIONS.len() as u32;
+
+/// The oldest schema version that can be opened as read-only without being migrated.
+pub const OLDEST_READABLE_SCHEMA_VERSION: u32 = 1;
+
+const MIGRATIONSUFFIX: &str = "migration";
+const OLDSUFFIX: &str = "premigration";
+
+//
+
+//
+
+/// Migrates `database` to `CURRENT_SCHEMA_VERSION`.
+///
+/// Each migration is run on a copy of the previous version placed next to the database, and is
+/// verified against that version before the next migration runs. The original is only replaced
+/// once every migration has succeeded, so a failed or interrupted migration leaves it untouched.
+/// The copies left behind by an interrupted migration have to be removed before the database can
+/// be migrated again.
+///
+/// Data files that can't be read end up in the quarantine folder of the migrated database. See
+/// `migrate_copy`.
+pub(crate) fn migrate(database: &DataBase) -> Result<()> {
+    let from_version = database.settings().schema_version;
+    let original = database.path();
+    let storage = original.storage();
+    let old_path = scratch_path(original.root(), OLDSUFFIX)?;
+    let copy_paths = (from_version + 1..=CURRENT_SCHEMA_VERSION)
+        .map(|version| migration_path(original.root(), version))
+        .collect::<Result<Vec<_>>>()?;
+
+    info!(
+        "Migrating the database at {:?} from schema version {} to {}...",
+        original.root(),
+        from_version,
+        CURRENT_SCHEMA_VERSION
+    );
+    let db_status = database.lock(ActiveTask::None)?;
+    let scratch_paths = copy_paths
+        .iter()
+        .chain([&old_path])
+        .map(PathBuf::as_path)
+        .collect::<Vec<_>>();
+    if let Err(error) = ensure_no_leftover_copies(storage, &scratch_paths) {
+        db_status.unlock();
+        return Err(error);
+    }
+
+    let migrated = match run_migrations(original, from_version) {
+        Ok(migrated) => migrated,
+        Err(error) => {
+            error!("Failed to migrate the database! The original database was left unchanged.");
+            db_status.unlock();
+            return Err(error);
+        }
+    };
+
+    if let Err(error) = swap_in_copy(storage, original.root(), migrated.root(), &old_path) {
+        error!("Failed to replace the database with the migrated copy!");
+        discard_copy(storage, original.root(), migrated.root());
+        db_status.unlock();
+        return Err(error);
+    }
+
+    // The lock file was removed together with the original database.
+    drop(db_status);
+    info!(
+        "Finished migrating the database to schema version {}!",
+        CURRENT_SCHEMA_VERSION
+    );
+    Ok(())
+}
+
+//
+
+//
+
+/// Runs every migration after `from_version`, each on its own copy of the previous version.
+/// Returns the path of the fully migrated copy.
+///
+/// Every copy except the returned one is removed, also when a migration fails.
+fn run_migrations(original: &DataBasePath, from_version: u32) -> Result<DataBasePath> {
+    let storage = original.storage();
+    let mut previous = original.clone();
+    for (index, migration) in MIGRATIONS.iter().enumerate().skip(from_version as usize) {
+        let version = index as u32 + 1;
+        info!(
+            "Running migration to schema version {}: {}",
+            version, migration.description
+        );
+        let copy_path = migration_path(original.root(), version)?;
+        let result = migrate_copy(&previous, &copy_path, version, migration);
+
+        if previous.root() != original.root() {
+            discard_copy(storage, original.root(), previous.root());
+        }
+        previous = match result {
+            Ok(migrated) => migrated,
+            Err(error) => {
+                discard_copy(storage, original.root(), &copy_path);
+                return Err(error);
+            }
+        };
+    }
+    Ok(previous)
+}
+
+/// Returns the path of the copy holding the database at `db_root` migrated to schema `version`.
+fn migration_path(db_root: &Path, version: u32) -> Result<PathBuf> {
+    scratch_path(db_root, &format!("{}_v{}", MIGRATIONSUFFIX, version))
+}
+
+//
+
+//
+
+/// Copies the database at `previous` to `copy_path`, then runs and verifies `migration` on the
+/// copy, leaving it at schema `version`.
+///
+/// Data files that can't be read are moved into the quarantine folder of the copy first, and are
+/// recorded in its repair log. They would otherwise be left unconverted in a database claiming the
+/// newer schema version.
+fn migrate_copy(
+    previous: &DataBasePath,
+    copy_path: &Path,
+    version: u32,
+    migration: &Migration,
+) -> Result<DataBasePath> {
+    let storage = previous.storage();
+    let skipped_paths = [
+        previous.root().join(".status.txt"),
+        previous.root().join(".writers"),
+    ];
+    copy_directory(storage, previous.root(), copy_path, &skipped_paths)?;
+    let copy = DataBasePath::with_storage(copy_path.to_path_buf(), previous.shared_storage())?;
+
+    // The data files only exist once the legacy format has been converted.
+    if version > 1 {
+        let reason = format!("unreadable before the migration to schema version {version}");
+        let quarantined = repair::quarantine_unreadable_data_files(&copy, &reason)?;
+        if !quarantined.is_empty() {
+            warn!(
+                "Moved {} unreadable data files into the quarantine folder before migrating!",
+                quarantined.len()
+            );
+        }
+    }
+
+    let mut settings = Settings::load(&copy)?;
+    (migration.up)(&copy, &settings)?;
+    settings.schema_version = version;
+    settings.save(&copy)?;
+
+    (migration.verify)(previous, &copy)?;
+    Ok(copy)
+}
+
+//
+
+//
+
+/// Schema version 1: Converts the text based day files and averages of the first versions into
+/// binary data files and stat sums.
+fn convert_legacy_format(db_path: &DataBasePath, _settings: &Settings) -> Result<()> {
+    if !db_path.storage().is_file_system() {
+        error!("Only databases stored in the file system can be converted from the legacy format!");
+        return Err(Error::with_kind(ErrorKind::NotImplemented));
+    }
+
+    if let Err(e) = update_database::temporary_update_database(db_path) {
+        error!(
+            "Encountered error: [{:?}] when converting the legacy database at {:?}",
+            e,
+            db_path.root()
+        );
+        return Err(Error::with_kind(ErrorKind::MigrationFailed(1)));
+    }
+    Ok(())
+}
+
+/// Checks that every legacy day file was converted into a readable data file, and that the tags
+/// file can be read.
+fn verify_legacy_format(previous: &DataBasePath, migrated: &DataBasePath) -> Result<()> {
+    let legacy_day_files = previous
+        .storage()
+        .walk_files(&previous.data())?
+        .into_iter()
+        .filter(|path| path.extension() == Some(OsStr::new("txt")))
+        .count();
+    let data_files = read_data_files(migrated)?.len();
+    TagList::from_file(migrated)?;
+
+    if legacy_day_files != data_files {
+        error!(
+            "Converted {} legacy day files into {} data files!",
+            legacy_day_files, data_files
+        );
+        return Err(Error::with_kind(ErrorKind::MigrationFailed(1)));
+    }
+    Ok(())
+}
+
+//
+
+//
+
+/// Schema version 2: Data files store offset-compensated hours instead of wall-clock hours.
+fn compensate_hours(db_path: &DataBasePath, settings: &Settings) -> Result<()> {
+    for mut data_file in read_data_files(db_path)? {
+        data_file
+            .compensate_hours(settings.day_switch_offset)
+            .save()?;
+    }
+    Ok(())
+}
+
+/// Checks that every data file holds the same entries as before, with compensated hours.
+fn verify_compensated_hours(previous: &DataBasePath, migrated: &DataBasePath) -> Result<()> {
+    let day_switch_offset = Settings::load(previous)?.day_switch_offset;
+    let previous_files = read_data_files(previous)?;
+    let migrated_files = read_data_files(migrated)?;
+
+    let matches = previous_files.len() == migrated_files.len()
+        && previous_files.into_iter().zip(&migrated_files).all(
+            |(mut previous_file, migrated_file)| {
+                relative_path(previous, previous_file.file_path())
+                    == relative_path(migrated, migrated_file.file_path())
+                    && previous_file.compensate_hours(day_switch_offset).entries()
+                        == migrated_file.entries()
+            },
+        );
+
+    if !matches {
+        error!("The data files with compensated hours do not match the original data files!");
+        return Err(Error::with_kind(ErrorKind::MigrationFailed(2)));
+    }
+    Ok(())
+}
+
+//
+
+//
+
+/// Schema version 3: Data file entries store their minute next to their hour. The entries of the
+/// previous versions get the minute 0.
+fn store_minutes(db_path: &DataBasePath, _settings: &Settings) -> Result<()> {
+    for mut data_file in read_data_files(db_path)? {
+        data_file.save()?;
+    }
+    Ok(())
+}
+
+/// Checks that every data file holds the same entries as before, and was rewritten with minutes.
+fn verify_stored_minutes(previous: &DataBasePath, migrated: &DataBasePath) -> Result<()> {
+    let previous_files = read_data_files(previous)?;
+    let migrated_files = read_data_files(migrated)?;
+
+    let mut matches = previous_files.len() == migrated_files.len()
+        && previous_files
+            .iter()
+            .zip(&migrated_files)
+            .all(|(previous_file, migrated_file)| {
+                relative_path(previous, previous_file.file_path())
+                    == relative_path(migrated, migrated_file.file_path())
+                    && previous_file.entries() == migrated_file.entries()
+            });
+    // Data files are saved in the newest format, which stores minutes as well.
+    for migrated_file in &migrated_files {
+        let bytes = migrated.storage().read(migrated_file.file_path())?;
+        matches &= DataFileFormat::detect(&bytes) != DataFileFormat::Hours;
+    }
+
+    if !matches {
+        error!("The data files with minutes do not match the original data files!");
+        return Err(Error::with_kind(ErrorKind::MigrationFailed(3)));
+    }
+    Ok(())
+}
+
+//
+
+//
+
+/// Schema version 4: Data file entries store a list of metric values instead of the mental and
+/// physical score, which become the built-in metrics listed in a new metrics file. The caches
+/// list the scores of every metric, so they are regenerated.
+fn store_metrics(db_path: &DataBasePath, _settings: &Settings) -> Result<()> {
+    metrics::write_built_in_metrics(db_path.storage(), db_path.root())?;
+    for mut data_file in read_data_files(db_path)? {
+        data_file.save()?;
+    }
+    regeneration::regenerate(
+        db_path,
+        RegenerationTargets::CACHES,
+        0,
+        &TaskProgress::default(),
+    )?;
+    Ok(())
+}
+
+/// Checks that every data file holds the same entries as before and was rewritten with metric
+/// values, and that the metrics file can be read.
+fn verify_stored_metrics(previous: &DataBasePath, migrated: &DataBasePath) -> Result<()> {
+    let previous_files = read_data_files(previous)?;
+    let migrated_files = read_data_files(migrated)?;
+    MetricList::from_file(migrated)?;
+
+    let mut matches = previous_files.len() == migrated_files.len()
+        && previous_files
+            .iter()
+            .zip(&migrated_files)
+            .all(|(previous_file, migrated_file)| {
+                relative_path(previous, previous_file.file_path())
+                    == relative_path(migrated, migrated_file.file_path())
+                    && previous_file.entries() == migrated_file.entries()
+            });
+    for migrated_file in &migrated_files {
+        let bytes = migrated.storage().read(migrated_file.file_path())?;
+        matches &= DataFileFormat::detect(&bytes) == DataFileFormat::Metrics;
+    }
+
+    if !matches {
+        error!("The data files with metric values do not match the original data files!");
+        return Err(Error::with_kind(ErrorKind::MigrationFailed(4)));
+    }
+    Ok(())
+}
+
+//
+
+//
+
+/// Returns every readable data file in the database, sorted by path. Corrupted and misplaced data
+/// files are logged and skipped. They are only found in the database being migrated from, since
+/// they are moved out of the copy before it is migrated. See `migrate_copy`.
+fn read_data_files(db_path: &DataBasePath) -> Result<Vec<DataFile>> {
+    let storage = db_path.storage();
+    let mut data_files = Vec::new();
+    for file_path in storage.walk_files(&db_path.data())? {
+        if !DataFile::is_data_file(storage, &file_path) {
+            continue;
+        }
+        match DataFile::read_from_file(db_path, &file_path) {
+            Ok(data_file) => data_files.push(data_file),
+            Err(crate::data_entry::Error::Io(io_err)) => return Err(io_err.into()),
+            Err(_) => error!(
+                "Data file [{:?}] is corrupted or misplaced! Skipping file...",
+                file_path
+            ),
+        }
+    }
+    Ok(data_files)
+}
+
+/// Returns `path` relative to the root of `db_path`.
+fn relative_path(db_path: &DataBasePath, path: &Path) -> PathBuf {
+    path.strip_prefix(db_path.root())
+        .unwrap_or(path)
+        .to_path_buf()
+}
+
+//
+
+//
+
+#[cfg(test)]
+mod tests {
+    use time::Month;
+
+    use super::CURRENT_SCHEMA_VERSION;
+    use crate::{
+        data_base::DataBase,
+        data_entry::DataFileFormat,
+        test_utils::{create_database, data_file_path, date, day_entries},
+    };
+
+    #[test]
+    fn migration_compensates_the_hours_of_legacy_data_files() {
+        let database = create_database("migration", 4);
+        let storage = database.path().shared_storage();
+        let db_root = database.database_path().to_path_buf();
+
+        // An entry at 02:00 on the 2nd belongs to the 1st with the compensated hour 26.
+        let file_path = data_file_path(&database, date(2024, Month::January, 1));
+        storage.create_dir_all(file_path.parent().unwrap()).unwrap();
+        storage.write(&file_path, &[2, 60, 70, 0xFF, 0xFF]).unwrap();
+        storage
+            .write(
+                &db_root.join("db_settings.txt"),
+                b"day_switch_offset=4\nschema_version=1\n",
+            )
+            .unwrap();
+        drop(database);
+
+        let database = DataBase::load_with_storage(db_root, storage.clone()).unwrap();
+        assert_eq!(database.schema_version(), CURRENT_SCHEMA_VERSION);
+        assert_eq!(
+            day_entries(&database, date(2024, Month::January, 1)),
+            vec![(26, 0, vec![])]
+        );
+        assert_eq!(
+            DataFileFormat::detect(&storage.read(&file_path).unwrap()),
+            DataFileFormat::Metrics
+        );
+    }
+
+    #[test]
+    fn unreadable_data_files_are_quarantined_before_migrating() {
+        let database = create_database("migration_quarantine", 0);
+        let storage = database.path().shared_storage();
+        let db_root = database.database_path().to_path_buf();
+
+        // Data files of schema version 3 store minutes, but no metric values.
+        let readable = data_file_path(&database, date(2024, Month::January, 1));
+        let truncated = data_file_path(&database, date(2024, Month::January, 2));
+        let misplaced = readable.with_file_name("3-4.statdiary");
+        storage.create_dir_all(readable.parent().unwrap()).unwrap();
+        let entry_bytes = [0xFF, 0xFF, 8, 15, 60, 70, 0xFF, 0xFF];
+        storage.write(&readable, &entry_bytes).unwrap();
+        storage.write(&truncated, &entry_bytes[..5]).unwrap();
+        storage.write(&misplaced, &entry_bytes).unwrap();
+        storage
+            .write(
+                &db_root.join("db_settings.txt"),
+                b"day_switch_offset=0\nschema_version=3\n",
+            )
+            .unwrap();
+        drop(database);
+
+        let database = DataBase::load_with_storage(db_root.clone(), storage.clone()).unwrap();
+        assert_eq!(database.schema_version(), CURRENT_SCHEMA_VERSION);
+        assert_eq!(
+            day_entries(&database, date(2024, Month::January, 1)),
+            vec![(8, 15, vec![])]
+        );
+        assert!(!storage.exists(&truncated));
+        assert!(!storage.exists(&misplaced));
+        for file in [&truncated, &misplaced] {
+            let relative_path = file.strip_prefix(&db_root).unwrap();
+            let quarantined = db_root.join("quarantine").join(relative_path);
+            assert!(storage.read(&quarantined).unwrap().len() <= entry_bytes.len());
+        }
+        let repair_log = storage
+            .read(&db_root.join("quarantine").join("repairs.txt"))
+            .unwrap();
+        let repair_log = String::from_utf8(repair_log).unwrap();
+        assert_eq!(
+            repair_log
+                .matches("unreadable before the migration to schema version 4")
+                .count(),
+            2
+        );
+        assert!(database.check().unwrap().is_ok());
+    }
+}
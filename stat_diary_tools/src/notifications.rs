@@ -0,0 +1,991 @@
+use std::{
+    collections::{BTreeSet, HashMap},
+    io,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Condvar, Mutex, MutexGuard,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant, SystemTime},
+};
+
+use log::warn;
+use time::Date;
+
+use crate::{
+    data_entry::{DataEntry, DataFile},
+    db_path::DataBasePath,
+    diary_file::DiaryFile,
+    history::{self, Operation},
+    metrics::{Metric, MetricList, METRICSFILENAME},
+    tags::TagList,
+};
+
+const DEFAULTPOLLINTERVAL: Duration = Duration::from_secs(1);
+const TAGSFILENAME: &str = "tags.txt";
+const MONTHCACHEFILENAME: &str = "month_cache.txt";
+const YEARCACHEFILENAME: &str = "year_cache.txt";
+
+//
+
+//
+
+/// A change made to the database, as reported to the callbacks registered using
+/// `DataBase::subscribe`.
+///
+/// Dates, hours and minutes are those of the data files, meaning the hours are
+/// offset-compensated.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ChangeEvent {
+    EntryAdded {
+        date: Date,
+        hour: i8,
+        minute: u8,
+    },
+    EntryOverwritten {
+        date: Date,
+        hour: i8,
+        minute: u8,
+    },
+    EntryRemoved {
+        date: Date,
+        hour: i8,
+        minute: u8,
+    },
+    TagAdded {
+        tag_id: u16,
+        name: String,
+    },
+    TagRenamed {
+        tag_id: u16,
+        old_name: String,
+        new_name: String,
+    },
+    /// `tag_1` was merged into `tag_2`. The entries changed by a local merge are not reported one
+    /// by one.
+    ///
+    /// The tags file can't tell a merge from a removal, so merges made by another process are
+    /// recognized by the undo record they leave in the history of the database, and are reported
+    /// together with every entry that changed. A merge that left no undo record is reported as
+    /// `TagRemoved`.
+    TagsMerged {
+        tag_1: u16,
+        tag_2: u16,
+    },
+    TagRemoved {
+        tag_id: u16,
+    },
+    DiaryEntryAdded {
+        date: Date,
+    },
+    /// A diary entry of the day at `date` was removed. Reported once for every entry removed.
+    DiaryEntryRemoved {
+        date: Date,
+    },
+    CachesRegenerated,
+    MetricAdded {
+        metric_id: u16,
+        name: String,
+    },
+    MetricRenamed {
+        metric_id: u16,
+        old_name: String,
+        new_name: String,
+    },
+}
+
+/// Where a `ChangeEvent` came from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChangeOrigin {
+    /// The change was made through this `DataBase`.
+    Local,
+    /// The change was found in the files of the database, meaning it was made by another process
+    /// or another `DataBase` instance.
+    External,
+}
+
+/// Identifies a callback registered using `DataBase::subscribe`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SubscriptionId(pub(crate) u64);
+
+type Callback = Arc<dyn Fn(&ChangeEvent, ChangeOrigin) + Send + Sync>;
+
+/// The modification time and length of a file. Scans compare these instead of the content, so
+/// that only the files that changed have to be read.
+type FileState = (SystemTime, u64);
+
+/// The state of a data file together with its entries.
+type DataFileState = (FileState, HashMap<(i8, u8), DataEntry>);
+
+//
+
+//
+
+/// Delivers the changes made to a database to the subscribed callbacks.
+///
+/// While at least one callback is subscribed a background thread runs, which calls the callbacks
+/// and scans the files of the database for changes made by other processes every poll interval.
+/// A scan only reads the files whose modification time or length changed since the last scan.
+///
+/// Changes made through the owning `DataBase` are collected by the method making them, using a
+/// `LocalChanges` guard. The guard keeps the background thread from scanning until the files
+/// touched by the write have been taken into the snapshot, so they are never reported twice.
+/// Only the touched files are read again, so a write costs the same no matter the size of the
+/// database. Tasks rewriting files throughout the database instead mark the snapshot for a
+/// resync, after which the next scan takes every change into the snapshot without reporting it.
+/// A change made by another process at the same time as a local write might be taken into the
+/// snapshot as well, and is then not reported.
+pub(crate) struct Notifier {
+    shared: Arc<Shared>,
+    watcher: Mutex<Option<JoinHandle<()>>>,
+}
+
+struct Shared {
+    db_path: DataBasePath,
+    /// The state of the database after the last scan, or `None` while nothing is subscribed.
+    snapshot: Mutex<Option<Snapshot>>,
+    subscribers: Mutex<Vec<(SubscriptionId, Callback)>>,
+    next_id: AtomicU64,
+    poll_interval: Mutex<Duration>,
+    queue: Mutex<Queue>,
+    queue_changed: Condvar,
+}
+
+/// The local changes waiting to be delivered by the background thread.
+#[derive(Default)]
+struct Queue {
+    events: Vec<ChangeEvent>,
+    /// Increased every time the background thread is stopped. A thread keeps running as long as
+    /// this matches the generation it was started with.
+    generation: u64,
+}
+
+impl Notifier {
+    pub fn new(db_path: DataBasePath) -> Notifier {
+        Notifier {
+            shared: Arc::new(Shared {
+                db_path,
+                snapshot: Mutex::new(None),
+                subscribers: Mutex::new(Vec::new()),
+                next_id: AtomicU64::new(1),
+                poll_interval: Mutex::new(DEFAULTPOLLINTERVAL),
+                queue: Mutex::new(Queue::default()),
+                queue_changed: Condvar::new(),
+            }),
+            watcher: Mutex::new(None),
+        }
+    }
+
+    //
+
+    //
+
+    /// Registers `callback`, starting the background thread if it isn't running yet.
+    pub fn subscribe(
+        &self,
+        callback: impl Fn(&ChangeEvent, ChangeOrigin) + Send + Sync + 'static,
+    ) -> io::Result<SubscriptionId> {
+        {
+            let mut snapshot = lock(&self.shared.snapshot);
+            if snapshot.is_none() {
+                let (scanned, _) = Snapshot::scan(&self.shared.db_path, &Snapshot::default())?;
+                *snapshot = Some(scanned);
+            }
+        }
+
+        let id = SubscriptionId(self.shared.next_id.fetch_add(1, Ordering::Relaxed));
+        lock(&self.shared.subscribers).push((id, Arc::new(callback)));
+
+        let mut watcher = lock(&self.watcher);
+        if watcher.is_none() {
+            let generation = lock(&self.shared.queue).generation;
+            let shared = Arc::clone(&self.shared);
+            *watcher = Some(thread::spawn(move || shared.watch(generation)));
+        }
+        Ok(id)
+    }
+
+    /// Removes the callback registered as `id`. The background thread is stopped once no
+    /// callback is left. Returns false if no callback is registered as `id`.
+    pub fn unsubscribe(&self, id: SubscriptionId) -> bool {
+        let is_empty = {
+            let mut subscribers = lock(&self.shared.subscribers);
+            let count = subscribers.len();
+            subscribers.retain(|(subscriber_id, _)| *subscriber_id != id);
+            if subscribers.len() == count {
+                return false;
+            }
+            subscribers.is_empty()
+        };
+
+        if is_empty {
+            self.stop_watcher();
+            *lock(&self.shared.snapshot) = None;
+        }
+        true
+    }
+
+    /// Sets the poll interval, which is used from the next scan on.
+    pub fn set_poll_interval(&self, interval: Duration) {
+        *lock(&self.shared.poll_interval) = interval;
+    }
+
+    pub fn poll_interval(&self) -> Duration {
+        *lock(&self.shared.poll_interval)
+    }
+
+    /// Returns a guard collecting the changes of a local write. See `LocalChanges`.
+    pub fn local_changes(&self) -> LocalChanges<'_> {
+        LocalChanges {
+            shared: &self.shared,
+            snapshot: lock(&self.shared.snapshot),
+            events: Vec::new(),
+            touched: Vec::new(),
+            resync: false,
+        }
+    }
+
+    //
+
+    //
+
+    /// Stops the background thread, dropping any changes it hasn't delivered yet.
+    fn stop_watcher(&self) {
+        let Some(watcher) = lock(&self.watcher).take() else {
+            return;
+        };
+        {
+            let mut queue = lock(&self.shared.queue);
+            queue.generation += 1;
+            queue.events.clear();
+        }
+        self.shared.queue_changed.notify_all();
+
+        // A callback run by the background thread might be the one unsubscribing, in which case
+        // the thread stops on its own once the callback returns.
+        if watcher.thread().id() != thread::current().id() && watcher.join().is_err() {
+            warn!("The change notification thread panicked!");
+        }
+    }
+}
+
+impl Drop for Notifier {
+    fn drop(&mut self) {
+        self.stop_watcher();
+    }
+}
+
+//
+
+//
+
+impl Shared {
+    /// Delivers the queued local changes as soon as they arrive, and scans the database every
+    /// poll interval, until the thread of `generation` is stopped.
+    fn watch(&self, generation: u64) {
+        let mut last_poll = Instant::now();
+        loop {
+            let timeout = lock(&self.poll_interval).saturating_sub(last_poll.elapsed());
+            let queue = lock(&self.queue);
+            let (mut queue, _) = self
+                .queue_changed
+                .wait_timeout_while(queue, timeout, |queue| {
+                    queue.generation == generation && queue.events.is_empty()
+                })
+                .unwrap_or_else(|poisoned| poisoned.into_inner());
+            if queue.generation != generation {
+                return;
+            }
+            let local_events = std::mem::take(&mut queue.events);
+            drop(queue);
+
+            self.dispatch(&local_events, ChangeOrigin::Local);
+            if last_poll.elapsed() >= *lock(&self.poll_interval) {
+                self.poll();
+                last_poll = Instant::now();
+            }
+        }
+    }
+
+    /// Scans the database, reporting every change found as a `External` change.
+    fn poll(&self) {
+        let events = {
+            let mut snapshot = lock(&self.snapshot);
+            let Some(previous) = snapshot.as_mut() else {
+                return;
+            };
+            match Snapshot::scan(&self.db_path, previous) {
+                Ok((scanned, events)) => {
+                    let resync = previous.resync;
+                    *previous = scanned;
+                    if resync {
+                        Vec::new()
+                    } else {
+                        events
+                    }
+                }
+                Err(error) => {
+                    warn!("Failed to scan the database for changes: {error}");
+                    return;
+                }
+            }
+        };
+        self.dispatch(&events, ChangeOrigin::External);
+    }
+
+    /// Calls every subscribed callback with each event. The callbacks are called without holding
+    /// any lock, so they are free to use the database.
+    fn dispatch(&self, events: &[ChangeEvent], origin: ChangeOrigin) {
+        if events.is_empty() {
+            return;
+        }
+        let callbacks: Vec<Callback> = lock(&self.subscribers)
+            .iter()
+            .map(|(_, callback)| Arc::clone(callback))
+            .collect();
+        for event in events {
+            for callback in &callbacks {
+                callback(event, origin);
+            }
+        }
+    }
+}
+
+//
+
+//
+
+/// Collects the changes made by a local write. Held from before the first file is written until
+/// the write is complete.
+///
+/// When dropped, the files touched by the write are taken into the snapshot without being
+/// reported, after which the collected changes are queued for the background thread. The changes
+/// are therefore delivered shortly after the write returns, once the database is no longer
+/// locked by it.
+///
+/// Every file the write creates, changes or removes must be passed to `touch`, unless
+/// `touch_all` is called. Files that are not touched are reported as external changes by the
+/// next scan.
+pub(crate) struct LocalChanges<'a> {
+    shared: &'a Shared,
+    snapshot: MutexGuard<'a, Option<Snapshot>>,
+    events: Vec<ChangeEvent>,
+    touched: Vec<PathBuf>,
+    resync: bool,
+}
+
+impl LocalChanges<'_> {
+    pub fn push(&mut self, event: ChangeEvent) {
+        self.events.push(event);
+    }
+
+    /// Takes the file at `path` into the snapshot once the write is complete.
+    pub fn touch(&mut self, path: &Path) {
+        self.touched.push(path.to_path_buf());
+    }
+
+    /// Takes the data file at `path` into the snapshot once the write is complete, together with
+    /// the month and year caches updated along with it.
+    pub fn touch_data_file(&mut self, path: &Path) {
+        self.touch(path);
+        if let Some(month_path) = path.parent() {
+            self.touch(&month_path.join(MONTHCACHEFILENAME));
+            if let Some(year_path) = month_path.parent() {
+                self.touch(&year_path.join(YEARCACHEFILENAME));
+            }
+        }
+    }
+
+    /// Marks the snapshot for a resync, for writes touching files throughout the database. The
+    /// next scan takes every change into the snapshot without reporting it.
+    pub fn touch_all(&mut self) {
+        self.resync = true;
+    }
+}
+
+impl Drop for LocalChanges<'_> {
+    fn drop(&mut self) {
+        // Nothing is subscribed, so there is no snapshot to update.
+        let Some(snapshot) = self.snapshot.as_mut() else {
+            return;
+        };
+        if self.resync {
+            snapshot.resync = true;
+        } else {
+            snapshot.update(&self.shared.db_path, &self.touched);
+        }
+
+        if !self.events.is_empty() {
+            lock(&self.shared.queue).events.append(&mut self.events);
+            self.shared.queue_changed.notify_all();
+        }
+    }
+}
+
+//
+
+//
+
+/// The state of every file in the database that changes are reported for.
+#[derive(Default)]
+struct Snapshot {
+    tags_file: Option<FileState>,
+    tags: HashMap<u16, String>,
+    metrics_file: Option<FileState>,
+    metrics: Vec<Metric>,
+    /// Every entry of every data file, so that a changed file can be compared entry by entry.
+    /// While something is subscribed the whole database is therefore held in memory, taking
+    /// roughly as much space as the data files take on disk plus the overhead of the maps.
+    data_files: HashMap<PathBuf, DataFileState>,
+    /// The number of diary entries in each diary file.
+    diary_files: HashMap<PathBuf, (FileState, usize)>,
+    caches: HashMap<PathBuf, FileState>,
+    /// Set when a local write changed files throughout the database without taking them into
+    /// the snapshot. The next scan does not report the changes it finds.
+    resync: bool,
+}
+
+impl Snapshot {
+    /// Scans the database, only reading the files that changed since `previous`. Returns the new
+    /// snapshot together with the changes found.
+    ///
+    /// A file that can't be read, for example because another process is in the middle of
+    /// writing it, keeps its previous state and is read again by the next scan.
+    fn scan(
+        db_path: &DataBasePath,
+        previous: &Snapshot,
+    ) -> io::Result<(Snapshot, Vec<ChangeEvent>)> {
+        let storage = db_path.storage();
+        let mut snapshot = Snapshot::default();
+        let mut events = Vec::new();
+
+        let tags_path = db_path.root().join(TAGSFILENAME);
+        snapshot.tags_file = file_state(db_path, &tags_path);
+        if snapshot.tags_file == previous.tags_file {
+            snapshot.tags = previous.tags.clone();
+        } else {
+            match TagList::from_file(db_path) {
+                Ok(tag_list) => {
+                    snapshot.tags = tag_map(&tag_list);
+                    tag_events(db_path, &previous.tags, &snapshot.tags, &mut events);
+                }
+                Err(_) => {
+                    snapshot.tags_file = previous.tags_file;
+                    snapshot.tags = previous.tags.clone();
+                }
+            }
+        }
+
+        let metrics_path = db_path.root().join(METRICSFILENAME);
+        snapshot.metrics_file = file_state(db_path, &metrics_path);
+        // Databases without a metrics file hold the built-in metrics, so the metrics are read even
+        // if the file is missing from both scans.
+        if snapshot.metrics_file == previous.metrics_file && !previous.metrics.is_empty() {
+            snapshot.metrics = previous.metrics.clone();
+        } else {
+            match MetricList::from_file(db_path) {
+                Ok(metric_list) => {
+                    snapshot.metrics = metric_list.iter().cloned().collect();
+                    metric_events(&previous.metrics, &snapshot.metrics, &mut events);
+                }
+                Err(_) => {
+                    snapshot.metrics_file = previous.metrics_file;
+                    snapshot.metrics = previous.metrics.clone();
+                }
+            }
+        }
+
+        let files = if storage.exists(&db_path.data()) {
+            storage.walk_files(&db_path.data())?
+        } else {
+            Vec::new()
+        };
+
+        let mut data_changed = false;
+        let mut caches_changed = false;
+        for file in files {
+            let Some(state) = file_state(db_path, &file) else {
+                continue;
+            };
+
+            if DataFile::is_data_file(storage, &file) {
+                let entries = match previous.data_files.get(&file) {
+                    Some((previous_state, entries)) if *previous_state == state => entries.clone(),
+                    previous_file => match DataFile::read_from_file(db_path, &file) {
+                        Ok(data_file) => {
+                            data_changed = true;
+                            data_file.entries().clone()
+                        }
+                        Err(_) => {
+                            if let Some(previous_file) = previous_file {
+                                snapshot.data_files.insert(file, previous_file.clone());
+                            }
+                            continue;
+                        }
+                    },
+                };
+                snapshot.data_files.insert(file, (state, entries));
+            } else if DiaryFile::is_diary_file(&file) {
+                let entry_count = match previous.diary_files.get(&file) {
+                    Some((previous_state, entry_count)) if *previous_state == state => *entry_count,
+                    previous_file => match DiaryFile::from_file(db_path, &file) {
+                        Ok(diary_file) => diary_file.read().len(),
+                        Err(_) => {
+                            if let Some(previous_file) = previous_file {
+                                snapshot.diary_files.insert(file, *previous_file);
+                            }
+                            continue;
+                        }
+                    },
+                };
+                snapshot.diary_files.insert(file, (state, entry_count));
+            } else if is_cache_file(&file) {
+                caches_changed |= previous.caches.get(&file) != Some(&state);
+                snapshot.caches.insert(file, state);
+            }
+        }
+
+        data_changed |= previous
+            .data_files
+            .keys()
+            .any(|file| !snapshot.data_files.contains_key(file));
+        caches_changed |= previous
+            .caches
+            .keys()
+            .any(|file| !snapshot.caches.contains_key(file));
+
+        entry_events(&previous.data_files, &snapshot.data_files, &mut events);
+        diary_events(&previous.diary_files, &snapshot.diary_files, &mut events);
+        // Writing a entry updates the caches of its day as well, so changed caches are only
+        // reported as a regeneration if no data file changed.
+        if caches_changed && !data_changed {
+            events.push(ChangeEvent::CachesRegenerated);
+        }
+
+        Ok((snapshot, events))
+    }
+
+    /// Takes the current state of `files` into the snapshot without reporting any change. Files
+    /// that no longer exist are removed from the snapshot.
+    ///
+    /// A file that can't be read keeps its previous state, meaning the next scan reports it.
+    fn update(&mut self, db_path: &DataBasePath, files: &[PathBuf]) {
+        let storage = db_path.storage();
+        for file in files {
+            let state = file_state(db_path, file);
+            if *file == db_path.root().join(TAGSFILENAME) {
+                if let Ok(tag_list) = TagList::from_file(db_path) {
+                    self.tags_file = state;
+                    self.tags = tag_map(&tag_list);
+                }
+                continue;
+            }
+            if *file == db_path.root().join(METRICSFILENAME) {
+                if let Ok(metric_list) = MetricList::from_file(db_path) {
+                    self.metrics_file = state;
+                    self.metrics = metric_list.iter().cloned().collect();
+                }
+                continue;
+            }
+
+            let Some(state) = state else {
+                self.data_files.remove(file);
+                self.diary_files.remove(file);
+                self.caches.remove(file);
+                continue;
+            };
+            if DataFile::is_data_file(storage, file) {
+                if let Ok(data_file) = DataFile::read_from_file(db_path, file) {
+                    self.data_files
+                        .insert(file.clone(), (state, data_file.entries().clone()));
+                }
+            } else if DiaryFile::is_diary_file(file) {
+                if let Ok(diary_file) = DiaryFile::from_file(db_path, file) {
+                    self.diary_files
+                        .insert(file.clone(), (state, diary_file.read().len()));
+                }
+            } else if is_cache_file(file) {
+                self.caches.insert(file.clone(), state);
+            }
+        }
+    }
+}
+
+//
+
+//
+
+/// Adds a event for every tag added, renamed, merged or removed between `previous` and
+/// `current`. A removed tag is reported as merged if the history of the database at `db_path`
+/// shows it was merged. See `ChangeEvent::TagsMerged`.
+fn tag_events(
+    db_path: &DataBasePath,
+    previous: &HashMap<u16, String>,
+    current: &HashMap<u16, String>,
+    events: &mut Vec<ChangeEvent>,
+) {
+    let tag_ids: BTreeSet<u16> = previous.keys().chain(current.keys()).copied().collect();
+    for tag_id in tag_ids {
+        match (previous.get(&tag_id), current.get(&tag_id)) {
+            (None, Some(name)) => events.push(ChangeEvent::TagAdded {
+                tag_id,
+                name: name.clone(),
+            }),
+            (Some(old_name), Some(new_name)) if old_name != new_name => {
+                events.push(ChangeEvent::TagRenamed {
+                    tag_id,
+                    old_name: old_name.clone(),
+                    new_name: new_name.clone(),
+                })
+            }
+            (Some(_), None) => match merged_into(db_path, tag_id) {
+                Some(tag_2) => events.push(ChangeEvent::TagsMerged {
+                    tag_1: tag_id,
+                    tag_2,
+                }),
+                None => events.push(ChangeEvent::TagRemoved { tag_id }),
+            },
+            _ => {}
+        }
+    }
+}
+
+/// Returns the tag `tag_id` was merged into, if the last operation in the history removing the
+/// tag was a merge.
+fn merged_into(db_path: &DataBasePath, tag_id: u16) -> Option<u16> {
+    match history::last_removing(db_path, tag_id) {
+        Ok(Some(undo_record)) => match undo_record.operation() {
+            Operation::MergeTags(_, tag_2) => Some(*tag_2),
+            Operation::RemoveTag(_) => None,
+        },
+        Ok(None) => None,
+        Err(error) => {
+            warn!(
+                "Failed to read the history while looking for a merge of tag [{tag_id}]: {error:?}"
+            );
+            None
+        }
+    }
+}
+
+/// Adds a event for every metric added or renamed between `previous` and `current`. Metrics can't
+/// be removed, so a metric missing from `current` is not reported.
+fn metric_events(previous: &[Metric], current: &[Metric], events: &mut Vec<ChangeEvent>) {
+    for metric in current {
+        match previous.iter().find(|other| other.id == metric.id) {
+            None => events.push(ChangeEvent::MetricAdded {
+                metric_id: metric.id,
+                name: metric.name.clone(),
+            }),
+            Some(other) if other.name != metric.name => events.push(ChangeEvent::MetricRenamed {
+                metric_id: metric.id,
+                old_name: other.name.clone(),
+                new_name: metric.name.clone(),
+            }),
+            _ => {}
+        }
+    }
+}
+
+/// Adds a event for every entry added, overwritten or removed between `previous` and `current`.
+fn entry_events(
+    previous: &HashMap<PathBuf, DataFileState>,
+    current: &HashMap<PathBuf, DataFileState>,
+    events: &mut Vec<ChangeEvent>,
+) {
+    let no_entries = HashMap::new();
+    let files: BTreeSet<&PathBuf> = previous.keys().chain(current.keys()).collect();
+    for file in files {
+        let Some(date) = DataFile::date_from_path(file) else {
+            continue;
+        };
+        let previous_entries = previous
+            .get(file)
+            .map_or(&no_entries, |(_, entries)| entries);
+        let current_entries = current
+            .get(file)
+            .map_or(&no_entries, |(_, entries)| entries);
+
+        let times: BTreeSet<(i8, u8)> = previous_entries
+            .keys()
+            .chain(current_entries.keys())
+            .copied()
+            .collect();
+        for (hour, minute) in times {
+            match (
+                previous_entries.get(&(hour, minute)),
+                current_entries.get(&(hour, minute)),
+            ) {
+                (None, Some(_)) => events.push(ChangeEvent::EntryAdded { date, hour, minute }),
+                (Some(old_entry), Some(new_entry)) if old_entry != new_entry => {
+                    events.push(ChangeEvent::EntryOverwritten { date, hour, minute })
+                }
+                (Some(_), None) => events.push(ChangeEvent::EntryRemoved { date, hour, minute }),
+                _ => {}
+            }
+        }
+    }
+}
+
+/// Adds a event for every diary entry added or removed between `previous` and `current`.
+fn diary_events(
+    previous: &HashMap<PathBuf, (FileState, usize)>,
+    current: &HashMap<PathBuf, (FileState, usize)>,
+    events: &mut Vec<ChangeEvent>,
+) {
+    let files: BTreeSet<&PathBuf> = previous.keys().chain(current.keys()).collect();
+    for file in files {
+        let Some(date) = DataFile::date_from_path(file) else {
+            continue;
+        };
+        let previous_count = previous
+            .get(file)
+            .map_or(0, |(_, entry_count)| *entry_count);
+        let current_count = current.get(file).map_or(0, |(_, entry_count)| *entry_count);
+        for _ in previous_count..current_count {
+            events.push(ChangeEvent::DiaryEntryAdded { date });
+        }
+        for _ in current_count..previous_count {
+            events.push(ChangeEvent::DiaryEntryRemoved { date });
+        }
+    }
+}
+
+//
+
+//
+
+fn is_cache_file(path: &Path) -> bool {
+    path.file_name()
+        .is_some_and(|file_name| file_name == MONTHCACHEFILENAME || file_name == YEARCACHEFILENAME)
+}
+
+fn tag_map(tag_list: &TagList) -> HashMap<u16, String> {
+    tag_list
+        .iter()
+        .map(|(tag_id, name)| (tag_id, name.to_string()))
+        .collect()
+}
+
+fn file_state(db_path: &DataBasePath, path: &Path) -> Option<FileState> {
+    let metadata = db_path.storage().metadata(path).ok()?;
+    Some((metadata.modified, metadata.len))
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::{mpsc, Mutex},
+        time::{Duration, Instant},
+    };
+
+    use time::Month;
+
+    use super::{ChangeEvent, ChangeOrigin};
+    use crate::{
+        data_base::DataBase,
+        test_utils::{create_database, date, entry},
+    };
+
+    const POLLINTERVAL: Duration = Duration::from_millis(10);
+    /// Long enough for many scans to run after the change.
+    const SETTLETIME: Duration = Duration::from_millis(300);
+
+    /// Subscribes to `database`, scanning every `POLLINTERVAL`, and returns the receiving end of
+    /// the events delivered.
+    fn subscribe(database: &mut DataBase) -> mpsc::Receiver<(ChangeEvent, ChangeOrigin)> {
+        database.set_poll_interval(POLLINTERVAL);
+        let (sender, receiver) = mpsc::channel();
+        let sender = Mutex::new(sender);
+        database
+            .subscribe(move |event, origin| {
+                sender
+                    .lock()
+                    .unwrap()
+                    .send((event.clone(), origin))
+                    .unwrap();
+            })
+            .unwrap();
+        receiver
+    }
+
+    /// Returns every event delivered within `SETTLETIME`.
+    fn events(
+        receiver: &mpsc::Receiver<(ChangeEvent, ChangeOrigin)>,
+    ) -> Vec<(ChangeEvent, ChangeOrigin)> {
+        let deadline = Instant::now() + SETTLETIME;
+        let mut events = Vec::new();
+        while let Some(timeout) = deadline.checked_duration_since(Instant::now()) {
+            if let Ok(event) = receiver.recv_timeout(timeout) {
+                events.push(event);
+            }
+        }
+        events
+    }
+
+    /// Loads a second instance of `database`, sharing its storage, to make external changes with.
+    fn other_instance(database: &DataBase) -> DataBase {
+        DataBase::load_with_storage(
+            database.database_path().to_path_buf(),
+            database.path().shared_storage(),
+        )
+        .unwrap()
+    }
+
+    //
+
+    //
+
+    #[test]
+    fn local_write_is_delivered_once_as_local() {
+        let mut database = create_database("notify_local", 0);
+        database.add_tag("walk".to_string()).unwrap();
+        let receiver = subscribe(&mut database);
+
+        database
+            .add_data_entry(2024, 1, 1, entry(8, 30, vec![1]))
+            .unwrap();
+
+        assert_eq!(
+            events(&receiver),
+            vec![(
+                ChangeEvent::EntryAdded {
+                    date: date(2024, Month::January, 1),
+                    hour: 8,
+                    minute: 30,
+                },
+                ChangeOrigin::Local
+            )]
+        );
+    }
+
+    #[test]
+    fn external_change_is_reported_as_external() {
+        let mut database = create_database("notify_external", 0);
+        let other = other_instance(&database);
+        database
+            .add_data_entry(2024, 1, 1, entry(8, 30, vec![]))
+            .unwrap();
+        let receiver = subscribe(&mut database);
+
+        other
+            .add_data_entry(2024, 1, 1, entry(9, 0, vec![]))
+            .unwrap();
+        other.remove_data_entry(2024, 1, 1, 8, 30).unwrap();
+        other.add_tag("walk".to_string()).unwrap();
+
+        let day = date(2024, Month::January, 1);
+        let mut events = events(&receiver);
+        events.sort_by_key(|(event, _)| format!("{event:?}"));
+        assert_eq!(
+            events,
+            vec![
+                (
+                    ChangeEvent::EntryAdded {
+                        date: day,
+                        hour: 9,
+                        minute: 0,
+                    },
+                    ChangeOrigin::External
+                ),
+                (
+                    ChangeEvent::EntryRemoved {
+                        date: day,
+                        hour: 8,
+                        minute: 30,
+                    },
+                    ChangeOrigin::External
+                ),
+                (
+                    ChangeEvent::TagAdded {
+                        tag_id: 1,
+                        name: "walk".to_string(),
+                    },
+                    ChangeOrigin::External
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn external_merge_is_inferred_from_the_tags_file_and_history() {
+        let mut database = create_database("notify_merge", 0);
+        let other = other_instance(&database);
+        database.add_tag("walk".to_string()).unwrap();
+        database.add_tag("run".to_string()).unwrap();
+        database.add_tag("swim".to_string()).unwrap();
+        database
+            .add_data_entry(2024, 1, 1, entry(8, 30, vec![1]))
+            .unwrap();
+        let receiver = subscribe(&mut database);
+
+        other.merge_tags(1, 2).unwrap();
+        other.remove_tag(3).unwrap();
+
+        let events = events(&receiver);
+        assert!(events
+            .iter()
+            .all(|(_, origin)| *origin == ChangeOrigin::External));
+        let tag_events: Vec<&ChangeEvent> = events
+            .iter()
+            .map(|(event, _)| event)
+            .filter(|event| {
+                matches!(
+                    event,
+                    ChangeEvent::TagsMerged { .. } | ChangeEvent::TagRemoved { .. }
+                )
+            })
+            .collect();
+        assert_eq!(
+            tag_events,
+            vec![
+                &ChangeEvent::TagsMerged { tag_1: 1, tag_2: 2 },
+                &ChangeEvent::TagRemoved { tag_id: 3 },
+            ]
+        );
+        assert!(events.contains(&(
+            ChangeEvent::EntryOverwritten {
+                date: date(2024, Month::January, 1),
+                hour: 8,
+                minute: 30,
+            },
+            ChangeOrigin::External
+        )));
+    }
+
+    #[test]
+    fn resynced_tasks_are_not_reported_again_by_the_scan() {
+        let mut database = create_database("notify_resync", 0);
+        database.add_tag("walk".to_string()).unwrap();
+        database.add_tag("run".to_string()).unwrap();
+        database
+            .add_data_entry(2024, 1, 1, entry(8, 30, vec![1]))
+            .unwrap();
+        let receiver = subscribe(&mut database);
+
+        database.regen_caches().unwrap();
+        assert_eq!(
+            events(&receiver),
+            vec![(ChangeEvent::CachesRegenerated, ChangeOrigin::Local)]
+        );
+
+        database.merge_tags(1, 2).unwrap();
+        assert_eq!(
+            events(&receiver),
+            vec![
+                (
+                    ChangeEvent::TagsMerged { tag_1: 1, tag_2: 2 },
+                    ChangeOrigin::Local
+                ),
+                (ChangeEvent::CachesRegenerated, ChangeOrigin::Local),
+            ]
+        );
+    }
+}
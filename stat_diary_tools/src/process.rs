@@ -0,0 +1,262 @@
+use time::OffsetDateTime;
+
+/// What is known about the process with a given id on the current host.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum ProcessStatus {
+    /// No process with the id exists.
+    Exited,
+    /// A process with the id exists. Holds the time it was started, if the platform exposes it.
+    Running(Option<OffsetDateTime>),
+    /// The process can't be looked up on this platform, or the lookup was denied.
+    Unknown,
+}
+
+/// Returns the status of the process with the id `pid` on the current host.
+pub(crate) fn process_status(pid: u32) -> ProcessStatus {
+    platform::process_status(pid)
+}
+
+/// Returns the name of the current host, or an empty string if it can't be determined.
+pub(crate) fn hostname() -> String {
+    platform::hostname().unwrap_or_default()
+}
+
+//
+
+//
+
+#[cfg(unix)]
+mod platform {
+    use super::ProcessStatus;
+
+    /// The size of the buffer the host name is read into. Host names are at most 255 bytes long
+    /// on every unix platform.
+    const HOSTNAMEBUFFERSIZE: usize = 256;
+
+    pub(super) fn process_status(pid: u32) -> ProcessStatus {
+        // A pid of 0 or -1 would address a group of processes instead.
+        let Ok(pid) = libc::pid_t::try_from(pid) else {
+            return ProcessStatus::Unknown;
+        };
+        if pid <= 0 {
+            return ProcessStatus::Unknown;
+        }
+
+        // Signal 0 only checks whether the process exists and may be signalled.
+        // SAFETY: kill has no memory safety requirements and signal 0 is never delivered.
+        if unsafe { libc::kill(pid, 0) } == 0 {
+            return ProcessStatus::Running(start_time(pid));
+        }
+        match std::io::Error::last_os_error().raw_os_error() {
+            Some(libc::ESRCH) => ProcessStatus::Exited,
+            // The process exists but belongs to another user.
+            Some(libc::EPERM) => ProcessStatus::Running(start_time(pid)),
+            _ => ProcessStatus::Unknown,
+        }
+    }
+
+    pub(super) fn hostname() -> Option<String> {
+        let mut buffer = [0u8; HOSTNAMEBUFFERSIZE];
+        // SAFETY: The buffer is valid for writes of its full length.
+        let result = unsafe { libc::gethostname(buffer.as_mut_ptr().cast(), buffer.len()) };
+        if result != 0 {
+            return None;
+        }
+        // The name is not null terminated if it was truncated.
+        let length = buffer.iter().position(|byte| *byte == 0)?;
+        String::from_utf8(buffer[..length].to_vec()).ok()
+    }
+
+    /// Returns the start time of the process with the id `pid`, read from `/proc`.
+    #[cfg(target_os = "linux")]
+    fn start_time(pid: libc::pid_t) -> Option<time::OffsetDateTime> {
+        let stat = std::fs::read_to_string(format!("/proc/{}/stat", pid)).ok()?;
+        // The command name in the second field may contain spaces and parentheses, so the fields
+        // are counted from the last closing parenthesis. The start time is the 22nd field, counted
+        // in clock ticks since the system booted.
+        let (_, fields) = stat.rsplit_once(')')?;
+        let start_ticks = fields.split_whitespace().nth(19)?.parse::<i64>().ok()?;
+
+        let boot_time = std::fs::read_to_string("/proc/stat")
+            .ok()?
+            .lines()
+            .find_map(|line| line.strip_prefix("btime "))?
+            .trim()
+            .parse::<i64>()
+            .ok()?;
+        // SAFETY: sysconf has no memory safety requirements.
+        let ticks_per_second = unsafe { libc::sysconf(libc::_SC_CLK_TCK) };
+        if ticks_per_second <= 0 {
+            return None;
+        }
+
+        let boot_time = time::OffsetDateTime::from_unix_timestamp(boot_time).ok()?;
+        Some(boot_time + time::Duration::milliseconds(start_ticks * 1000 / ticks_per_second))
+    }
+
+    /// Returns the start time of the process with the id `pid`, read using `proc_pidinfo`.
+    #[cfg(target_os = "macos")]
+    fn start_time(pid: libc::pid_t) -> Option<time::OffsetDateTime> {
+        // SAFETY: proc_bsdinfo is plain data, for which all zero bytes is a valid value.
+        let mut info: libc::proc_bsdinfo = unsafe { std::mem::zeroed() };
+        let size = std::mem::size_of::<libc::proc_bsdinfo>() as libc::c_int;
+        // SAFETY: The buffer is a proc_bsdinfo of the provided size, as PROC_PIDTBSDINFO expects.
+        let written = unsafe {
+            libc::proc_pidinfo(
+                pid,
+                libc::PROC_PIDTBSDINFO,
+                0,
+                (&mut info as *mut libc::proc_bsdinfo).cast(),
+                size,
+            )
+        };
+        if written != size {
+            return None;
+        }
+        time::OffsetDateTime::from_unix_timestamp(info.pbi_start_tvsec as i64).ok()
+    }
+
+    /// The start time of processes is not looked up on the remaining unix platforms.
+    #[cfg(not(any(target_os = "linux", target_os = "macos")))]
+    fn start_time(_pid: libc::pid_t) -> Option<time::OffsetDateTime> {
+        None
+    }
+}
+
+//
+
+//
+
+#[cfg(windows)]
+mod platform {
+    use time::OffsetDateTime;
+    use windows_sys::Win32::{
+        Foundation::{CloseHandle, GetLastError, ERROR_INVALID_PARAMETER, FILETIME, STILL_ACTIVE},
+        System::{
+            SystemInformation::{ComputerNameDnsHostname, GetComputerNameExW},
+            Threading::{
+                GetExitCodeProcess, GetProcessTimes, OpenProcess, PROCESS_QUERY_LIMITED_INFORMATION,
+            },
+        },
+    };
+
+    use super::ProcessStatus;
+
+    /// The number of 100 nanosecond intervals between 1601-01-01, where FILETIME starts counting,
+    /// and the unix epoch.
+    const FILETIMEUNIXOFFSET: i128 = 116_444_736_000_000_000;
+
+    pub(super) fn process_status(pid: u32) -> ProcessStatus {
+        // SAFETY: OpenProcess has no memory safety requirements. The handle is closed below.
+        let handle = unsafe { OpenProcess(PROCESS_QUERY_LIMITED_INFORMATION, 0, pid) };
+        if handle.is_null() {
+            // An invalid parameter means no process with the id exists. Any other error, like
+            // access being denied, says nothing about whether it exists.
+            // SAFETY: GetLastError has no memory safety requirements.
+            return match unsafe { GetLastError() } {
+                ERROR_INVALID_PARAMETER => ProcessStatus::Exited,
+                _ => ProcessStatus::Unknown,
+            };
+        }
+
+        let mut exit_code = 0u32;
+        // SAFETY: The handle is valid and exit_code is valid for writes.
+        let status = if unsafe { GetExitCodeProcess(handle, &mut exit_code) } == 0 {
+            ProcessStatus::Unknown
+        } else if exit_code != STILL_ACTIVE as u32 {
+            ProcessStatus::Exited
+        } else {
+            ProcessStatus::Running(start_time(handle))
+        };
+
+        // SAFETY: The handle is valid and not used after this.
+        unsafe { CloseHandle(handle) };
+        status
+    }
+
+    pub(super) fn hostname() -> Option<String> {
+        // The first call fails, but reports the buffer size needed including the null terminator.
+        let mut size = 0u32;
+        // SAFETY: A null buffer with a size of 0 only queries the needed size.
+        unsafe { GetComputerNameExW(ComputerNameDnsHostname, std::ptr::null_mut(), &mut size) };
+        if size == 0 {
+            return None;
+        }
+
+        let mut buffer = vec![0u16; size as usize];
+        // SAFETY: The buffer is valid for writes of `size` characters.
+        let result =
+            unsafe { GetComputerNameExW(ComputerNameDnsHostname, buffer.as_mut_ptr(), &mut size) };
+        if result == 0 {
+            return None;
+        }
+        // On success `size` holds the length of the name without the null terminator.
+        String::from_utf16(&buffer[..size as usize]).ok()
+    }
+
+    /// Returns the start time of the process behind the provided handle.
+    fn start_time(handle: windows_sys::Win32::Foundation::HANDLE) -> Option<OffsetDateTime> {
+        let mut creation_time = FILETIME::default();
+        let mut exit_time = FILETIME::default();
+        let mut kernel_time = FILETIME::default();
+        let mut user_time = FILETIME::default();
+        // SAFETY: The handle is valid and every FILETIME is valid for writes.
+        let result = unsafe {
+            GetProcessTimes(
+                handle,
+                &mut creation_time,
+                &mut exit_time,
+                &mut kernel_time,
+                &mut user_time,
+            )
+        };
+        if result == 0 {
+            return None;
+        }
+
+        let intervals =
+            ((creation_time.dwHighDateTime as i128) << 32) | creation_time.dwLowDateTime as i128;
+        OffsetDateTime::from_unix_timestamp_nanos((intervals - FILETIMEUNIXOFFSET) * 100).ok()
+    }
+}
+
+//
+
+//
+
+#[cfg(not(any(unix, windows)))]
+mod platform {
+    use super::ProcessStatus;
+
+    pub(super) fn process_status(_pid: u32) -> ProcessStatus {
+        ProcessStatus::Unknown
+    }
+
+    pub(super) fn hostname() -> Option<String> {
+        None
+    }
+}
+
+//
+
+//
+
+#[cfg(test)]
+mod tests {
+    use super::{process_status, ProcessStatus};
+
+    #[test]
+    fn current_process_is_running() {
+        assert!(matches!(
+            process_status(std::process::id()),
+            ProcessStatus::Running(_)
+        ));
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn hostname_matches_the_kernel_host_name() {
+        let kernel_hostname = std::fs::read_to_string("/proc/sys/kernel/hostname").unwrap();
+        assert_eq!(super::hostname(), kernel_hostname.trim());
+    }
+}
@@ -0,0 +1,385 @@
+use std::{
+    io,
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+    thread,
+};
+
+use log::{error, warn};
+
+use crate::{
+    cache_handling::{self, Overview},
+    data_entry::DataFile,
+    db_path::DataBasePath,
+    stat_sums::TagSums,
+    task_progress::{Cancelled, TaskProgress},
+};
+
+//
+
+//
+
+#[derive(Debug)]
+pub enum RegenerationError {
+    Io(io::Error),
+    Cancelled,
+}
+
+impl From<io::Error> for RegenerationError {
+    fn from(value: io::Error) -> Self {
+        Self::Io(value)
+    }
+}
+
+impl From<Cancelled> for RegenerationError {
+    fn from(_: Cancelled) -> Self {
+        Self::Cancelled
+    }
+}
+
+//
+
+//
+
+/// Selects what `regenerate` writes back to the database.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RegenerationTargets {
+    pub caches: bool,
+    pub tag_sums: bool,
+}
+
+impl RegenerationTargets {
+    pub const CACHES: RegenerationTargets = RegenerationTargets {
+        caches: true,
+        tag_sums: false,
+    };
+    pub const TAG_SUMS: RegenerationTargets = RegenerationTargets {
+        caches: false,
+        tag_sums: true,
+    };
+    pub const ALL: RegenerationTargets = RegenerationTargets {
+        caches: true,
+        tag_sums: true,
+    };
+}
+
+/// Everything read from the data files of a single month folder.
+#[derive(Debug, Default)]
+pub(crate) struct MonthData {
+    /// The file name and overview of each readable data file, in file name order.
+    pub day_overviews: Vec<(String, Overview)>,
+    /// The overview of all days in the month.
+    pub overview: Overview,
+    pub tag_sums: TagSums,
+}
+
+/// The caches and tag sums of a database as written by a regeneration.
+pub(crate) struct RegeneratedData {
+    /// Each month folder together with the file name and overview of each day in it.
+    pub months: Vec<(PathBuf, Vec<(String, Overview)>)>,
+    /// Each year folder together with the index and overview of each month in it.
+    pub years: Vec<(PathBuf, Vec<(u8, Overview)>)>,
+    pub tag_sums: TagSums,
+}
+
+/// A month folder found in the data folder.
+struct MonthFolder {
+    /// Index of the year folder in the list of year folders.
+    year: usize,
+    month_index: u8,
+    path: PathBuf,
+}
+
+//
+
+//
+
+/// Regenerates the caches and/or tag sums of the database, reading every data file only once.
+///
+/// The month folders are read by up to `threads` threads at the same time, where 0 uses one
+/// thread per available cpu. The results are merged in the same order no matter how many threads
+/// are used, so the written files are identical to those of a single threaded run.
+///
+/// Each data file read is reported to the provided `progress`. Nothing is written until every
+/// data file has been read, so if the task is cancelled the existing caches and tag sums are left
+/// unchanged.
+pub fn regenerate(
+    db_path: &DataBasePath,
+    targets: RegenerationTargets,
+    threads: usize,
+    progress: &TaskProgress,
+) -> Result<(), RegenerationError> {
+    let storage = db_path.storage();
+    let regenerated = read_database(db_path, threads, progress)?;
+
+    if targets.caches {
+        for (month_path, day_overviews) in &regenerated.months {
+            cache_handling::write_month_cache(storage, month_path, day_overviews)?;
+        }
+        for (year_path, month_overviews) in &regenerated.years {
+            cache_handling::write_year_cache(storage, year_path, month_overviews)?;
+        }
+    }
+    if targets.tag_sums {
+        regenerated.tag_sums.save(db_path)?;
+    }
+
+    Ok(())
+}
+
+//
+
+//
+
+/// Reads every data file in the database, returning the caches and tag sums a regeneration
+/// writes without writing anything. See `regenerate`.
+pub(crate) fn read_database(
+    db_path: &DataBasePath,
+    threads: usize,
+    progress: &TaskProgress,
+) -> Result<RegeneratedData, RegenerationError> {
+    let storage = db_path.storage();
+
+    let data_path = db_path.data();
+    let year_paths = if storage.exists(&data_path) {
+        storage.list(&data_path)?
+    } else {
+        Vec::new()
+    };
+
+    // TODO ############################################################ TODO
+    // Better checks to ensure a year folder is actually a valid year folder?
+    let mut years = Vec::new();
+    let mut months = Vec::new();
+    for year_path in year_paths {
+        if storage.is_file(&year_path) {
+            warn!(
+                "Encountered unexpected file in data folder! {:?}",
+                year_path
+            );
+            continue;
+        }
+
+        for month_path in storage.list(&year_path)? {
+            let Ok(month_index) = cache_handling::is_month_folder(storage, &month_path) else {
+                continue;
+            };
+            months.push(MonthFolder {
+                year: years.len(),
+                month_index,
+                path: month_path,
+            });
+        }
+        years.push((year_path, Vec::new()));
+    }
+
+    let month_data = read_months(db_path, &months, threads, progress)?;
+
+    let mut regenerated = RegeneratedData {
+        months: Vec::new(),
+        years,
+        tag_sums: TagSums::default(),
+    };
+    for (month, data) in months.into_iter().zip(month_data) {
+        regenerated.years[month.year]
+            .1
+            .push((month.month_index, data.overview));
+        regenerated.months.push((month.path, data.day_overviews));
+        regenerated.tag_sums.merge(data.tag_sums);
+    }
+
+    Ok(regenerated)
+}
+
+//
+
+//
+
+/// Reads every month folder in `months` using up to `threads` threads, returning the data of
+/// each month in the same order as `months`.
+///
+/// As soon as any month fails to be read the remaining months are skipped and the error is
+/// returned.
+fn read_months(
+    db_path: &DataBasePath,
+    months: &[MonthFolder],
+    threads: usize,
+    progress: &TaskProgress,
+) -> Result<Vec<MonthData>, RegenerationError> {
+    let threads = thread_count(threads).min(months.len());
+    if threads <= 1 {
+        return months
+            .iter()
+            .map(|month| read_month(db_path, &month.path, progress))
+            .collect();
+    }
+
+    let next_month = AtomicUsize::new(0);
+    let failed = AtomicBool::new(false);
+    let mut results: Vec<(usize, Result<MonthData, RegenerationError>)> = thread::scope(|scope| {
+        let workers: Vec<_> = (0..threads)
+            .map(|_| {
+                scope.spawn(|| {
+                    let mut results = Vec::new();
+                    while !failed.load(Ordering::Relaxed) {
+                        let index = next_month.fetch_add(1, Ordering::Relaxed);
+                        let Some(month) = months.get(index) else {
+                            break;
+                        };
+                        let result = read_month(db_path, &month.path, progress);
+                        if result.is_err() {
+                            failed.store(true, Ordering::Relaxed);
+                        }
+                        results.push((index, result));
+                    }
+                    results
+                })
+            })
+            .collect();
+
+        workers
+            .into_iter()
+            .flat_map(|worker| {
+                worker
+                    .join()
+                    .unwrap_or_else(|panic| std::panic::resume_unwind(panic))
+            })
+            .collect()
+    });
+
+    results.sort_by_key(|(index, _)| *index);
+    results.into_iter().map(|(_, result)| result).collect()
+}
+
+//
+
+//
+
+/// Returns the number of threads to use for `threads`, where 0 means one thread per available
+/// cpu.
+fn thread_count(threads: usize) -> usize {
+    if threads > 0 {
+        return threads;
+    }
+    thread::available_parallelism()
+        .map(|threads| threads.get())
+        .unwrap_or(1)
+}
+
+//
+
+//
+
+/// Reads every data file in the provided month folder, collecting the overview of each day and
+/// the tag sums of the whole month.
+///
+/// Corrupted and misplaced data files are logged and skipped.
+pub(crate) fn read_month(
+    db_path: &DataBasePath,
+    month_folder: &Path,
+    progress: &TaskProgress,
+) -> Result<MonthData, RegenerationError> {
+    let storage = db_path.storage();
+    let mut month_data = MonthData::default();
+
+    for file in storage.list(month_folder)? {
+        if !DataFile::is_data_file(storage, &file) {
+            continue;
+        }
+        progress.file_processed()?;
+
+        let Some(filename) = file.file_name() else {
+            warn!("Skipping data file without name: {:?}", file);
+            continue;
+        };
+
+        let data_file = match DataFile::read_from_file(db_path, &file) {
+            Ok(data_file) => data_file,
+            Err(crate::data_entry::Error::CorruptedDataFile) => {
+                error!("Data file [{:?}] is corrupted! This file will not be represented in the caches or stat sums!", file);
+                continue;
+            }
+            Err(crate::data_entry::Error::MisplacedDataFile) => {
+                error!("Data file [{:?}] is misplaced or misnamed! This file will not be represented in the caches or stat sums!", file);
+                continue;
+            }
+            Err(crate::data_entry::Error::Io(io_err)) => return Err(io_err.into()),
+            _ => continue, // Remaining errors can not occur here.
+        };
+
+        let overview = data_file.get_overview();
+        month_data.overview.merge(&overview);
+        month_data
+            .day_overviews
+            .push((filename.to_string_lossy().to_string(), overview));
+        month_data.tag_sums.add_data_file(&data_file);
+    }
+
+    Ok(month_data)
+}
+
+//
+
+//
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::BTreeMap, path::PathBuf};
+
+    use crate::{
+        data_base::DataBase,
+        metrics::MENTALSCORE,
+        test_utils::{create_database, derived_files, entry},
+    };
+
+    /// Removes every cache and stat sum file, then regenerates them using `threads` threads.
+    fn regenerate_with(database: &mut DataBase, threads: usize) -> BTreeMap<PathBuf, Vec<u8>> {
+        let storage = database.path().shared_storage();
+        for file in derived_files(database).keys() {
+            storage.remove_file(file).unwrap();
+        }
+        database.set_regeneration_threads(threads);
+        database.regen_caches().unwrap();
+        database.regen_tag_sums().unwrap();
+        derived_files(database)
+    }
+
+    #[test]
+    fn regeneration_is_identical_for_any_number_of_threads() {
+        let mut database = create_database("regeneration_threads", 4);
+        for tag in ["a", "b", "c", "d"] {
+            database.add_tag(tag.to_string()).unwrap();
+        }
+        let mut value = 0;
+        for year in 2021..=2023 {
+            for month in [1, 2, 6, 11, 12] {
+                for day in [1, 10, 28] {
+                    for hour in [6, 13, 26] {
+                        value = (value + 37) % 101;
+                        let tags = (1..=4).filter(|tag| (value + tag) % 3 == 0).collect();
+                        let mut data_entry = entry(hour, (value % 60) as u8, tags);
+                        data_entry.metrics.insert(MENTALSCORE, value as i16);
+                        database
+                            .insert_data_entry(year, month, day, data_entry)
+                            .unwrap();
+                    }
+                }
+            }
+        }
+
+        let single_thread = regenerate_with(&mut database, 1);
+        assert_eq!(
+            single_thread
+                .keys()
+                .filter(|file| file.ends_with("year_cache.txt"))
+                .count(),
+            3
+        );
+        assert!(single_thread
+            .keys()
+            .any(|file| file.starts_with(database.path().stat_sums())));
+        for threads in [2, 4, 16, 0] {
+            assert_eq!(regenerate_with(&mut database, threads), single_thread);
+        }
+    }
+}
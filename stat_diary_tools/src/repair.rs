@@ -0,0 +1,357 @@
+use std::{
+    fmt,
+    path::{Path, PathBuf},
+};
+
+use log::{info, warn};
+use time::OffsetDateTime;
+
+use crate::{
+    data_base::Error,
+    data_entry::{self, DataFile},
+    db_path::DataBasePath,
+};
+
+type Result<T> = std::result::Result<T, Error>;
+
+const QUARANTINEPATH: &str = "quarantine";
+const REPAIRLOGFILE: &str = "repairs.txt";
+
+/// A corrupted data file rewritten by `DataBase::repair`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RepairedFile {
+    /// The path of the rewritten data file. The file was removed if no entry could be salvaged.
+    pub file: PathBuf,
+    /// The path the original file was moved to.
+    pub quarantined: PathBuf,
+    /// The number of entries recovered from the original file.
+    pub salvaged_entries: usize,
+    /// The number of bytes at the end of the original file that could not be read.
+    pub lost_bytes: usize,
+}
+
+impl fmt::Display for RepairedFile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Salvaged {} entries from {:?}, lost {} bytes. The original was moved to {:?}",
+            self.salvaged_entries, self.file, self.lost_bytes, self.quarantined
+        )
+    }
+}
+
+/// The result of `DataBase::repair`, listing every data file that was rewritten.
+#[derive(Debug, Default)]
+pub struct RepairReport {
+    pub repaired: Vec<RepairedFile>,
+}
+
+/// Lists one repaired file per line.
+impl fmt::Display for RepairReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for repaired_file in &self.repaired {
+            writeln!(f, "{repaired_file}")?;
+        }
+        Ok(())
+    }
+}
+
+//
+
+//
+
+/// Salvages every corrupted data file in the database at `db_path`. See `DataBase::repair`.
+///
+/// Misplaced data files are logged and left as they are, since their entries can't be assigned
+/// to a day.
+pub(crate) fn repair_data_files(db_path: &DataBasePath) -> Result<RepairReport> {
+    let storage = db_path.storage();
+    let mut report = RepairReport::default();
+    if !storage.exists(&db_path.data()) {
+        return Ok(report);
+    }
+
+    for file in storage.walk_files(&db_path.data())? {
+        if !DataFile::is_data_file(storage, &file) {
+            continue;
+        }
+
+        match DataFile::read_from_file(db_path, &file) {
+            Ok(_) => {}
+            Err(data_entry::Error::CorruptedDataFile) => {
+                report.repaired.push(repair_data_file(db_path, &file)?);
+            }
+            Err(data_entry::Error::MisplacedDataFile) => {
+                warn!(
+                    "Data file [{:?}] is misplaced or misnamed and can not be repaired! Skipping file...",
+                    file
+                );
+            }
+            Err(error) => return Err(error.into()),
+        }
+    }
+
+    if report.repaired.is_empty() {
+        info!("Found no corrupted data files to repair.");
+    } else {
+        info!("Repaired {} corrupted data files.", report.repaired.len());
+    }
+    Ok(report)
+}
+
+//
+
+//
+
+/// Rewrites the corrupted data file at `file` with every entry that could be salvaged, and moves
+/// the original into the quarantine folder. The repair is recorded in the repair log of the
+/// quarantine folder.
+///
+/// The original is copied into the quarantine folder before the clean file replaces it using
+/// `Storage::write_atomic`, so the original is never lost and the day never lacks its data file
+/// should the repair be interrupted. If no entry could be salvaged the original is moved instead,
+/// leaving the day without a data file.
+fn repair_data_file(db_path: &DataBasePath, file: &Path) -> Result<RepairedFile> {
+    let storage = db_path.storage();
+    let (data_file, lost_bytes) = DataFile::salvage_from_file(db_path, file)?;
+
+    let relative_path = file.strip_prefix(db_path.root()).unwrap_or(file);
+    let quarantined = quarantine_path(db_path, relative_path);
+    if let Some(parent) = quarantined.parent() {
+        storage.create_dir_all(parent)?;
+    }
+
+    if data_file.entries().is_empty() {
+        storage.rename(file, &quarantined)?;
+    } else {
+        storage.write(&quarantined, &storage.read(file)?)?;
+        storage.write_atomic(file, &data_file.to_bytes()?)?;
+    }
+
+    let repaired_file = RepairedFile {
+        file: file.to_path_buf(),
+        quarantined,
+        salvaged_entries: data_file.entries().len(),
+        lost_bytes,
+    };
+    warn!("Repaired corrupted data file! {}", repaired_file);
+
+    let outcome = format!(
+        "salvaged {} entries | lost {} bytes",
+        repaired_file.salvaged_entries, repaired_file.lost_bytes
+    );
+    append_to_repair_log(db_path, relative_path, &outcome, &repaired_file.quarantined)?;
+
+    Ok(repaired_file)
+}
+
+//
+
+//
+
+/// Moves every data file in the database at `db_path` that can't be read, because it is
+/// corrupted or misplaced, into the quarantine folder. Each file is recorded in the repair log of
+/// the quarantine folder together with `reason`. Returns the paths the files were moved to.
+///
+/// Used by migrations, which can only convert the data files they can read. A file left behind
+/// in the format of an older schema version would be misread once the version is raised.
+pub(crate) fn quarantine_unreadable_data_files(
+    db_path: &DataBasePath,
+    reason: &str,
+) -> Result<Vec<PathBuf>> {
+    let storage = db_path.storage();
+    let mut quarantined_files = Vec::new();
+    if !storage.exists(&db_path.data()) {
+        return Ok(quarantined_files);
+    }
+
+    for file in storage.walk_files(&db_path.data())? {
+        if !DataFile::is_data_file(storage, &file) {
+            continue;
+        }
+        match DataFile::read_from_file(db_path, &file) {
+            Ok(_) => continue,
+            Err(data_entry::Error::CorruptedDataFile | data_entry::Error::MisplacedDataFile) => {}
+            Err(error) => return Err(error.into()),
+        }
+
+        let relative_path = file.strip_prefix(db_path.root()).unwrap_or(&file);
+        let quarantined = quarantine_path(db_path, relative_path);
+        if let Some(parent) = quarantined.parent() {
+            storage.create_dir_all(parent)?;
+        }
+        storage.rename(&file, &quarantined)?;
+        warn!(
+            "Moved the unreadable data file {:?} to {:?}! {}",
+            file, quarantined, reason
+        );
+        append_to_repair_log(db_path, relative_path, reason, &quarantined)?;
+        quarantined_files.push(quarantined);
+    }
+    Ok(quarantined_files)
+}
+
+/// Appends a line recording that the file at `relative_path` was moved to `quarantined` to the
+/// repair log of the quarantine folder.
+fn append_to_repair_log(
+    db_path: &DataBasePath,
+    relative_path: &Path,
+    outcome: &str,
+    quarantined: &Path,
+) -> Result<()> {
+    let log_line = format!(
+        "{} | {:?} | {} | {:?}\n",
+        OffsetDateTime::now_utc(),
+        relative_path,
+        outcome,
+        quarantined
+            .strip_prefix(db_path.root())
+            .unwrap_or(quarantined),
+    );
+    db_path.storage().append(
+        &db_path.root().join(QUARANTINEPATH).join(REPAIRLOGFILE),
+        log_line.as_bytes(),
+    )?;
+    Ok(())
+}
+
+/// Returns the path in the quarantine folder for the file at `relative_path` in the database.
+/// If a file from an earlier repair is already there, a number is appended to the file name.
+fn quarantine_path(db_path: &DataBasePath, relative_path: &Path) -> PathBuf {
+    let path = db_path.root().join(QUARANTINEPATH).join(relative_path);
+    let mut candidate = path.clone();
+    let mut number = 1;
+    while db_path.storage().exists(&candidate) {
+        let mut file_name = path.file_name().unwrap_or_default().to_os_string();
+        file_name.push(format!(".{number}"));
+        candidate = path.with_file_name(file_name);
+        number += 1;
+    }
+    candidate
+}
+
+//
+
+//
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use time::Month;
+
+    use crate::{
+        check::Problem,
+        data_base::DataBase,
+        test_utils::{create_database, data_file_path, date, day_entries, entry},
+    };
+
+    /// Adds two entries to a day and cuts the last `cut_bytes` bytes off its data file.
+    fn truncated_data_file(database: &DataBase, cut_bytes: usize) -> PathBuf {
+        database
+            .insert_data_entry(2024, 1, 1, entry(8, 0, vec![]))
+            .unwrap();
+        database
+            .insert_data_entry(2024, 1, 1, entry(9, 0, vec![]))
+            .unwrap();
+
+        let file_path = data_file_path(database, date(2024, Month::January, 1));
+        let storage = database.path().storage();
+        let bytes = storage.read(&file_path).unwrap();
+        storage
+            .write(&file_path, &bytes[..bytes.len() - cut_bytes])
+            .unwrap();
+        file_path
+    }
+
+    fn quarantined_files(database: &DataBase) -> Vec<PathBuf> {
+        let quarantine_path = database.database_path().join("quarantine");
+        database
+            .path()
+            .storage()
+            .walk_files(&quarantine_path)
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|path| path.extension() != Some("txt".as_ref()))
+            .collect()
+    }
+
+    #[test]
+    fn repair_salvages_the_entries_of_a_truncated_file() {
+        let database = create_database("repair_truncated", 0);
+        let file_path = truncated_data_file(&database, 3);
+        let storage = database.path().storage();
+        let original = storage.read(&file_path).unwrap();
+
+        let report = database.check().unwrap();
+        assert!(report
+            .problems
+            .contains(&Problem::CorruptedDataFile(file_path.clone())));
+
+        let report = database.repair().unwrap();
+        assert_eq!(report.repaired.len(), 1);
+        let repaired = &report.repaired[0];
+        assert_eq!(repaired.file, file_path);
+        assert_eq!(repaired.salvaged_entries, 1);
+        assert_eq!(repaired.lost_bytes, 10);
+        let relative_path = file_path.strip_prefix(database.database_path()).unwrap();
+        assert_eq!(
+            repaired.quarantined,
+            database
+                .database_path()
+                .join("quarantine")
+                .join(relative_path)
+        );
+        assert_eq!(
+            quarantined_files(&database),
+            vec![repaired.quarantined.clone()]
+        );
+        assert_eq!(storage.read(&repaired.quarantined).unwrap(), original);
+        let repair_log = storage
+            .read(&database.database_path().join("quarantine/repairs.txt"))
+            .unwrap();
+        assert!(String::from_utf8(repair_log)
+            .unwrap()
+            .contains("| salvaged 1 entries | lost 10 bytes |"));
+
+        assert_eq!(
+            day_entries(&database, date(2024, Month::January, 1)).len(),
+            1
+        );
+        assert!(database.check().unwrap().is_ok());
+
+        // A later repair of the same day keeps the earlier original.
+        let bytes = storage.read(&file_path).unwrap();
+        storage
+            .write(&file_path, &bytes[..bytes.len() - 3])
+            .unwrap();
+        let report = database.repair().unwrap();
+        let mut second_quarantined = repaired.quarantined.clone();
+        second_quarantined.add_extension("1");
+        assert_eq!(report.repaired[0].quarantined, second_quarantined);
+        assert_eq!(report.repaired[0].salvaged_entries, 0);
+        assert_eq!(quarantined_files(&database).len(), 2);
+    }
+
+    #[test]
+    fn repair_removes_a_data_file_without_salvaged_entries() {
+        let database = create_database("repair_empty", 0);
+        let file_path = truncated_data_file(&database, 0);
+        let storage = database.path().storage();
+        storage.write(&file_path, &[0xFF, 0xFE, 8, 0]).unwrap();
+
+        let report = database.repair().unwrap();
+        assert_eq!(report.repaired[0].salvaged_entries, 0);
+        assert_eq!(report.repaired[0].lost_bytes, 2);
+        assert_eq!(
+            storage.read(&report.repaired[0].quarantined).unwrap(),
+            vec![0xFF, 0xFE, 8, 0]
+        );
+        assert!(!storage.exists(&file_path));
+        assert_eq!(
+            quarantined_files(&database),
+            vec![report.repaired[0].quarantined.clone()]
+        );
+        assert!(database.check().unwrap().is_ok());
+    }
+}
@@ -1,9 +1,6 @@
-use std::{fs, io};
+use std::io;
 
-use crate::{
-    db_path::{self, DataBasePath},
-    utilities,
-};
+use crate::db_path::DataBasePath;
 
 pub enum Error {
     Io(io::Error),
@@ -17,8 +14,12 @@ impl From<io::Error> for Error {
     }
 }
 
+#[derive(Debug, Clone)]
 pub struct Settings {
     pub day_switch_offset: i8,
+    /// The version of the format the database is stored in. Databases from before the version was
+    /// recorded get the version matching their `compensated_hours` setting.
+    pub schema_version: u32,
 }
 
 type Result<T> = std::result::Result<T, Error>;
@@ -26,18 +27,56 @@ type Result<T> = std::result::Result<T, Error>;
 impl Settings {
     pub fn load(db_path: &DataBasePath) -> Result<Settings> {
         let settings_path = db_path.root().join("db_settings.txt");
-        if !settings_path.exists() {
+        if !db_path.storage().exists(&settings_path) {
             log::error!(
                 "Database at [{:?}] does not contain a settings file!",
                 db_path.root()
             );
             return Err(Error::DoesNotExist);
         }
-        let mut lines = utilities::read_lines(settings_path)?;
+        let mut lines = db_path.storage().read_lines(&settings_path)?.into_iter();
         let day_switch_offset =
             Settings::get_day_switch_offset(&lines.next().ok_or(Error::IsCorrupted)?)?;
 
-        Ok(Settings { day_switch_offset })
+        let mut schema_version = None;
+        let mut compensated_hours = false;
+        for line in lines {
+            match line.split_once('=') {
+                Some(("schema_version", value)) => {
+                    schema_version = Some(value.parse::<u32>().map_err(|_| Error::IsCorrupted)?)
+                }
+                // Replaced by the schema version, where version 2 introduced compensated hours.
+                Some(("compensated_hours", value)) => {
+                    compensated_hours = value.parse::<bool>().map_err(|_| Error::IsCorrupted)?
+                }
+                _ => log::warn!("Settings::load(): Ignoring unknown setting [{line}]"),
+            }
+        }
+
+        Ok(Settings {
+            day_switch_offset,
+            schema_version: schema_version.unwrap_or(if compensated_hours { 2 } else { 1 }),
+        })
+    }
+
+    /// Saves the settings to the settings file of the database at `db_path`, overwriting the old
+    /// settings.
+    pub fn save(&self, db_path: &DataBasePath) -> Result<()> {
+        let settings_path = db_path.root().join("db_settings.txt");
+        let content = format!(
+            "day_switch_offset={}\nschema_version={}\n",
+            self.day_switch_offset, self.schema_version
+        );
+
+        db_path
+            .storage()
+            .write_atomic(&settings_path, content.as_bytes())?;
+        Ok(())
+    }
+
+    /// Returns true if the data files store offset-compensated hours instead of wall-clock hours.
+    pub fn compensated_hours(&self) -> bool {
+        self.schema_version >= 2
     }
 }
 
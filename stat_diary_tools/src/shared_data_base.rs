@@ -0,0 +1,466 @@
+use std::{
+    ops::Deref,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, MutexGuard, RwLock, RwLockReadGuard, RwLockWriteGuard, TryLockError},
+    time::{Duration, Instant},
+};
+
+use time::Date;
+
+use crate::{
+    data_base::{
+        ChangeEvent, ChangeOrigin, CheckReport, DataBase, Error, ErrorKind, LockInfo, Metric,
+        RepairReport, SubscriptionId,
+    },
+    data_entry::{DataEntry, DataFile},
+    settings_file::Settings,
+    task_progress::TaskProgress,
+};
+
+type Result<T> = std::result::Result<T, Error>;
+
+/// How often a change to the configuration of the database retries while the database is in use.
+const WRITEPOLLINTERVAL: Duration = Duration::from_millis(10);
+
+/// A `DataBase` that can be shared between threads.
+///
+/// Short writes, like adding a entry or a tag, are run one at a time, so writes from different
+/// threads never interleave. Any number of reads can run alongside each other and alongside a
+/// short write.
+///
+/// Long tasks, like merging tags or regenerating the caches, lock the database for the whole task
+/// instead. Reads keep running while a task does, and short writes started meanwhile fail as busy,
+/// after waiting for the lock timeout if one is set. See `DataBase::set_lock_timeout`.
+///
+/// Changing the configuration of the handle, like the lock timeout or the day_switch_offset, waits
+/// for the running reads and writes to finish. If a task is running the change fails as busy as
+/// well, instead of waiting for the whole task.
+///
+/// The `TagList` and `Settings` of the database are kept in memory between calls. If either file
+/// is changed, for example by another process, it is read again by the next call using it.
+///
+/// Cloning a `SharedDataBase` returns a new handle to the same database.
+#[derive(Clone)]
+pub struct SharedDataBase {
+    data_base: Arc<RwLock<DataBase>>,
+    /// Held by every short write, so that only one short write runs at a time.
+    writes: Arc<Mutex<()>>,
+}
+
+/// Shared access to the database for a short write. No other short write runs while it is held.
+struct WriteAccess<'a> {
+    data_base: RwLockReadGuard<'a, DataBase>,
+    _writes: MutexGuard<'a, ()>,
+}
+
+impl Deref for WriteAccess<'_> {
+    type Target = DataBase;
+
+    fn deref(&self) -> &DataBase {
+        &self.data_base
+    }
+}
+
+impl SharedDataBase {
+    /// Wraps `data_base` so that it can be shared between threads.
+    pub fn new(data_base: DataBase) -> SharedDataBase {
+        SharedDataBase {
+            data_base: Arc::new(RwLock::new(data_base)),
+            writes: Arc::new(Mutex::new(())),
+        }
+    }
+
+    /// Loads the database at `db_path` and wraps it so that it can be shared between threads.
+    ///
+    /// # Errors
+    ///
+    /// See `DataBase::load`.
+    pub fn load(db_path: PathBuf) -> Result<SharedDataBase> {
+        Ok(SharedDataBase::new(DataBase::load(db_path)?))
+    }
+
+    //
+
+    //
+
+    /// Returns a copy of the current settings of the database.
+    pub fn settings(&self) -> Result<Settings> {
+        Ok(self.read()?.settings().clone())
+    }
+
+    /// Returns the root path of the database.
+    pub fn database_path(&self) -> Result<PathBuf> {
+        Ok(self.read()?.database_path().to_path_buf())
+    }
+
+    /// See `DataBase::get_data_entries`.
+    pub fn get_data_entries(&self, year: i32, month: u8, day: u8) -> Result<Vec<DataEntry>> {
+        self.read()?.get_data_entries(year, month, day)
+    }
+
+    /// See `DataBase::entries_between`. The entries are collected before the database is
+    /// released, so later writes are not reflected in the returned entries.
+    pub fn entries_between(&self, start: Date, end: Date) -> Result<Vec<(Date, DataEntry)>> {
+        Ok(self.read()?.entries_between(start, end)?.collect())
+    }
+
+    /// See `DataBase::data_files`.
+    pub fn data_files(&self) -> Result<Vec<DataFile>> {
+        self.read()?.data_files()
+    }
+
+    /// See `DataBase::metrics`.
+    pub fn metrics(&self) -> Result<Vec<Metric>> {
+        self.read()?.metrics()
+    }
+
+    /// See `DataBase::check`.
+    pub fn check(&self) -> Result<CheckReport> {
+        self.read()?.check()
+    }
+
+    /// See `DataBase::lock_info`.
+    pub fn lock_info(&self) -> Result<Option<LockInfo>> {
+        self.read()?.lock_info()
+    }
+
+    //
+
+    //
+
+    /// See `DataBase::insert_data_entry`.
+    pub fn insert_data_entry(
+        &self,
+        year: i32,
+        month: u8,
+        day: u8,
+        new_entry: DataEntry,
+    ) -> Result<()> {
+        self.short_write()?
+            .insert_data_entry(year, month, day, new_entry)
+    }
+
+    /// See `DataBase::add_data_entry`.
+    pub fn add_data_entry(
+        &self,
+        year: i32,
+        month: u8,
+        day: u8,
+        new_entry: DataEntry,
+    ) -> Result<()> {
+        self.short_write()?
+            .add_data_entry(year, month, day, new_entry)
+    }
+
+    /// See `DataBase::remove_data_entry`.
+    pub fn remove_data_entry(
+        &self,
+        year: i32,
+        month: u8,
+        day: u8,
+        hour: i8,
+        minute: u8,
+    ) -> Result<()> {
+        self.short_write()?
+            .remove_data_entry(year, month, day, hour, minute)
+    }
+
+    /// See `DataBase::add_diary_entry`.
+    pub fn add_diary_entry(&self, title: String, text: String) -> Result<()> {
+        self.short_write()?.add_diary_entry(title, text)
+    }
+
+    /// See `DataBase::add_tag`.
+    pub fn add_tag(&self, tag_name: String) -> Result<()> {
+        self.short_write()?.add_tag(tag_name)
+    }
+
+    /// See `DataBase::rename_tag`.
+    pub fn rename_tag(&self, old_tag: String, new_tag: String) -> Result<()> {
+        self.short_write()?.rename_tag(old_tag, new_tag)
+    }
+
+    /// See `DataBase::add_metric`.
+    pub fn add_metric(&self, name: String, min: i16, max: i16) -> Result<u16> {
+        self.short_write()?.add_metric(name, min, max)
+    }
+
+    /// See `DataBase::rename_metric`.
+    pub fn rename_metric(&self, old_name: String, new_name: String) -> Result<()> {
+        self.short_write()?.rename_metric(old_name, new_name)
+    }
+
+    /// See `DataBase::merge_tags_with_progress`.
+    pub fn merge_tags(&self, tag_1: u16, tag_2: u16, progress: &TaskProgress) -> Result<()> {
+        self.read()?
+            .merge_tags_with_progress(tag_1, tag_2, progress)
+    }
+
+    /// See `DataBase::remove_tag_with_progress`.
+    pub fn remove_tag(&self, tag_id: u16, progress: &TaskProgress) -> Result<()> {
+        self.read()?.remove_tag_with_progress(tag_id, progress)
+    }
+
+    /// See `DataBase::undo_last_with_progress`.
+    pub fn undo_last(&self, progress: &TaskProgress) -> Result<()> {
+        self.read()?.undo_last_with_progress(progress)
+    }
+
+    /// See `DataBase::regen_caches_with_progress`.
+    pub fn regen_caches(&self, progress: &TaskProgress) -> Result<()> {
+        self.read()?.regen_caches_with_progress(progress)
+    }
+
+    /// See `DataBase::regen_tag_sums_with_progress`.
+    pub fn regen_tag_sums(&self, progress: &TaskProgress) -> Result<()> {
+        self.read()?.regen_tag_sums_with_progress(progress)
+    }
+
+    /// See `DataBase::resume_task_with_progress`.
+    pub fn resume_task(&self, progress: &TaskProgress) -> Result<()> {
+        self.read()?.resume_task_with_progress(progress)
+    }
+
+    /// See `DataBase::repair_with_progress`.
+    pub fn repair(&self, progress: &TaskProgress) -> Result<RepairReport> {
+        self.read()?.repair_with_progress(progress)
+    }
+
+    /// See `DataBase::change_day_switch_offset`.
+    pub fn change_day_switch_offset(&self, new_offset: i8) -> Result<()> {
+        self.write()?.change_day_switch_offset(new_offset)
+    }
+
+    /// See `DataBase::compress_to_image`. Short writes are held back until the image is complete,
+    /// so the image never contains a half finished write.
+    pub fn compress_to_image(&self, target_path: &Path) -> Result<()> {
+        self.short_write()?.compress_to_image(target_path)
+    }
+
+    /// See `DataBase::break_lock`.
+    pub fn break_lock(&self) -> Result<()> {
+        self.read()?.break_lock()
+    }
+
+    /// See `DataBase::set_lock_timeout`.
+    pub fn set_lock_timeout(&self, timeout: Option<Duration>) -> Result<()> {
+        self.write()?.set_lock_timeout(timeout);
+        Ok(())
+    }
+
+    /// See `DataBase::set_regeneration_threads`.
+    pub fn set_regeneration_threads(&self, threads: usize) -> Result<()> {
+        self.write()?.set_regeneration_threads(threads);
+        Ok(())
+    }
+
+    /// See `DataBase::subscribe`.
+    pub fn subscribe(
+        &self,
+        callback: impl Fn(&ChangeEvent, ChangeOrigin) + Send + Sync + 'static,
+    ) -> Result<SubscriptionId> {
+        self.read()?.subscribe(callback)
+    }
+
+    /// See `DataBase::unsubscribe`.
+    pub fn unsubscribe(&self, id: SubscriptionId) -> Result<bool> {
+        Ok(self.read()?.unsubscribe(id))
+    }
+
+    /// See `DataBase::set_poll_interval`.
+    pub fn set_poll_interval(&self, interval: Duration) -> Result<()> {
+        self.write()?.set_poll_interval(interval);
+        Ok(())
+    }
+
+    //
+
+    //
+
+    /// Takes shared access to the database, first reloading the settings if the settings file
+    /// has changed.
+    fn read(&self) -> Result<RwLockReadGuard<'_, DataBase>> {
+        {
+            let data_base = self.lock_read();
+            if !data_base.settings_changed() {
+                return Ok(data_base);
+            }
+        }
+
+        // Another reader might reload the settings first, in which case reloading is skipped.
+        drop(self.write()?);
+        Ok(self.lock_read())
+    }
+
+    /// Takes shared access to the database for a short write, waiting for any other short write
+    /// to finish first.
+    fn short_write(&self) -> Result<WriteAccess<'_>> {
+        let writes = self
+            .writes
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        Ok(WriteAccess {
+            data_base: self.read()?,
+            _writes: writes,
+        })
+    }
+
+    /// Takes exclusive access to the database, first reloading the settings if the settings file
+    /// has changed.
+    ///
+    /// Waiting in `RwLock::write` would hold back every later read until the running tasks are
+    /// done, so the lock is polled instead. If a task is running this fails as busy once the lock
+    /// timeout has passed.
+    fn write(&self) -> Result<RwLockWriteGuard<'_, DataBase>> {
+        let start = Instant::now();
+        let mut data_base = loop {
+            match self.data_base.try_write() {
+                Ok(data_base) => break data_base,
+                Err(TryLockError::Poisoned(poisoned)) => break poisoned.into_inner(),
+                Err(TryLockError::WouldBlock) => {
+                    let data_base = self.lock_read();
+                    let timeout = data_base.lock_timeout().unwrap_or_default();
+                    if start.elapsed() >= timeout {
+                        if let Some(lock_info) = data_base.lock_info()? {
+                            log::error!(
+                                "Can't change the database while the task {:?} is running!",
+                                lock_info.task
+                            );
+                            return Err(Error::with_kind(ErrorKind::DataBaseBusy));
+                        }
+                    }
+                }
+            }
+            std::thread::sleep(WRITEPOLLINTERVAL);
+        };
+        if data_base.settings_changed() {
+            log::info!("The settings file has changed. Reloading the settings...");
+            data_base.reload_settings()?;
+        }
+        Ok(data_base)
+    }
+
+    fn lock_read(&self) -> RwLockReadGuard<'_, DataBase> {
+        self.data_base
+            .read()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+// A `SharedDataBase` is only useful if it can be sent to and shared between threads.
+const _: () = {
+    const fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<SharedDataBase>();
+};
+
+impl From<DataBase> for SharedDataBase {
+    fn from(data_base: DataBase) -> Self {
+        SharedDataBase::new(data_base)
+    }
+}
+
+//
+
+//
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::{mpsc, Arc, Barrier},
+        thread,
+        time::Duration,
+    };
+
+    use super::SharedDataBase;
+    use crate::{
+        data_base::ErrorKind,
+        task_progress::TaskProgress,
+        test_utils::{create_database, entry},
+    };
+
+    #[test]
+    fn reads_and_writes_never_wait_for_a_running_task() {
+        let shared = SharedDataBase::new(create_database("shared_task", 0));
+        shared.add_tag("a".to_string()).unwrap();
+        shared.add_tag("b".to_string()).unwrap();
+        for day in 1..=3 {
+            shared
+                .insert_data_entry(2024, 1, day, entry(12, 0, vec![1]))
+                .unwrap();
+        }
+        shared
+            .set_lock_timeout(Some(Duration::from_millis(300)))
+            .unwrap();
+
+        // The merge stops after the first data file until it is released.
+        let (started_sender, started) = mpsc::channel();
+        let (release, released) = mpsc::channel::<()>();
+        let task = {
+            let shared = shared.clone();
+            thread::spawn(move || {
+                let progress = TaskProgress::new().with_callback(move |processed, _total| {
+                    if processed == 1 {
+                        started_sender.send(()).unwrap();
+                        released.recv().ok();
+                    }
+                });
+                shared.merge_tags(1, 2, &progress)
+            })
+        };
+        started.recv().unwrap();
+
+        // Waits for the lock timeout while the task holds the database.
+        let configuration = {
+            let shared = shared.clone();
+            thread::spawn(move || shared.set_regeneration_threads(2))
+        };
+        assert!(shared.lock_info().unwrap().is_some());
+        assert_eq!(shared.get_data_entries(2024, 1, 3).unwrap().len(), 1);
+        let error = shared
+            .insert_data_entry(2024, 1, 4, entry(12, 0, vec![]))
+            .unwrap_err();
+        assert!(matches!(error.kind, ErrorKind::DataBaseBusy));
+        let error = configuration.join().unwrap().unwrap_err();
+        assert!(matches!(error.kind, ErrorKind::DataBaseBusy));
+        assert_eq!(shared.get_data_entries(2024, 1, 3).unwrap().len(), 1);
+
+        drop(release);
+        task.join().unwrap().unwrap();
+        shared
+            .insert_data_entry(2024, 1, 4, entry(12, 0, vec![]))
+            .unwrap();
+        shared.set_regeneration_threads(2).unwrap();
+        assert_eq!(
+            shared.get_data_entries(2024, 1, 3).unwrap()[0].tags,
+            vec![2]
+        );
+    }
+
+    #[test]
+    fn short_writes_from_two_threads_never_interleave() {
+        let shared = SharedDataBase::new(create_database("shared_writes", 0));
+        let start = Arc::new(Barrier::new(2));
+        let writers: Vec<_> = [8, 9]
+            .into_iter()
+            .map(|hour| {
+                let shared = shared.clone();
+                let start = start.clone();
+                thread::spawn(move || {
+                    start.wait();
+                    for minute in 0..60 {
+                        shared
+                            .insert_data_entry(2024, 1, 1, entry(hour, minute, vec![]))
+                            .unwrap();
+                    }
+                })
+            })
+            .collect();
+        for writer in writers {
+            writer.join().unwrap();
+        }
+
+        // Both threads rewrote the same data file, and no entry was lost.
+        assert_eq!(shared.get_data_entries(2024, 1, 1).unwrap().len(), 120);
+        assert!(shared.check().unwrap().is_ok());
+    }
+}
@@ -1,15 +1,16 @@
 use std::{
     collections::HashMap,
-    fs::{self, File},
-    io::{self, BufWriter, Write},
+    fmt::Write,
+    io,
     path::{Path, PathBuf},
+    sync::Arc,
 };
 
-use log::error;
 use time::Date;
-use walkdir::WalkDir;
 
-use crate::{data_entry::DataFile, db_path::DataBasePath, utilities::read_lines};
+use crate::{
+    data_entry::DataFile, db_path::DataBasePath, storage::Storage, task_progress::Cancelled,
+};
 
 //
 
@@ -18,8 +19,8 @@ use crate::{data_entry::DataFile, db_path::DataBasePath, utilities::read_lines};
 #[derive(Debug)]
 pub enum StatSumsError {
     Io(io::Error),
-    WalkDir(walkdir::Error),
     CorruptedStatSumFile,
+    Cancelled,
 }
 
 impl From<io::Error> for StatSumsError {
@@ -28,9 +29,9 @@ impl From<io::Error> for StatSumsError {
     }
 }
 
-impl From<walkdir::Error> for StatSumsError {
-    fn from(value: walkdir::Error) -> Self {
-        Self::WalkDir(value)
+impl From<Cancelled> for StatSumsError {
+    fn from(_: Cancelled) -> Self {
+        Self::Cancelled
     }
 }
 
@@ -47,6 +48,35 @@ pub struct Tags {
 }
 
 impl Tags {
+    /// Reads the stat sum file at `path`. If the file does not exist a empty Tags instance is
+    /// returned.
+    pub fn read(storage: &dyn Storage, path: &Path) -> Result<Tags, StatSumsError> {
+        if !storage.exists(path) {
+            return Ok(Tags::default());
+        }
+
+        let mut tags = HashMap::new();
+        for line in storage.read_lines(path)? {
+            let line_values: Vec<u32> = line
+                .split(|c: char| !c.is_ascii_digit())
+                .filter(|s| !s.is_empty())
+                .map(|s| {
+                    s.parse::<u32>()
+                        .map_err(|_| StatSumsError::CorruptedStatSumFile)
+                })
+                .collect::<Result<_, _>>()?;
+            if line_values.len() != 2 {
+                log::error!(
+                    "StatSumsFile::load(): Unexpected amount of numbers in line: [{}]",
+                    line
+                );
+                return Err(StatSumsError::CorruptedStatSumFile);
+            }
+            tags.insert(line_values[1] as u16, line_values[0]);
+        }
+        Ok(Tags { tags })
+    }
+
     /// Adds one occurance of the provided tag_id to this tags instance.
     fn add(&mut self, tag_id: u16) {
         *self.tags.entry(tag_id).or_default() += 1;
@@ -70,12 +100,31 @@ impl Tags {
         }
     }
 
+    /// Returns true if both instances hold the same number of occurances of every tag.
+    pub fn matches(&self, other: &Tags) -> bool {
+        let occuring = |tags: &Tags| -> HashMap<u16, u32> {
+            tags.tags
+                .iter()
+                .filter(|(_, occurances)| **occurances > 0)
+                .map(|(tag_id, occurances)| (*tag_id, *occurances))
+                .collect()
+        };
+        occuring(self) == occuring(other)
+    }
+
+    /// Adds all occurances in `other` to this tags instance.
+    fn merge(&mut self, other: Tags) {
+        for (tag_id, occurances) in other.tags {
+            *self.tags.entry(tag_id).or_default() += occurances;
+        }
+    }
+
     /// Extracts the internal hashmap of this Tags instance, then returning it as a sorted vec of (id,
     /// occurances) where it is sorted by the number of occurances with the most common placed
-    /// first.
+    /// first. Tags with the same number of occurances are sorted by id.
     fn as_sorted_vec(&self) -> Vec<(u16, u32)> {
         let mut tags: Vec<(u16, u32)> = self.tags.clone().into_iter().collect();
-        tags.sort_by(|a, b| b.1.cmp(&a.1));
+        tags.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
         tags
     }
 }
@@ -83,46 +132,25 @@ impl Tags {
 pub struct StatSumFile {
     tags: Tags,
     path: PathBuf,
+    storage: Arc<dyn Storage>,
 }
 
 impl StatSumFile {
     /// Attempts to load the statsum file at the provided path. If none exist a empty StatSumFile
     /// is returned.
     /// Remembers the path provided and uses it later when the save() function is called.
-    pub fn load(path: &Path) -> Result<StatSumFile, StatSumsError> {
-        let Ok(lines) = read_lines(path) else {
-            // File does not exist
+    pub fn load(db_path: &DataBasePath, path: &Path) -> Result<StatSumFile, StatSumsError> {
+        let storage = db_path.storage();
+        if !storage.exists(path) {
             if let Some(parent) = path.parent() {
-                create_directory(parent)?;
+                storage.create_dir_all(parent)?;
             }
-            return Ok(StatSumFile {
-                tags: Tags::default(),
-                path: path.to_path_buf(),
-            });
-        };
-
-        let mut tags = HashMap::new();
-        for line in lines {
-            let line_values: Vec<u32> = line
-                .split(|c: char| !c.is_ascii_digit())
-                .filter(|s| !s.is_empty())
-                .map(|s| {
-                    s.parse::<u32>()
-                        .map_err(|_| StatSumsError::CorruptedStatSumFile)
-                })
-                .collect::<Result<_, _>>()?;
-            if line_values.len() != 2 {
-                log::error!(
-                    "StatSumsFile::load(): Unexpected amount of numbers in line: [{}]",
-                    line
-                );
-                return Err(StatSumsError::CorruptedStatSumFile);
-            }
-            tags.insert(line_values[1] as u16, line_values[0]);
         }
+
         Ok(StatSumFile {
-            tags: Tags { tags },
+            tags: Tags::read(storage, path)?,
             path: path.to_path_buf(),
+            storage: db_path.shared_storage(),
         })
     }
 
@@ -145,17 +173,14 @@ impl StatSumFile {
     }
 
     pub fn save(&mut self) -> Result<(), StatSumsError> {
-        let tmp_path = self.path.with_extension("tmp");
-        let mut writer = BufWriter::new(File::create(&tmp_path)?);
-
+        let mut content = String::new();
         for (id, occurances) in self.tags.as_sorted_vec() {
             if occurances == 0 {
                 continue;
             }
-            writeln!(writer, "{} {}", occurances, id)?;
+            writeln!(content, "{} {}", occurances, id).expect("Writing to a String can not fail.");
         }
-        writer.flush()?;
-        fs::rename(tmp_path, &self.path)?;
+        self.storage.write_atomic(&self.path, content.as_bytes())?;
         Ok(())
     }
 }
@@ -185,9 +210,11 @@ pub fn add_tags(
 ) -> Result<(), StatSumsError> {
     let (global, time, time_and_day) = get_paths(db_path, date, hour);
 
-    StatSumFile::load(&global)?.add_tags(tags).save()?;
-    StatSumFile::load(&time)?.add_tags(tags).save()?;
-    StatSumFile::load(&time_and_day)?.add_tags(tags).save()?;
+    StatSumFile::load(db_path, &global)?.add_tags(tags).save()?;
+    StatSumFile::load(db_path, &time)?.add_tags(tags).save()?;
+    StatSumFile::load(db_path, &time_and_day)?
+        .add_tags(tags)
+        .save()?;
     Ok(())
 }
 
@@ -204,9 +231,15 @@ pub fn remove_tags(
 ) -> Result<(), StatSumsError> {
     let (global, time, time_and_day) = get_paths(db_path, date, hour);
 
-    StatSumFile::load(&global)?.remove_tags(tags).save()?;
-    StatSumFile::load(&time)?.remove_tags(tags).save()?;
-    StatSumFile::load(&time_and_day)?.remove_tags(tags).save()?;
+    StatSumFile::load(db_path, &global)?
+        .remove_tags(tags)
+        .save()?;
+    StatSumFile::load(db_path, &time)?
+        .remove_tags(tags)
+        .save()?;
+    StatSumFile::load(db_path, &time_and_day)?
+        .remove_tags(tags)
+        .save()?;
     Ok(())
 }
 
@@ -214,108 +247,94 @@ pub fn remove_tags(
 
 //
 
-/// Regenerates all types of tag sums for the entire database.
-pub fn regenerate_tag_sums(db_path: &DataBasePath) -> Result<(), io::Error> {
-    let mut general = Tags::default();
-    let mut times: HashMap<u8, Tags> = HashMap::new();
-    let mut day_and_times: HashMap<u8, HashMap<u8, Tags>> = HashMap::new();
-
-    for path in WalkDir::new(db_path.data()) {
-        let path = path?;
-        let filepath = path.path();
-
-        if !DataFile::is_data_file(filepath) {
-            continue;
-        }
-
-        let weekday_nr = match weekday_nr_from_filename(filepath) {
-            Some(nr) => nr,
-            None => {
-                log::warn!("Could not get weekday number from file: {filepath:?}");
-                continue;
-            }
-        };
-        let weekday_times = day_and_times.entry(weekday_nr).or_default();
+/// Tag sums of all types, collected from any number of data files.
+#[derive(Debug, Default)]
+pub struct TagSums {
+    general: Tags,
+    times: HashMap<u8, Tags>,
+    day_and_times: HashMap<u8, HashMap<u8, Tags>>,
+}
 
-        let data_file = match DataFile::read_from_file(filepath) {
-            Ok(data_file) => data_file,
-            Err(crate::data_entry::Error::CorruptedDataFile) => {
-                error!("Data file [{:?}] is corrupted! This file will not be represented in the stat sums!", filepath);
-                continue;
-            }
-            Err(crate::data_entry::Error::Io(io_err)) => return Err(io_err),
-            _ => continue, // Remaining errors can not occur here.
-        };
+impl TagSums {
+    /// Adds the tags of every entry in the provided data file to the sums.
+    pub fn add_data_file(&mut self, data_file: &DataFile) {
+        let weekday_nr = data_file.date().weekday().number_days_from_monday();
+        let weekday_times = self.day_and_times.entry(weekday_nr).or_default();
 
         for data_entry in data_file.entries().values() {
             for tag in &data_entry.tags {
-                general.add(*tag);
-                times.entry(data_entry.hour).or_default().add(*tag);
-                weekday_times.entry(data_entry.hour).or_default().add(*tag);
+                self.general.add(*tag);
+                self.times
+                    .entry(data_entry.wall_clock_hour())
+                    .or_default()
+                    .add(*tag);
+                weekday_times
+                    .entry(data_entry.wall_clock_hour())
+                    .or_default()
+                    .add(*tag);
             }
         }
     }
 
-    save_stat_sums(db_path, general, times, day_and_times)?;
-
-    Ok(())
-}
-
-//
-
-//
-
-/// Saves the provided stat sums to the provided database.
-fn save_stat_sums(
-    db_path: &DataBasePath,
-    general: Tags,
-    times: HashMap<u8, Tags>,
-    day_and_times: HashMap<u8, HashMap<u8, Tags>>,
-) -> Result<(), io::Error> {
-    let stat_sums_path = db_path.stat_sums();
-    create_directory(&stat_sums_path)?;
-    write_to_file(general, &stat_sums_path.join("global_sums.txt"))?;
-
-    let time_sums_path = stat_sums_path.join("time");
-    time_stats(times, &time_sums_path)?;
-
-    let time_and_day_sums_path = stat_sums_path.join("time_and_day");
-    create_directory(&time_and_day_sums_path)?;
-    for (day_index, time_tags) in day_and_times.into_iter() {
-        time_stats(
-            time_tags,
-            &time_and_day_sums_path.join(weekday_str(day_index)),
-        )?;
+    /// Adds all sums of `other` to these sums.
+    pub fn merge(&mut self, other: TagSums) {
+        self.general.merge(other.general);
+        for (hour, tags) in other.times {
+            self.times.entry(hour).or_default().merge(tags);
+        }
+        for (day_index, time_tags) in other.day_and_times {
+            let weekday_times = self.day_and_times.entry(day_index).or_default();
+            for (hour, tags) in time_tags {
+                weekday_times.entry(hour).or_default().merge(tags);
+            }
+        }
     }
-    Ok(())
-}
-
-//
 
-//
+    /// Saves the sums to the provided database, replacing the stat sum files already there.
+    pub fn save(self, db_path: &DataBasePath) -> Result<(), io::Error> {
+        let storage = db_path.storage();
+        storage.create_dir_all(&db_path.stat_sums().join("time"))?;
+        storage.create_dir_all(&db_path.stat_sums().join("time_and_day"))?;
+        for (file_path, tags) in self.into_files(db_path) {
+            if let Some(parent) = file_path.parent() {
+                storage.create_dir_all(parent)?;
+            }
+            write_to_file(storage, tags, &file_path)?;
+        }
+        Ok(())
+    }
 
-/// Creates a directory at the provided path, then saving each time_tags instance to its own file
-/// within said directory.
-fn time_stats(time_tags: HashMap<u8, Tags>, path: &Path) -> Result<(), io::Error> {
-    create_directory(path)?;
-    for (hour, tags) in time_tags.into_iter() {
-        write_to_file(tags, &path.join(format!("{:02}.txt", hour)))?;
+    /// Returns the path of every stat sum file the sums are saved to, together with the tags
+    /// saved to it.
+    pub fn into_files(self, db_path: &DataBasePath) -> Vec<(PathBuf, Tags)> {
+        let stat_sums_path = db_path.stat_sums();
+        let mut files = vec![(stat_sums_path.join("global_sums.txt"), self.general)];
+
+        let time_sums_path = stat_sums_path.join("time");
+        files.extend(time_files(self.times, &time_sums_path));
+
+        let time_and_day_sums_path = stat_sums_path.join("time_and_day");
+        for (day_index, time_tags) in self.day_and_times.into_iter() {
+            files.extend(time_files(
+                time_tags,
+                &time_and_day_sums_path.join(weekday_str(day_index)),
+            ));
+        }
+        files
     }
-    Ok(())
 }
 
 //
 
 //
 
-/// Ensures a directory exists at the provided path, creating one if it doesn't exist.
-fn create_directory(path: &Path) -> Result<(), io::Error> {
-    if let Err(e) = fs::create_dir_all(path) {
-        if e.kind() != io::ErrorKind::AlreadyExists {
-            return Err(e);
-        }
-    }
-    Ok(())
+/// Returns the path of the file within the directory at `path` each time_tags instance is saved
+/// to, together with the instance.
+fn time_files(time_tags: HashMap<u8, Tags>, path: &Path) -> Vec<(PathBuf, Tags)> {
+    time_tags
+        .into_iter()
+        .map(|(hour, tags)| (path.join(format!("{:02}.txt", hour)), tags))
+        .collect()
 }
 
 //
@@ -323,34 +342,12 @@ fn create_directory(path: &Path) -> Result<(), io::Error> {
 //
 
 /// Writes the provided tags instance to the provided file_path.
-fn write_to_file(tags: Tags, file_path: &Path) -> Result<(), io::Error> {
-    let mut writer = BufWriter::new(File::create(file_path)?);
+fn write_to_file(storage: &dyn Storage, tags: Tags, file_path: &Path) -> Result<(), io::Error> {
+    let mut content = String::new();
     for (tag_id, occurances) in tags.as_sorted_vec() {
-        writeln!(writer, "{} {}", occurances, tag_id)?;
+        writeln!(content, "{} {}", occurances, tag_id).expect("Writing to a String can not fail.");
     }
-    writer.flush()?;
-    Ok(())
-}
-
-//
-
-//
-
-/// Attempts to get the &str filename from the provided path, returning a StatSumsError::InvalidFileName
-/// if unsuccessful.
-fn filename(filepath: &Path) -> Option<&str> {
-    filepath.file_stem().and_then(|s| s.to_str())
-}
-
-//
-
-//
-
-/// Returns the weekday index in the second part of a datafile name.
-/// Datafiles use the format "{day_number}-{weekday_index}.statdiary"
-fn weekday_nr_from_filename(filepath: &Path) -> Option<u8> {
-    let name = filename(filepath)?;
-    name.split('-').nth(1)?.parse::<u8>().ok()
+    storage.write(file_path, content.as_bytes())
 }
 
 //
@@ -0,0 +1,596 @@
+use std::{
+    collections::BTreeMap,
+    fmt,
+    fs::{self, File},
+    io::{self, Write},
+    ops::Bound,
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::SystemTime,
+};
+
+//
+
+//
+
+/// The metadata of a file or directory in a `Storage`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Metadata {
+    pub is_dir: bool,
+    /// The length of the file in bytes. Always 0 for directories.
+    pub len: u64,
+    pub modified: SystemTime,
+}
+
+/// The backend a database stores its files in.
+///
+/// Every module reads and writes the database through the `Storage` of its `DataBasePath`,
+/// meaning a database can live in the file system using `FileSystemStorage`, or entirely in memory
+/// using `MemoryStorage`. Paths are handled the same way in every backend, so the database layout
+/// stays the same no matter where it is stored.
+pub trait Storage: fmt::Debug + Send + Sync {
+    /// Reads the entire file at `path`.
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>>;
+
+    /// Writes `data` to the file at `path`, replacing the file if it already exists. The parent
+    /// directory must exist.
+    fn write(&self, path: &Path, data: &[u8]) -> io::Result<()>;
+
+    /// Same as `write`, but fails with `io::ErrorKind::AlreadyExists` if the file already exists.
+    /// Checking and creating the file is a single atomic operation, making it usable for locks.
+    fn create_new(&self, path: &Path, data: &[u8]) -> io::Result<()>;
+
+    /// Appends `data` to the file at `path`, creating the file if it doesn't exist.
+    fn append(&self, path: &Path, data: &[u8]) -> io::Result<()>;
+
+    /// Atomically renames the file or directory at `from` to `to`. A file at `to` is replaced.
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()>;
+
+    /// Removes the file at `path`.
+    fn remove_file(&self, path: &Path) -> io::Result<()>;
+
+    /// Removes the directory at `path` together with everything inside it.
+    fn remove_dir_all(&self, path: &Path) -> io::Result<()>;
+
+    /// Creates the directory at `path` together with any missing parent directories.
+    fn create_dir_all(&self, path: &Path) -> io::Result<()>;
+
+    /// Returns the paths of every file and directory directly inside the directory at `path`,
+    /// sorted by path.
+    fn list(&self, path: &Path) -> io::Result<Vec<PathBuf>>;
+
+    /// Returns the metadata of the file or directory at `path`.
+    fn metadata(&self, path: &Path) -> io::Result<Metadata>;
+
+    /// Makes sure everything written to the file at `path` is stored durably. Does nothing for
+    /// backends without durable storage.
+    fn sync(&self, _path: &Path) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Returns whether this backend is the local file system. Functionality handing the database
+    /// files to other libraries, like compressing the database to a image, requires it.
+    fn is_file_system(&self) -> bool {
+        false
+    }
+
+    //
+
+    //
+
+    /// Returns whether a file or directory exists at `path`.
+    fn exists(&self, path: &Path) -> bool {
+        self.metadata(path).is_ok()
+    }
+
+    /// Returns whether a directory exists at `path`.
+    fn is_dir(&self, path: &Path) -> bool {
+        self.metadata(path).is_ok_and(|metadata| metadata.is_dir)
+    }
+
+    /// Returns whether a file exists at `path`.
+    fn is_file(&self, path: &Path) -> bool {
+        self.metadata(path).is_ok_and(|metadata| !metadata.is_dir)
+    }
+
+    /// Reads the entire file at `path` as a UTF-8 string.
+    fn read_to_string(&self, path: &Path) -> io::Result<String> {
+        String::from_utf8(self.read(path)?)
+            .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))
+    }
+
+    /// Reads the lines of the file at `path`.
+    fn read_lines(&self, path: &Path) -> io::Result<Vec<String>> {
+        Ok(self
+            .read_to_string(path)?
+            .lines()
+            .map(str::to_string)
+            .collect())
+    }
+
+    /// Writes `data` to a `.tmp` file next to `path`, which is then renamed to `path`. Ensures no
+    /// data is lost in the event of the program stopping mid-write.
+    fn write_atomic(&self, path: &Path, data: &[u8]) -> io::Result<()> {
+        let mut tmp_path = path.to_path_buf();
+        tmp_path.add_extension("tmp");
+        self.write(&tmp_path, data)?;
+        self.rename(&tmp_path, path)
+    }
+
+    /// Returns the paths of every file inside the directory at `path` and its subdirectories,
+    /// sorted by path.
+    fn walk_files(&self, path: &Path) -> io::Result<Vec<PathBuf>> {
+        let mut files = Vec::new();
+        for entry_path in self.list(path)? {
+            if self.is_dir(&entry_path) {
+                files.append(&mut self.walk_files(&entry_path)?);
+            } else {
+                files.push(entry_path);
+            }
+        }
+        Ok(files)
+    }
+}
+
+//
+
+//
+
+/// Stores the database in the local file system.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct FileSystemStorage;
+
+impl Storage for FileSystemStorage {
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+        fs::read(path)
+    }
+
+    fn write(&self, path: &Path, data: &[u8]) -> io::Result<()> {
+        fs::write(path, data)
+    }
+
+    fn create_new(&self, path: &Path, data: &[u8]) -> io::Result<()> {
+        File::create_new(path)?.write_all(data)
+    }
+
+    fn append(&self, path: &Path, data: &[u8]) -> io::Result<()> {
+        File::options()
+            .create(true)
+            .append(true)
+            .open(path)?
+            .write_all(data)
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        fs::rename(from, to)
+    }
+
+    fn remove_file(&self, path: &Path) -> io::Result<()> {
+        fs::remove_file(path)
+    }
+
+    fn remove_dir_all(&self, path: &Path) -> io::Result<()> {
+        fs::remove_dir_all(path)
+    }
+
+    fn create_dir_all(&self, path: &Path) -> io::Result<()> {
+        fs::create_dir_all(path)
+    }
+
+    fn list(&self, path: &Path) -> io::Result<Vec<PathBuf>> {
+        let mut paths = fs::read_dir(path)?
+            .map(|entry| entry.map(|entry| entry.path()))
+            .collect::<io::Result<Vec<_>>>()?;
+        paths.sort();
+        Ok(paths)
+    }
+
+    fn metadata(&self, path: &Path) -> io::Result<Metadata> {
+        let metadata = fs::metadata(path)?;
+        Ok(Metadata {
+            is_dir: metadata.is_dir(),
+            len: if metadata.is_dir() { 0 } else { metadata.len() },
+            modified: metadata.modified()?,
+        })
+    }
+
+    fn sync(&self, path: &Path) -> io::Result<()> {
+        File::open(path)?.sync_all()
+    }
+
+    fn is_file_system(&self) -> bool {
+        true
+    }
+}
+
+//
+
+//
+
+#[derive(Debug, Clone)]
+enum MemoryEntry {
+    Directory,
+    File { data: Vec<u8>, modified: SystemTime },
+}
+
+/// Stores the database in memory. Nothing is ever written to disk, and everything stored is lost
+/// once the storage is dropped.
+///
+/// Directories have to be created before files can be written to them, the same way as in the file
+/// system. A path without a parent, like `/`, always exists.
+#[derive(Debug, Default)]
+pub struct MemoryStorage {
+    entries: Mutex<BTreeMap<PathBuf, MemoryEntry>>,
+}
+
+impl MemoryStorage {
+    /// Creates a new empty `MemoryStorage`.
+    pub fn new() -> MemoryStorage {
+        MemoryStorage::default()
+    }
+
+    //
+
+    //
+
+    fn entries(&self) -> std::sync::MutexGuard<'_, BTreeMap<PathBuf, MemoryEntry>> {
+        self.entries
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    //
+
+    //
+
+    /// Returns the paths of `path` and everything inside it when `path` is a directory.
+    fn paths_within(entries: &BTreeMap<PathBuf, MemoryEntry>, path: &Path) -> Vec<PathBuf> {
+        entries
+            .range::<Path, _>((Bound::Included(path), Bound::Unbounded))
+            .map(|(entry_path, _)| entry_path)
+            .take_while(|entry_path| entry_path.starts_with(path))
+            .cloned()
+            .collect()
+    }
+
+    //
+
+    //
+
+    /// Returns a `NotFound` error unless the parent directory of `path` exists.
+    fn check_parent(entries: &BTreeMap<PathBuf, MemoryEntry>, path: &Path) -> io::Result<()> {
+        let Some(parent) = path.parent() else {
+            return Err(invalid_input(path));
+        };
+        if parent.parent().is_none() || parent.as_os_str().is_empty() {
+            return Ok(());
+        }
+        match entries.get(parent) {
+            Some(MemoryEntry::Directory) => Ok(()),
+            Some(MemoryEntry::File { .. }) => Err(invalid_input(parent)),
+            None => Err(not_found(parent)),
+        }
+    }
+
+    //
+
+    //
+
+    /// Writes `data` to the file at `path`, after checking that `path` isn't a directory.
+    fn write_file(
+        entries: &mut BTreeMap<PathBuf, MemoryEntry>,
+        path: &Path,
+        data: Vec<u8>,
+    ) -> io::Result<()> {
+        MemoryStorage::check_parent(entries, path)?;
+        if let Some(MemoryEntry::Directory) = entries.get(path) {
+            return Err(invalid_input(path));
+        }
+        entries.insert(
+            path.to_path_buf(),
+            MemoryEntry::File {
+                data,
+                modified: SystemTime::now(),
+            },
+        );
+        Ok(())
+    }
+}
+
+impl Storage for MemoryStorage {
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+        match self.entries().get(path) {
+            Some(MemoryEntry::File { data, .. }) => Ok(data.clone()),
+            Some(MemoryEntry::Directory) => Err(invalid_input(path)),
+            None => Err(not_found(path)),
+        }
+    }
+
+    fn write(&self, path: &Path, data: &[u8]) -> io::Result<()> {
+        MemoryStorage::write_file(&mut self.entries(), path, data.to_vec())
+    }
+
+    fn create_new(&self, path: &Path, data: &[u8]) -> io::Result<()> {
+        let mut entries = self.entries();
+        if entries.contains_key(path) {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("{:?} already exists", path),
+            ));
+        }
+        MemoryStorage::write_file(&mut entries, path, data.to_vec())
+    }
+
+    fn append(&self, path: &Path, data: &[u8]) -> io::Result<()> {
+        let mut entries = self.entries();
+        let mut new_data = match entries.get(path) {
+            Some(MemoryEntry::File { data, .. }) => data.clone(),
+            Some(MemoryEntry::Directory) => return Err(invalid_input(path)),
+            None => Vec::new(),
+        };
+        new_data.extend_from_slice(data);
+        MemoryStorage::write_file(&mut entries, path, new_data)
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        let mut entries = self.entries();
+        let Some(entry) = entries.get(from) else {
+            return Err(not_found(from));
+        };
+        MemoryStorage::check_parent(&entries, to)?;
+        if to.starts_with(from) && to != from {
+            return Err(invalid_input(to));
+        }
+
+        let replaces_non_empty_dir = MemoryStorage::paths_within(&entries, to).len() > 1;
+        match (entry, entries.get(to)) {
+            (MemoryEntry::File { .. }, Some(MemoryEntry::Directory))
+            | (MemoryEntry::Directory, Some(MemoryEntry::File { .. })) => {
+                return Err(invalid_input(to))
+            }
+            // Same as in the file system a directory may only replace a empty directory.
+            (MemoryEntry::Directory, Some(MemoryEntry::Directory)) if replaces_non_empty_dir => {
+                return Err(invalid_input(to))
+            }
+            _ => {}
+        }
+
+        for path in MemoryStorage::paths_within(&entries, from) {
+            let entry = entries.remove(&path).expect("The path was just listed.");
+            let relative_path = path.strip_prefix(from).expect("The path is within from.");
+            let new_path = if relative_path.as_os_str().is_empty() {
+                to.to_path_buf()
+            } else {
+                to.join(relative_path)
+            };
+            entries.insert(new_path, entry);
+        }
+        Ok(())
+    }
+
+    fn remove_file(&self, path: &Path) -> io::Result<()> {
+        let mut entries = self.entries();
+        match entries.get(path) {
+            Some(MemoryEntry::File { .. }) => {
+                entries.remove(path);
+                Ok(())
+            }
+            Some(MemoryEntry::Directory) => Err(invalid_input(path)),
+            None => Err(not_found(path)),
+        }
+    }
+
+    fn remove_dir_all(&self, path: &Path) -> io::Result<()> {
+        let mut entries = self.entries();
+        match entries.get(path) {
+            Some(MemoryEntry::Directory) => {
+                for path in MemoryStorage::paths_within(&entries, path) {
+                    entries.remove(&path);
+                }
+                Ok(())
+            }
+            Some(MemoryEntry::File { .. }) => Err(invalid_input(path)),
+            None => Err(not_found(path)),
+        }
+    }
+
+    fn create_dir_all(&self, path: &Path) -> io::Result<()> {
+        let mut entries = self.entries();
+        for ancestor in path.ancestors() {
+            if ancestor.parent().is_none() || ancestor.as_os_str().is_empty() {
+                continue;
+            }
+            match entries.get(ancestor) {
+                Some(MemoryEntry::File { .. }) => return Err(invalid_input(ancestor)),
+                Some(MemoryEntry::Directory) => {}
+                None => {
+                    entries.insert(ancestor.to_path_buf(), MemoryEntry::Directory);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn list(&self, path: &Path) -> io::Result<Vec<PathBuf>> {
+        let entries = self.entries();
+        let is_root = path.parent().is_none() || path.as_os_str().is_empty();
+        match entries.get(path) {
+            Some(MemoryEntry::Directory) => {}
+            None if is_root => {}
+            Some(MemoryEntry::File { .. }) => return Err(invalid_input(path)),
+            None => return Err(not_found(path)),
+        }
+
+        Ok(MemoryStorage::paths_within(&entries, path)
+            .into_iter()
+            .filter(|entry_path| entry_path.parent() == Some(path))
+            .collect())
+    }
+
+    fn metadata(&self, path: &Path) -> io::Result<Metadata> {
+        match self.entries().get(path) {
+            Some(MemoryEntry::Directory) => Ok(Metadata {
+                is_dir: true,
+                len: 0,
+                modified: SystemTime::UNIX_EPOCH,
+            }),
+            Some(MemoryEntry::File { data, modified }) => Ok(Metadata {
+                is_dir: false,
+                len: data.len() as u64,
+                modified: *modified,
+            }),
+            None => Err(not_found(path)),
+        }
+    }
+}
+
+//
+
+//
+
+fn not_found(path: &Path) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::NotFound,
+        format!("{:?} does not exist", path),
+    )
+}
+
+fn invalid_input(path: &Path) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidInput,
+        format!("{:?} is not a valid target for this operation", path),
+    )
+}
+
+//
+
+//
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io,
+        path::{Path, PathBuf},
+    };
+
+    use super::{FileSystemStorage, MemoryStorage, Storage};
+    use crate::test_utils::temp_dir;
+
+    /// Runs the checks every `Storage` backend has to pass inside the empty directory `root`.
+    fn check_storage(storage: &dyn Storage, root: &Path) {
+        let dir = root.join("dir");
+        let file = dir.join("file.txt");
+
+        let error = storage.write(&file, b"data").unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::NotFound);
+        storage.create_dir_all(&dir.join("nested/deeper")).unwrap();
+        assert!(storage.is_dir(&dir.join("nested")));
+
+        storage.write(&file, b"first").unwrap();
+        storage.write(&file, b"data").unwrap();
+        assert_eq!(storage.read(&file).unwrap(), b"data");
+        assert!(storage.is_file(&file) && !storage.is_dir(&file));
+        let metadata = storage.metadata(&file).unwrap();
+        assert_eq!((metadata.is_dir, metadata.len), (false, 4));
+
+        let error = storage.create_new(&file, b"new").unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::AlreadyExists);
+        assert_eq!(storage.read(&file).unwrap(), b"data");
+
+        storage.append(&file, b"\nmore").unwrap();
+        storage.append(&dir.join("log.txt"), b"line").unwrap();
+        assert_eq!(storage.read_lines(&file).unwrap(), vec!["data", "more"]);
+        assert_eq!(
+            storage.read_to_string(&dir.join("log.txt")).unwrap(),
+            "line"
+        );
+
+        storage.write_atomic(&file, b"atomic").unwrap();
+        assert_eq!(storage.read(&file).unwrap(), b"atomic");
+        assert!(!storage.exists(&dir.join("file.txt.tmp")));
+
+        assert_eq!(
+            storage.list(&dir).unwrap(),
+            vec![
+                dir.join("file.txt"),
+                dir.join("log.txt"),
+                dir.join("nested")
+            ]
+        );
+        storage
+            .write(&dir.join("nested/deeper/inner.txt"), b"inner")
+            .unwrap();
+        assert_eq!(
+            storage.walk_files(&dir).unwrap(),
+            vec![
+                dir.join("file.txt"),
+                dir.join("log.txt"),
+                dir.join("nested/deeper/inner.txt")
+            ]
+        );
+
+        // A file replaces an existing file, and a directory is moved together with its contents.
+        storage.rename(&dir.join("log.txt"), &file).unwrap();
+        assert_eq!(storage.read(&file).unwrap(), b"line");
+        let moved = root.join("moved");
+        storage.rename(&dir, &moved).unwrap();
+        assert!(!storage.exists(&dir));
+        assert_eq!(
+            storage
+                .read(&moved.join("nested/deeper/inner.txt"))
+                .unwrap(),
+            b"inner"
+        );
+
+        storage.remove_file(&moved.join("file.txt")).unwrap();
+        let error = storage.remove_file(&moved.join("file.txt")).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::NotFound);
+        storage.remove_dir_all(&moved).unwrap();
+        assert!(!storage.exists(&moved.join("nested")));
+        assert_eq!(storage.list(root).unwrap(), Vec::<PathBuf>::new());
+        let error = storage.read(&file).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::NotFound);
+    }
+
+    //
+
+    //
+
+    #[test]
+    fn memory_storage_behaves_like_a_file_system() {
+        let storage = MemoryStorage::new();
+        let root = Path::new("/root_dir");
+        storage.create_dir_all(root).unwrap();
+        check_storage(&storage, root);
+    }
+
+    #[test]
+    fn memory_storage_rejects_invalid_targets() {
+        let storage = MemoryStorage::new();
+        let dir = Path::new("/dir");
+        storage.create_dir_all(&dir.join("sub")).unwrap();
+        storage.write(&dir.join("file"), b"data").unwrap();
+
+        let error = storage.write(&dir.join("sub"), b"data").unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+        let error = storage.write(&dir.join("file/inner"), b"data").unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+        let error = storage.remove_file(&dir.join("sub")).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+        // A directory can't be moved into itself, or replace a directory that isn't empty.
+        let error = storage.rename(dir, &dir.join("sub/dir")).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+        storage.create_dir_all(Path::new("/other/sub")).unwrap();
+        let error = storage.rename(Path::new("/other"), dir).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn file_system_storage_passes_the_storage_checks() {
+        let root = temp_dir("storage");
+        let storage = FileSystemStorage;
+
+        check_storage(&storage, &root);
+        storage.remove_dir_all(&root).unwrap();
+    }
+}
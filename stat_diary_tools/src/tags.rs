@@ -1,11 +1,6 @@
-use std::{
-    collections::HashMap,
-    fs::{self, File},
-    io::{self, BufWriter, Read, Write},
-    path::Path,
-};
+use std::{collections::HashMap, fmt::Write, io, path::Path};
 
-use crate::{db_path::DataBasePath, utilities::read_lines};
+use crate::{db_path::DataBasePath, storage::Storage};
 
 #[derive(Debug)]
 pub enum TagsError {
@@ -26,6 +21,16 @@ type Result<T> = std::result::Result<T, TagsError>;
 
 const RECLAIMEDTAGSFILE: &str = "reclaimed.tags";
 
+/// The complete state of a tag list: every tag, the reclaimed ids in the order they are handed
+/// out, and the id the next new tag gets once the reclaimed ids run out.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TagsSnapshot {
+    /// The id and name of every tag, sorted by id.
+    pub tags: Vec<(u16, String)>,
+    pub reclaimed_ids: Vec<u16>,
+    pub next_id: u16,
+}
+
 /// This is a in-memory representation of a tag list.
 /// It provides a variety of functions, including getting the tag name from a id, or a id from a
 /// tag name.
@@ -49,7 +54,7 @@ impl TagList {
         let mut id_str_map = HashMap::new();
         let mut str_id_map = HashMap::new();
         let mut next_id = 0;
-        for line in read_lines(filepath)? {
+        for line in db_path.storage().read_lines(&filepath)? {
             let mut parts = line.split(' ');
             let (id, tag) = (
                 parts
@@ -79,8 +84,8 @@ impl TagList {
 
         let removed_ids = {
             let reclaimed_tags_path = db_path.root().join(RECLAIMEDTAGSFILE);
-            if Path::exists(&reclaimed_tags_path) {
-                Self::load_reclaimed_ids(&reclaimed_tags_path)?
+            if db_path.storage().exists(&reclaimed_tags_path) {
+                Self::load_reclaimed_ids(db_path.storage(), &reclaimed_tags_path)?
             } else {
                 Vec::new()
             }
@@ -101,11 +106,8 @@ impl TagList {
 
     //
 
-    fn load_reclaimed_ids(file_path: &Path) -> Result<Vec<u16>> {
-        let bytes: Vec<u8> = io::BufReader::new(File::open(file_path)?)
-            .bytes()
-            .map_while(std::result::Result::ok)
-            .collect::<Vec<u8>>();
+    fn load_reclaimed_ids(storage: &dyn Storage, file_path: &Path) -> Result<Vec<u16>> {
+        let bytes = storage.read(file_path)?;
 
         if !bytes.len().is_multiple_of(2) {
             log::error!("Tags::load_reclaimed_ids(): Reclaimed id file doesn't have a even number of bytes!");
@@ -167,6 +169,19 @@ impl TagList {
         self.id_str_map.contains_key(&tag_id)
     }
 
+    /// Returns the ids listed in the reclaimed.tags file, which are handed out to new tags before
+    /// any new id is.
+    pub fn reclaimed_ids(&self) -> &[u16] {
+        &self.removed_ids
+    }
+
+    /// Returns a iterator over the id and name of every tag, in no particular order.
+    pub fn iter(&self) -> impl Iterator<Item = (u16, &str)> {
+        self.id_str_map
+            .iter()
+            .map(|(tag_id, tag_name)| (*tag_id, tag_name.as_str()))
+    }
+
     /// Adds the provided id to the tags list. When selecting id reclaimed id's will be
     /// prioritized.
     pub fn add_tag(&mut self, tag_name: String) -> Result<&mut Self> {
@@ -222,6 +237,57 @@ impl TagList {
 
     //
 
+    /// Adds a previously removed tag back under its original `tag_id`, removing the id from the
+    /// reclaimed ids.
+    /// Will fail with a TagsError::TagAlreadyExists if the id or tag name is in use.
+    pub fn restore_tag(&mut self, tag_id: u16, tag_name: String) -> Result<&mut Self> {
+        if self.id_str_map.contains_key(&tag_id) || self.str_id_map.contains_key(&tag_name) {
+            return Err(TagsError::TagAlreadyExists);
+        }
+
+        self.removed_ids.retain(|removed_id| *removed_id != tag_id);
+        self.next_id = self.next_id.max(tag_id.saturating_add(1));
+        self.str_id_map.insert(tag_name.clone(), tag_id);
+        self.id_str_map.insert(tag_id, tag_name);
+        Ok(self)
+    }
+
+    //
+
+    //
+
+    /// Returns the complete state of this tag list, which `restore_snapshot` can restore exactly.
+    pub fn snapshot(&self) -> TagsSnapshot {
+        let mut tags: Vec<(u16, String)> = self
+            .id_str_map
+            .iter()
+            .map(|(tag_id, tag_name)| (*tag_id, tag_name.clone()))
+            .collect();
+        tags.sort_unstable();
+        TagsSnapshot {
+            tags,
+            reclaimed_ids: self.removed_ids.clone(),
+            next_id: self.next_id,
+        }
+    }
+
+    /// Replaces every tag, the reclaimed ids and the next id with those of `snapshot`.
+    pub fn restore_snapshot(&mut self, snapshot: TagsSnapshot) -> &mut Self {
+        self.str_id_map = snapshot
+            .tags
+            .iter()
+            .map(|(tag_id, tag_name)| (tag_name.clone(), *tag_id))
+            .collect();
+        self.id_str_map = snapshot.tags.into_iter().collect();
+        self.removed_ids = snapshot.reclaimed_ids;
+        self.next_id = snapshot.next_id;
+        self
+    }
+
+    //
+
+    //
+
     /// Attempts to rename old_tag to new_tag while keeping the same tag_id.
     /// If old_tag doesn't exist a TagsError::UnknownTag will be returned.
     /// If new_tag already exists this will fail with a TagsError::TagAlreadyExists.
@@ -265,24 +331,23 @@ impl TagList {
     /// Writes to a .tmp file which when completed is swapped with the original file, ensuring that
     /// no data is lost in the event of the program stopping mid-write.
     pub fn save(&mut self) -> Result<()> {
-        let tmp_path = self.db_path.root().join("tags.txt.tmp");
         let filepath = self.db_path.root().join("tags.txt");
+        let storage = self.db_path.storage();
 
-        let mut writer = BufWriter::new(File::create(&tmp_path)?);
-
+        let mut content = String::new();
         for (id, tag) in self.id_str_map.iter() {
-            writeln!(writer, "{} {}", id, tag)?;
+            writeln!(content, "{} {}", id, tag).expect("Writing to a String can not fail.");
         }
 
-        writer.flush()?;
-        fs::rename(tmp_path, filepath)?;
+        storage.write_atomic(&filepath, content.as_bytes())?;
 
+        let reclaimed_tags_path = self.db_path.root().join(RECLAIMEDTAGSFILE);
         if !self.removed_ids.is_empty() {
             self.save_removed_ids()?;
-        } else if Path::exists(&self.db_path.root().join(RECLAIMEDTAGSFILE)) {
+        } else if storage.exists(&reclaimed_tags_path) {
             // Propegate error just in case but this should never fail since we only run this IF
             // the file does exist.
-            fs::remove_file(self.db_path.root().join(RECLAIMEDTAGSFILE))?;
+            storage.remove_file(&reclaimed_tags_path)?;
         }
 
         Ok(())
@@ -297,11 +362,8 @@ impl TagList {
     /// Writes to a .tmp file which when completed is swapped with the original file, ensuring that
     /// no data is lost in the event of the program stopping mid-write.
     fn save_removed_ids(&self) -> Result<()> {
-        let tmp_path = self.db_path.root().join("reclaimed.tags.tmp");
         let filepath = self.db_path.root().join(RECLAIMEDTAGSFILE);
 
-        let mut writer = BufWriter::new(File::create(&tmp_path)?);
-
         // Since we load any reclaimed ids when creating the struct we don't need to read them when
         // we save it.
         /*
@@ -311,12 +373,13 @@ impl TagList {
             writer.write_all(&bytes)?;
         }*/
 
-        for tag_id in &self.removed_ids {
-            writer.write_all(&tag_id.to_be_bytes())?;
-        }
+        let bytes: Vec<u8> = self
+            .removed_ids
+            .iter()
+            .flat_map(|tag_id| tag_id.to_be_bytes())
+            .collect();
 
-        writer.flush()?;
-        fs::rename(tmp_path, filepath)?;
+        self.db_path.storage().write_atomic(&filepath, &bytes)?;
 
         Ok(())
     }
@@ -0,0 +1,136 @@
+use std::sync::{
+    atomic::{AtomicBool, AtomicU32, Ordering},
+    Mutex,
+};
+
+/// Returned by a long running task when it was cancelled through its `TaskProgress`.
+#[derive(Debug)]
+pub struct Cancelled;
+
+type ProgressCallback<'a> = Box<dyn FnMut(u32, u32) + Send + 'a>;
+type CancelCheck<'a> = Box<dyn Fn() -> bool + Send + Sync + 'a>;
+
+/// Reports the progress of a long running database task and allows said task to be cancelled.
+///
+/// The progress callback receives the number of processed files followed by the total number of
+/// files the task will process. A task checks for cancellation after each processed file, and
+/// when cancelled stops at that point leaving the database marked as busy. The task can then be
+/// finished later using `DataBase::resume_task`.
+///
+/// `TaskProgress::default()` reports nothing and is never cancelled.
+#[derive(Default)]
+pub struct TaskProgress<'a> {
+    callback: Mutex<Option<ProgressCallback<'a>>>,
+    cancel_check: Option<CancelCheck<'a>>,
+    processed: AtomicU32,
+    total: AtomicU32,
+}
+
+impl<'a> TaskProgress<'a> {
+    /// Creates a new `TaskProgress` without a callback or cancellation flag.
+    pub fn new() -> TaskProgress<'a> {
+        TaskProgress::default()
+    }
+
+    /// Sets the callback which receives `(processed, total)` each time a file is processed.
+    pub fn with_callback(mut self, callback: impl FnMut(u32, u32) + Send + 'a) -> Self {
+        self.callback = Mutex::new(Some(Box::new(callback)));
+        self
+    }
+
+    /// Sets the flag used to cancel the task. The task stops once the flag is set to `true`.
+    pub fn with_cancel_flag(self, cancel_flag: &'a AtomicBool) -> Self {
+        self.with_cancel_check(move || cancel_flag.load(Ordering::Relaxed))
+    }
+
+    /// Sets a function that is called to check whether the task should be cancelled.
+    pub(crate) fn with_cancel_check(
+        mut self,
+        cancel_check: impl Fn() -> bool + Send + Sync + 'a,
+    ) -> Self {
+        self.cancel_check = Some(Box::new(cancel_check));
+        self
+    }
+
+    /// Sets the total number of files the task will process and resets the processed count.
+    pub(crate) fn start(&self, total: u32) {
+        self.processed.store(0, Ordering::Relaxed);
+        self.total.store(total, Ordering::Relaxed);
+        self.report(0);
+    }
+
+    /// Marks one more file as processed and reports the new progress.
+    ///
+    /// Returns `Err(Cancelled)` if the task has been cancelled.
+    pub(crate) fn file_processed(&self) -> Result<(), Cancelled> {
+        let processed = self.processed.fetch_add(1, Ordering::Relaxed) + 1;
+        self.report(processed);
+        self.check_cancelled()
+    }
+
+    /// Returns `Err(Cancelled)` if the task has been cancelled.
+    pub(crate) fn check_cancelled(&self) -> Result<(), Cancelled> {
+        match &self.cancel_check {
+            Some(is_cancelled) if is_cancelled() => Err(Cancelled),
+            _ => Ok(()),
+        }
+    }
+
+    fn report(&self, processed: u32) {
+        let mut callback = self
+            .callback
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        if let Some(callback) = callback.as_mut() {
+            // Files can be added while a task runs. Never report a total below the processed count.
+            let total = self.total.load(Ordering::Relaxed).max(processed);
+            callback(processed, total);
+        }
+    }
+}
+
+//
+
+//
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex,
+    };
+
+    use super::TaskProgress;
+
+    #[test]
+    fn progress_is_reported_for_every_processed_file() {
+        let reports = Mutex::new(Vec::new());
+        let progress = TaskProgress::new().with_callback(|processed, total| {
+            reports.lock().unwrap().push((processed, total));
+        });
+
+        progress.start(2);
+        for _ in 0..3 {
+            progress.file_processed().unwrap();
+        }
+        drop(progress);
+        // A file added while the task runs raises the reported total.
+        assert_eq!(
+            reports.into_inner().unwrap(),
+            vec![(0, 2), (1, 2), (2, 2), (3, 3)]
+        );
+    }
+
+    #[test]
+    fn task_is_cancelled_once_the_flag_is_set() {
+        let cancel_flag = AtomicBool::new(false);
+        let progress = TaskProgress::new().with_cancel_flag(&cancel_flag);
+        progress.start(3);
+
+        assert!(progress.file_processed().is_ok());
+        cancel_flag.store(true, Ordering::Relaxed);
+        assert!(progress.check_cancelled().is_err());
+        assert!(progress.file_processed().is_err());
+        assert!(TaskProgress::default().check_cancelled().is_ok());
+    }
+}
@@ -0,0 +1,100 @@
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use time::{Date, Month};
+
+use crate::{
+    data_base::DataBase,
+    data_entry::DataEntry,
+    metrics::{MENTALSCORE, PHYSICALSCORE},
+    storage::MemoryStorage,
+    tags::TagList,
+    DATAFILEEXTENSION,
+};
+
+//
+
+//
+
+/// Creates a database named `name` in a new `MemoryStorage`.
+pub(crate) fn create_database(name: &str, day_switch_offset: i8) -> DataBase {
+    let db_path = PathBuf::from(format!("/{}", name));
+    DataBase::create_with_storage(db_path, day_switch_offset, Arc::new(MemoryStorage::new()))
+        .unwrap()
+}
+
+/// Returns a entry at `hour` and `minute` holding both built-in metrics and `tags`.
+pub(crate) fn entry(hour: i8, minute: u8, tags: Vec<u16>) -> DataEntry {
+    let metrics = BTreeMap::from([(MENTALSCORE, 60), (PHYSICALSCORE, 70)]);
+    DataEntry::new(hour, metrics, tags).with_minute(minute)
+}
+
+/// Returns a empty directory named after `name` in the temporary directory of the system. Tests
+/// using the file system remove the directory once they are done.
+pub(crate) fn temp_dir(name: &str) -> PathBuf {
+    let dir =
+        std::env::temp_dir().join(format!("stat_diary_tools_{}_{}", std::process::id(), name));
+    if dir.exists() {
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+pub(crate) fn date(year: i32, month: Month, day: u8) -> Date {
+    Date::from_calendar_date(year, month, day).unwrap()
+}
+
+//
+
+//
+
+/// Returns the path of the data file of `date`, including the extension.
+pub(crate) fn data_file_path(database: &DataBase, date: Date) -> PathBuf {
+    database
+        .day_file_path(date)
+        .with_extension(DATAFILEEXTENSION)
+}
+
+/// Returns the hour, minute and tags of every entry of the day.
+pub(crate) fn day_entries(database: &DataBase, date: Date) -> Vec<(i8, u8, Vec<u16>)> {
+    database
+        .get_data_entries(date.year(), date.month() as u8, date.day())
+        .unwrap()
+        .into_iter()
+        .map(|entry| (entry.hour, entry.minute, entry.tags))
+        .collect()
+}
+
+/// Returns the id and name of every tag, sorted by id.
+pub(crate) fn tags(database: &DataBase) -> Vec<(u16, String)> {
+    let mut tags: Vec<(u16, String)> = TagList::from_file(database.path())
+        .unwrap()
+        .iter()
+        .map(|(tag_id, name)| (tag_id, name.to_string()))
+        .collect();
+    tags.sort();
+    tags
+}
+
+/// Returns the content of every cache file and stat sum file of the database, keyed by path.
+pub(crate) fn derived_files(database: &DataBase) -> BTreeMap<PathBuf, Vec<u8>> {
+    let storage = database.path().storage();
+    storage
+        .walk_files(database.database_path())
+        .unwrap()
+        .into_iter()
+        .filter(|file| file.starts_with(database.path().stat_sums()) || is_cache_file(file))
+        .map(|file| {
+            let content = storage.read(&file).unwrap();
+            (file, content)
+        })
+        .collect()
+}
+
+fn is_cache_file(file: &Path) -> bool {
+    file.ends_with("month_cache.txt") || file.ends_with("year_cache.txt")
+}
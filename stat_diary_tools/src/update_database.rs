@@ -9,8 +9,12 @@ use std::{
 use walkdir::WalkDir;
 
 use crate::{
-    cache_handling::regenerate_caches, db_path::DataBasePath, stat_sums::regenerate_tag_sums,
-    tags::TagList, utilities::read_lines, DATAFILEEXTENSION,
+    db_path::DataBasePath,
+    regeneration::{regenerate, RegenerationTargets},
+    tags::TagList,
+    task_progress::TaskProgress,
+    utilities::read_lines,
+    DATAFILEEXTENSION,
 };
 
 pub fn temporary_update_database(db_path: &DataBasePath) -> Result<(), Box<dyn Error>> {
@@ -47,14 +51,17 @@ pub fn temporary_update_database(db_path: &DataBasePath) -> Result<(), Box<dyn E
     tags_writer.flush()?;
 
     // Remove the old averages folder and replace it with tag_sums.
-    fs::remove_dir_all(db_path.root().join("averages"))?;
-    if let Err(e) = regenerate_tag_sums(db_path) {
-        println!("regenerate_tag_sums error occured!\n{:?}", e);
+    if db_path.root().join("averages").exists() {
+        fs::remove_dir_all(db_path.root().join("averages"))?;
     }
-
-    // Generate caches.
-    if let Err(e) = regenerate_caches(db_path) {
-        println!("regenerate_caches error occured!\n{:?}", e);
+    // Generate tag sums and caches.
+    if let Err(e) = regenerate(
+        db_path,
+        RegenerationTargets::ALL,
+        0,
+        &TaskProgress::default(),
+    ) {
+        println!("regenerate error occured!\n{:?}", e);
     }
 
     Ok(())